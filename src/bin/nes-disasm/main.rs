@@ -0,0 +1,190 @@
+use std::{env, path::Path};
+
+use anyhow::Result;
+use nes_rs::{
+    cpu::disassembler::{Bank, disassemble},
+    nes::{
+        code_data_logger::CodeDataLog,
+        read_prg_rom,
+        symbols::{Location, SymbolTable},
+    },
+};
+use thiserror::Error;
+
+const DEFAULT_BANK_SIZE_K: usize = 16;
+
+const USAGE: &str = "Usage: nes-disasm <rom> [options]
+    --bank N         the PRG bank to disassemble, counting from 0 (default 0)
+    --bank-size K    bank size in KB, 8, 16 or 32 (default 16)
+    --org ADDR       hex address the bank is mapped at. Defaults to 0x8000, or the top
+                     of memory for the last bank, which is where the vectors have to be
+    --listing        print addresses and bytes instead of ca65 source
+    --cdl FILE       an FCEUX .cdl code/data log. Only logged code is disassembled,
+                     everything else becomes .byte lines
+    --symbols FILE   a ca65 .dbg, FCEUX .nl or Mesen .mlb file to label the disassembly
+                     with. Can be repeated";
+
+/**
+ * Disassembles a PRG bank of an iNES file into ca65 source
+ */
+fn main() -> Result<()> {
+    let options = Options::parse(env::args().skip(1))?;
+
+    let prg_rom = read_prg_rom(&options.rom)?;
+    let bank_size = options.bank_size_k * 1024;
+    let bank_count = prg_rom.len().div_ceil(bank_size);
+    if options.bank >= bank_count {
+        Err(DisasmError::NoSuchBank(options.bank, bank_count))?;
+    }
+    let bytes =
+        &prg_rom[options.bank * bank_size..((options.bank + 1) * bank_size).min(prg_rom.len())];
+    let org = match options.org {
+        Some(org) => org,
+        None if options.bank == bank_count - 1 => (0x10000 - bank_size) as u16,
+        None => 0x8000,
+    };
+    if org as usize + bytes.len() > 0x10000 {
+        Err(DisasmError::DoesNotFit(org))?;
+    }
+
+    let mut lines = match &options.cdl {
+        Some(path) => {
+            let log = CodeDataLog::load(Path::new(path), prg_rom.len())?;
+            let start = options.bank * bank_size;
+            log.disassemble(&prg_rom, start, start + bytes.len(), org)
+        }
+        None => {
+            let bank = Bank { base: org, bytes };
+            let end = (org as usize + bytes.len() - 1) as u16;
+            disassemble(&bank, org, end, None)
+        }
+    };
+
+    let mut symbols = SymbolTable::new();
+    for path in &options.symbols {
+        symbols.load(Path::new(path))?;
+    }
+    // only addresses in this bank can be labelled with PRG ROM symbols
+    let start = options.bank * bank_size;
+    let prg_rom_offset = |addr: u16| {
+        let offset = (addr as usize).checked_sub(org as usize)?;
+        (offset < bytes.len()).then_some(start + offset)
+    };
+    // ca65 doesn't allow scoped names to be defined outside their scope
+    let label = |addr| {
+        symbols
+            .label(addr, prg_rom_offset(addr))
+            .map(|l| l.replace("::", "_"))
+    };
+    for line in &mut lines {
+        line.label_operand(&label);
+    }
+
+    if options.listing {
+        for line in lines {
+            if let Some(name) = label(line.addr).filter(|l| !l.contains('+')) {
+                println!("{name}:");
+            }
+            println!("{line}");
+        }
+    } else {
+        println!(
+            "; {} PRG bank {} of {}",
+            options.rom, options.bank, bank_count
+        );
+        println!(".setcpu \"6502X\"");
+        for symbol in symbols.symbols() {
+            if let Location::Cpu(addr) = symbol.location {
+                println!("{} = ${addr:04X}", symbol.name.replace("::", "_"));
+            }
+        }
+        println!(".org ${org:04X}");
+        for line in lines {
+            if let Some(name) = label(line.addr).filter(|l| !l.contains('+')) {
+                println!("{name}:");
+            }
+            println!("        {:32} ; ${:04X}", line.source(), line.addr);
+        }
+    }
+    Ok(())
+}
+
+struct Options {
+    rom: String,
+    bank: usize,
+    bank_size_k: usize,
+    org: Option<u16>,
+    listing: bool,
+    cdl: Option<String>,
+    symbols: Vec<String>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut result = Self {
+            rom: String::new(),
+            bank: 0,
+            bank_size_k: DEFAULT_BANK_SIZE_K,
+            org: None,
+            listing: false,
+            cdl: None,
+            symbols: Vec::new(),
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| DisasmError::MissingValue(arg.clone()))
+            };
+            match arg.as_str() {
+                "--bank" => result.bank = parse_number(&value()?, 10)?,
+                "--bank-size" => {
+                    result.bank_size_k = parse_number(&value()?, 10)?;
+                    if ![8, 16, 32].contains(&result.bank_size_k) {
+                        Err(DisasmError::InvalidArgument(arg))?;
+                    }
+                }
+                "--org" => result.org = Some(parse_number(&value()?, 16)?),
+                "--listing" => result.listing = true,
+                "--cdl" => result.cdl = Some(value()?),
+                "--symbols" => result.symbols.push(value()?),
+                "--help" | "-h" => Err(DisasmError::Usage)?,
+                _ if arg.starts_with("--") || !result.rom.is_empty() => {
+                    Err(DisasmError::InvalidArgument(arg))?
+                }
+                _ => result.rom = arg,
+            }
+        }
+
+        if result.rom.is_empty() {
+            Err(DisasmError::Usage)?;
+        }
+        Ok(result)
+    }
+}
+
+/**
+ * Parses decimal or hex, which can optionally be written with a 0x or $ prefix
+ */
+fn parse_number<T: TryFrom<u32>>(text: &str, radix: u32) -> Result<T> {
+    let digits = text.trim_start_matches("0x").trim_start_matches('$');
+    let number = u32::from_str_radix(digits, radix)
+        .ok()
+        .and_then(|n| T::try_from(n).ok())
+        .ok_or_else(|| DisasmError::InvalidArgument(text.to_string()))?;
+    Ok(number)
+}
+
+#[derive(Error, Debug)]
+enum DisasmError {
+    #[error("{USAGE}")]
+    Usage,
+    #[error("Invalid argument '{0}'\n{USAGE}")]
+    InvalidArgument(String),
+    #[error("Missing a value for {0}\n{USAGE}")]
+    MissingValue(String),
+    #[error("There's no bank {0}, the PRG ROM has {1}")]
+    NoSuchBank(usize, usize),
+    #[error("The bank doesn't fit in memory at {0:#06x}")]
+    DoesNotFit(u16),
+}
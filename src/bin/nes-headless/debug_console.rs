@@ -0,0 +1,367 @@
+#[cfg(test)]
+mod unit_tests;
+
+use std::{
+    fs,
+    io::{BufRead, Write, stdin, stdout},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use nes_rs::cpu::assembler::assemble_with;
+use nes_rs::nes::{
+    NES,
+    debugger::{Access, AddressSpace, Debugger, RunMode, condition::Condition},
+    memory_domains::MemoryDomain,
+    symbols::Labels,
+};
+use thiserror::Error;
+
+use crate::parse_number;
+
+const DISASSEMBLY_LINES: usize = 10;
+const MEMORY_BYTES: usize = 0x40;
+const MEMORY_BYTES_PER_LINE: usize = 0x10;
+
+const HELP: &str = "Commands, addresses are hex or labels like player_update+3:
+    b ADDR [if COND]                         break before ADDR runs
+    w r|w|rw|x [cpu|ppu] START[-END] [if COND] watch accesses to a range
+    d ID                                     delete a breakpoint or watchpoint
+    l                                        list breakpoints and watchpoints
+    c                                        continue
+    s                                        step into
+    n                                        step over
+    o                                        step out
+    line N                                   run until scanline N (decimal)
+    r                                        show the registers
+    apu                                      show what each APU channel is doing
+    mapper                                   show the mapper's banks, mirroring, IRQ and registers
+    u [ADDR] [COUNT]                         disassemble COUNT instructions from ADDR, or PC
+    p EXPR                                   print an expression
+    m [DOMAIN] ADDR [COUNT]                  show COUNT (hex) bytes of memory
+    poke [DOMAIN] ADDR VALUE...              write hex bytes, patching ROM rather than banking
+    a ADDR INSTRUCTION                       assemble an instruction at ADDR, e.g. a C000 lda #$40
+    dump DOMAIN FILE                         save a whole memory domain to a file
+    import DOMAIN FILE                       load a file into a memory domain
+    sym FILE                                 load a ca65 .dbg, FCEUX .nl or Mesen .mlb symbol file
+    q                                        stop emulating
+Conditions can use A X Y SP PC P, the flags C Z I D V N, CYCLE SCANLINE DOT,
+ADDRESS and VALUE for watchpoints, and [ADDR] to read memory, e.g. A == $40 && [$10] != 0
+Memory domains are cpu (the default), ppu, ram, prg-rom, prg-ram, chr, ciram, palette,
+oam and oam2. Memory is read and written without side effects.";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Break(u16, Option<Condition>),
+    Watch(AddressSpace, u16, u16, Access, Option<Condition>),
+    Delete(usize),
+    List,
+    Run(RunMode),
+    Registers,
+    Apu,
+    Mapper,
+    Disassemble(Option<u16>, usize),
+    Print(Condition),
+    Memory(MemoryDomain, usize, usize),
+    Poke(MemoryDomain, usize, Vec<u8>),
+    Assemble(u16, Vec<u8>),
+    Dump(MemoryDomain, PathBuf),
+    Import(MemoryDomain, PathBuf),
+    Symbols(String),
+    Quit,
+    Help,
+}
+
+impl Command {
+    /**
+     * Parses a command without any labels
+     */
+    #[cfg(test)]
+    pub fn parse(line: &str) -> Result<Self> {
+        Self::parse_with(line, &|_| None)
+    }
+
+    /**
+     * Addresses can also be labels, which resolve looks up
+     */
+    pub fn parse_with(line: &str, resolve: &dyn Fn(&str) -> Option<u16>) -> Result<Self> {
+        let (line, condition) = match line.split_once(" if ") {
+            Some((line, condition)) => (line, Some(Condition::parse(condition)?)),
+            None => (line, None),
+        };
+        let parts = line.split_whitespace().collect::<Vec<_>>();
+        let invalid = || DebugConsoleError::InvalidCommand(line.trim().to_string());
+        let address = |text| parse_address(text, resolve);
+
+        let command = match parts[..] {
+            ["b", addr] => Command::Break(address(addr)?, condition),
+            ["w", access, range] => Self::watch(access, "cpu", range, condition, resolve)?,
+            ["w", access, space, range] => Self::watch(access, space, range, condition, resolve)?,
+            ["d", id] => Command::Delete(parse_number(id, 10)?),
+            ["l"] => Command::List,
+            ["c"] => Command::Run(RunMode::Continue),
+            ["s"] => Command::Run(RunMode::StepInto),
+            ["n"] => Command::Run(RunMode::StepOver),
+            ["o"] => Command::Run(RunMode::StepOut),
+            ["line", line] => Command::Run(RunMode::ToScanLine(
+                line.parse::<i16>().map_err(|_| invalid())?,
+            )),
+            ["r"] => Command::Registers,
+            ["apu"] => Command::Apu,
+            ["mapper"] => Command::Mapper,
+            ["u"] => Command::Disassemble(None, DISASSEMBLY_LINES),
+            ["u", addr] => Command::Disassemble(Some(address(addr)?), DISASSEMBLY_LINES),
+            ["u", addr, count] => {
+                Command::Disassemble(Some(address(addr)?), parse_number(count, 10)?)
+            }
+            ["p", ..] => Command::Print(Condition::parse(&line.trim()[1..])?),
+            ["m", ..] => {
+                let (domain, addr, rest) = Self::domain_address(&parts[1..], resolve)?;
+                let count = match rest {
+                    [] => MEMORY_BYTES,
+                    [count] => parse_number(count, 16)?,
+                    _ => Err(invalid())?,
+                };
+                Command::Memory(domain, addr, count)
+            }
+            ["poke", ..] => {
+                let (domain, addr, values) = Self::domain_address(&parts[1..], resolve)?;
+                if values.is_empty() {
+                    Err(invalid())?;
+                }
+                let values = values
+                    .iter()
+                    .map(|value| parse_number(value, 16))
+                    .collect::<Result<_>>()?;
+                Command::Poke(domain, addr, values)
+            }
+            ["a", addr, _, ..] => {
+                let addr = address(addr)?;
+                let (_, source) = line.trim()[1..]
+                    .trim()
+                    .split_once(char::is_whitespace)
+                    .unwrap();
+                Command::Assemble(addr, assemble_with(source, addr, resolve)?)
+            }
+            ["dump", domain, path] => Command::Dump(Self::domain(domain)?, PathBuf::from(path)),
+            ["import", domain, path] => Command::Import(Self::domain(domain)?, PathBuf::from(path)),
+            ["sym", _, ..] => Command::Symbols(line.trim()[3..].trim().to_string()),
+            ["q"] => Command::Quit,
+            ["h" | "help" | "?"] => Command::Help,
+            _ => Err(invalid())?,
+        };
+        Ok(command)
+    }
+
+    fn domain(name: &str) -> Result<MemoryDomain> {
+        Ok(MemoryDomain::from_name(name)
+            .ok_or_else(|| DebugConsoleError::UnknownDomain(name.to_string()))?)
+    }
+
+    // an optional domain, then an address in it. Only CPU addresses can be labels.
+    fn domain_address<'a>(
+        parts: &'a [&'a str],
+        resolve: &dyn Fn(&str) -> Option<u16>,
+    ) -> Result<(MemoryDomain, usize, &'a [&'a str])> {
+        let (domain, parts) = match parts {
+            [name, rest @ ..] if !rest.is_empty() && MemoryDomain::from_name(name).is_some() => {
+                (Self::domain(name)?, rest)
+            }
+            _ => (MemoryDomain::CpuBus, parts),
+        };
+        let [addr, rest @ ..] = parts else {
+            Err(DebugConsoleError::InvalidCommand(parts.join(" ")))?
+        };
+        let addr = match domain {
+            MemoryDomain::CpuBus => parse_address(addr, resolve)? as usize,
+            _ => parse_number(addr, 16)?,
+        };
+        Ok((domain, addr, rest))
+    }
+
+    fn watch(
+        access: &str,
+        space: &str,
+        range: &str,
+        condition: Option<Condition>,
+        resolve: &dyn Fn(&str) -> Option<u16>,
+    ) -> Result<Self> {
+        let access = match access {
+            "r" => Access::Read,
+            "w" => Access::Write,
+            "rw" => Access::Read | Access::Write,
+            "x" => Access::Execute,
+            _ => Err(DebugConsoleError::InvalidCommand(access.to_string()))?,
+        };
+        let space = match space {
+            "cpu" => AddressSpace::Cpu,
+            "ppu" => AddressSpace::Ppu,
+            _ => Err(DebugConsoleError::InvalidCommand(space.to_string()))?,
+        };
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (parse_address(start, resolve)?, parse_address(end, resolve)?),
+            None => {
+                let addr = parse_address(range, resolve)?;
+                (addr, addr)
+            }
+        };
+        Ok(Command::Watch(space, start, end, access, condition))
+    }
+}
+
+// hex, or a label with an optional decimal offset like player_update+3. A label
+// that's also a hex number, like "beef", is taken as the label.
+fn parse_address(text: &str, resolve: &dyn Fn(&str) -> Option<u16>) -> Result<u16> {
+    if let Some(addr) = resolve(text) {
+        return Ok(addr);
+    }
+    if let Ok(addr) = parse_number(text, 16) {
+        return Ok(addr);
+    }
+    let (name, offset) = match text.rsplit_once('+') {
+        Some((name, offset)) => (name, parse_number::<u16>(offset, 10)?),
+        None => (text, 0),
+    };
+    let addr = resolve(name).ok_or_else(|| DebugConsoleError::UnknownLabel(name.to_string()))?;
+    Ok(addr.wrapping_add(offset))
+}
+
+/**
+ * An interactive prompt on stdin for the debugger. The NES is clocked through
+ * the console, which reads commands whenever the debugger pauses.
+ */
+pub struct DebugConsole {
+    pub debugger: Debugger,
+}
+
+impl DebugConsole {
+    pub fn attach(nes: &mut NES) -> Self {
+        println!("{HELP}");
+        Self {
+            debugger: Debugger::attach(nes),
+        }
+    }
+
+    /**
+     * Reads commands until one of them resumes the NES. Returns false when
+     * the user quits
+     */
+    pub fn prompt_while_paused(&mut self, nes: &mut NES) -> Result<bool> {
+        if !self.debugger.is_paused() {
+            return Ok(true);
+        }
+        if let Some(reason) = self.debugger.stop_reason() {
+            let pc = self.debugger.registers(nes).pc;
+            match self.debugger.label(pc) {
+                Some(label) => println!("Stopped at {reason} in {label}"),
+                None => println!("Stopped at {reason}"),
+            }
+        }
+        println!("{}", self.debugger.registers(nes));
+        self.print_disassembly(nes, None, 1);
+
+        let mut lines = stdin().lock().lines();
+        loop {
+            print!("> ");
+            stdout().flush()?;
+            let Some(line) = lines.next() else {
+                return Ok(false);
+            };
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let labels = self.debugger.labels().cloned();
+            let resolve = |name: &str| labels.as_ref().and_then(|l| l.resolve(name));
+            match Command::parse_with(&line, &resolve)
+                .and_then(|command| self.execute(nes, command))
+            {
+                Ok(Some(keep_running)) => return Ok(keep_running),
+                Ok(None) => {}
+                Err(e) => println!("{e}"),
+            }
+        }
+    }
+
+    // returns whether to keep emulating once the command resumes or quits
+    fn execute(&mut self, nes: &mut NES, command: Command) -> Result<Option<bool>> {
+        match command {
+            Command::Break(addr, condition) => {
+                let id = self.debugger.add_breakpoint(addr, condition);
+                println!("Added #{id}");
+            }
+            Command::Watch(space, start, end, access, condition) => {
+                let id = self
+                    .debugger
+                    .add_watchpoint(space, start, end, access, condition)?;
+                println!("Added #{id}");
+            }
+            Command::Delete(id) => self.debugger.remove(id)?,
+            Command::List => {
+                for breakpoint in self.debugger.breakpoints() {
+                    println!("{breakpoint}");
+                }
+            }
+            Command::Run(mode) => {
+                self.debugger.resume(nes, mode);
+                return Ok(Some(true));
+            }
+            Command::Registers => println!("{}", self.debugger.registers(nes)),
+            Command::Apu => println!("{}", nes.apu_channels()),
+            Command::Mapper => println!("{}", nes.mapper_description()),
+            Command::Disassemble(addr, count) => self.print_disassembly(nes, addr, count),
+            Command::Print(expression) => {
+                let value = self.debugger.evaluate(nes, &expression);
+                println!("{value} (${value:X})");
+            }
+            Command::Memory(domain, addr, count) => {
+                let end = (addr + count).min(nes.memory_size(domain));
+                for start in (addr..end).step_by(MEMORY_BYTES_PER_LINE) {
+                    let bytes = (start..end.min(start + MEMORY_BYTES_PER_LINE))
+                        .map(|addr| Ok(format!("{:02X}", nes.peek(domain, addr)?)))
+                        .collect::<Result<Vec<_>>>()?;
+                    println!("{start:04X}: {}", bytes.join(" "));
+                }
+            }
+            Command::Poke(domain, addr, values) => nes.import(domain, addr, &values)?,
+            Command::Assemble(addr, bytes) => {
+                nes.import(MemoryDomain::CpuBus, addr as usize, &bytes)?;
+                self.print_disassembly(nes, Some(addr), 1);
+            }
+            Command::Dump(domain, path) => {
+                fs::write(&path, nes.dump(domain))?;
+                println!("Saved {:#x} bytes of {domain}", nes.memory_size(domain));
+            }
+            Command::Import(domain, path) => nes.import(domain, 0, &fs::read(&path)?)?,
+            Command::Symbols(path) => {
+                if self.debugger.labels().is_none() {
+                    self.debugger.set_labels(Labels::new(nes));
+                }
+                if let Some(labels) = self.debugger.labels() {
+                    let count = labels.load(Path::new(&path))?;
+                    println!("Loaded {count} symbols");
+                }
+            }
+            Command::Quit => return Ok(Some(false)),
+            Command::Help => println!("{HELP}"),
+        }
+        Ok(None)
+    }
+
+    fn print_disassembly(&self, nes: &NES, addr: Option<u16>, count: usize) {
+        let addr = addr.unwrap_or_else(|| self.debugger.registers(nes).pc);
+        for line in self.debugger.disassemble(nes, addr, count) {
+            println!("{line}");
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+enum DebugConsoleError {
+    #[error("Invalid command '{0}', h shows the commands")]
+    InvalidCommand(String),
+    #[error("There's no label '{0}'")]
+    UnknownLabel(String),
+    #[error("There's no memory domain '{0}', h lists them")]
+    UnknownDomain(String),
+}
@@ -0,0 +1,144 @@
+use std::path::PathBuf;
+
+use nes_rs::nes::{
+    debugger::{Access, AddressSpace, RunMode, condition::Condition},
+    memory_domains::MemoryDomain,
+};
+
+use crate::debug_console::Command;
+
+#[test]
+fn test_breakpoints() {
+    assert_eq!(
+        Command::Break(0xC000, None),
+        Command::parse("b C000").unwrap()
+    );
+    assert_eq!(
+        Command::Break(0xC000, Some(Condition::parse("A == $40").unwrap())),
+        Command::parse("b $c000 if A == $40").unwrap()
+    );
+}
+
+#[test]
+fn test_watchpoints() {
+    assert_eq!(
+        Command::Watch(AddressSpace::Cpu, 0x10, 0x10, Access::Write, None),
+        Command::parse("w w 10").unwrap()
+    );
+    assert_eq!(
+        Command::Watch(
+            AddressSpace::Ppu,
+            0x2000,
+            0x23FF,
+            Access::Read | Access::Write,
+            Some(Condition::parse("VALUE != 0").unwrap())
+        ),
+        Command::parse("w rw ppu 2000-23FF if VALUE != 0").unwrap()
+    );
+    assert!(Command::parse("w q 10").is_err());
+    assert!(Command::parse("w r apu 10").is_err());
+}
+
+#[test]
+fn test_run_commands() {
+    assert_eq!(
+        Command::Run(RunMode::Continue),
+        Command::parse("c").unwrap()
+    );
+    assert_eq!(
+        Command::Run(RunMode::StepOver),
+        Command::parse(" n ").unwrap()
+    );
+    assert_eq!(
+        Command::Run(RunMode::ToScanLine(-1)),
+        Command::parse("line -1").unwrap()
+    );
+}
+
+#[test]
+fn test_apu() {
+    assert_eq!(Command::Apu, Command::parse("apu").unwrap());
+    assert!(Command::parse("apu 1").is_err());
+}
+
+#[test]
+fn test_mapper() {
+    assert_eq!(Command::Mapper, Command::parse("mapper").unwrap());
+    assert!(Command::parse("mapper 4").is_err());
+}
+
+#[test]
+fn test_disassemble() {
+    assert_eq!(Command::Disassemble(None, 10), Command::parse("u").unwrap());
+    assert_eq!(
+        Command::Disassemble(Some(0xC000), 3),
+        Command::parse("u C000 3").unwrap()
+    );
+}
+
+#[test]
+fn test_print_and_invalid() {
+    assert_eq!(
+        Command::Print(Condition::parse("[$10] + 1").unwrap()),
+        Command::parse("p [$10] + 1").unwrap()
+    );
+    assert!(Command::parse("frobnicate").is_err());
+    assert!(Command::parse("b").is_err());
+}
+
+#[test]
+fn test_labels() {
+    let resolve = |name: &str| match name {
+        "player_update" => Some(0xC0F3),
+        "beef" => Some(0x0300),
+        _ => None,
+    };
+    assert_eq!(
+        Command::Break(0xC0F6, None),
+        Command::parse_with("b player_update+3", &resolve).unwrap()
+    );
+    assert_eq!(
+        Command::Disassemble(Some(0x0300), 10),
+        Command::parse_with("u beef", &resolve).unwrap()
+    );
+    assert_eq!(
+        Command::Watch(AddressSpace::Cpu, 0x0300, 0xC0F3, Access::Read, None),
+        Command::parse_with("w r beef-player_update", &resolve).unwrap()
+    );
+    assert_eq!(
+        Command::Break(0xC000, None),
+        Command::parse_with("b C000", &resolve).unwrap()
+    );
+    assert!(Command::parse_with("b nowhere", &resolve).is_err());
+    assert_eq!(
+        Command::Symbols("game.dbg".to_string()),
+        Command::parse("sym game.dbg").unwrap()
+    );
+}
+
+#[test]
+fn test_memory() {
+    assert_eq!(
+        Command::Memory(MemoryDomain::CpuBus, 0xC000, 0x40),
+        Command::parse("m C000").unwrap()
+    );
+    assert_eq!(
+        Command::Memory(MemoryDomain::Oam, 0x10, 0x20),
+        Command::parse("m oam 10 20").unwrap()
+    );
+    assert_eq!(
+        Command::Poke(MemoryDomain::PrgRom, 0x4000, vec![0xEA, 0x60]),
+        Command::parse("poke prg-rom 4000 ea 60").unwrap()
+    );
+    assert_eq!(
+        Command::Dump(MemoryDomain::Chr, PathBuf::from("chr.bin")),
+        Command::parse("dump chr chr.bin").unwrap()
+    );
+    assert!(Command::parse("poke 10").is_err());
+    assert_eq!(
+        Command::Assemble(0xC000, vec![0xBD, 0x00, 0x02]),
+        Command::parse("a C000 lda $0200,x").unwrap()
+    );
+    assert!(Command::parse("a C000 lda").is_err());
+    assert!(Command::parse("dump rom rom.bin").is_err());
+}
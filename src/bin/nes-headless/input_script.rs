@@ -0,0 +1,88 @@
+#[cfg(test)]
+mod unit_tests;
+
+use anyhow::Result;
+use nes_rs::nes::controllers::JoyPadButton;
+use thiserror::Error;
+
+/**
+ * Controller input read from a text file. Each line is
+ *
+ * `<frame> <player> <buttons>`
+ *
+ * where player is 1 or 2 and buttons is a comma separated list of
+ * a, b, select, start, up, down, left and right, or `none`. The buttons
+ * stay held from that frame until the player's next line. Blank lines and
+ * anything after a `#` are ignored.
+ */
+#[derive(Debug, Default)]
+pub struct InputScript {
+    // (frame, player index, buttons) sorted by frame
+    events: Vec<(u32, usize, u8)>,
+}
+
+impl InputScript {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut events = Vec::new();
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || InputScriptError::InvalidLine(line_number + 1, line.to_string());
+
+            let parts = line.split_whitespace().collect::<Vec<_>>();
+            let [frame, player, buttons] = parts[..] else {
+                Err(invalid())?
+            };
+            let frame = frame.parse::<u32>().map_err(|_| invalid())?;
+            let player = match player {
+                "1" => 0,
+                "2" => 1,
+                _ => Err(invalid())?,
+            };
+            let buttons = parse_buttons(buttons).ok_or_else(invalid)?;
+            events.push((frame, player, buttons));
+        }
+        // stable so that later lines for the same frame win
+        events.sort_by_key(|(frame, _, _)| *frame);
+        Ok(Self { events })
+    }
+
+    /**
+     * The buttons held by each player during the given frame
+     */
+    pub fn buttons_at(&self, frame: u32) -> [u8; 2] {
+        let mut result = [0; 2];
+        for (_, player, buttons) in self.events.iter().take_while(|(f, _, _)| *f <= frame) {
+            result[*player] = *buttons;
+        }
+        result
+    }
+}
+
+fn parse_buttons(text: &str) -> Option<u8> {
+    if text == "none" {
+        return Some(0);
+    }
+    text.split(',').try_fold(0, |buttons, name| {
+        let button = match name.to_ascii_lowercase().as_str() {
+            "a" => JoyPadButton::A,
+            "b" => JoyPadButton::B,
+            "select" => JoyPadButton::Select,
+            "start" => JoyPadButton::Start,
+            "up" => JoyPadButton::Up,
+            "down" => JoyPadButton::Down,
+            "left" => JoyPadButton::Left,
+            "right" => JoyPadButton::Right,
+            _ => return None,
+        };
+        Some(buttons | button)
+    })
+}
+
+#[derive(Error, Debug)]
+pub enum InputScriptError {
+    #[error("Invalid input script line {0}: '{1}'")]
+    InvalidLine(usize, String),
+}
@@ -0,0 +1,44 @@
+use nes_rs::nes::controllers::JoyPadButton;
+
+use crate::input_script::InputScript;
+
+#[test]
+fn test_buttons_hold_until_changed() {
+    let script = InputScript::parse(
+        "
+        # press start to get past the title screen
+        10 1 start
+        12 1 none
+        20 1 a,right
+        20 2 select
+        ",
+    )
+    .unwrap();
+
+    assert_eq!([0, 0], script.buttons_at(0));
+    assert_eq!([JoyPadButton::Start as u8, 0], script.buttons_at(10));
+    assert_eq!([JoyPadButton::Start as u8, 0], script.buttons_at(11));
+    assert_eq!([0, 0], script.buttons_at(12));
+    assert_eq!(
+        [
+            JoyPadButton::A | JoyPadButton::Right,
+            JoyPadButton::Select as u8
+        ],
+        script.buttons_at(500)
+    );
+}
+
+#[test]
+fn test_lines_can_be_out_of_order() {
+    let script = InputScript::parse("30 1 b\n5 1 up # trailing comment").unwrap();
+
+    assert_eq!([JoyPadButton::Up as u8, 0], script.buttons_at(29));
+    assert_eq!([JoyPadButton::B as u8, 0], script.buttons_at(30));
+}
+
+#[test]
+fn test_invalid_lines() {
+    for text in ["10 3 a", "ten 1 a", "10 1 jump", "10 1", "10 1 a b"] {
+        assert!(InputScript::parse(text).is_err(), "{text}");
+    }
+}
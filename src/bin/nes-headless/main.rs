@@ -0,0 +1,491 @@
+use std::{
+    cell::RefCell,
+    env,
+    fs::{self, File},
+    io::{BufReader, BufWriter, Write, sink, stdout},
+    path::{Path, PathBuf},
+    process,
+    rc::Rc,
+};
+
+use anyhow::Result;
+use blip_buf::BlipBuf;
+use debug_console::DebugConsole;
+use input_script::InputScript;
+use nes_rs::nes::{
+    NES,
+    apu_inspector::ApuChannel,
+    code_data_logger::{CodeDataLog, CodeDataLogger},
+    controllers::JoyPad,
+    gdb_server::GdbServer,
+    ppu_events::{PpuEventLogger, PpuEvents, overlay_image},
+    profiler::Profiler,
+    read_prg_rom,
+    symbols::Labels,
+    trace_logger::{Comparison, Divergence, TraceFormat, TraceLogger, TraceOptions},
+};
+use output::Screen;
+use thiserror::Error;
+
+mod debug_console;
+mod input_script;
+mod output;
+
+const PPU_CLOCK_SPEED: usize = 5369318;
+const SAMPLE_RATE: u32 = 44100;
+// enough room for a whole frame of downsampled audio
+const BLIP_BUFF_SIZE: usize = 30000;
+const VOLUME: f32 = 0.5;
+const DEFAULT_FRAMES: u32 = 600;
+// routines and instructions listed in a profile report
+const PROFILE_TOP: usize = 30;
+
+const USAGE: &str = "Usage: nes-headless <rom> [options]
+    --frames N              stop after N frames (default 600)
+    --until ADDR=VALUE      stop once the CPU reads hex VALUE from hex ADDR, checked after each frame.
+                            Exits with an error if it never happens
+    --input FILE            scripted controller input, see input_script.rs for the format
+    --screenshot FRAME=PNG  save the given frame (counting from 1) as a PNG. Can be repeated
+    --png PNG               save the last frame as a PNG
+    --wav WAV               save all of the audio as a WAV
+    --solo CHANNELS         only play the comma separated APU channels: pulse1, pulse2,
+                            triangle, noise and dmc. Expansion audio still plays
+    --debug                 start paused in an interactive debugger on stdin
+    --gdb PORT              start paused and wait for a gdb remote protocol client on
+                            127.0.0.1:PORT, e.g. `target remote :PORT`
+    --trace FILE            log every instruction the CPU runs
+    --trace-format FORMAT   nestest, mesen or fceux (default nestest)
+    --trace-no-ppu          leave out the PPU position columns
+    --trace-range START-END only log instructions at hex addresses START to END
+    --trace-frames A-B      only log frames A to B
+    --trace-diff LOG        compare the trace with a reference log and stop where they differ
+    --trace-ignore NAMES    comma separated columns the comparison skips, e.g. PPU,CYC
+    --cdl FILE              log which PRG ROM bytes run as code or are read as data, and
+                            which CHR ROM bytes are drawn, as an FCEUX .cdl file. An
+                            existing log is added to
+    --symbols FILE          a ca65 .dbg, FCEUX .nl or Mesen .mlb file to label the debugger
+                            and trace with. Can be repeated
+    --profile FILE          write where the CPU's cycles went: by subroutine, instruction and frame
+    --profile-folded FILE   write the profile's call stacks in the folded format flamegraph.pl reads
+    --ppu-events FILE       write each frame's writes to the PPU registers, OAM DMA and mapper
+                            registers, with the scanline and dot they happened on
+    --ppu-events-png PNG    plot the last whole frame's PPU events over the screen
+    --dev-cart              enable the developer registers at $4018-$401F: writing $4018 prints
+                            a character, $4019 exits with the value written, and $401A and
+                            $401B start and stop a stopwatch that prints the CPU cycles between";
+
+/**
+ * Runs a ROM without a window or sound card, for batch and CI runs
+ */
+fn main() -> Result<()> {
+    let options = Options::parse(env::args().skip(1))?;
+
+    let mut nes = NES::new();
+    nes.load_cartridge(options.rom.clone())?;
+    let joypads = [
+        Rc::new(RefCell::new(JoyPad::new())),
+        Rc::new(RefCell::new(JoyPad::new())),
+    ];
+    nes.plugin_controller1(joypads[0].clone());
+    nes.plugin_controller2(joypads[1].clone());
+    nes.reset();
+    if !options.solo.is_empty() {
+        for channel in ApuChannel::ALL {
+            nes.set_channel_muted(channel, !options.solo.contains(&channel));
+        }
+    }
+    let labels = load_symbols(&nes, &options)?;
+    let logger = attach_trace_logger(&mut nes, &options, labels.as_ref())?;
+    let code_data_log = attach_code_data_logger(&mut nes, &options)?;
+    let profile = (options.profile.is_some() || options.profile_folded.is_some())
+        .then(|| Profiler::attach(&mut nes));
+    let ppu_events = attach_ppu_event_logger(&mut nes, &options)?;
+    if options.dev_cart {
+        nes.attach_developer_cartridge(Box::new(stdout()));
+    }
+    let mut console = options.debug.then(|| {
+        let mut console = DebugConsole::attach(&mut nes);
+        if let Some(labels) = &labels {
+            console.debugger.set_labels(labels.clone());
+        }
+        console
+    });
+    let mut gdb = match options.gdb {
+        Some(port) => {
+            eprintln!("Waiting for a gdb client on 127.0.0.1:{port}");
+            Some(GdbServer::accept(&mut nes, ("127.0.0.1", port))?)
+        }
+        None => None,
+    };
+
+    let mut screen = Screen::new();
+    let mut blip = BlipBuf::new(SAMPLE_RATE / 60 * 2 + 1);
+    blip.set_rates(PPU_CLOCK_SPEED as f64, SAMPLE_RATE as f64);
+    let mut blip_buffer = [0; BLIP_BUFF_SIZE];
+    let mut audio = Vec::new();
+    let mut last_sample = 0;
+    let mut clocks = 0;
+
+    let mut frame = 1;
+    let mut condition_met = false;
+    apply_input(&options.input, &joypads, frame);
+    let trace_finished = || logger.as_ref().is_some_and(|l| l.borrow().is_finished());
+    while frame <= options.frames
+        && !condition_met
+        && !trace_finished()
+        && nes.developer_exit_code().is_none()
+    {
+        let clocked = if let Some(console) = &mut console {
+            if !console.prompt_while_paused(&mut nes)? {
+                break;
+            }
+            console.debugger.clock(&mut nes)
+        } else if let Some(server) = &mut gdb {
+            if !server.serve_while_paused(&mut nes)? {
+                break;
+            }
+            server.clock(&mut nes)?
+        } else {
+            Some(nes.clock())
+        };
+        // the debugger paused before clocking
+        if let Some(error) = nes.take_monitor_error() {
+            return Err(error.context("A CPU monitor failed"));
+        }
+        let Some((frame_complete, pixel_info, sample_opt)) = clocked else {
+            continue;
+        };
+
+        if let Some(p) = pixel_info {
+            screen.set_pixel(p.x as usize, p.y as usize, [p.r, p.g, p.b]);
+        }
+
+        if let Some(sample_float) = sample_opt {
+            // same scaling as the windowed frontend
+            let sample_normed = ((sample_float - 0.5) * VOLUME).clamp(-VOLUME, VOLUME);
+            let sample = (sample_normed * (i16::MAX as f32)) as i32;
+            blip.add_delta(clocks, sample - last_sample);
+            last_sample = sample;
+        }
+        clocks += 1;
+
+        if frame_complete {
+            blip.end_frame(clocks);
+            clocks = 0;
+            while blip.samples_avail() != 0 {
+                let count = blip.read_samples(&mut blip_buffer, false);
+                audio.extend_from_slice(&blip_buffer[..count]);
+            }
+
+            for (screenshot_frame, path) in &options.screenshots {
+                if *screenshot_frame == frame {
+                    screen.write_png(path)?;
+                }
+            }
+
+            if let Some((addr, value)) = options.until {
+                condition_met = nes.peek_cpu(addr) == value;
+            }
+
+            frame += 1;
+            apply_input(&options.input, &joypads, frame);
+        }
+    }
+
+    if let Some(path) = &options.png {
+        screen.write_png(path)?;
+    }
+    if let Some(path) = &options.wav {
+        output::write_wav(path, SAMPLE_RATE, &audio)?;
+    }
+    nes.save_sram()?;
+    if let (Some(log), Some(path)) = (&code_data_log, &options.cdl) {
+        log.borrow().save(path)?;
+        println!("{}", log.borrow().summary());
+    }
+    if let Some(profile) = &profile {
+        let profile = profile.borrow();
+        if let Some(path) = &options.profile {
+            let mut writer = BufWriter::new(File::create(path)?);
+            profile.write_report(&mut writer, labels.as_ref(), PROFILE_TOP)?;
+            writer.flush()?;
+        }
+        if let Some(path) = &options.profile_folded {
+            let mut writer = BufWriter::new(File::create(path)?);
+            profile.write_folded(&mut writer, labels.as_ref())?;
+            writer.flush()?;
+        }
+    }
+
+    if let Some(events) = &ppu_events {
+        let mut events = events.borrow_mut();
+        events.finish()?;
+        if let Some(path) = &options.ppu_events_png {
+            let image = overlay_image(events.last_frame(), Some(&screen.to_image()));
+            output::write_image_png(path, &image)?;
+        }
+    }
+
+    if let Some(logger) = &logger {
+        match logger.borrow_mut().finish()? {
+            Comparison::Diverged(divergence) => {
+                Err(HeadlessError::TraceDiverged(divergence.clone()))?
+            }
+            Comparison::Matched(lines) => println!("All {lines} lines of the reference log match"),
+            Comparison::Running => {}
+        }
+    }
+
+    match (nes.developer_exit_code(), options.until) {
+        (Some(0), _) => Ok(()),
+        (Some(code), _) => process::exit(code as i32),
+        (None, Some((addr, value))) if !condition_met => {
+            Err(HeadlessError::ConditionNotMet(addr, value, options.frames))?
+        }
+        _ => Ok(()),
+    }
+}
+
+fn load_symbols(nes: &NES, options: &Options) -> Result<Option<Labels>> {
+    if options.symbols.is_empty() {
+        return Ok(None);
+    }
+    let labels = Labels::new(nes);
+    for path in &options.symbols {
+        labels.load(path)?;
+    }
+    Ok(Some(labels))
+}
+
+fn attach_trace_logger(
+    nes: &mut NES,
+    options: &Options,
+    labels: Option<&Labels>,
+) -> Result<Option<Rc<RefCell<TraceLogger>>>> {
+    if options.trace.is_none() && options.trace_diff.is_none() {
+        return Ok(None);
+    }
+    let writer: Box<dyn Write> = match &options.trace {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(sink()),
+    };
+    let logger = TraceLogger::attach(nes, options.trace_options.clone(), writer)?;
+    if let Some(path) = &options.trace_diff {
+        let ignored = options
+            .trace_ignore
+            .iter()
+            .map(|c| c.as_str())
+            .collect::<Vec<_>>();
+        let reference = BufReader::new(File::open(path)?);
+        logger
+            .borrow_mut()
+            .compare_with(Box::new(reference), &ignored);
+    } else if let Some(labels) = labels {
+        // other emulators' logs have no labels to compare with
+        logger.borrow_mut().set_labels(labels.clone());
+    }
+    Ok(Some(logger))
+}
+
+fn attach_code_data_logger(
+    nes: &mut NES,
+    options: &Options,
+) -> Result<Option<Rc<RefCell<CodeDataLog>>>> {
+    let Some(path) = &options.cdl else {
+        return Ok(None);
+    };
+    let log = if path.exists() {
+        let log = CodeDataLog::load(path, read_prg_rom(&options.rom)?.len())?;
+        CodeDataLogger::attach_with(nes, log)?
+    } else {
+        CodeDataLogger::attach(nes)
+    };
+    Ok(Some(log))
+}
+
+fn attach_ppu_event_logger(
+    nes: &mut NES,
+    options: &Options,
+) -> Result<Option<Rc<RefCell<PpuEvents>>>> {
+    if options.ppu_events.is_none() && options.ppu_events_png.is_none() {
+        return Ok(None);
+    }
+    let writer: Option<Box<dyn Write>> = match &options.ppu_events {
+        Some(path) => Some(Box::new(BufWriter::new(File::create(path)?))),
+        None => None,
+    };
+    Ok(Some(PpuEventLogger::attach(nes, writer)))
+}
+
+fn apply_input(script: &InputScript, joypads: &[Rc<RefCell<JoyPad>>; 2], frame: u32) {
+    for (joypad, buttons) in joypads.iter().zip(script.buttons_at(frame)) {
+        joypad.borrow_mut().set_buttons(buttons);
+    }
+}
+
+struct Options {
+    rom: String,
+    frames: u32,
+    until: Option<(u16, u8)>,
+    input: InputScript,
+    screenshots: Vec<(u32, PathBuf)>,
+    png: Option<PathBuf>,
+    wav: Option<PathBuf>,
+    solo: Vec<ApuChannel>,
+    debug: bool,
+    gdb: Option<u16>,
+    trace: Option<PathBuf>,
+    trace_options: TraceOptions,
+    trace_diff: Option<PathBuf>,
+    trace_ignore: Vec<String>,
+    cdl: Option<PathBuf>,
+    symbols: Vec<PathBuf>,
+    profile: Option<PathBuf>,
+    profile_folded: Option<PathBuf>,
+    ppu_events: Option<PathBuf>,
+    ppu_events_png: Option<PathBuf>,
+    dev_cart: bool,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut result = Self {
+            rom: String::new(),
+            frames: DEFAULT_FRAMES,
+            until: None,
+            input: InputScript::default(),
+            screenshots: Vec::new(),
+            png: None,
+            wav: None,
+            solo: Vec::new(),
+            debug: false,
+            gdb: None,
+            trace: None,
+            trace_options: TraceOptions::default(),
+            trace_diff: None,
+            trace_ignore: Vec::new(),
+            cdl: None,
+            symbols: Vec::new(),
+            profile: None,
+            profile_folded: None,
+            ppu_events: None,
+            ppu_events_png: None,
+            dev_cart: false,
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| HeadlessError::MissingValue(arg.clone()))
+            };
+            match arg.as_str() {
+                "--frames" => result.frames = parse_number(&value()?, 10)?,
+                "--until" => {
+                    let (addr, value) = split_pair(&value()?)?;
+                    result.until = Some((parse_number(&addr, 16)?, parse_number(&value, 16)?));
+                }
+                "--input" => {
+                    result.input = InputScript::parse(&fs::read_to_string(value()?)?)?;
+                }
+                "--screenshot" => {
+                    let (frame, path) = split_pair(&value()?)?;
+                    result
+                        .screenshots
+                        .push((parse_number(&frame, 10)?, PathBuf::from(path)));
+                }
+                "--png" => result.png = Some(PathBuf::from(value()?)),
+                "--wav" => result.wav = Some(PathBuf::from(value()?)),
+                "--solo" => {
+                    result.solo = value()?
+                        .split(',')
+                        .map(|name| {
+                            ApuChannel::from_name(name)
+                                .ok_or_else(|| HeadlessError::InvalidArgument(name.to_string()))
+                        })
+                        .collect::<Result<_, _>>()?;
+                }
+                "--debug" => result.debug = true,
+                "--gdb" => result.gdb = Some(parse_number(&value()?, 10)?),
+                "--trace" => result.trace = Some(PathBuf::from(value()?)),
+                "--trace-format" => {
+                    let name = value()?;
+                    result.trace_options.format = TraceFormat::from_name(&name)
+                        .ok_or(HeadlessError::InvalidArgument(name))?;
+                }
+                "--trace-no-ppu" => result.trace_options.ppu_columns = false,
+                "--trace-range" => {
+                    result.trace_options.addresses = Some(split_range(&value()?, 16)?);
+                }
+                "--trace-frames" => {
+                    result.trace_options.frames = Some(split_range(&value()?, 10)?);
+                }
+                "--trace-diff" => result.trace_diff = Some(PathBuf::from(value()?)),
+                "--cdl" => result.cdl = Some(PathBuf::from(value()?)),
+                "--symbols" => result.symbols.push(PathBuf::from(value()?)),
+                "--profile" => result.profile = Some(PathBuf::from(value()?)),
+                "--profile-folded" => result.profile_folded = Some(PathBuf::from(value()?)),
+                "--ppu-events" => result.ppu_events = Some(PathBuf::from(value()?)),
+                "--ppu-events-png" => result.ppu_events_png = Some(PathBuf::from(value()?)),
+                "--dev-cart" => result.dev_cart = true,
+                "--trace-ignore" => {
+                    result.trace_ignore = value()?.split(',').map(|c| c.to_string()).collect();
+                }
+                "--help" | "-h" => Err(HeadlessError::Usage)?,
+                _ if arg.starts_with("--") || !result.rom.is_empty() => {
+                    Err(HeadlessError::InvalidArgument(arg))?
+                }
+                _ => result.rom = arg,
+            }
+        }
+
+        if result.rom.is_empty() || !Path::new(&result.rom).exists() {
+            Err(HeadlessError::Usage)?;
+        }
+        if result.debug && result.gdb.is_some() {
+            Err(HeadlessError::TwoDebuggers)?;
+        }
+        Ok(result)
+    }
+}
+
+fn split_pair(text: &str) -> Result<(String, String)> {
+    let (left, right) = text
+        .split_once('=')
+        .ok_or_else(|| HeadlessError::InvalidArgument(text.to_string()))?;
+    Ok((left.to_string(), right.to_string()))
+}
+
+fn split_range<T: TryFrom<u32>>(text: &str, radix: u32) -> Result<(T, T)> {
+    let (start, end) = text
+        .split_once('-')
+        .ok_or_else(|| HeadlessError::InvalidArgument(text.to_string()))?;
+    Ok((parse_number(start, radix)?, parse_number(end, radix)?))
+}
+
+/**
+ * Parses decimal or hex, which can optionally be written with a 0x or $ prefix
+ */
+fn parse_number<T: TryFrom<u32>>(text: &str, radix: u32) -> Result<T> {
+    let digits = text.trim_start_matches("0x").trim_start_matches('$');
+    let number = u32::from_str_radix(digits, radix)
+        .ok()
+        .and_then(|n| T::try_from(n).ok())
+        .ok_or_else(|| HeadlessError::InvalidArgument(text.to_string()))?;
+    Ok(number)
+}
+
+#[derive(Error, Debug)]
+enum HeadlessError {
+    #[error("{USAGE}")]
+    Usage,
+    #[error("Invalid argument '{0}'\n{USAGE}")]
+    InvalidArgument(String),
+    #[error("Missing a value for {0}\n{USAGE}")]
+    MissingValue(String),
+    #[error("--debug and --gdb can't be used together")]
+    TwoDebuggers,
+    #[error("{0:#06x} never read {1:#04x} within {2} frames")]
+    ConditionNotMet(u16, u8, u32),
+    #[error("{0}")]
+    TraceDiverged(Divergence),
+}
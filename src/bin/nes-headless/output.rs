@@ -0,0 +1,96 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::Result;
+use nes_rs::nes::ppu_viewer::Image;
+
+pub const NES_WIDTH: usize = 256;
+pub const NES_HEIGHT: usize = 240;
+
+/**
+ * An RGB image of one NES frame
+ */
+pub struct Screen {
+    pixels: Vec<u8>,
+}
+
+impl Screen {
+    pub fn new() -> Self {
+        Self {
+            pixels: vec![0; NES_WIDTH * NES_HEIGHT * 3],
+        }
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, rgb: [u8; 3]) {
+        let index = (y * NES_WIDTH + x) * 3;
+        self.pixels[index..index + 3].copy_from_slice(&rgb);
+    }
+
+    pub fn write_png(&self, path: &Path) -> Result<()> {
+        write_rgb_png(path, NES_WIDTH, NES_HEIGHT, &self.pixels)
+    }
+
+    pub fn to_image(&self) -> Image {
+        let mut image = Image::new(NES_WIDTH, NES_HEIGHT);
+        for (pixel, rgb) in image.pixels.iter_mut().zip(self.pixels.chunks(3)) {
+            *pixel = u32::from_be_bytes([0, rgb[0], rgb[1], rgb[2]]);
+        }
+        image
+    }
+}
+
+/**
+ * Writes a viewer's image, e.g. the PPU event overlay
+ */
+pub fn write_image_png(path: &Path, image: &Image) -> Result<()> {
+    let pixels = image
+        .pixels
+        .iter()
+        .flat_map(|pixel| pixel.to_be_bytes()[1..].to_vec())
+        .collect::<Vec<_>>();
+    write_rgb_png(path, image.width, image.height, &pixels)
+}
+
+fn write_rgb_png(path: &Path, width: usize, height: usize, pixels: &[u8]) -> Result<()> {
+    let writer = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(writer, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(pixels)?;
+    Ok(())
+}
+
+/**
+ * Writes 16 bit mono PCM. The header is written last since it needs the total length.
+ */
+pub fn write_wav(path: &Path, sample_rate: u32, samples: &[i16]) -> Result<()> {
+    const CHANNELS: u16 = 1;
+    const BYTES_PER_SAMPLE: u16 = 2;
+    let data_size = (samples.len() * BYTES_PER_SAMPLE as usize) as u32;
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_size).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    // PCM
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&CHANNELS.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * (CHANNELS * BYTES_PER_SAMPLE) as u32).to_le_bytes())?;
+    writer.write_all(&(CHANNELS * BYTES_PER_SAMPLE).to_le_bytes())?;
+    writer.write_all(&(BYTES_PER_SAMPLE * 8).to_le_bytes())?;
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())?;
+    for sample in samples {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+    writer.flush()?;
+    Ok(())
+}
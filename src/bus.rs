@@ -5,6 +5,17 @@ pub trait BusDevice {
     fn read(&mut self, addr: u16) -> u8;
     fn write(&mut self, addr: u16, data: u8) -> u8;
     fn bus_clock(&mut self) -> InterruptFlags;
+
+    /**
+     * What a read would return, without any of its side effects. For debuggers and other tools.
+     */
+    fn peek(&self, addr: u16) -> u8;
+
+    /**
+     * Stores data without any side effects, e.g. patching ROM rather than
+     * switching banks. Registers that can't be set that way ignore it.
+     */
+    fn poke(&mut self, addr: u16, data: u8);
 }
 
 type AddrRange = (u16, u16);
@@ -42,6 +53,24 @@ impl Bus {
         0
     }
 
+    pub fn peek(&self, addr: u16) -> u8 {
+        for device in &self.bus_devices {
+            if device.0.0 <= addr && addr <= device.0.1 {
+                return device.1.borrow().peek(addr);
+            }
+        }
+        0
+    }
+
+    pub fn poke(&self, addr: u16, data: u8) {
+        for device in &self.bus_devices {
+            if device.0.0 <= addr && addr <= device.0.1 {
+                device.1.borrow_mut().poke(addr, data);
+                return;
+            }
+        }
+    }
+
     pub fn clock(&self) -> InterruptFlags {
         let mut flags = InterruptFlags::empty();
         for device in &self.bus_devices {
@@ -100,4 +129,12 @@ impl BusDevice for Interruptor {
     fn bus_clock(&mut self) -> InterruptFlags {
         self.flags
     }
+
+    fn peek(&self, _addr: u16) -> u8 {
+        unreachable!()
+    }
+
+    fn poke(&mut self, _addr: u16, _data: u8) {
+        unreachable!()
+    }
 }
@@ -5,29 +5,41 @@
 #[cfg(test)]
 mod unit_tests {
     mod test_addressing_modes;
+    mod test_assembler;
+    mod test_bus_cycles;
     mod test_clock_and_interrupts;
     mod test_decode;
+    mod test_disassembler;
     mod test_instructions;
+    mod test_monitor;
     mod test_unofficial_instructions;
 }
 #[cfg(test)]
 mod functional_tests {
     mod functional_test;
 }
+pub mod assembler;
 pub mod decode;
+pub mod disassembler;
 pub mod flags;
 pub mod instructions;
+mod microcode;
 pub mod monitor;
 
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use anyhow::Result;
+
 use crate::bus::Bus;
 use crate::bus::BusDevice;
 use crate::bus::InterruptFlags;
 use crate::cpu::flags::*;
 use crate::cpu::instructions::*;
+use crate::save_state::{SaveState, SaveStateError, StateReader, StateWriter};
 
+use self::microcode::MicroProgram;
+use self::monitor::CompositeMonitor;
 use self::monitor::Monitor;
 use self::monitor::NulMonitor;
 
@@ -49,19 +61,35 @@ pub struct CPU {
     pub pc: u16,
     pub cycles: usize,
     rdy: bool,
+    // the address of the read the cpu was halted on while RDY is low
+    halted_at: Option<u16>,
+    // the address of the last bus access
+    address_bus: u16,
     jammed: bool,
     trapped: bool,
     // internal state of executing instuciton
     instruction: Instruction,
     mode: Mode,
-    remaining_cycles: u8,
-    extra_cycles: u8,
-    cycle_on_page_boundary: bool,
+    program: MicroProgram,
+    // cycle within the instruction. 0 means the next cycle fetches an opcode
+    step: u8,
+    address: u16,
+    pointer: u8,
+    data: u8,
+    page_boundary: PageBoundary,
+    branch: Branch,
+    cycle_type: CPUCycleType,
+    // the interrupt that will run instead of the next instruction, decided by the last poll
     interrupt: Option<Interrupt>,
+    skip_interrupt_poll: bool,
     pub monitor: Box<dyn Monitor>,
+    // the first error a monitor returned, the cpu is halted until it's taken
+    monitor_error: Option<anyhow::Error>,
     bus: Bus,
     interrupt_flags: InterruptFlags,
     nmi_was_enabled: bool,
+    nmi_pending: bool,
+    irq_pending: bool,
 }
 
 impl Default for CPU {
@@ -84,16 +112,27 @@ impl CPU {
             trapped: false,
             instruction: Instruction::NOP,
             mode: Mode::Imp,
-            remaining_cycles: 0,
-            extra_cycles: 0,
-            cycle_on_page_boundary: false,
+            program: MicroProgram::new(Instruction::NOP, Mode::Imp),
+            step: 0,
+            address: 0,
+            pointer: 0,
+            data: 0,
+            page_boundary: PageBoundary::NotCrossed,
+            branch: Branch::NotTaken,
+            cycle_type: CPUCycleType::Read,
             interrupt: None,
+            skip_interrupt_poll: false,
             cycles: 0,
             rdy: true,
+            halted_at: None,
+            address_bus: 0,
             monitor: Box::new(NulMonitor {}),
+            monitor_error: None,
             bus: Bus::new(),
             interrupt_flags: InterruptFlags::empty(),
             nmi_was_enabled: false,
+            nmi_pending: false,
+            irq_pending: false,
         }
     }
 
@@ -109,14 +148,12 @@ impl CPU {
     pub fn run_instruction(&mut self) -> u8 {
         let mut cycles = 0;
 
-        if self.remaining_cycles == 0 && self.extra_cycles == 0 {
+        loop {
             self.clock();
             cycles += 1;
-        }
-        while self.remaining_cycles != 0 || self.extra_cycles != 0 {
-            self.clock();
-
-            cycles += 1;
+            if self.step == 0 || self.jammed {
+                break;
+            }
         }
 
         cycles
@@ -131,33 +168,49 @@ impl CPU {
         self.cycles = 0;
         // while other interrupts will wait for the current instruction
         // to complete, reset starts on the next clock
-        self.remaining_cycles = 0;
-        self.extra_cycles = 0;
+        self.step = 0;
         self.jammed = false;
+        self.rdy = true;
+        self.halted_at = None;
         self.interrupt = Some(Interrupt::RST);
         self.trapped = false;
 
         self.interrupt_flags = InterruptFlags::empty();
         self.nmi_was_enabled = false;
+        self.nmi_pending = false;
+        self.irq_pending = false;
     }
 
-    fn poll_interrupts(&mut self) {
+    // source https://www.nesdev.org/wiki/CPU_interrupts
+    // The interrupt lines are sampled at the end of every cycle. NMI is edge
+    // detected and stays pending until it's handled, IRQ is level detected
+    // and masked by the interrupt disable flag.
+    fn detect_interrupts(&mut self) {
         if self.interrupt_flags.contains(InterruptFlags::NMI) {
             self.nmi_was_enabled = true;
         } else if self.nmi_was_enabled {
-            if self.interrupt != Some(Interrupt::RST) {
-                self.interrupt = Some(Interrupt::NMI);
-            }
-            self.trapped = false;
+            self.nmi_pending = true;
             self.nmi_was_enabled = false;
         }
 
-        if self.interrupt_flags.contains(InterruptFlags::IRQ)
-            && self.interrupt.is_none()
-            && !self.read_flag(StatusFlags::InterruptDisable)
-        {
-            self.interrupt = Some(Interrupt::IRQ);
-            self.trapped = false;
+        self.irq_pending = self.interrupt_flags.contains(InterruptFlags::IRQ)
+            && !self.read_flag(StatusFlags::InterruptDisable);
+    }
+
+    // Every cycle but the last one of an instruction polls the detectors, so it's the
+    // second to last cycle that decides whether an interrupt runs next. That's why
+    // CLI, SEI and PLP only take effect after the following instruction.
+    fn poll_interrupts(&mut self) {
+        self.detect_interrupts();
+
+        if self.interrupt != Some(Interrupt::RST) {
+            self.interrupt = if self.nmi_pending {
+                Some(Interrupt::NMI)
+            } else if self.irq_pending {
+                Some(Interrupt::IRQ)
+            } else {
+                None
+            };
         }
     }
 
@@ -165,83 +218,196 @@ impl CPU {
         self.interrupt_flags = self.bus.clock();
     }
 
+    /**
+     * Runs one cycle, which is exactly one read or write on the bus
+     */
     pub fn clock(&mut self) -> CPUCycleType {
-        let (cycle_type, instruction_complete) = if self.jammed || !self.rdy {
-            (CPUCycleType::Read, self.rdy)
-        } else {
-            let instruction_complete = (self.remaining_cycles + self.extra_cycles) == 1;
-            let cycle_type = if self.remaining_cycles > 0 {
-                if self.remaining_cycles == 1 {
-                    if self.mode == Mode::Imp {
-                        // no arg operations get an extra dummy read
-                        self.read_bus_byte(self.pc);
-                    }
-                    let (page_boundary, branch_taken) = self.execute(self.instruction, self.mode);
-                    if page_boundary == PageBoundary::Crossed && self.cycle_on_page_boundary {
-                        self.extra_cycles += 1;
-                    }
-                    if branch_taken == Branch::Taken {
-                        self.extra_cycles += 1;
-                    }
-                    self.monitor.end_instruction().unwrap(); // TODO propogate error
-                };
-                self.remaining_cycles -= 1;
-                CPUCycleType::Write
-            } else if self.extra_cycles > 0 {
-                self.extra_cycles -= 1;
-                CPUCycleType::Read
+        self.cycle_type = CPUCycleType::Read;
+        let stopped = self.jammed || self.monitor_error.is_some();
+        let running = !stopped && self.halted_at.is_none();
+        if running {
+            if self.step == 0 {
+                self.start_instruction();
             } else {
-                let (instruction, mode, cycles, cycle_on_boundary) = match self.interrupt {
-                    Some(interrupt) => {
-                        let instruction = match interrupt {
-                            Interrupt::BRK => {
-                                unreachable!("Interrupted with BRK, which shouldn't be possible")
-                            }
-                            Interrupt::IRQ => Instruction::IRQ,
-                            Interrupt::NMI => Instruction::NMI,
-                            Interrupt::RST => Instruction::RST,
-                        };
-                        (instruction, Mode::Imp, 7, false)
-                    }
-                    None => {
-                        self.monitor
-                            .new_instruction(
-                                self.cycles,
-                                self.pc,
-                                self.sp,
-                                self.a,
-                                self.x,
-                                self.y,
-                                self.status.bits(),
-                            )
-                            .unwrap(); // TODO propogate error
-                        let op = self.fetch_byte();
-                        crate::cpu::decode::decode(op)
-                    }
-                };
-
-                self.instruction = instruction;
-                self.mode = mode;
-                self.remaining_cycles = cycles.wrapping_sub(1);
-                self.extra_cycles = 0;
-                self.cycle_on_page_boundary = cycle_on_boundary;
-                CPUCycleType::Read
-            };
+                self.run_micro_op();
+            }
+            let result = self.monitor.cycle(self.cycles);
+            self.check_monitor(result);
             self.cycles += 1;
-            (cycle_type, instruction_complete)
-        };
 
-        if instruction_complete {
-            self.poll_interrupts();
+            // RDY only stops the cpu on a read, writes carry on regardless
+            if !self.rdy && self.cycle_type == CPUCycleType::Read {
+                self.halted_at = Some(self.address_bus);
+            }
+        } else if !stopped && let Some(addr) = self.halted_at {
+            let result = self.monitor.dma_cycle(self.cycles, addr);
+            self.check_monitor(result);
+            if self.rdy {
+                // the cpu repeats the read it was halted on before carrying on
+                self.halted_at = None;
+                self.read_bus_byte(addr);
+            }
         }
+
         self.clock_bus();
-        cycle_type
+        if running && self.step != 0 && !self.skip_interrupt_poll {
+            self.poll_interrupts();
+        } else {
+            self.detect_interrupts();
+        }
+        self.skip_interrupt_poll = false;
+        self.cycle_type
+    }
+
+    fn start_instruction(&mut self) {
+        match self.interrupt.take() {
+            Some(interrupt) => {
+                // the opcode still gets read, but it's thrown away
+                self.read_bus_byte(self.pc);
+                self.trapped = false;
+                let instruction = match interrupt {
+                    Interrupt::IRQ => Instruction::IRQ,
+                    Interrupt::NMI => Instruction::NMI,
+                    Interrupt::RST => Instruction::RST,
+                };
+                self.begin_instruction(instruction, Mode::Imp);
+            }
+            None => {
+                let result = self.monitor.new_instruction(
+                    self.cycles,
+                    self.pc,
+                    self.sp,
+                    self.a,
+                    self.x,
+                    self.y,
+                    self.status.bits(),
+                );
+                self.check_monitor(result);
+                let op = self.fetch_byte();
+                let (instruction, mode, _, _) = decode::decode(op);
+                self.begin_instruction(instruction, mode);
+            }
+        }
+    }
+
+    fn begin_instruction(&mut self, instruction: Instruction, mode: Mode) {
+        self.instruction = instruction;
+        self.mode = mode;
+        self.program = MicroProgram::new(instruction, mode);
+        self.step = 1;
+        self.page_boundary = PageBoundary::NotCrossed;
+        self.branch = Branch::NotTaken;
+    }
+
+    fn run_micro_op(&mut self) {
+        let op = self.program.op(self.step - 1);
+        self.step += 1;
+        op(self);
+        if self.step > self.program.len() {
+            self.end_instruction();
+        }
+    }
+
+    // micro ops can end the instruction early, e.g. when a branch isn't taken
+    fn end_instruction(&mut self) {
+        self.step = 0;
+        let result = self.monitor.end_instruction();
+        self.check_monitor(result);
+    }
+
+    fn check_monitor(&mut self, result: Result<()>) {
+        if let Err(error) = result
+            && self.monitor_error.is_none()
+        {
+            self.monitor_error = Some(error);
+        }
+    }
+
+    /**
+     * The error a monitor returned. The cpu stops where it is, part way through
+     * an instruction if need be, until the error is taken.
+     */
+    pub fn monitor_error(&self) -> Option<&anyhow::Error> {
+        self.monitor_error.as_ref()
+    }
+
+    /**
+     * Takes the error a monitor returned, which lets the cpu carry on
+     */
+    pub fn take_monitor_error(&mut self) -> Option<anyhow::Error> {
+        self.monitor_error.take()
+    }
+
+    /**
+     * Attaches a monitor alongside any that are already attached
+     */
+    pub fn add_monitor(&mut self, monitor: Box<dyn Monitor>) {
+        if let Some(composite) = self.monitor.as_any().downcast_mut::<CompositeMonitor>() {
+            composite.add(monitor);
+            return;
+        }
+
+        let mut composite = CompositeMonitor::new();
+        let mut current = std::mem::replace(&mut self.monitor, Box::new(NulMonitor {}));
+        if !current.as_any().is::<NulMonitor>() {
+            composite.add(current);
+        }
+        composite.add(monitor);
+        self.monitor = Box::new(composite);
+    }
+
+    /**
+     * Detaches the monitors of type T and returns the first of them
+     */
+    pub fn remove_monitor<T: Monitor>(&mut self) -> Option<Box<dyn Monitor>> {
+        if self.monitor.as_any().is::<T>() {
+            return Some(std::mem::replace(
+                &mut self.monitor,
+                Box::new(NulMonitor {}),
+            ));
+        }
+        let composite = self.monitor.as_any().downcast_mut::<CompositeMonitor>()?;
+        let removed = composite.remove::<T>().into_iter().next();
+        if composite.is_empty() {
+            self.monitor = Box::new(NulMonitor {});
+        }
+        removed
+    }
+
+    /**
+     * The attached monitor of type T, whether it was attached on its own or
+     * alongside others
+     */
+    pub fn monitor_mut<T: Monitor>(&mut self) -> Option<&mut T> {
+        if self.monitor.as_any().is::<T>() {
+            return self.monitor.as_any().downcast_mut::<T>();
+        }
+        self.monitor
+            .as_any()
+            .downcast_mut::<CompositeMonitor>()?
+            .get_mut::<T>()
+    }
+
+    /**
+     * Runs all the cycles of an already decoded instruction
+     */
+    #[cfg(test)]
+    fn execute(&mut self, instruction: Instruction, mode: Mode) -> (PageBoundary, Branch) {
+        self.begin_instruction(instruction, mode);
+        while self.step != 0 {
+            self.run_micro_op();
+        }
+        (self.page_boundary, self.branch)
     }
 
     pub fn add_device(&mut self, device: Rc<RefCell<dyn BusDevice>>) {
         self.bus.add_device(device);
     }
 
+    /**
+     * Pulling RDY low halts the cpu on its next read cycle. Once it's
+     * released the halted read is done again and the cpu carries on.
+     */
     pub fn set_rdy(&mut self, rdy: bool) {
         self.rdy = rdy;
     }
@@ -250,220 +416,252 @@ impl CPU {
         self.rdy
     }
 
-    #[cfg(test)]
-    pub fn cycles(&self) -> usize {
-        self.cycles
+    /**
+     * True when the next cycle starts a new instruction or interrupt
+     */
+    pub fn at_instruction_boundary(&self) -> bool {
+        self.step == 0 && self.halted_at.is_none() && !self.jammed
     }
 
-    fn interrupt(&mut self, interrupt: Interrupt) -> (PageBoundary, Branch) {
-        if interrupt == Interrupt::RST {
-            self.sp = self.sp.wrapping_sub(3); // reset doesn't push onto the stack, but simulates it
-        } else {
-            self.push_word(
-                self.pc
-                    .wrapping_add(if interrupt == Interrupt::BRK { 1 } else { 0 }),
-            );
-            let status = if interrupt == Interrupt::BRK {
-                self.status | StatusFlags::Break
-            } else {
-                self.status & !StatusFlags::Break
-            };
+    /**
+     * True when the next instruction will be replaced by an interrupt
+     */
+    pub fn interrupt_pending(&self) -> bool {
+        self.interrupt.is_some()
+    }
 
-            self.push_byte(status.bits());
-        }
-        self.set_flag(StatusFlags::InterruptDisable, true);
+    pub fn is_jammed(&self) -> bool {
+        self.jammed
+    }
 
-        let addr = match interrupt {
-            Interrupt::BRK => IRQ_ADDR,
-            Interrupt::IRQ => IRQ_ADDR,
-            Interrupt::NMI => NMI_ADDR,
-            Interrupt::RST => RESET_ADDR,
-        };
-        self.pc = self.read_bus_word(addr);
-        self.interrupt = None;
+    /**
+     * The address of the read the cpu is halted on, if it's halted
+     */
+    pub fn halted_at(&self) -> Option<u16> {
+        self.halted_at
+    }
 
-        (PageBoundary::NotCrossed, Branch::NotTaken)
+    #[cfg(test)]
+    pub fn cycles(&self) -> usize {
+        self.cycles
     }
 
-    fn push_word(&mut self, value: u16) {
-        self.push_byte(CPU::high_byte(value));
-        self.push_byte(CPU::low_byte(value));
+    fn stack_address(&self) -> u16 {
+        (self.sp as u16) | STACK_BASE
     }
 
     fn push_byte(&mut self, value: u8) {
-        self.write_bus_byte((self.sp as u16) | STACK_BASE, value);
+        self.write_bus_byte(self.stack_address(), value);
         self.sp = self.sp.wrapping_sub(1);
     }
 
-    fn pop_word(&mut self) -> u16 {
-        let low_byte = self.pop_byte();
-        let high_byte = self.pop_byte();
-        CPU::to_word(low_byte, high_byte)
-    }
-
     fn pop_byte(&mut self) -> u8 {
         self.sp = self.sp.wrapping_add(1);
-        self.read_bus_byte((self.sp as u16) | STACK_BASE)
+        self.read_bus_byte(self.stack_address())
     }
 
     fn fetch_byte(&mut self) -> u8 {
         let value = self.read_bus_byte(self.pc);
-        self.monitor.fetch_instruction_byte(value).unwrap(); // TODO propogate error?
+        let result = self.monitor.fetch_instruction_byte(value);
+        self.check_monitor(result);
         self.pc = self.pc.wrapping_add(1);
         value
     }
 
-    fn fetch_word(&mut self) -> u16 {
-        let lb = self.fetch_byte();
-        let hb = self.fetch_byte();
+    fn read_data_byte(&mut self, addr: u16) -> u8 {
+        let value = self.read_bus_byte(addr);
+        let result = self.monitor.read_data_byte(addr, value);
+        self.check_monitor(result);
+        value
+    }
+
+    fn write_data_byte(&mut self, addr: u16, value: u8) {
+        let old = self.write_bus_byte(addr, value);
+        let result = self.monitor.write_data_byte(addr, old, value);
+        self.check_monitor(result);
+    }
+
+    pub fn read_bus_byte(&mut self, addr: u16) -> u8 {
+        self.address_bus = addr;
+        self.bus.read(addr)
+    }
+
+    /**
+     * Reads a DMC sample byte on behalf of DMA
+     */
+    pub fn read_sample_byte(&mut self, addr: u16) -> u8 {
+        let value = self.read_bus_byte(addr);
+        let result = self.monitor.sample_read(addr, value);
+        self.check_monitor(result);
+        value
+    }
+
+    pub fn read_bus_word(&mut self, addr: u16) -> u16 {
+        let lb = self.read_bus_byte(addr);
+        let hb = self.read_bus_byte(addr.wrapping_add(1));
         CPU::to_word(lb, hb)
     }
 
+    pub fn write_bus_byte(&mut self, addr: u16, data: u8) -> u8 {
+        self.cycle_type = CPUCycleType::Write;
+        self.address_bus = addr;
+        let old = self.bus.write(addr, data);
+        let result = self.monitor.bus_write(addr, old, data);
+        self.check_monitor(result);
+        old
+    }
+
+    fn low_byte(value: u16) -> u8 {
+        (value & LOW_BYTE_MASK) as u8
+    }
+
+    fn high_byte(value: u16) -> u8 {
+        (value >> 8) as u8
+    }
+
+    fn to_word(low_byte: u8, high_byte: u8) -> u16 {
+        ((high_byte as u16) << 8) | (low_byte as u16)
+    }
+
     // source https://www.pagetable.com/c64ref/6502/
     // https://www.masswerk.at/6502/6502_instruction_set.html
 
-    fn execute(&mut self, instruction: Instruction, mode: Mode) -> (PageBoundary, Branch) {
-        match instruction {
-            Instruction::ADC => self.adc(mode),
-            Instruction::AND => self.binary(|x, y| x & y, mode),
-            Instruction::ASL => self.shift_left(ShiftStyle::ShiftOff, mode),
-            Instruction::BCC => self.branch(mode, BranchType::CC),
-            Instruction::BCS => self.branch(mode, BranchType::CS),
-            Instruction::BEQ => self.branch(mode, BranchType::EQ),
-            Instruction::BIT => self.test_bits(mode),
-            Instruction::BMI => self.branch(mode, BranchType::MI),
-            Instruction::BNE => self.branch(mode, BranchType::NE),
-            Instruction::BPL => self.branch(mode, BranchType::PL),
-            Instruction::BRK => self.interrupt(Interrupt::BRK),
-            Instruction::BVC => self.branch(mode, BranchType::VC),
-            Instruction::BVS => self.branch(mode, BranchType::VS),
+    fn execute_implied(&mut self) {
+        match self.instruction {
             Instruction::CLC => self.set_flag(StatusFlags::Carry, false),
             Instruction::CLD => self.set_flag(StatusFlags::Decimal, false),
             Instruction::CLI => self.set_flag(StatusFlags::InterruptDisable, false),
             Instruction::CLV => self.set_flag(StatusFlags::Overflow, false),
-            Instruction::CMP => self.compare(mode, self.a),
-            Instruction::CPX => self.compare(mode, self.x),
-            Instruction::CPY => self.compare(mode, self.y),
-            Instruction::DEC => self.unary(|x| x.wrapping_sub(1), mode),
-            Instruction::DEX => self.unary(|x| x.wrapping_sub(1), Mode::X),
-            Instruction::DEY => self.unary(|x| x.wrapping_sub(1), Mode::Y),
-            Instruction::EOR => self.binary(|x, y| x ^ y, mode),
-            Instruction::INC => self.unary(|x| x.wrapping_add(1), mode),
-            Instruction::INX => self.unary(|x| x.wrapping_add(1), Mode::X),
-            Instruction::INY => self.unary(|x| x.wrapping_add(1), Mode::Y),
-            Instruction::JMP => self.branch(mode, BranchType::JMP),
-            Instruction::JSR => self.branch(mode, BranchType::JSR),
-            Instruction::LDA => self.transfer(mode, Mode::A),
-            Instruction::LDX => self.transfer(mode, Mode::X),
-            Instruction::LDY => self.transfer(mode, Mode::Y),
-            Instruction::LSR => self.shift_right(ShiftStyle::ShiftOff, mode),
-            Instruction::NOP => self.nop(mode),
-            Instruction::ORA => self.binary(|x, y| x | y, mode),
-            Instruction::PHA => self.push(Mode::A),
-            Instruction::PHP => self.push(Mode::Status),
-            Instruction::PLA => self.pop(Mode::A),
-            Instruction::PLP => self.pop(Mode::Status),
-            Instruction::ROL => self.shift_left(ShiftStyle::Rotate, mode),
-            Instruction::ROR => self.shift_right(ShiftStyle::Rotate, mode),
-            Instruction::RTI => self.rti(),
-            Instruction::RTS => self.rts(),
-            Instruction::SBC => self.sbc(mode),
             Instruction::SEC => self.set_flag(StatusFlags::Carry, true),
             Instruction::SED => self.set_flag(StatusFlags::Decimal, true),
             Instruction::SEI => self.set_flag(StatusFlags::InterruptDisable, true),
-            Instruction::STA => self.transfer_without_flags(Mode::A, mode),
-            Instruction::STX => self.transfer_without_flags(Mode::X, mode),
-            Instruction::STY => self.transfer_without_flags(Mode::Y, mode),
-            Instruction::TAX => self.transfer(Mode::A, Mode::X),
-            Instruction::TAY => self.transfer(Mode::A, Mode::Y),
-            Instruction::TSX => self.transfer(Mode::SP, Mode::X),
-            Instruction::TXA => self.transfer(Mode::X, Mode::A),
-            Instruction::TXS => self.transfer_without_flags(Mode::X, Mode::SP),
-            Instruction::TYA => self.transfer(Mode::Y, Mode::A),
-            Instruction::RST => self.interrupt(Interrupt::RST),
-            Instruction::IRQ => self.interrupt(Interrupt::IRQ),
-            Instruction::NMI => self.interrupt(Interrupt::NMI),
+            Instruction::DEX => self.load_x(self.x.wrapping_sub(1)),
+            Instruction::DEY => self.load_y(self.y.wrapping_sub(1)),
+            Instruction::INX => self.load_x(self.x.wrapping_add(1)),
+            Instruction::INY => self.load_y(self.y.wrapping_add(1)),
+            Instruction::TAX => self.load_x(self.a),
+            Instruction::TAY => self.load_y(self.a),
+            Instruction::TSX => self.load_x(self.sp),
+            Instruction::TXA => self.load_a(self.x),
+            Instruction::TXS => self.sp = self.x,
+            Instruction::TYA => self.load_a(self.y),
+            Instruction::NOP => (),
+            _ => unreachable!("{} has no implied form", self.instruction),
+        }
+    }
+
+    fn execute_read(&mut self) {
+        let value = self.data;
+        match self.instruction {
+            Instruction::ADC => self.adc(value),
+            Instruction::AND => self.load_a(self.a & value),
+            Instruction::BIT => self.test_bits(value),
+            Instruction::CMP => self.compare(self.a, value),
+            Instruction::CPX => self.compare(self.x, value),
+            Instruction::CPY => self.compare(self.y, value),
+            Instruction::EOR => self.load_a(self.a ^ value),
+            Instruction::LDA => self.load_a(value),
+            Instruction::LDX => self.load_x(value),
+            Instruction::LDY => self.load_y(value),
+            Instruction::NOP => (),
+            Instruction::ORA => self.load_a(self.a | value),
+            Instruction::SBC => self.sbc(value),
             // unofficial instructions
-            Instruction::ANC => self.anc(mode),
-            Instruction::ASR => self.asr(mode),
-            Instruction::ARR => self.arr(mode),
-            Instruction::DCP => self.dcp(mode),
-            Instruction::ISC => self.isc(mode),
-            Instruction::JAM => self.jam(),
-            Instruction::LAS => self.las(mode),
-            Instruction::LAX => self.lax(mode),
-            Instruction::RLA => self.rla(mode),
-            Instruction::RRA => self.rra(mode),
-            Instruction::SAX => self.sax(mode),
-            Instruction::SBX => self.sbx(mode),
-            Instruction::SHA => self.sha(mode),
-            Instruction::SHS => self.shs(mode),
-            Instruction::SHX => self.shx(mode),
-            Instruction::SHY => self.shy(mode),
-            Instruction::SLO => self.slo(mode),
-            Instruction::SRE => self.sre(mode),
-            Instruction::XXA => self.xxa(mode),
+            Instruction::ANC => self.anc(value),
+            Instruction::ARR => self.arr(value),
+            Instruction::ASR => self.asr(value),
+            Instruction::LAS => self.las(value),
+            Instruction::LAX => self.lax(value),
+            Instruction::SBX => self.sbx(value),
+            Instruction::XXA => self.load_a((self.a | 0xEE) & self.x & value),
+            _ => unreachable!("{} doesn't read an operand", self.instruction),
         }
     }
 
-    fn shift_left(&mut self, shift_style: ShiftStyle, mode: Mode) -> (PageBoundary, Branch) {
-        let location = self.get_location(mode);
+    fn execute_write(&mut self) -> u8 {
+        match self.instruction {
+            Instruction::STA => self.a,
+            Instruction::STX => self.x,
+            Instruction::STY => self.y,
+            // unofficial instructions
+            Instruction::SAX => self.a & self.x,
+            Instruction::SHA => self.unstable_store(self.a & self.x),
+            Instruction::SHS => {
+                self.sp = self.a & self.x;
+                self.unstable_store(self.sp)
+            }
+            Instruction::SHX => self.unstable_store(self.x),
+            Instruction::SHY => self.unstable_store(self.y),
+            _ => unreachable!("{} doesn't write an operand", self.instruction),
+        }
+    }
 
-        let m = self.read_value(location);
-        self.write_value(location, m.0); // dummy write
+    fn execute_read_modify_write(&mut self, value: u8) -> u8 {
+        match self.instruction {
+            Instruction::ASL => self.shift_left(value, false),
+            Instruction::DEC => self.with_flags(value.wrapping_sub(1)),
+            Instruction::INC => self.with_flags(value.wrapping_add(1)),
+            Instruction::LSR => self.shift_right(value, false),
+            Instruction::ROL => self.shift_left(value, self.read_flag(StatusFlags::Carry)),
+            Instruction::ROR => self.shift_right(value, self.read_flag(StatusFlags::Carry)),
+            // unofficial instructions
+            Instruction::DCP => self.dcp(value),
+            Instruction::ISC => self.isc(value),
+            Instruction::RLA => self.rla(value),
+            Instruction::RRA => self.rra(value),
+            Instruction::SLO => self.slo(value),
+            Instruction::SRE => self.sre(value),
+            _ => unreachable!("{} doesn't modify an operand", self.instruction),
+        }
+    }
 
-        self.write_value(
-            location,
-            (m.0 << 1)
-                | if shift_style == ShiftStyle::Rotate && self.read_flag(StatusFlags::Carry) {
-                    1
-                } else {
-                    0
-                },
-        );
-        self.set_flag(StatusFlags::Carry, m.0 & SIGN_BIT != 0);
-        (m.1, Branch::NotTaken)
+    fn branch_condition(&self) -> bool {
+        match self.instruction {
+            Instruction::BCC => !self.read_flag(StatusFlags::Carry),
+            Instruction::BCS => self.read_flag(StatusFlags::Carry),
+            Instruction::BNE => !self.read_flag(StatusFlags::Zero),
+            Instruction::BEQ => self.read_flag(StatusFlags::Zero),
+            Instruction::BPL => !self.read_flag(StatusFlags::Negative),
+            Instruction::BMI => self.read_flag(StatusFlags::Negative),
+            Instruction::BVC => !self.read_flag(StatusFlags::Overflow),
+            Instruction::BVS => self.read_flag(StatusFlags::Overflow),
+            _ => unreachable!("{} isn't a branch", self.instruction),
+        }
     }
 
-    fn shift_right(&mut self, shift_style: ShiftStyle, mode: Mode) -> (PageBoundary, Branch) {
-        let location = self.get_location(mode);
-        let m = self.read_value(location);
-        self.write_value(location, m.0); // dummy write
+    fn set_negative_and_zero(&mut self, value: u8) {
+        self.set_flag(StatusFlags::Negative, value & SIGN_BIT != 0);
+        self.set_flag(StatusFlags::Zero, value == 0);
+    }
 
-        self.write_value(
-            location,
-            (m.0 >> 1)
-                | if shift_style == ShiftStyle::Rotate && self.read_flag(StatusFlags::Carry) {
-                    SIGN_BIT
-                } else {
-                    0
-                },
-        );
-        self.set_flag(StatusFlags::Carry, m.0 & 1 != 0);
-        (m.1, Branch::NotTaken)
+    fn with_flags(&mut self, value: u8) -> u8 {
+        self.set_negative_and_zero(value);
+        value
     }
 
-    fn binary(&mut self, f: fn(u8, u8) -> u8, mode: Mode) -> (PageBoundary, Branch) {
-        let location = self.get_location(mode);
-        let m = self.read_value(location);
-        self.write_value(Location::A, f(self.a, m.0));
-        (m.1, Branch::NotTaken)
+    fn load_a(&mut self, value: u8) {
+        self.a = self.with_flags(value);
     }
 
-    fn unary(&mut self, f: fn(u8) -> u8, mode: Mode) -> (PageBoundary, Branch) {
-        let location = self.get_location(mode);
+    fn load_x(&mut self, value: u8) {
+        self.x = self.with_flags(value);
+    }
 
-        let m = self.read_value(location);
-        self.write_value(location, m.0); // dummy write
+    fn load_y(&mut self, value: u8) {
+        self.y = self.with_flags(value);
+    }
 
-        self.write_value(location, f(m.0));
-        (m.1, Branch::NotTaken)
+    fn shift_left(&mut self, value: u8, carry_in: bool) -> u8 {
+        self.set_flag(StatusFlags::Carry, value & SIGN_BIT != 0);
+        self.with_flags((value << 1) | if carry_in { 1 } else { 0 })
     }
 
-    fn adc(&mut self, mode: Mode) -> (PageBoundary, Branch) {
-        let location = self.get_location(mode);
-        let m_orig = self.read_value(location);
-        let m = m_orig.0;
+    fn shift_right(&mut self, value: u8, carry_in: bool) -> u8 {
+        self.set_flag(StatusFlags::Carry, value & 1 != 0);
+        self.with_flags((value >> 1) | if carry_in { SIGN_BIT } else { 0 })
+    }
+
+    fn adc(&mut self, m: u8) {
         let carry = if self.read_flag(StatusFlags::Carry) {
             1
         } else {
@@ -522,14 +720,10 @@ impl CPU {
             self.set_flag(StatusFlags::Carry, tmp > 0xFF);
         };
 
-        self.write_value_without_flags(Location::A, (tmp & 0xFF) as u8);
-        (m_orig.1, Branch::NotTaken)
+        self.a = (tmp & 0xFF) as u8;
     }
 
-    fn sbc(&mut self, mode: Mode) -> (PageBoundary, Branch) {
-        let location = self.get_location(mode);
-        let m_orig = self.read_value(location);
-        let m = m_orig.0;
+    fn sbc(&mut self, m: u8) {
         let borrow = if self.read_flag(StatusFlags::Carry) {
             0
         } else {
@@ -565,326 +759,37 @@ impl CPU {
 
         self.set_flag(StatusFlags::Carry, tmp < 0x0100);
 
-        self.write_value_without_flags(Location::A, (tmp & 0xFF) as u8);
-        (m_orig.1, Branch::NotTaken)
+        self.a = (tmp & 0xFF) as u8;
     }
 
-    fn compare(&mut self, mode: Mode, register: u8) -> (PageBoundary, Branch) {
-        let location = self.get_location(mode);
-        let m = self.read_value(location);
-        self.set_flag(StatusFlags::Zero, register == m.0);
-        self.set_flag(StatusFlags::Carry, register >= m.0);
+    fn compare(&mut self, register: u8, m: u8) {
+        self.set_flag(StatusFlags::Zero, register == m);
+        self.set_flag(StatusFlags::Carry, register >= m);
 
-        let tmp = register.wrapping_sub(m.0);
+        let tmp = register.wrapping_sub(m);
 
         self.set_flag(StatusFlags::Negative, (tmp & SIGN_BIT) != 0);
-        (m.1, Branch::NotTaken)
-    }
-
-    fn branch(&mut self, mode: Mode, branch_type: BranchType) -> (PageBoundary, Branch) {
-        let condition = match branch_type {
-            BranchType::CC => !self.read_flag(StatusFlags::Carry),
-            BranchType::CS => self.read_flag(StatusFlags::Carry),
-            BranchType::NE => !self.read_flag(StatusFlags::Zero),
-            BranchType::EQ => self.read_flag(StatusFlags::Zero),
-            BranchType::PL => !self.read_flag(StatusFlags::Negative),
-            BranchType::MI => self.read_flag(StatusFlags::Negative),
-            BranchType::VC => !self.read_flag(StatusFlags::Overflow),
-            BranchType::VS => self.read_flag(StatusFlags::Overflow),
-            BranchType::JMP => true,
-            BranchType::JSR => true,
-        };
-        let starting_pc = self.pc.wrapping_sub(1);
-        let location = self.get_location(mode);
-
-        if branch_type == BranchType::JSR {
-            self.push_word(self.pc.wrapping_sub(1));
-        }
-
-        if let Location::Addr(_, addr, _) = location {
-            if condition {
-                // a jmp back to itself is the most common form of
-                // trap used in tests. Another common one is a conditional
-                // branch relative back to the same spot. Detecting
-                // these conditions should make it easier to run tests to completion
-                if starting_pc == addr {
-                    self.trapped = true;
-                }
-                self.pc = addr;
-                (location.page_boundary(), Branch::Taken)
-            } else {
-                (PageBoundary::NotCrossed, Branch::NotTaken)
-            }
-        } else {
-            unreachable!("Branching to unsupported location {:?}", location)
-        }
-    }
-
-    fn transfer(&mut self, in_mode: Mode, out_mode: Mode) -> (PageBoundary, Branch) {
-        let in_location = self.get_location(in_mode);
-        let value = self.read_value(in_location);
-        let out_location = self.get_location(out_mode);
-        self.write_value(out_location, value.0);
-        (value.1, Branch::NotTaken)
-    }
-
-    fn transfer_without_flags(&mut self, in_mode: Mode, out_mode: Mode) -> (PageBoundary, Branch) {
-        let in_location = self.get_location(in_mode);
-        let value = self.read_value(in_location);
-        let out_location = self.get_location(out_mode);
-        self.write_value_without_flags(out_location, value.0);
-        (value.1, Branch::NotTaken)
-    }
-
-    fn read_value(&mut self, location: Location) -> (u8, PageBoundary) {
-        let boundary = &location.page_boundary();
-        let value = match location {
-            Location::A => self.a,
-            Location::X => self.x,
-            Location::Y => self.y,
-            Location::SP => self.sp,
-            Location::Status => self.status.bits(),
-            Location::Addr(_, addr, dummy_addr) => {
-                if dummy_addr != addr {
-                    self.read_bus_byte(dummy_addr);
-                }
-                let value = self.read_bus_byte(addr);
-                self.monitor.read_data_byte(addr, value).unwrap();
-                value
-            }
-            Location::Imm => self.fetch_byte(),
-            Location::Imp => {
-                unreachable!("Attempt to read value on implied addressing mode")
-            }
-        };
-
-        (value, *boundary)
-    }
-
-    fn write_value(&mut self, location: Location, value: u8) -> PageBoundary {
-        let page_boundary = self.write_value_without_flags(location, value);
-
-        self.set_flag(StatusFlags::Negative, value & SIGN_BIT != 0);
-        self.set_flag(StatusFlags::Zero, value == 0);
-
-        page_boundary
-    }
-
-    fn write_value_without_flags(&mut self, location: Location, value: u8) -> PageBoundary {
-        let boundary = &location.page_boundary();
-        match location {
-            Location::A => self.a = value,
-            Location::X => self.x = value,
-            Location::Y => self.y = value,
-            Location::SP => self.sp = value,
-            Location::Status => self.status = StatusFlags::from_bits_truncate(value),
-            Location::Addr(_, addr, dummy_addr) => {
-                if dummy_addr != addr {
-                    self.read_bus_byte(dummy_addr);
-                }
-                let old = self.write_bus_byte(addr, value);
-                self.monitor.read_data_byte(addr, old).unwrap();
-            }
-            Location::Imm => {
-                unreachable!("Attempt to write value on immediate addressing mode")
-            }
-            Location::Imp => {
-                unreachable!("Attempt to write value on implied addressing mode")
-            }
-        }
-
-        *boundary
     }
 
-    fn set_flag(&mut self, flag: StatusFlags, value: bool) -> (PageBoundary, Branch) {
+    fn set_flag(&mut self, flag: StatusFlags, value: bool) {
         self.status.set(flag, value);
-        (PageBoundary::NotCrossed, Branch::NotTaken)
     }
 
     fn read_flag(&self, flag: StatusFlags) -> bool {
         self.status.contains(flag)
     }
 
-    fn calc_addr(addr: u16, addition: u8) -> Location {
-        let (low, high) = CPU::bytes(addr);
-        let new_low = low.wrapping_add(addition);
-        let unfixed = ((high as u16) << 8) | new_low as u16;
-        Location::Addr(addr, addr.wrapping_add(addition as u16), unfixed)
-    }
-
-    fn simple_addr(addr: u16) -> Location {
-        Location::Addr(addr, addr, addr)
-    }
-
-    fn get_location(&mut self, mode: Mode) -> Location {
-        match mode {
-            Mode::Abs => {
-                let addr = self.fetch_word();
-                CPU::simple_addr(addr)
-            }
-            Mode::AbsX => {
-                let orig_addr = self.fetch_word();
-                CPU::calc_addr(orig_addr, self.x)
-            }
-            Mode::AbsY => {
-                let orig_addr = self.fetch_word();
-                CPU::calc_addr(orig_addr, self.y)
-            }
-            Mode::Status => Location::Status,
-            Mode::A => Location::A,
-            Mode::X => Location::X,
-            Mode::Y => Location::Y,
-            Mode::SP => Location::SP,
-            Mode::Imm => Location::Imm,
-            Mode::Imp => Location::Imp,
-            Mode::AbsInd => {
-                let orig_addr = self.fetch_word();
-                let lb = self
-                    .read_value(Location::Addr(orig_addr, orig_addr, orig_addr))
-                    .0;
-                // the 6502 has a bug where instead of incrementing the full address before
-                // reading the the next byte, it only increments the low byte of the address.
-                // Weird? yes. Hence never "JMP ($xxFF)" because what happens will be weird as it
-                // will read the address from $xxFF and then $xx00
-                let addr_high = CPU::high_byte(orig_addr);
-                let addr_low = CPU::low_byte(orig_addr).wrapping_add(1);
-                let hb_addr = CPU::to_word(addr_low, addr_high);
-                let hb = self.read_value(CPU::simple_addr(hb_addr)).0;
-                let addr = CPU::to_word(lb, hb);
-                Location::Addr(orig_addr, addr, addr)
-            }
-            Mode::IndX => {
-                let zp_addr_low = self.fetch_byte().wrapping_add(self.x) as u16;
-                let zp_addr_high = (zp_addr_low as u8).wrapping_add(1) as u16;
-                let addr_low = self.read_value(CPU::simple_addr(zp_addr_low)).0;
-                let addr_high = self.read_value(CPU::simple_addr(zp_addr_high)).0;
-                let addr = CPU::to_word(addr_low, addr_high);
-                CPU::simple_addr(addr)
-            }
-            Mode::IndY => {
-                let zp_addr_low = self.fetch_byte() as u16;
-                let zp_addr_high = (zp_addr_low as u8).wrapping_add(1) as u16;
-                let orig_addr_low = self
-                    .read_value(Location::Addr(zp_addr_low, zp_addr_low, zp_addr_low))
-                    .0;
-                let orig_addr_high = self
-                    .read_value(Location::Addr(zp_addr_high, zp_addr_high, zp_addr_high))
-                    .0;
-                let orig_addr = CPU::to_word(orig_addr_low, orig_addr_high);
-                CPU::calc_addr(orig_addr, self.y)
-            }
-            Mode::Rel => {
-                let offset = self.fetch_byte();
-                let new_pc = if offset & SIGN_BIT != 0 {
-                    let offset = !offset + 1;
-                    self.pc.wrapping_sub(offset as u16)
-                } else {
-                    self.pc.wrapping_add(offset as u16)
-                };
-
-                Location::Addr(self.pc, new_pc, new_pc)
-            }
-
-            Mode::Zp => {
-                let addr = self.fetch_byte() as u16;
-                CPU::simple_addr(addr)
-            }
-            Mode::Zpx => {
-                let addr = (self.fetch_byte().wrapping_add(self.x)) as u16;
-                CPU::simple_addr(addr)
-            }
-            Mode::Zpy => {
-                let addr = (self.fetch_byte().wrapping_add(self.y)) as u16;
-                CPU::simple_addr(addr)
-            }
-        }
-    }
-
-    pub fn read_bus_byte(&mut self, addr: u16) -> u8 {
-        self.bus.read(addr)
-    }
-
-    pub fn read_bus_word(&mut self, addr: u16) -> u16 {
-        let lb = self.read_bus_byte(addr);
-        let hb = self.read_bus_byte(addr.wrapping_add(1));
-        CPU::to_word(lb, hb)
-    }
-
-    pub fn write_bus_byte(&mut self, addr: u16, data: u8) -> u8 {
-        self.bus.write(addr, data)
-    }
-
-    fn low_byte(value: u16) -> u8 {
-        (value & LOW_BYTE_MASK) as u8
-    }
-
-    fn high_byte(value: u16) -> u8 {
-        (value >> 8) as u8
-    }
-
-    fn bytes(value: u16) -> (u8, u8) {
-        (CPU::low_byte(value), CPU::high_byte(value))
-    }
-
-    fn to_word(low_byte: u8, high_byte: u8) -> u16 {
-        ((high_byte as u16) << 8) | (low_byte as u16)
-    }
-
-    fn push(&mut self, mode: Mode) -> (PageBoundary, Branch) {
-        let location = self.get_location(mode);
-        let m = self.read_value(location);
-        self.push_byte(m.0);
-        (m.1, Branch::NotTaken)
-    }
-
-    fn pop(&mut self, mode: Mode) -> (PageBoundary, Branch) {
-        let value = self.pop_byte()
-            | if mode == Mode::Status {
-                // in the real 6502, break and unused aren't
-                // actually flags. But when pushed on the
-                // stack Unused is always set and break is
-                // set except on NMI or IRQ. The easiest way
-                // to make that happen is to set Break and Unused
-                // flags on power up and on pop
-                (StatusFlags::Break | StatusFlags::Unused).bits()
-            } else {
-                0
-            };
-        let location = self.get_location(mode);
-        let page_boundary = if location == Location::Status {
-            self.write_value_without_flags(location, value)
-        } else {
-            self.write_value(location, value)
-        };
-
-        (page_boundary, Branch::NotTaken)
-    }
-
-    fn rti(&mut self) -> (PageBoundary, Branch) {
-        self.pop(Mode::Status);
-        self.pc = self.pop_word();
-        (PageBoundary::NotCrossed, Branch::NotTaken)
-    }
-
-    fn rts(&mut self) -> (PageBoundary, Branch) {
-        self.pc = self.pop_word().wrapping_add(1);
-        (PageBoundary::NotCrossed, Branch::NotTaken)
-    }
-
-    fn test_bits(&mut self, mode: Mode) -> (PageBoundary, Branch) {
-        let location = self.get_location(mode);
-        let m = self.read_value(location);
-        let result = self.a & m.0;
+    fn test_bits(&mut self, m: u8) {
+        let result = self.a & m;
         self.set_flag(
             StatusFlags::Negative,
-            (m.0 & StatusFlags::Negative.bits()) != 0,
+            (m & StatusFlags::Negative.bits()) != 0,
         );
         self.set_flag(
             StatusFlags::Overflow,
-            (m.0 & StatusFlags::Overflow.bits()) != 0,
+            (m & StatusFlags::Overflow.bits()) != 0,
         );
         self.set_flag(StatusFlags::Zero, result == 0);
-        (m.1, Branch::NotTaken)
     }
 
     fn bcd_enabled(&self) -> bool {
@@ -894,36 +799,13 @@ impl CPU {
         }
     }
 
-    // all the unofficial nops need to have side effects
-    fn nop(&mut self, mode: Mode) -> (PageBoundary, Branch) {
-        let location = self.get_location(mode);
-        match location {
-            Location::A => (),
-            Location::X => (),
-            Location::Y => (),
-            Location::SP => (),
-            Location::Status => (),
-            Location::Addr(_, _, _) => {
-                self.read_value(location);
-            }
-            Location::Imm => {
-                self.read_value(location);
-            }
-            Location::Imp => (),
-        }
-        (location.page_boundary(), Branch::NotTaken)
-    }
-
-    fn anc(&mut self, mode: Mode) -> (PageBoundary, Branch) {
-        let result = self.binary(|a, m| a & m, mode);
+    fn anc(&mut self, m: u8) {
+        self.load_a(self.a & m);
         self.set_flag(StatusFlags::Carry, self.a & SIGN_BIT != 0);
-        result
     }
 
-    fn arr(&mut self, mode: Mode) -> (PageBoundary, Branch) {
-        let location = self.get_location(mode);
-        let operand = self.read_value(location);
-        let anded = self.a & operand.0;
+    fn arr(&mut self, m: u8) {
+        let anded = self.a & m;
         let value = (anded >> 1)
             | if self.read_flag(StatusFlags::Carry) {
                 SIGN_BIT
@@ -931,40 +813,25 @@ impl CPU {
                 0
             };
 
-        self.write_value(Location::A, value);
+        self.load_a(value);
         self.set_flag(
             StatusFlags::Overflow,
             ((value & 0b010000000) >> 1) != value & 0b00100000,
         );
         if self.bcd_enabled() && self.read_flag(StatusFlags::Decimal) {
-            self.set_flag(
-                StatusFlags::Carry,
-                (operand.0 & 0xF0).wrapping_add(operand.0 & 0x10) > 0x50,
-            );
+            self.set_flag(StatusFlags::Carry, (m & 0xF0).wrapping_add(m & 0x10) > 0x50);
         } else {
             self.set_flag(StatusFlags::Carry, value & 0b01000000 != 0);
         }
-
-        (location.page_boundary(), Branch::NotTaken)
     }
 
-    fn asr(&mut self, mode: Mode) -> (PageBoundary, Branch) {
-        let location = self.get_location(mode);
-        let orig = self.a & self.read_value(location).0;
-        let value = orig >> 1;
-
-        self.set_flag(StatusFlags::Negative, false);
+    fn asr(&mut self, m: u8) {
+        let orig = self.a & m;
         self.set_flag(StatusFlags::Carry, orig & 1 != 0);
-        self.set_flag(StatusFlags::Zero, value == 0);
-
-        self.a = value;
-        (location.page_boundary(), Branch::NotTaken)
+        self.load_a(orig >> 1);
     }
 
-    fn dcp(&mut self, mode: Mode) -> (PageBoundary, Branch) {
-        let location = self.get_location(mode);
-        let orig = self.read_value(location).0;
-        self.write_value(location, orig); // dummy write
+    fn dcp(&mut self, orig: u8) -> u8 {
         let value = orig.wrapping_sub(1);
 
         let diff = self.a.wrapping_sub(value);
@@ -973,16 +840,10 @@ impl CPU {
         self.set_flag(StatusFlags::Zero, diff == 0);
         self.set_flag(StatusFlags::Carry, value <= self.a);
 
-        (
-            self.write_value_without_flags(location, value),
-            Branch::NotTaken,
-        )
+        value
     }
 
-    fn isc(&mut self, mode: Mode) -> (PageBoundary, Branch) {
-        let location = self.get_location(mode);
-        let orig = self.read_value(location).0;
-        self.write_value(location, orig); // dummy write
+    fn isc(&mut self, orig: u8) -> u8 {
         let m = orig.wrapping_add(1);
 
         let borrow = if self.read_flag(StatusFlags::Carry) {
@@ -1002,86 +863,41 @@ impl CPU {
 
         self.set_flag(StatusFlags::Carry, tmp < 0x0100);
 
-        self.write_value_without_flags(Location::A, (tmp & 0xFF) as u8);
-        (
-            self.write_value_without_flags(location, m),
-            Branch::NotTaken,
-        )
+        self.a = (tmp & 0xFF) as u8;
+        m
     }
 
-    fn jam(&mut self) -> (PageBoundary, Branch) {
-        self.jammed = true;
-        (PageBoundary::NotCrossed, Branch::NotTaken)
-    }
-
-    fn las(&mut self, mode: Mode) -> (PageBoundary, Branch) {
-        let location = self.get_location(mode);
-        let v1 = self.read_value(location);
-        let value = v1.0 & self.sp;
-        self.write_value(Location::A, value);
+    fn las(&mut self, m: u8) {
+        let value = m & self.sp;
+        self.load_a(value);
         self.x = value;
         self.sp = value;
-        (location.page_boundary(), Branch::NotTaken)
-    }
-
-    fn lax(&mut self, mode: Mode) -> (PageBoundary, Branch) {
-        let location = self.get_location(mode);
-        let value = self.read_value(location);
-        self.write_value(Location::A, value.0);
-        self.write_value_without_flags(Location::X, value.0);
-        (location.page_boundary(), Branch::NotTaken)
-    }
-
-    fn sax(&mut self, mode: Mode) -> (PageBoundary, Branch) {
-        let value = self.a & self.x;
-        let location = self.get_location(mode);
-        (
-            self.write_value_without_flags(location, value),
-            Branch::NotTaken,
-        )
     }
 
-    fn sha(&mut self, mode: Mode) -> (PageBoundary, Branch) {
-        let location = self.get_location(mode);
-
-        let value = CPU::h_plus_1(location);
-        let data = self.a & self.x & value;
-        (
-            self.write_value_without_flags(location, data),
-            Branch::NotTaken,
-        )
+    fn lax(&mut self, m: u8) {
+        self.load_a(m);
+        self.x = m;
     }
 
-    fn h_plus_1(location: Location) -> u8 {
-        match location {
-            Location::Addr(orig, _, _) => ((orig >> 8) as u8).wrapping_add(1),
-            _ => unreachable!("SHA, SHX, SHY, or TAS with invalid location {:?}", location),
+    // SHA, SHS, SHX and SHY AND the value with the high byte of the base address + 1.
+    // If the index crossed a page, that value also replaces the high byte of the address
+    fn unstable_store(&mut self, value: u8) -> u8 {
+        let crossed = self.page_boundary == PageBoundary::Crossed;
+        let base_high = CPU::high_byte(self.address).wrapping_sub(if crossed { 1 } else { 0 });
+        let value = value & base_high.wrapping_add(1);
+        if crossed {
+            self.address = CPU::to_word(CPU::low_byte(self.address), value);
         }
+        value
     }
 
-    fn rla(&mut self, mode: Mode) -> (PageBoundary, Branch) {
-        let location = self.get_location(mode);
-        let orig = self.read_value(location).0;
-        self.write_value(location, orig); // dummy write
-        let m = (orig << 1)
-            | if self.read_flag(StatusFlags::Carry) {
-                1
-            } else {
-                0
-            };
-
-        self.set_flag(StatusFlags::Carry, orig & SIGN_BIT != 0);
-        self.write_value(Location::A, self.a & m);
-        (
-            self.write_value_without_flags(location, m),
-            Branch::NotTaken,
-        )
+    fn rla(&mut self, orig: u8) -> u8 {
+        let m = self.shift_left(orig, self.read_flag(StatusFlags::Carry));
+        self.load_a(self.a & m);
+        m
     }
 
-    fn rra(&mut self, mode: Mode) -> (PageBoundary, Branch) {
-        let location = self.get_location(mode);
-        let orig = self.read_value(location).0;
-        self.write_value(location, orig); // dummy write
+    fn rra(&mut self, orig: u8) -> u8 {
         let m = (orig >> 1)
             | if self.read_flag(StatusFlags::Carry) {
                 SIGN_BIT
@@ -1089,8 +905,6 @@ impl CPU {
                 0
             };
 
-        self.set_flag(StatusFlags::Carry, orig & 1 != 0);
-
         let value = (self.a as u16)
             .wrapping_add(m as u16)
             .wrapping_add((orig & 1) as u16);
@@ -1106,81 +920,28 @@ impl CPU {
             (self.a ^ m) & SIGN_BIT == 0 && (self.a as u16 ^ value) & SIGN_BIT as u16 != 0,
         );
 
-        self.write_value(Location::A, (value & 0xFF) as u8);
-        (
-            self.write_value_without_flags(location, m),
-            Branch::NotTaken,
-        )
+        self.load_a((value & 0xFF) as u8);
+        m
     }
 
-    fn sbx(&mut self, mode: Mode) -> (PageBoundary, Branch) {
-        let location = self.get_location(mode);
-        let value = (self.a & self.x).wrapping_sub(self.read_value(location).0);
+    fn sbx(&mut self, m: u8) {
+        let value = (self.a & self.x).wrapping_sub(m);
 
         self.set_flag(StatusFlags::Carry, (value & SIGN_BIT) == 0);
 
-        (self.write_value(Location::X, value), Branch::NotTaken)
-    }
-
-    fn shs(&mut self, mode: Mode) -> (PageBoundary, Branch) {
-        let v1 = self.a & self.x;
-        self.sp = v1;
-        let location = self.get_location(mode);
-        let value = v1 & CPU::h_plus_1(location);
-        (
-            self.write_value_without_flags(location, value),
-            Branch::NotTaken,
-        )
-    }
-
-    fn shx(&mut self, mode: Mode) -> (PageBoundary, Branch) {
-        let location = self.get_location(mode);
-        let value = self.x & CPU::h_plus_1(location);
-        (
-            self.write_value_without_flags(location, value),
-            Branch::NotTaken,
-        )
-    }
-
-    fn shy(&mut self, mode: Mode) -> (PageBoundary, Branch) {
-        let location = self.get_location(mode);
-        let value = self.y & CPU::h_plus_1(location);
-        (
-            self.write_value_without_flags(location, value),
-            Branch::NotTaken,
-        )
-    }
-
-    fn slo(&mut self, mode: Mode) -> (PageBoundary, Branch) {
-        let location = self.get_location(mode);
-        let orig = self.read_value(location).0;
-        self.write_value(location, orig); // dummy write
-        let m = orig << 1;
-
-        self.write_value_without_flags(location, m);
-        self.set_flag(StatusFlags::Carry, orig & SIGN_BIT != 0);
-        self.write_value(Location::A, self.a | m);
-        (location.page_boundary(), Branch::NotTaken)
+        self.load_x(value);
     }
 
-    fn sre(&mut self, mode: Mode) -> (PageBoundary, Branch) {
-        let location = self.get_location(mode);
-        let orig = self.read_value(location).0;
-        self.write_value(location, orig); // dummy write
-        let m = orig >> 1;
-
-        self.write_value_without_flags(location, m);
-        self.set_flag(StatusFlags::Carry, orig & 1 != 0);
-        self.write_value(Location::A, self.a ^ m);
-        (location.page_boundary(), Branch::NotTaken)
+    fn slo(&mut self, orig: u8) -> u8 {
+        let m = self.shift_left(orig, false);
+        self.load_a(self.a | m);
+        m
     }
 
-    fn xxa(&mut self, mode: Mode) -> (PageBoundary, Branch) {
-        let location = self.get_location(mode);
-        let m = self.read_value(location).0;
-        let value = (self.a | 0xEE) & self.x & m;
-        self.write_value(location, value);
-        (location.page_boundary(), Branch::NotTaken)
+    fn sre(&mut self, orig: u8) -> u8 {
+        let m = self.shift_right(orig, false);
+        self.load_a(self.a ^ m);
+        m
     }
 }
 
@@ -1190,53 +951,6 @@ pub enum CPUType {
     MOS6502,
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-enum Location {
-    A,
-    X,
-    Y,
-    SP,
-    Status,
-    Addr(u16, u16, u16),
-    Imm,
-    Imp,
-}
-
-impl Location {
-    fn page_boundary(&self) -> PageBoundary {
-        match self {
-            Location::A => PageBoundary::NotCrossed,
-            Location::X => PageBoundary::NotCrossed,
-            Location::Y => PageBoundary::NotCrossed,
-            Location::SP => PageBoundary::NotCrossed,
-            Location::Status => PageBoundary::NotCrossed,
-            Location::Addr(addr1, addr2, _) => {
-                if (addr1 & HIGH_BYTE_MASK) != (addr2 & HIGH_BYTE_MASK) {
-                    PageBoundary::Crossed
-                } else {
-                    PageBoundary::NotCrossed
-                }
-            }
-            Location::Imm => PageBoundary::NotCrossed,
-            Location::Imp => PageBoundary::NotCrossed,
-        }
-    }
-}
-
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-enum BranchType {
-    CC,
-    CS,
-    EQ,
-    MI,
-    NE,
-    PL,
-    VC,
-    VS,
-    JMP,
-    JSR,
-}
-
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 enum Branch {
     NotTaken,
@@ -1249,15 +963,8 @@ enum PageBoundary {
     Crossed,
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-enum ShiftStyle {
-    ShiftOff,
-    Rotate,
-}
-
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 enum Interrupt {
-    BRK,
     IRQ,
     NMI,
     RST,
@@ -1278,3 +985,93 @@ pub enum CPUCycleType {
     Read,
     Write,
 }
+
+impl SaveState for CPU {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("CPU");
+        writer.write_u8(self.a);
+        writer.write_u8(self.x);
+        writer.write_u8(self.y);
+        writer.write_u8(self.status.bits());
+        writer.write_u8(self.sp);
+        writer.write_u16(self.pc);
+        writer.write_usize(self.cycles);
+        writer.write_bool(self.rdy);
+        writer.write_bool(self.halted_at.is_some());
+        writer.write_u16(self.halted_at.unwrap_or(0));
+        writer.write_bool(self.jammed);
+        writer.write_bool(self.trapped);
+        writer.write_u8(self.instruction as u8);
+        writer.write_u8(self.mode as u8);
+        writer.write_u8(self.step);
+        writer.write_u16(self.address);
+        writer.write_u8(self.pointer);
+        writer.write_u8(self.data);
+        writer.write_bool(self.page_boundary == PageBoundary::Crossed);
+        writer.write_bool(self.branch == Branch::Taken);
+        writer.write_u8(match self.interrupt {
+            None => 0,
+            Some(Interrupt::IRQ) => 1,
+            Some(Interrupt::NMI) => 2,
+            Some(Interrupt::RST) => 3,
+        });
+        writer.write_u8(self.interrupt_flags.bits());
+        writer.write_bool(self.nmi_was_enabled);
+        writer.write_bool(self.nmi_pending);
+        writer.write_bool(self.irq_pending);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("CPU")?;
+        self.a = reader.read_u8()?;
+        self.x = reader.read_u8()?;
+        self.y = reader.read_u8()?;
+        self.status = StatusFlags::from_bits_retain(reader.read_u8()?);
+        self.sp = reader.read_u8()?;
+        self.pc = reader.read_u16()?;
+        self.cycles = reader.read_usize()?;
+        self.rdy = reader.read_bool()?;
+        let halted = reader.read_bool()?;
+        let halted_at = reader.read_u16()?;
+        self.halted_at = if halted { Some(halted_at) } else { None };
+        self.jammed = reader.read_bool()?;
+        self.trapped = reader.read_bool()?;
+        let instruction = reader.read_u8()?;
+        self.instruction = Instruction::from_repr(instruction as usize).ok_or(
+            SaveStateError::InvalidValue("instruction", instruction as u32),
+        )?;
+        let mode = reader.read_u8()?;
+        self.mode = Mode::from_repr(mode as usize)
+            .ok_or(SaveStateError::InvalidValue("mode", mode as u32))?;
+        self.program = MicroProgram::new(self.instruction, self.mode);
+        self.step = reader.read_u8()?;
+        if self.step > self.program.len() {
+            Err(SaveStateError::InvalidValue("step", self.step as u32))?;
+        }
+        self.address = reader.read_u16()?;
+        self.pointer = reader.read_u8()?;
+        self.data = reader.read_u8()?;
+        self.page_boundary = if reader.read_bool()? {
+            PageBoundary::Crossed
+        } else {
+            PageBoundary::NotCrossed
+        };
+        self.branch = if reader.read_bool()? {
+            Branch::Taken
+        } else {
+            Branch::NotTaken
+        };
+        self.interrupt = match reader.read_u8()? {
+            0 => None,
+            1 => Some(Interrupt::IRQ),
+            2 => Some(Interrupt::NMI),
+            3 => Some(Interrupt::RST),
+            n => Err(SaveStateError::InvalidValue("interrupt", n as u32))?,
+        };
+        self.interrupt_flags = InterruptFlags::from_bits_truncate(reader.read_u8()?);
+        self.nmi_was_enabled = reader.read_bool()?;
+        self.nmi_pending = reader.read_bool()?;
+        self.irq_pending = reader.read_bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,489 @@
+// Turns ca65 style assembly into bytes, the inverse of decode. It reads what the
+// disassembler writes, along with labels, constants, .byte and .word. Unofficial
+// opcodes are named after Instruction, or ca65's 6502X names where they differ.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use thiserror::Error;
+
+use crate::cpu::{
+    decode::decode,
+    disassembler::{instruction_length, is_official},
+    instructions::{Instruction, Mode},
+};
+
+/**
+ * Assembles source as if it's placed at origin
+ */
+pub fn assemble(source: &str, origin: u16) -> Result<Vec<u8>> {
+    assemble_with(source, origin, &|_| None)
+}
+
+/**
+ * Assembles source as if it's placed at origin. Names that source doesn't define
+ * are looked up with resolve, e.g. in a debugger's labels.
+ */
+pub fn assemble_with(
+    source: &str,
+    origin: u16,
+    resolve: &dyn Fn(&str) -> Option<u16>,
+) -> Result<Vec<u8>> {
+    let mut assembler = Assembler {
+        symbols: HashMap::new(),
+        resolve,
+        statements: Vec::new(),
+        addr: origin,
+    };
+    for (i, line) in source.lines().enumerate() {
+        assembler.first_pass(i + 1, line)?;
+    }
+    let mut bytes = Vec::new();
+    for statement in &assembler.statements {
+        assembler.second_pass(statement, &mut bytes)?;
+    }
+    Ok(bytes)
+}
+
+struct Assembler<'a> {
+    symbols: HashMap<String, u16>,
+    resolve: &'a dyn Fn(&str) -> Option<u16>,
+    statements: Vec<Statement>,
+    addr: u16,
+}
+
+struct Statement {
+    line: usize,
+    addr: u16,
+    kind: StatementKind,
+}
+
+enum StatementKind {
+    Instruction(u8, Mode, Option<Expression>),
+    Bytes(Vec<Data>),
+    Words(Vec<Expression>),
+}
+
+enum Data {
+    Expression(Expression),
+    Text(String),
+}
+
+impl Assembler<'_> {
+    // works out where everything goes: labels get their addresses and
+    // instructions their opcodes
+    fn first_pass(&mut self, line: usize, text: &str) -> Result<()> {
+        let mut rest = strip_comment(text).trim();
+
+        while let Some((name, after)) = split_name(rest)
+            && let Some(after) = after.strip_prefix(':')
+            && !after.starts_with(':')
+        {
+            self.define(line, name, self.addr)?;
+            rest = after.trim_start();
+        }
+        if rest.is_empty() {
+            return Ok(());
+        }
+
+        if let Some((name, after)) = split_name(rest)
+            && let Some(value) = after.trim_start().strip_prefix('=')
+        {
+            let value = self.evaluate(line, self.addr, &parse_expression(line, value)?)?;
+            return self.define(line, name, value);
+        }
+
+        let (word, operand) = match rest.find(char::is_whitespace) {
+            Some(end) => (&rest[..end], rest[end..].trim()),
+            None => (rest, ""),
+        };
+
+        let kind = match word.to_lowercase().as_str() {
+            ".byte" | ".byt" | ".db" => {
+                let data = split_list(operand)
+                    .map(|item| match item.strip_prefix('"') {
+                        Some(text) => match text.strip_suffix('"') {
+                            Some(text) => Ok(Data::Text(text.to_string())),
+                            None => Err(AssemblerError::InvalidSyntax(line, item.to_string())),
+                        },
+                        None => Ok(Data::Expression(parse_expression(line, item)?)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                StatementKind::Bytes(data)
+            }
+            ".word" | ".addr" | ".dw" => StatementKind::Words(
+                split_list(operand)
+                    .map(|item| parse_expression(line, item))
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            mnemonic => {
+                let instruction = parse_mnemonic(mnemonic)
+                    .ok_or_else(|| AssemblerError::UnknownInstruction(line, word.to_string()))?;
+                let (mode, expression) = self.choose_mode(line, instruction, operand)?;
+                let op = opcode(instruction, mode)
+                    .ok_or_else(|| AssemblerError::InvalidMode(line, rest.to_string()))?;
+                StatementKind::Instruction(op, mode, expression)
+            }
+        };
+        let statement = Statement {
+            line,
+            addr: self.addr,
+            kind,
+        };
+        self.addr = self.addr.wrapping_add(statement.len());
+        self.statements.push(statement);
+        Ok(())
+    }
+
+    fn second_pass(&self, statement: &Statement, bytes: &mut Vec<u8>) -> Result<()> {
+        use Mode::*;
+
+        let line = statement.line;
+        let byte = |value: u16| match u8::try_from(value) {
+            Ok(value) => Ok(value),
+            Err(_) => Err(AssemblerError::OutOfRange(line, value)),
+        };
+
+        match &statement.kind {
+            StatementKind::Instruction(op, mode, expression) => {
+                bytes.push(*op);
+                let Some(expression) = expression else {
+                    return Ok(());
+                };
+                let value = self.evaluate(line, statement.addr, expression)?;
+                match mode {
+                    Rel => {
+                        let offset = value.wrapping_sub(statement.addr.wrapping_add(2)) as i16;
+                        let offset = i8::try_from(offset)
+                            .map_err(|_| AssemblerError::BranchTooFar(line, value))?;
+                        bytes.push(offset as u8);
+                    }
+                    Abs | AbsX | AbsY | AbsInd => bytes.extend(value.to_le_bytes()),
+                    _ => bytes.push(byte(value)?),
+                }
+            }
+            StatementKind::Bytes(data) => {
+                for item in data {
+                    match item {
+                        Data::Expression(expression) => {
+                            bytes.push(byte(self.evaluate(line, statement.addr, expression)?)?)
+                        }
+                        Data::Text(text) => bytes.extend(text.bytes()),
+                    }
+                }
+            }
+            StatementKind::Words(words) => {
+                for expression in words {
+                    let value = self.evaluate(line, statement.addr, expression)?;
+                    bytes.extend(value.to_le_bytes());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn choose_mode(
+        &self,
+        line: usize,
+        instruction: Instruction,
+        operand: &str,
+    ) -> Result<(Mode, Option<Expression>)> {
+        use Mode::*;
+
+        let has = |mode| opcode(instruction, mode).is_some();
+        let lower = operand.to_lowercase();
+
+        if operand.is_empty() {
+            // ca65 accepts asl for asl a
+            let mode = if has(Imp) { Imp } else { A };
+            return Ok((mode, None));
+        }
+        if lower == "a" {
+            return Ok((A, None));
+        }
+        if let Some(value) = operand.strip_prefix('#') {
+            return Ok((Imm, Some(parse_expression(line, value)?)));
+        }
+        if let Some(inner) = lower.strip_prefix('(') {
+            let (mode, inner) = if let Some(inner) = inner.strip_suffix("),y") {
+                (IndY, inner)
+            } else if let Some(inner) = inner.strip_suffix(",x)") {
+                (IndX, inner)
+            } else if let Some(inner) = inner.strip_suffix(')') {
+                (AbsInd, inner)
+            } else {
+                Err(AssemblerError::InvalidSyntax(line, operand.to_string()))?
+            };
+            // keep the case of the names in the expression
+            let inner = &operand[1..1 + inner.len()];
+            return Ok((mode, Some(parse_expression(line, inner)?)));
+        }
+
+        let (value, index) = if let Some(value) = lower.strip_suffix(",x") {
+            (&operand[..value.len()], Some(X))
+        } else if let Some(value) = lower.strip_suffix(",y") {
+            (&operand[..value.len()], Some(Y))
+        } else {
+            (operand, None)
+        };
+        let value = value.trim();
+        let (size, value) = if let Some(value) = value.strip_prefix("a:") {
+            (Some(Abs), value)
+        } else if let Some(value) = value.strip_prefix("z:") {
+            (Some(Zp), value)
+        } else {
+            (None, value)
+        };
+        let expression = parse_expression(line, value)?;
+
+        let (zp, abs) = match index {
+            Some(X) => (Zpx, AbsX),
+            Some(_) => (Zpy, AbsY),
+            None if has(Rel) => return Ok((Rel, Some(expression))),
+            None => (Zp, Abs),
+        };
+        // like ca65, zero page is used when the address is known to be in it by now
+        let mode = match size {
+            Some(Abs) => abs,
+            Some(_) => zp,
+            None if !has(zp) => abs,
+            None => match self.lookup(&expression) {
+                Some(value) if value < 0x100 => zp,
+                _ => abs,
+            },
+        };
+        Ok((mode, Some(expression)))
+    }
+
+    fn define(&mut self, line: usize, name: &str, value: u16) -> Result<()> {
+        if self.symbols.insert(name.to_string(), value).is_some() {
+            Err(AssemblerError::DuplicateName(line, name.to_string()))?;
+        }
+        Ok(())
+    }
+
+    // the value if every name in it is known in the first pass
+    fn lookup(&self, expression: &Expression) -> Option<u16> {
+        expression
+            .evaluate(self.addr, &|name| self.symbol(name))
+            .ok()
+    }
+
+    fn evaluate(&self, line: usize, addr: u16, expression: &Expression) -> Result<u16> {
+        let value = expression
+            .evaluate(addr, &|name| self.symbol(name))
+            .map_err(|name| AssemblerError::UndefinedName(line, name))?;
+        Ok(value)
+    }
+
+    fn symbol(&self, name: &str) -> Option<u16> {
+        self.symbols
+            .get(name)
+            .copied()
+            .or_else(|| (self.resolve)(name))
+    }
+}
+
+impl Statement {
+    fn len(&self) -> u16 {
+        match &self.kind {
+            StatementKind::Instruction(_, mode, _) => instruction_length(*mode),
+            StatementKind::Bytes(data) => data
+                .iter()
+                .map(|item| match item {
+                    Data::Expression(_) => 1,
+                    Data::Text(text) => text.len() as u16,
+                })
+                .sum(),
+            StatementKind::Words(words) => 2 * words.len() as u16,
+        }
+    }
+}
+
+/**
+ * The opcode for the instruction in the mode, preferring documented opcodes
+ * and then the lowest, like ca65
+ */
+pub fn opcode(instruction: Instruction, mode: Mode) -> Option<u8> {
+    let matches = |op: &u8| {
+        let (i, m, _, _) = decode(*op);
+        i == instruction && m == mode
+    };
+    (0..=0xFF)
+        .filter(matches)
+        .find(|op| is_official(*op))
+        .or_else(|| (0..=0xFF).find(matches))
+}
+
+fn parse_mnemonic(mnemonic: &str) -> Option<Instruction> {
+    use Instruction::*;
+
+    // ca65's names for the unofficial instructions that Instruction names differently
+    let alias = match mnemonic {
+        "alr" => Some(ASR),
+        "axs" => Some(SBX),
+        "tas" => Some(SHS),
+        "ane" => Some(XXA),
+        "isb" => Some(ISC),
+        _ => None,
+    };
+    alias.or_else(|| {
+        (0..=0xFF)
+            .map(|op| decode(op).0)
+            .find(|instruction| instruction.to_string().eq_ignore_ascii_case(mnemonic))
+    })
+}
+
+/**
+ * A sum of numbers and names, e.g. `enemies+2`, optionally with `<` or `>` to
+ * take the low or high byte
+ */
+struct Expression {
+    byte: Option<Byte>,
+    terms: Vec<(bool, Term)>,
+}
+
+enum Byte {
+    Low,
+    High,
+}
+
+enum Term {
+    Number(u16),
+    Name(String),
+    // * is the address of the statement
+    Here,
+}
+
+impl Expression {
+    // Err is the name that isn't known
+    fn evaluate(&self, addr: u16, symbol: &dyn Fn(&str) -> Option<u16>) -> Result<u16, String> {
+        let mut value = 0u16;
+        for (negative, term) in &self.terms {
+            let term = match term {
+                Term::Number(number) => *number,
+                Term::Name(name) => symbol(name).ok_or_else(|| name.clone())?,
+                Term::Here => addr,
+            };
+            value = match negative {
+                true => value.wrapping_sub(term),
+                false => value.wrapping_add(term),
+            };
+        }
+        Ok(match self.byte {
+            Some(Byte::Low) => value & 0xFF,
+            Some(Byte::High) => value >> 8,
+            None => value,
+        })
+    }
+}
+
+fn parse_expression(line: usize, text: &str) -> Result<Expression, AssemblerError> {
+    let invalid = || AssemblerError::InvalidSyntax(line, text.trim().to_string());
+
+    let mut rest = text.trim();
+    let byte = if let Some(after) = rest.strip_prefix('<') {
+        rest = after.trim_start();
+        Some(Byte::Low)
+    } else if let Some(after) = rest.strip_prefix('>') {
+        rest = after.trim_start();
+        Some(Byte::High)
+    } else {
+        None
+    };
+
+    let mut terms = Vec::new();
+    let mut negative = false;
+    if let Some(after) = rest.strip_prefix('-') {
+        rest = after.trim_start();
+        negative = true;
+    }
+    loop {
+        let end = rest
+            .find(|c: char| c == '+' || c == '-' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let (token, after) = rest.split_at(end);
+        let term = if token == "*" {
+            Term::Here
+        } else if let Some(hex) = token.strip_prefix('$') {
+            Term::Number(u16::from_str_radix(hex, 16).map_err(|_| invalid())?)
+        } else if let Some(binary) = token.strip_prefix('%') {
+            Term::Number(u16::from_str_radix(binary, 2).map_err(|_| invalid())?)
+        } else if token.starts_with(|c: char| c.is_ascii_digit()) {
+            Term::Number(token.parse().map_err(|_| invalid())?)
+        } else if split_name(token)
+            .is_some_and(|(name, after)| !name.is_empty() && after.is_empty())
+        {
+            Term::Name(token.to_string())
+        } else {
+            Err(invalid())?
+        };
+        terms.push((negative, term));
+
+        rest = after.trim_start();
+        match rest.chars().next() {
+            None => break,
+            Some('+') => negative = false,
+            Some('-') => negative = true,
+            Some(_) => Err(invalid())?,
+        }
+        rest = rest[1..].trim_start();
+    }
+    Ok(Expression { byte, terms })
+}
+
+// a name at the start of text, e.g. `player::update` or `@loop`, and what follows it
+fn split_name(text: &str) -> Option<(&str, &str)> {
+    if !text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '@' || c == '.') {
+        return None;
+    }
+    let mut end = 0;
+    let bytes = text.as_bytes();
+    while end < bytes.len() {
+        let c = bytes[end];
+        if c.is_ascii_alphanumeric() || matches!(c, b'_' | b'@' | b'.') {
+            end += 1;
+        } else if text[end..].starts_with("::") {
+            end += 2;
+        } else {
+            break;
+        }
+    }
+    Some(text.split_at(end))
+}
+
+fn split_list(text: &str) -> impl Iterator<Item = &str> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+#[derive(Error, Debug)]
+pub enum AssemblerError {
+    #[error("Line {0}: '{1}' isn't an instruction or directive")]
+    UnknownInstruction(usize, String),
+    #[error("Line {0}: '{1}' doesn't have that addressing mode")]
+    InvalidMode(usize, String),
+    #[error("Line {0}: '{1}' isn't understood")]
+    InvalidSyntax(usize, String),
+    #[error("Line {0}: '{1}' isn't defined")]
+    UndefinedName(usize, String),
+    #[error("Line {0}: '{1}' is already defined")]
+    DuplicateName(usize, String),
+    #[error("Line {0}: ${1:X} doesn't fit in a byte")]
+    OutOfRange(usize, u16),
+    #[error("Line {0}: ${1:04X} is too far away to branch to")]
+    BranchTooFar(usize, u16),
+}
@@ -0,0 +1,421 @@
+// Turns bytes back into ca65 syntax. Unofficial opcodes are named after Instruction,
+// which mostly agrees with ca65's 6502X cpu.
+
+use std::fmt::Display;
+
+use crate::cpu::{
+    decode::decode,
+    instructions::{Instruction, Mode},
+};
+
+/**
+ * Somewhere to read instruction bytes from. None means the address can't be read,
+ * either because it's outside of the source or because reading it has side effects.
+ */
+pub trait MemorySource {
+    fn peek(&self, addr: u16) -> Option<u8>;
+}
+
+/**
+ * A run of bytes, e.g. a PRG bank, that appears at base in the address space
+ */
+pub struct Bank<'a> {
+    pub base: u16,
+    pub bytes: &'a [u8],
+}
+
+impl MemorySource for Bank<'_> {
+    fn peek(&self, addr: u16) -> Option<u8> {
+        let offset = addr.checked_sub(self.base)? as usize;
+        self.bytes.get(offset).copied()
+    }
+}
+
+impl<F: Fn(u16) -> Option<u8>> MemorySource for F {
+    fn peek(&self, addr: u16) -> Option<u8> {
+        self(addr)
+    }
+}
+
+/**
+ * The index registers at the time the instruction runs. With them, lines are
+ * annotated with the address the instruction accesses and the value there.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct IndexRegisters {
+    pub x: u8,
+    pub y: u8,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Line {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    /**
+     * None when the bytes couldn't all be read and are shown as data
+     */
+    pub instruction: Option<(Instruction, Mode)>,
+    /**
+     * The instruction in ca65 syntax, e.g. `lda $0200,x`
+     */
+    pub text: String,
+    /**
+     * The effective address and value, e.g. `@ $0201 = $00`
+     */
+    pub annotation: Option<String>,
+}
+
+impl Line {
+    pub fn len(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /**
+     * ca65 source that assembles back to the same bytes. Opcodes that ca65 would
+     * encode differently, e.g. 0xEB SBC, become .byte directives.
+     */
+    pub fn source(&self) -> String {
+        match self.instruction {
+            Some(_) if assembles_back(self.bytes[0]) => self.text.clone(),
+            Some(_) => format!("{} ; {}", byte_directive(&self.bytes), self.text),
+            None => self.text.clone(),
+        }
+    }
+
+    /**
+     * Writes the address the operand refers to as a label where labels has one,
+     * e.g. `jsr player_update` or `lda enemies+2,x`
+     */
+    pub fn label_operand(&mut self, labels: &dyn Fn(u16) -> Option<String>) {
+        use Mode::*;
+
+        let Some((instruction, mode)) = self.instruction else {
+            return;
+        };
+        let operand = operand(&self.bytes);
+        let target = match mode {
+            Rel => branch_target(self.addr, operand as u8),
+            Zp | Zpx | Zpy | Abs | AbsX | AbsY | AbsInd | IndX | IndY => operand,
+            A | Imm | Imp | Status | SP | X | Y => return,
+        };
+        if let Some(label) = labels(target) {
+            self.text = format!(
+                "{}{}",
+                instruction.to_string().to_lowercase(),
+                format_operand(mode, self.addr, operand, Some(&label))
+            );
+        }
+    }
+}
+
+impl Display for Line {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bytes = self
+            .bytes
+            .iter()
+            .map(|b| format!("{b:02X}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        write!(f, "{:04X}  {:9} {}", self.addr, bytes, self.text)?;
+        if let Some(annotation) = &self.annotation {
+            write!(f, " ; {annotation}")?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Whether the opcode is one of the documented 151
+ */
+pub fn is_official(op: u8) -> bool {
+    use Instruction::*;
+
+    let (instruction, _, _, _) = decode(op);
+    match instruction {
+        // the documented NOP and SBC only have one opcode each
+        NOP => op == 0xEA,
+        SBC => op != 0xEB,
+        ANC | ARR | ASR | DCP | ISC | JAM | LAS | LAX | RLA | RRA | SAX | SBX | SHA | SHS | SHX
+        | SHY | SLO | SRE | XXA => false,
+        _ => true,
+    }
+}
+
+// whether ca65 will produce this opcode for its instruction and mode. It knows
+// ASR, SBX, SHS and XXA as ALR, AXS, TAS and ANE
+fn assembles_back(op: u8) -> bool {
+    use Instruction::*;
+
+    let (instruction, mode, _, _) = decode(op);
+    if is_official(op) {
+        return true;
+    }
+    let first = (0..=0xFF).find(|o| {
+        let (i, m, _, _) = decode(*o);
+        i == instruction && m == mode
+    });
+    !matches!(instruction, NOP | ASR | SBX | SHS | XXA) && first == Some(op)
+}
+
+pub fn instruction_length(mode: Mode) -> u16 {
+    use Mode::*;
+
+    match mode {
+        Imm | Zp | Zpx | Zpy | IndX | IndY | Rel => 2,
+        Abs | AbsX | AbsY | AbsInd => 3,
+        A | Imp | Status | SP | X | Y => 1,
+    }
+}
+
+/**
+ * Disassembles the instruction at addr
+ */
+pub fn disassemble_one(
+    memory: &dyn MemorySource,
+    addr: u16,
+    registers: Option<IndexRegisters>,
+) -> Line {
+    let Some(op) = memory.peek(addr) else {
+        return Line {
+            addr,
+            bytes: Vec::new(),
+            instruction: None,
+            text: String::new(),
+            annotation: None,
+        };
+    };
+    let (instruction, mode, _, _) = decode(op);
+    let bytes = (0..instruction_length(mode))
+        .map_while(|i| memory.peek(addr.wrapping_add(i)))
+        .collect::<Vec<_>>();
+    if bytes.len() as u16 != instruction_length(mode) {
+        return data_line(addr, bytes);
+    }
+
+    let operand = operand(&bytes);
+    let text = format!(
+        "{}{}",
+        instruction.to_string().to_lowercase(),
+        format_operand(mode, addr, operand, None)
+    );
+    let annotation = registers.and_then(|r| annotate(memory, instruction, mode, operand, r));
+
+    Line {
+        addr,
+        bytes,
+        instruction: Some((instruction, mode)),
+        text,
+        annotation,
+    }
+}
+
+/**
+ * Disassembles from start up to and including end. An instruction that starts
+ * before end can run past it.
+ */
+pub fn disassemble(
+    memory: &dyn MemorySource,
+    start: u16,
+    end: u16,
+    registers: Option<IndexRegisters>,
+) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut addr = start as u32;
+    while addr <= end as u32 {
+        let line = disassemble_one(memory, addr as u16, registers);
+        if line.is_empty() {
+            break;
+        }
+        addr += line.len() as u32;
+        lines.push(line);
+    }
+    lines
+}
+
+/**
+ * What a byte is known to be, e.g. from a code/data log
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ByteKind {
+    Code,
+    Data,
+    Unknown,
+}
+
+// how many bytes go on a .byte line
+const DATA_LINE_LENGTH: usize = 8;
+
+/**
+ * Like disassemble, but only bytes that classify says are code become instructions.
+ * The rest become .byte lines, split wherever the kind changes, and unknown ones
+ * are annotated as such.
+ */
+pub fn disassemble_classified(
+    memory: &dyn MemorySource,
+    start: u16,
+    end: u16,
+    classify: &dyn Fn(u16) -> ByteKind,
+) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut addr = start as u32;
+    while addr <= end as u32 {
+        let kind = classify(addr as u16);
+        if kind == ByteKind::Code {
+            let line = disassemble_one(memory, addr as u16, None);
+            // an instruction whose operand wasn't executed was probably never an instruction
+            let all_code = (0..line.len() as u32)
+                .all(|i| addr + i <= 0xFFFF && classify((addr + i) as u16) == ByteKind::Code);
+            if line.instruction.is_some() && all_code {
+                addr += line.len() as u32;
+                lines.push(line);
+                continue;
+            }
+        }
+
+        let data_kind = if kind == ByteKind::Unknown {
+            ByteKind::Unknown
+        } else {
+            ByteKind::Data
+        };
+        let mut bytes = Vec::new();
+        while addr <= end as u32 && bytes.len() < DATA_LINE_LENGTH {
+            let kind = classify(addr as u16);
+            let same_kind = match data_kind {
+                ByteKind::Unknown => kind == ByteKind::Unknown,
+                _ => kind == ByteKind::Data || (kind == ByteKind::Code && bytes.is_empty()),
+            };
+            let Some(byte) = memory.peek(addr as u16).filter(|_| same_kind) else {
+                break;
+            };
+            bytes.push(byte);
+            addr += 1;
+        }
+        if bytes.is_empty() {
+            break;
+        }
+        let mut line = data_line((addr - bytes.len() as u32) as u16, bytes);
+        if data_kind == ByteKind::Unknown {
+            line.annotation = Some("not accessed".to_string());
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/**
+ * Bytes shown as a .byte directive rather than an instruction
+ */
+pub fn data_line(addr: u16, bytes: Vec<u8>) -> Line {
+    Line {
+        addr,
+        text: byte_directive(&bytes),
+        bytes,
+        instruction: None,
+        annotation: None,
+    }
+}
+
+fn operand(bytes: &[u8]) -> u16 {
+    match bytes[..] {
+        [_, lo, hi] => u16::from_le_bytes([lo, hi]),
+        [_, lo] => lo as u16,
+        _ => 0,
+    }
+}
+
+// label replaces the address the operand refers to
+fn format_operand(mode: Mode, addr: u16, operand: u16, label: Option<&str>) -> String {
+    use Mode::*;
+
+    let zp = || label.map_or_else(|| format!("${operand:02X}"), |l| l.to_string());
+    let abs = || label.map_or_else(|| format!("${operand:04X}"), |l| l.to_string());
+    // ca65 would pick zero page for an absolute address under 0x100
+    // unless it's told not to
+    let force_abs = if operand < 0x100 { "a:" } else { "" };
+    match mode {
+        Imp | Status | SP | X | Y => String::new(),
+        A => " a".to_string(),
+        Imm => format!(" #${operand:02X}"),
+        Zp => format!(" {}", zp()),
+        Zpx => format!(" {},x", zp()),
+        Zpy => format!(" {},y", zp()),
+        Abs => format!(" {force_abs}{}", abs()),
+        AbsX => format!(" {force_abs}{},x", abs()),
+        AbsY => format!(" {force_abs}{},y", abs()),
+        AbsInd => format!(" ({})", abs()),
+        IndX => format!(" ({},x)", zp()),
+        IndY => format!(" ({}),y", zp()),
+        Rel => match label {
+            Some(label) => format!(" {label}"),
+            None => format!(" ${:04X}", branch_target(addr, operand as u8)),
+        },
+    }
+}
+
+/**
+ * Where a branch at addr goes when it's taken
+ */
+pub fn branch_target(addr: u16, offset: u8) -> u16 {
+    addr.wrapping_add(2).wrapping_add(offset as i8 as u16)
+}
+
+fn annotate(
+    memory: &dyn MemorySource,
+    instruction: Instruction,
+    mode: Mode,
+    operand: u16,
+    registers: IndexRegisters,
+) -> Option<String> {
+    use Mode::*;
+
+    let read_word =
+        |lo: u16, hi: u16| Some(u16::from_le_bytes([memory.peek(lo)?, memory.peek(hi)?]));
+    let value = |addr: u16| match memory.peek(addr) {
+        Some(value) => format!(" = ${value:02X}"),
+        None => String::new(),
+    };
+
+    let annotation = match mode {
+        // the operand is where execution goes, not data
+        Abs if matches!(instruction, Instruction::JMP | Instruction::JSR) => return None,
+        Zp | Abs => format!("= ${:02X}", memory.peek(operand)?),
+        Zpx => indexed(operand.wrapping_add(registers.x as u16) & 0xFF, &value),
+        Zpy => indexed(operand.wrapping_add(registers.y as u16) & 0xFF, &value),
+        AbsX => indexed(operand.wrapping_add(registers.x as u16), &value),
+        AbsY => indexed(operand.wrapping_add(registers.y as u16), &value),
+        // the pointer's high byte comes from the same page
+        AbsInd => {
+            let hi = (operand & 0xFF00) | (operand.wrapping_add(1) & 0x00FF);
+            format!("= ${:04X}", read_word(operand, hi)?)
+        }
+        IndX => {
+            let pointer = (operand as u8).wrapping_add(registers.x);
+            let addr = read_word(pointer as u16, pointer.wrapping_add(1) as u16)?;
+            indexed(addr, &value)
+        }
+        IndY => {
+            let pointer = operand as u8;
+            let base = read_word(pointer as u16, pointer.wrapping_add(1) as u16)?;
+            indexed(base.wrapping_add(registers.y as u16), &value)
+        }
+        A | Imm | Imp | Rel | Status | SP | X | Y => return None,
+    };
+    Some(annotation)
+}
+
+fn indexed(addr: u16, value: &dyn Fn(u16) -> String) -> String {
+    format!("@ ${addr:04X}{}", value(addr))
+}
+
+fn byte_directive(bytes: &[u8]) -> String {
+    let bytes = bytes
+        .iter()
+        .map(|b| format!("${b:02X}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(".byte {bytes}")
+}
@@ -1,6 +1,6 @@
-use strum_macros::Display;
+use strum_macros::{Display, FromRepr};
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Display)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Display, FromRepr)]
 pub enum Instruction {
     ADC,
     AND,
@@ -86,7 +86,7 @@ pub enum Instruction {
     XXA,
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, FromRepr)]
 pub enum Mode {
     Abs,
     AbsX,
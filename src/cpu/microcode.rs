@@ -0,0 +1,522 @@
+// source https://www.nesdev.org/6502_cpu.txt
+// Each instruction is broken down into the micro operations that happen on each
+// cycle after the opcode fetch. Every micro operation does exactly one bus access,
+// so reads and writes land on the same cycle as they do on the real chip.
+
+use crate::cpu::flags::StatusFlags;
+use crate::cpu::instructions::{Instruction, Mode};
+use crate::cpu::monitor::InterruptKind;
+use crate::cpu::{
+    Branch, CPU, HIGH_BYTE_MASK, IRQ_ADDR, LOW_BYTE_MASK, NMI_ADDR, PageBoundary, RESET_ADDR,
+};
+
+pub type MicroOp = fn(&mut CPU);
+
+// the longest instructions are the 8 cycle indirect read/modify/write ones
+const MAX_MICRO_OPS: usize = 7;
+
+#[derive(Copy, Clone)]
+pub struct MicroProgram {
+    ops: [MicroOp; MAX_MICRO_OPS],
+    len: u8,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Access {
+    Read,
+    Write,
+    ReadModifyWrite,
+}
+
+impl MicroProgram {
+    pub fn new(instruction: Instruction, mode: Mode) -> Self {
+        use Instruction::*;
+
+        match instruction {
+            BRK => Self::from(&[
+                CPU::read_padding_byte,
+                CPU::push_pc_high,
+                CPU::push_pc_low,
+                CPU::push_status_for_interrupt,
+                CPU::read_vector_low,
+                CPU::read_vector_high,
+            ]),
+            IRQ | NMI => Self::from(&[
+                CPU::dummy_read_pc,
+                CPU::push_pc_high,
+                CPU::push_pc_low,
+                CPU::push_status_for_interrupt,
+                CPU::read_vector_low,
+                CPU::read_vector_high,
+            ]),
+            // reset goes through the motions of an interrupt, but the stack writes are reads
+            RST => Self::from(&[
+                CPU::dummy_read_pc,
+                CPU::dummy_push,
+                CPU::dummy_push,
+                CPU::dummy_push,
+                CPU::read_vector_low,
+                CPU::read_vector_high,
+            ]),
+            JMP if mode == Mode::AbsInd => Self::from(&[
+                CPU::fetch_address_low,
+                CPU::fetch_address_high,
+                CPU::read_indirect_low,
+                CPU::jump_indirect,
+            ]),
+            JMP => Self::from(&[CPU::fetch_address_low, CPU::jump_absolute]),
+            JSR => Self::from(&[
+                CPU::fetch_address_low,
+                CPU::dummy_read_stack,
+                CPU::push_pc_high,
+                CPU::push_pc_low,
+                CPU::jump_subroutine,
+            ]),
+            RTS => Self::from(&[
+                CPU::dummy_read_pc,
+                CPU::dummy_read_stack,
+                CPU::pull_pc_low,
+                CPU::pull_pc_high,
+                CPU::increment_pc,
+            ]),
+            RTI => Self::from(&[
+                CPU::dummy_read_pc,
+                CPU::dummy_read_stack,
+                CPU::pull_status,
+                CPU::pull_pc_low,
+                CPU::pull_pc_high,
+            ]),
+            PHA => Self::from(&[CPU::dummy_read_pc, CPU::push_accumulator]),
+            PHP => Self::from(&[CPU::dummy_read_pc, CPU::push_status]),
+            PLA => Self::from(&[
+                CPU::dummy_read_pc,
+                CPU::dummy_read_stack,
+                CPU::pull_accumulator,
+            ]),
+            PLP => Self::from(&[CPU::dummy_read_pc, CPU::dummy_read_stack, CPU::pull_status]),
+            BCC | BCS | BEQ | BMI | BNE | BPL | BVC | BVS => Self::from(&[
+                CPU::fetch_branch_offset,
+                CPU::take_branch,
+                CPU::fix_branch_page,
+            ]),
+            JAM => Self::from(&[CPU::jam]),
+            _ => Self::addressed(access(instruction), mode),
+        }
+    }
+
+    fn addressed(access: Access, mode: Mode) -> Self {
+        use Mode::*;
+
+        let mut program = match mode {
+            Imp => return Self::from(&[CPU::implied]),
+            A => return Self::from(&[CPU::modify_accumulator]),
+            Imm => return Self::from(&[CPU::read_immediate]),
+            Zp => Self::from(&[CPU::fetch_address_low]),
+            Zpx => Self::from(&[CPU::fetch_address_low, CPU::index_zero_page_x]),
+            Zpy => Self::from(&[CPU::fetch_address_low, CPU::index_zero_page_y]),
+            Abs => Self::from(&[CPU::fetch_address_low, CPU::fetch_address_high]),
+            IndX => Self::from(&[
+                CPU::fetch_pointer,
+                CPU::index_pointer_x,
+                CPU::read_pointer_low,
+                CPU::read_pointer_high,
+            ]),
+            AbsX => {
+                let program =
+                    Self::from(&[CPU::fetch_address_low, CPU::fetch_address_high_index_x]);
+                return program.indexed(access);
+            }
+            AbsY => {
+                let program =
+                    Self::from(&[CPU::fetch_address_low, CPU::fetch_address_high_index_y]);
+                return program.indexed(access);
+            }
+            IndY => {
+                let program = Self::from(&[
+                    CPU::fetch_pointer,
+                    CPU::read_pointer_low,
+                    CPU::read_pointer_high_index_y,
+                ]);
+                return program.indexed(access);
+            }
+            _ => unreachable!("{:?} can't be used to address memory", mode),
+        };
+
+        match access {
+            Access::Read => program.push(CPU::read_operand),
+            Access::Write => program.push(CPU::write_operand),
+            Access::ReadModifyWrite => program.push_read_modify_write(),
+        }
+        program
+    }
+
+    // the high byte of an indexed address is fixed up a cycle after the low byte is
+    // added, so the cpu reads from the wrong page first. Reads get to skip fixing it
+    // if the page wasn't crossed
+    fn indexed(mut self, access: Access) -> Self {
+        match access {
+            Access::Read => {
+                self.push(CPU::read_operand_or_fix_page);
+                self.push(CPU::read_operand);
+            }
+            Access::Write => {
+                self.push(CPU::dummy_read_fix_page);
+                self.push(CPU::write_operand);
+            }
+            Access::ReadModifyWrite => {
+                self.push(CPU::dummy_read_fix_page);
+                self.push_read_modify_write();
+            }
+        }
+        self
+    }
+
+    fn push_read_modify_write(&mut self) {
+        self.push(CPU::read_modify_operand);
+        self.push(CPU::dummy_write_operand);
+        self.push(CPU::write_modified_operand);
+    }
+
+    fn from(ops: &[MicroOp]) -> Self {
+        let mut program = Self {
+            ops: [CPU::jam; MAX_MICRO_OPS],
+            len: 0,
+        };
+        for op in ops {
+            program.push(*op);
+        }
+        program
+    }
+
+    fn push(&mut self, op: MicroOp) {
+        self.ops[self.len as usize] = op;
+        self.len += 1;
+    }
+
+    pub fn len(&self) -> u8 {
+        self.len
+    }
+
+    pub fn op(&self, index: u8) -> MicroOp {
+        self.ops[index as usize]
+    }
+}
+
+fn access(instruction: Instruction) -> Access {
+    use Instruction::*;
+
+    match instruction {
+        STA | STX | STY | SAX | SHA | SHS | SHX | SHY => Access::Write,
+        ASL | DEC | INC | LSR | ROL | ROR | DCP | ISC | RLA | RRA | SLO | SRE => {
+            Access::ReadModifyWrite
+        }
+        _ => Access::Read,
+    }
+}
+
+impl CPU {
+    fn dummy_read_pc(&mut self) {
+        self.read_bus_byte(self.pc);
+    }
+
+    fn dummy_read_stack(&mut self) {
+        self.read_bus_byte(self.stack_address());
+    }
+
+    fn read_padding_byte(&mut self) {
+        self.read_bus_byte(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+    }
+
+    fn implied(&mut self) {
+        self.dummy_read_pc();
+        self.execute_implied();
+    }
+
+    fn jam(&mut self) {
+        self.jammed = true;
+    }
+
+    fn read_immediate(&mut self) {
+        self.data = self.fetch_byte();
+        self.execute_read();
+    }
+
+    fn modify_accumulator(&mut self) {
+        self.dummy_read_pc();
+        self.a = self.execute_read_modify_write(self.a);
+    }
+
+    fn fetch_address_low(&mut self) {
+        self.address = self.fetch_byte() as u16;
+    }
+
+    fn fetch_address_high(&mut self) {
+        self.address |= (self.fetch_byte() as u16) << 8;
+    }
+
+    fn fetch_address_high_index_x(&mut self) {
+        let high = self.fetch_byte();
+        self.index_address(high, self.x);
+    }
+
+    fn fetch_address_high_index_y(&mut self) {
+        let high = self.fetch_byte();
+        self.index_address(high, self.y);
+    }
+
+    fn index_address(&mut self, high: u8, index: u8) {
+        let (low, carry) = (self.address as u8).overflowing_add(index);
+        self.address = CPU::to_word(low, high);
+        self.page_boundary = if carry {
+            PageBoundary::Crossed
+        } else {
+            PageBoundary::NotCrossed
+        };
+    }
+
+    fn index_zero_page_x(&mut self) {
+        self.read_bus_byte(self.address);
+        self.address = (self.address as u8).wrapping_add(self.x) as u16;
+    }
+
+    fn index_zero_page_y(&mut self) {
+        self.read_bus_byte(self.address);
+        self.address = (self.address as u8).wrapping_add(self.y) as u16;
+    }
+
+    fn fetch_pointer(&mut self) {
+        self.pointer = self.fetch_byte();
+    }
+
+    fn index_pointer_x(&mut self) {
+        self.read_bus_byte(self.pointer as u16);
+        self.pointer = self.pointer.wrapping_add(self.x);
+    }
+
+    fn read_pointer_low(&mut self) {
+        self.address = self.read_data_byte(self.pointer as u16) as u16;
+    }
+
+    fn read_pointer_high(&mut self) {
+        let high = self.read_data_byte(self.pointer.wrapping_add(1) as u16);
+        self.address |= (high as u16) << 8;
+    }
+
+    fn read_pointer_high_index_y(&mut self) {
+        let high = self.read_data_byte(self.pointer.wrapping_add(1) as u16);
+        self.index_address(high, self.y);
+    }
+
+    fn fix_page(&mut self) {
+        if self.page_boundary == PageBoundary::Crossed {
+            self.address = self.address.wrapping_add(0x0100);
+        }
+    }
+
+    fn dummy_read_fix_page(&mut self) {
+        self.read_bus_byte(self.address);
+        self.fix_page();
+    }
+
+    fn read_operand_or_fix_page(&mut self) {
+        if self.page_boundary == PageBoundary::Crossed {
+            self.dummy_read_fix_page();
+        } else {
+            self.read_operand();
+            self.end_instruction();
+        }
+    }
+
+    fn read_operand(&mut self) {
+        self.data = self.read_data_byte(self.address);
+        self.execute_read();
+    }
+
+    fn read_modify_operand(&mut self) {
+        self.data = self.read_data_byte(self.address);
+    }
+
+    fn write_operand(&mut self) {
+        let value = self.execute_write();
+        self.write_data_byte(self.address, value);
+    }
+
+    fn dummy_write_operand(&mut self) {
+        self.write_bus_byte(self.address, self.data);
+    }
+
+    fn write_modified_operand(&mut self) {
+        let value = self.execute_read_modify_write(self.data);
+        self.write_data_byte(self.address, value);
+    }
+
+    fn fetch_branch_offset(&mut self) {
+        self.data = self.fetch_byte();
+        if self.branch_condition() {
+            // taken branches only poll for interrupts before this cycle and, if the page
+            // is crossed, before fixing the high byte
+            self.skip_interrupt_poll = true;
+        } else {
+            self.end_instruction();
+        }
+    }
+
+    fn take_branch(&mut self) {
+        self.dummy_read_pc();
+        self.branch = Branch::Taken;
+        let target = self.pc.wrapping_add(self.data as i8 as u16);
+        self.check_trap(target, 2);
+        // the low byte is added first and the high byte fixed on the next cycle
+        self.address = target;
+        self.pc = (self.pc & HIGH_BYTE_MASK) | (target & LOW_BYTE_MASK);
+        if self.pc == target {
+            self.end_instruction();
+        } else {
+            self.page_boundary = PageBoundary::Crossed;
+        }
+    }
+
+    fn fix_branch_page(&mut self) {
+        self.dummy_read_pc();
+        self.pc = self.address;
+    }
+
+    fn jump_absolute(&mut self) {
+        self.fetch_address_high();
+        self.check_trap(self.address, 3);
+        self.pc = self.address;
+        self.branch = Branch::Taken;
+    }
+
+    fn read_indirect_low(&mut self) {
+        self.data = self.read_data_byte(self.address);
+    }
+
+    fn jump_indirect(&mut self) {
+        // the 6502 has a bug where instead of incrementing the full address before
+        // reading the the next byte, it only increments the low byte of the address.
+        // Weird? yes. Hence never "JMP ($xxFF)" because what happens will be weird as it
+        // will read the address from $xxFF and then $xx00
+        let high_addr =
+            (self.address & HIGH_BYTE_MASK) | ((self.address as u8).wrapping_add(1) as u16);
+        let target = CPU::to_word(self.data, self.read_data_byte(high_addr));
+        if (self.address & HIGH_BYTE_MASK) != (target & HIGH_BYTE_MASK) {
+            self.page_boundary = PageBoundary::Crossed;
+        }
+        self.check_trap(target, 3);
+        self.pc = target;
+        self.branch = Branch::Taken;
+    }
+
+    fn jump_subroutine(&mut self) {
+        self.fetch_address_high();
+        self.pc = self.address;
+        self.branch = Branch::Taken;
+    }
+
+    fn push_pc_high(&mut self) {
+        self.push_byte(CPU::high_byte(self.pc));
+    }
+
+    fn push_pc_low(&mut self) {
+        self.push_byte(CPU::low_byte(self.pc));
+    }
+
+    fn push_accumulator(&mut self) {
+        self.push_byte(self.a);
+    }
+
+    fn push_status(&mut self) {
+        self.push_byte(self.status.bits());
+    }
+
+    fn push_status_for_interrupt(&mut self) {
+        let status = if self.instruction == Instruction::BRK {
+            self.status | StatusFlags::Break
+        } else {
+            self.status & !StatusFlags::Break
+        };
+        self.push_byte(status.bits());
+
+        // an NMI that is detected before now hijacks a BRK or IRQ. The pushed
+        // status is unchanged, but the NMI vector is used
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            self.instruction = Instruction::NMI;
+        }
+    }
+
+    fn dummy_push(&mut self) {
+        self.dummy_read_stack();
+        self.sp = self.sp.wrapping_sub(1);
+    }
+
+    fn pull_accumulator(&mut self) {
+        let value = self.pop_byte();
+        self.a = value;
+        self.set_negative_and_zero(value);
+    }
+
+    fn pull_status(&mut self) {
+        // in the real 6502, break and unused aren't
+        // actually flags. But when pushed on the
+        // stack Unused is always set and break is
+        // set except on NMI or IRQ. The easiest way
+        // to make that happen is to set Break and Unused
+        // flags on power up and on pop
+        let value = self.pop_byte() | (StatusFlags::Break | StatusFlags::Unused).bits();
+        self.status = StatusFlags::from_bits_truncate(value);
+    }
+
+    fn pull_pc_low(&mut self) {
+        self.data = self.pop_byte();
+    }
+
+    fn pull_pc_high(&mut self) {
+        let high = self.pop_byte();
+        self.pc = CPU::to_word(self.data, high);
+    }
+
+    fn increment_pc(&mut self) {
+        self.dummy_read_pc();
+        self.pc = self.pc.wrapping_add(1);
+    }
+
+    fn read_vector_low(&mut self) {
+        self.set_flag(StatusFlags::InterruptDisable, true);
+        self.data = self.read_bus_byte(self.vector());
+    }
+
+    fn read_vector_high(&mut self) {
+        let high = self.read_bus_byte(self.vector().wrapping_add(1));
+        self.pc = CPU::to_word(self.data, high);
+
+        let kind = match self.instruction {
+            Instruction::NMI => InterruptKind::NMI,
+            Instruction::RST => InterruptKind::RST,
+            Instruction::BRK => InterruptKind::BRK,
+            _ => InterruptKind::IRQ,
+        };
+        let result = self.monitor.interrupt(self.cycles, kind, self.pc);
+        self.check_monitor(result);
+    }
+
+    fn vector(&self) -> u16 {
+        match self.instruction {
+            Instruction::NMI => NMI_ADDR,
+            Instruction::RST => RESET_ADDR,
+            _ => IRQ_ADDR,
+        }
+    }
+
+    // a jmp back to itself is the most common form of
+    // trap used in tests. Another common one is a conditional
+    // branch relative back to the same spot. Detecting
+    // these conditions should make it easier to run tests to completion
+    fn check_trap(&mut self, target: u16, instruction_length: u16) {
+        if self.pc.wrapping_sub(instruction_length) == target {
+            self.trapped = true;
+        }
+    }
+}
@@ -2,6 +2,10 @@ use std::any::Any;
 
 use anyhow::{Ok, Result};
 
+/**
+ * Watches the cpu run. An error from any of the callbacks halts the cpu, see
+ * CPU::monitor_error. Only the instruction callbacks have to be implemented.
+ */
 pub trait Monitor: Any {
     #[allow(clippy::too_many_arguments)]
     fn new_instruction(
@@ -16,11 +20,67 @@ pub trait Monitor: Any {
     ) -> Result<()>;
     fn fetch_instruction_byte(&mut self, byte: u8) -> Result<()>;
     fn read_data_byte(&mut self, addr: u16, data: u8) -> Result<()>;
+    /**
+     * Called after the write, with the value the location held before it
+     */
+    fn write_data_byte(&mut self, addr: u16, old: u8, data: u8) -> Result<()>;
     fn end_instruction(&mut self) -> Result<()>;
 
+    /**
+     * Called for every write on the bus, including stack pushes and
+     * writes made by DMA, with the value the location held before it
+     */
+    #[allow(unused_variables)]
+    fn bus_write(&mut self, addr: u16, old: u8, data: u8) -> Result<()> {
+        Ok(())
+    }
+
+    /**
+     * Called when the cpu reads the interrupt vector, with the address of the
+     * handler it jumps to. An NMI that hijacks a BRK or IRQ is reported as an NMI.
+     */
+    #[allow(unused_variables)]
+    fn interrupt(&mut self, cycle: usize, kind: InterruptKind, handler: u16) -> Result<()> {
+        Ok(())
+    }
+
+    /**
+     * Called for every cycle the cpu spends halted by RDY while DMA uses the bus.
+     * These cycles aren't counted in CPU::cycles.
+     */
+    #[allow(unused_variables)]
+    fn dma_cycle(&mut self, cycle: usize, halted_at: u16) -> Result<()> {
+        Ok(())
+    }
+
+    /**
+     * Called when DMA fetches a DMC sample byte
+     */
+    #[allow(unused_variables)]
+    fn sample_read(&mut self, addr: u16, data: u8) -> Result<()> {
+        Ok(())
+    }
+
+    /**
+     * Called at the end of every cycle the cpu runs
+     */
+    #[allow(unused_variables)]
+    fn cycle(&mut self, cycle: usize) -> Result<()> {
+        Ok(())
+    }
+
     fn as_any(&mut self) -> &mut dyn Any;
 }
 
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum InterruptKind {
+    IRQ,
+    NMI,
+    RST,
+    BRK,
+}
+
 pub struct NulMonitor {}
 
 #[allow(unused_variables)]
@@ -45,6 +105,10 @@ impl Monitor for NulMonitor {
         Ok(())
     }
 
+    fn write_data_byte(&mut self, addr: u16, old: u8, data: u8) -> Result<()> {
+        Ok(())
+    }
+
     fn end_instruction(&mut self) -> Result<()> {
         Ok(())
     }
@@ -53,3 +117,121 @@ impl Monitor for NulMonitor {
         self
     }
 }
+
+/**
+ * Passes every event on to several monitors, in the order they were added.
+ * All of them see the event even when one fails, and the first error is returned.
+ */
+#[derive(Default)]
+pub struct CompositeMonitor {
+    monitors: Vec<Box<dyn Monitor>>,
+}
+
+impl CompositeMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, monitor: Box<dyn Monitor>) {
+        self.monitors.push(monitor);
+    }
+
+    /**
+     * Takes out the monitors of type T
+     */
+    pub fn remove<T: Monitor>(&mut self) -> Vec<Box<dyn Monitor>> {
+        let mut removed = Vec::new();
+        let mut i = 0;
+        while i < self.monitors.len() {
+            if self.monitors[i].as_any().is::<T>() {
+                removed.push(self.monitors.remove(i));
+            } else {
+                i += 1;
+            }
+        }
+        removed
+    }
+
+    /**
+     * The first monitor of type T
+     */
+    pub fn get_mut<T: Monitor>(&mut self) -> Option<&mut T> {
+        self.monitors
+            .iter_mut()
+            .find_map(|m| m.as_any().downcast_mut::<T>())
+    }
+
+    pub fn len(&self) -> usize {
+        self.monitors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.monitors.is_empty()
+    }
+
+    fn each(&mut self, mut event: impl FnMut(&mut dyn Monitor) -> Result<()>) -> Result<()> {
+        let mut result = Ok(());
+        for monitor in self.monitors.iter_mut() {
+            let r = event(monitor.as_mut());
+            if result.is_ok() {
+                result = r;
+            }
+        }
+        result
+    }
+}
+
+impl Monitor for CompositeMonitor {
+    fn new_instruction(
+        &mut self,
+        cycle: usize,
+        pc: u16,
+        sp: u8,
+        a: u8,
+        x: u8,
+        y: u8,
+        status: u8,
+    ) -> Result<()> {
+        self.each(|m| m.new_instruction(cycle, pc, sp, a, x, y, status))
+    }
+
+    fn fetch_instruction_byte(&mut self, byte: u8) -> Result<()> {
+        self.each(|m| m.fetch_instruction_byte(byte))
+    }
+
+    fn read_data_byte(&mut self, addr: u16, data: u8) -> Result<()> {
+        self.each(|m| m.read_data_byte(addr, data))
+    }
+
+    fn write_data_byte(&mut self, addr: u16, old: u8, data: u8) -> Result<()> {
+        self.each(|m| m.write_data_byte(addr, old, data))
+    }
+
+    fn end_instruction(&mut self) -> Result<()> {
+        self.each(|m| m.end_instruction())
+    }
+
+    fn bus_write(&mut self, addr: u16, old: u8, data: u8) -> Result<()> {
+        self.each(|m| m.bus_write(addr, old, data))
+    }
+
+    fn interrupt(&mut self, cycle: usize, kind: InterruptKind, handler: u16) -> Result<()> {
+        self.each(|m| m.interrupt(cycle, kind, handler))
+    }
+
+    fn dma_cycle(&mut self, cycle: usize, halted_at: u16) -> Result<()> {
+        self.each(|m| m.dma_cycle(cycle, halted_at))
+    }
+
+    fn sample_read(&mut self, addr: u16, data: u8) -> Result<()> {
+        self.each(|m| m.sample_read(addr, data))
+    }
+
+    fn cycle(&mut self, cycle: usize) -> Result<()> {
+        self.each(|m| m.cycle(cycle))
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -0,0 +1,138 @@
+use crate::cpu::assembler::{assemble, assemble_with, opcode};
+use crate::cpu::disassembler::{Bank, disassemble_one};
+use crate::cpu::instructions::{Instruction, Mode};
+
+fn bytes(source: &str) -> Vec<u8> {
+    assemble(source, 0xC000).unwrap()
+}
+
+#[test]
+fn test_modes() {
+    assert_eq!(vec![0x00], bytes("brk"));
+    assert_eq!(vec![0x0A], bytes("asl a"));
+    assert_eq!(vec![0x0A], bytes("asl"));
+    assert_eq!(vec![0xA9, 0x40], bytes("lda #$40"));
+    assert_eq!(vec![0xA5, 0x10], bytes("lda $10"));
+    assert_eq!(vec![0xB5, 0x10], bytes("lda $10,x"));
+    assert_eq!(vec![0xB6, 0x10], bytes("ldx $10,y"));
+    assert_eq!(vec![0xAD, 0x00, 0x02], bytes("lda $0200"));
+    assert_eq!(vec![0xBD, 0x00, 0x02], bytes("lda $0200,x"));
+    assert_eq!(vec![0xB9, 0x00, 0x02], bytes("lda $0200,y"));
+    assert_eq!(vec![0x6C, 0x00, 0x02], bytes("jmp ($0200)"));
+    assert_eq!(vec![0xA1, 0x10], bytes("lda ($10,x)"));
+    assert_eq!(vec![0xB1, 0x10], bytes("lda ($10),y"));
+    assert_eq!(vec![0xD0, 0x02], bytes("bne $C004"));
+    assert_eq!(vec![0xD0, 0xFC], bytes("bne $BFFE"));
+}
+
+#[test]
+fn test_address_sizes() {
+    // zero page unless told otherwise or there's no zero page mode
+    assert_eq!(vec![0xA5, 0x10], bytes("lda $0010"));
+    assert_eq!(vec![0xAD, 0x10, 0x00], bytes("lda a:$10"));
+    assert_eq!(vec![0xB9, 0x10, 0x00], bytes("lda $10,y"));
+    assert_eq!(vec![0x20, 0x10, 0x00], bytes("jsr $10"));
+    assert!(assemble("lda z:$0200,y", 0).is_err());
+}
+
+#[test]
+fn test_syntax() {
+    assert_eq!(vec![0xA9, 0x0A], bytes("  LDA #10  ; ten"));
+    assert_eq!(vec![0xA9, 0x05], bytes("lda #%101"));
+    assert_eq!(
+        vec![0xA2, 0x34, 0xA0, 0x12],
+        bytes("ldx #<$1234\nldy #>$1234")
+    );
+    assert_eq!(vec![0x4C, 0x00, 0xC0], bytes("jmp *"));
+    assert_eq!(vec![0xAD, 0x02, 0x02], bytes("lda $0200 + 4 - 2"));
+}
+
+#[test]
+fn test_unofficial_instructions() {
+    assert_eq!(vec![0xA7, 0x10], bytes("lax $10"));
+    assert_eq!(vec![0x4B, 0x0F], bytes("asr #$0F"));
+    assert_eq!(vec![0x4B, 0x0F], bytes("alr #$0F"));
+    assert_eq!(vec![0xCB, 0x01], bytes("axs #1"));
+    assert_eq!(vec![0xE7, 0x10], bytes("isb $10"));
+    // the documented opcode comes first, then the lowest
+    assert_eq!(vec![0xEA], bytes("nop"));
+    assert_eq!(vec![0xE9, 0x01], bytes("sbc #1"));
+    assert_eq!(vec![0x04, 0x10], bytes("nop $10"));
+    assert_eq!(Some(0x02), opcode(Instruction::JAM, Mode::Imp));
+}
+
+#[test]
+fn test_labels_and_data() {
+    let source = "
+        count = 3
+        start:
+            ldx #count
+        loop: dex
+            bne loop
+            jmp end
+        table: .byte 1, $02, \"AB\"
+            .word start, table
+        end: rts
+    ";
+    assert_eq!(
+        vec![
+            0xA2, 0x03, // ldx #count
+            0xCA, // dex
+            0xD0, 0xFD, // bne loop
+            0x4C, 0x10, 0xC0, // jmp end
+            0x01, 0x02, b'A', b'B', // .byte
+            0x00, 0xC0, 0x08, 0xC0, // .word
+            0x60, // rts
+        ],
+        bytes(source)
+    );
+}
+
+#[test]
+fn test_resolved_names() {
+    let resolve = |name: &str| (name == "player_x").then_some(0x0040);
+    assert_eq!(
+        vec![0xA5, 0x40, 0x8D, 0x41, 0x00],
+        assemble_with("lda player_x\nsta a:player_x+1", 0, &resolve).unwrap()
+    );
+}
+
+#[test]
+fn test_errors() {
+    let error = |source: &str| assemble(source, 0xC000).unwrap_err().to_string();
+
+    assert_eq!(
+        "Line 2: 'foo' isn't an instruction or directive",
+        error("nop\nfoo")
+    );
+    assert_eq!(
+        "Line 1: 'jmp $10,x' doesn't have that addressing mode",
+        error("jmp $10,x")
+    );
+    assert_eq!("Line 1: 'nowhere' isn't defined", error("jmp nowhere"));
+    assert_eq!("Line 2: 'a' is already defined", error("a: nop\na: nop"));
+    assert_eq!("Line 1: $100 doesn't fit in a byte", error("lda #$100"));
+    assert_eq!("Line 1: '$' isn't understood", error("lda #$"));
+    assert_eq!(
+        "Line 2: $C100 is too far away to branch to",
+        error("nop\nbne $C100")
+    );
+}
+
+#[test]
+fn test_disassembly_assembles_back() {
+    for op in 0..=0xFF {
+        let memory = [op, 0x34, 0x12];
+        let bank = Bank {
+            base: 0xC000,
+            bytes: &memory,
+        };
+        let line = disassemble_one(&bank, 0xC000, None);
+        assert_eq!(
+            line.bytes,
+            assemble(&line.source(), 0xC000).unwrap(),
+            "{op:02X} {}",
+            line.source()
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{
+    bus::{BusDevice, InterruptFlags},
+    cpu::{assembler::assemble, *},
+    ram::RAM,
+};
+
+use instructions::Instruction::*;
+
+/**
+ * RAM that remembers every access made to it
+ */
+struct RecordingRAM {
+    ram: RAM,
+    accesses: Vec<(CPUCycleType, u16, u8)>,
+}
+
+impl BusDevice for RecordingRAM {
+    fn get_address_range(&self) -> (u16, u16) {
+        (0x0000, 0xFFFF)
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        let data = self.ram.read(addr);
+        self.accesses.push((CPUCycleType::Read, addr, data));
+        data
+    }
+
+    fn write(&mut self, addr: u16, data: u8) -> u8 {
+        self.accesses.push((CPUCycleType::Write, addr, data));
+        self.ram.write(addr, data)
+    }
+
+    fn bus_clock(&mut self) -> InterruptFlags {
+        InterruptFlags::empty()
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.ram.peek(addr)
+    }
+
+    fn poke(&mut self, addr: u16, data: u8) {
+        self.ram.poke(addr, data);
+    }
+}
+
+fn run_program(program: &[u8], x: u8) -> (CPU, Vec<(CPUCycleType, u16, u8)>) {
+    let mem = Rc::new(RefCell::new(RecordingRAM {
+        ram: RAM::new(0x0000, 0xFFFF, 0xFFFF),
+        accesses: Vec::new(),
+    }));
+    let mut cpu = CPU::default();
+    cpu.add_device(mem.clone());
+    cpu.reset_to(0x0200);
+    cpu.x = x;
+    for (i, byte) in program.iter().enumerate() {
+        cpu.write_bus_byte(0x0200 + i as u16, *byte);
+    }
+    mem.borrow_mut().accesses.clear();
+
+    let mut cycle_types = Vec::new();
+    cycle_types.push(cpu.clock());
+    while cpu.step != 0 {
+        cycle_types.push(cpu.clock());
+    }
+
+    let accesses = mem.borrow_mut().accesses.clone();
+    assert_eq!(
+        cycle_types,
+        accesses.iter().map(|a| a.0).collect::<Vec<_>>(),
+        "one bus access per cycle"
+    );
+    (cpu, accesses)
+}
+
+fn run_source(source: &str, x: u8) -> (CPU, Vec<(CPUCycleType, u16, u8)>) {
+    run_program(&assemble(source, 0x0200).unwrap(), x)
+}
+
+#[test]
+fn test_cycle_counts_match_decode() {
+    for op in 0..=0xFF {
+        let (instruction, _, cycles, _) = decode::decode(op);
+        if instruction == JAM {
+            continue;
+        }
+        // operands of 1 keep indexing on the same page and make branches land past pc
+        let (cpu, accesses) = run_program(&[op, 0x01, 0x01], 0);
+        let extra = match instruction {
+            // decode counts jumps like a taken branch, so they're listed a cycle short
+            JMP | JSR => 1,
+            BCC | BCS | BEQ | BMI | BNE | BPL | BVC | BVS if cpu.pc == 0x0203 => 1,
+            _ => 0,
+        };
+
+        assert_eq!(cycles + extra, accesses.len() as u8, "opcode {:#04x}", op);
+    }
+}
+
+#[test]
+fn test_read_modify_write_indexed() {
+    use CPUCycleType::*;
+
+    let (_, accesses) = run_source("inc $12F0,x", 0x20);
+    assert_eq!(
+        vec![
+            (Read, 0x0200, 0xFE),
+            (Read, 0x0201, 0xF0),
+            (Read, 0x0202, 0x12),
+            // read from the wrong page before the high byte is fixed
+            (Read, 0x1210, 0x00),
+            (Read, 0x1310, 0x00),
+            // the unmodified value is written back first
+            (Write, 0x1310, 0x00),
+            (Write, 0x1310, 0x01),
+        ],
+        accesses
+    );
+}
+
+#[test]
+fn test_indexed_page_crossing() {
+    use CPUCycleType::*;
+
+    // LDA only has the extra read when the page is crossed
+    let (_, accesses) = run_source("lda $1210,x", 0x20);
+    assert_eq!(4, accesses.len());
+    assert_eq!((Read, 0x1230, 0x00), accesses[3]);
+
+    let (_, accesses) = run_source("lda $12F0,x", 0x20);
+    assert_eq!(5, accesses.len());
+    assert_eq!((Read, 0x1210, 0x00), accesses[3]);
+    assert_eq!((Read, 0x1310, 0x00), accesses[4]);
+
+    // STA always does the dummy read
+    let (_, accesses) = run_source("sta $1210,x", 0x20);
+    assert_eq!(
+        vec![(Read, 0x1230, 0x00), (Write, 0x1230, 0x00)],
+        accesses[3..]
+    );
+}
+
+#[test]
+fn test_rdy_only_halts_on_reads() {
+    use CPUCycleType::*;
+
+    let mem = Rc::new(RefCell::new(RecordingRAM {
+        ram: RAM::new(0x0000, 0xFFFF, 0xFFFF),
+        accesses: Vec::new(),
+    }));
+    let mut cpu = CPU::default();
+    cpu.add_device(mem.clone());
+    cpu.reset_to(0x0200);
+    cpu.x = 0x20;
+    // INC $12F0,X then NOP
+    for (i, byte) in [0xFE, 0xF0, 0x12, 0xEA].iter().enumerate() {
+        cpu.write_bus_byte(0x0200 + i as u16, *byte);
+    }
+    for _ in 0..5 {
+        cpu.clock();
+    }
+    mem.borrow_mut().accesses.clear();
+
+    // the two writes still happen, it's the NOP's opcode fetch that halts
+    cpu.set_rdy(false);
+    for _ in 0..3 {
+        cpu.clock();
+    }
+    assert_eq!(Some(0x0203), cpu.halted_at());
+    for _ in 0..10 {
+        cpu.clock();
+    }
+    assert_eq!(3, mem.borrow().accesses.len());
+
+    // the opcode is read again when it's released, then the NOP carries on
+    cpu.set_rdy(true);
+    cpu.clock();
+    cpu.clock();
+    assert_eq!(None, cpu.halted_at());
+    assert_eq!(
+        vec![
+            (Write, 0x1310, 0x00),
+            (Write, 0x1310, 0x01),
+            (Read, 0x0203, 0xEA),
+            (Read, 0x0203, 0xEA),
+            (Read, 0x0204, 0x00),
+        ],
+        mem.borrow().accesses
+    );
+}
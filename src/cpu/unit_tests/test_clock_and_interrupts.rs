@@ -63,6 +63,7 @@ fn test_irq() {
     cpu.write_bus_byte(0xFFFD, 0x12);
     cpu.write_bus_byte(0x1234, 0x78); // SEI
     cpu.write_bus_byte(0x1235, 0x58); // CLI
+    cpu.write_bus_byte(0x1236, 0xEA); // NOP
     cpu.write_bus_byte(0xFFFE, 0xAB);
     cpu.write_bus_byte(0xFFFF, 0x89);
 
@@ -74,11 +75,14 @@ fn test_irq() {
     cpu.run_instruction(); // CLI
     assert_eq!(0x1236, cpu.pc);
     assert!(!cpu.read_flag(StatusFlags::InterruptDisable));
+    // CLI clears the flag after the interrupts were polled, so one more instruction runs
+    cpu.run_instruction(); // NOP
+    assert_eq!(0x1237, cpu.pc);
     cpu.run_instruction();
     assert_eq!(0x89AB, cpu.pc);
     assert_eq!(0xFA, cpu.sp);
     assert_eq!(0x12, cpu.read_bus_byte(0x1FD));
-    assert_eq!(0x36, cpu.read_bus_byte(0x1FC));
+    assert_eq!(0x37, cpu.read_bus_byte(0x1FC));
     let pushed_status = cpu.read_bus_byte(0x1FB);
     assert!((pushed_status & StatusFlags::Break.bits()) == 0);
     assert!((pushed_status & StatusFlags::Unused.bits()) != 0);
@@ -86,3 +90,65 @@ fn test_irq() {
     assert!(cpu.read_flag(StatusFlags::InterruptDisable));
     assert_eq!(0xFA, cpu.sp);
 }
+
+fn create_interrupt_configuration() -> (CPU, Rc<RefCell<Interruptor>>) {
+    let (mut cpu, _mem) = crate::cpu::create_test_configuration();
+    let interruptor = Rc::new(RefCell::new(Interruptor::new()));
+    cpu.add_device(interruptor.clone());
+
+    cpu.write_bus_byte(0xFFFC, 0x34);
+    cpu.write_bus_byte(0xFFFD, 0x12);
+    cpu.write_bus_byte(0xFFFA, 0x67);
+    cpu.write_bus_byte(0xFFFB, 0x45);
+    cpu.write_bus_byte(0xFFFE, 0xAB);
+    cpu.write_bus_byte(0xFFFF, 0x89);
+
+    // the NMI line is high until something pulls it low
+    interruptor.borrow_mut().flags = InterruptFlags::NMI;
+    cpu.reset();
+    cpu.run_instruction();
+    (cpu, interruptor)
+}
+
+#[test]
+fn test_nmi_hijacks_brk() {
+    let (mut cpu, interruptor) = create_interrupt_configuration();
+    cpu.write_bus_byte(0x1234, 0x00); // BRK
+
+    cpu.clock();
+    cpu.clock();
+    cpu.clock();
+    interruptor.borrow_mut().flags = InterruptFlags::empty();
+    cpu.clock(); // NMI detected at the end of the 4th cycle
+    while cpu.step != 0 {
+        cpu.clock();
+    }
+
+    assert_eq!(0x4567, cpu.pc);
+    assert_eq!(0x12, cpu.read_bus_byte(0x1FD));
+    assert_eq!(0x36, cpu.read_bus_byte(0x1FC));
+    // still looks like a BRK on the stack
+    let pushed_status = cpu.read_bus_byte(0x1FB);
+    assert!((pushed_status & StatusFlags::Break.bits()) != 0);
+
+    // the NMI was handled by the BRK
+    cpu.write_bus_byte(0x4567, 0xEA); // NOP
+    cpu.run_instruction();
+    assert_eq!(0x4568, cpu.pc);
+}
+
+#[test]
+fn test_late_nmi_waits_for_next_instruction() {
+    let (mut cpu, interruptor) = create_interrupt_configuration();
+    cpu.write_bus_byte(0x1234, 0xEA); // NOP
+    cpu.write_bus_byte(0x1235, 0xEA); // NOP
+
+    cpu.clock();
+    interruptor.borrow_mut().flags = InterruptFlags::empty();
+    cpu.clock(); // NMI detected on the last cycle of the first NOP
+
+    cpu.run_instruction();
+    assert_eq!(0x1236, cpu.pc);
+    cpu.run_instruction();
+    assert_eq!(0x4567, cpu.pc);
+}
@@ -0,0 +1,188 @@
+use crate::cpu::disassembler::{
+    Bank, ByteKind, IndexRegisters, disassemble, disassemble_classified, disassemble_one,
+    is_official,
+};
+use crate::cpu::instructions::{Instruction, Mode};
+
+fn text(bytes: &[u8]) -> String {
+    let bank = Bank {
+        base: 0xC000,
+        bytes,
+    };
+    disassemble_one(&bank, 0xC000, None).text
+}
+
+#[test]
+fn test_operand_formats() {
+    assert_eq!("brk", text(&[0x00]));
+    assert_eq!("asl a", text(&[0x0A]));
+    assert_eq!("lda #$40", text(&[0xA9, 0x40]));
+    assert_eq!("lda $10", text(&[0xA5, 0x10]));
+    assert_eq!("lda $10,x", text(&[0xB5, 0x10]));
+    assert_eq!("ldx $10,y", text(&[0xB6, 0x10]));
+    assert_eq!("lda $0200", text(&[0xAD, 0x00, 0x02]));
+    assert_eq!("lda $0200,x", text(&[0xBD, 0x00, 0x02]));
+    assert_eq!("lda $0200,y", text(&[0xB9, 0x00, 0x02]));
+    assert_eq!("jmp ($0200)", text(&[0x6C, 0x00, 0x02]));
+    assert_eq!("lda ($10,x)", text(&[0xA1, 0x10]));
+    assert_eq!("lda ($10),y", text(&[0xB1, 0x10]));
+    // absolute addressing of the zero page needs forcing in ca65
+    assert_eq!("lda a:$0010", text(&[0xAD, 0x10, 0x00]));
+}
+
+#[test]
+fn test_branch_targets() {
+    assert_eq!("bne $C004", text(&[0xD0, 0x02]));
+    assert_eq!("bne $BFFE", text(&[0xD0, 0xFC]));
+}
+
+#[test]
+fn test_labelled_operands() {
+    let labels = |addr: u16| match addr {
+        0x10 => Some("frame_count".to_string()),
+        0xC004 => Some("loop".to_string()),
+        0x0302 => Some("enemies+2".to_string()),
+        _ => None,
+    };
+    let labelled = |bytes: &[u8]| {
+        let bank = Bank {
+            base: 0xC000,
+            bytes,
+        };
+        let mut line = disassemble_one(&bank, 0xC000, None);
+        line.label_operand(&labels);
+        line.text
+    };
+    assert_eq!("lda frame_count", labelled(&[0xA5, 0x10]));
+    assert_eq!("lda (frame_count),y", labelled(&[0xB1, 0x10]));
+    assert_eq!("lda a:frame_count", labelled(&[0xAD, 0x10, 0x00]));
+    assert_eq!("sta enemies+2,x", labelled(&[0x9D, 0x02, 0x03]));
+    assert_eq!("bne loop", labelled(&[0xD0, 0x02]));
+    assert_eq!("lda #$10", labelled(&[0xA9, 0x10]));
+    assert_eq!("lda $11", labelled(&[0xA5, 0x11]));
+}
+
+#[test]
+fn test_unofficial_names() {
+    assert_eq!("lax ($10,x)", text(&[0xA3, 0x10]));
+    assert_eq!("isc $10", text(&[0xE7, 0x10]));
+    assert_eq!("sbc #$10", text(&[0xEB, 0x10]));
+    assert_eq!("nop $10", text(&[0x04, 0x10]));
+    assert_eq!("jam", text(&[0x02]));
+
+    assert!(is_official(0xEA));
+    assert!(is_official(0xE9));
+    assert!(!is_official(0x1A));
+    assert!(!is_official(0xEB));
+    assert!(!is_official(0xA3));
+}
+
+#[test]
+fn test_source_assembles_back() {
+    let bank = Bank {
+        base: 0x8000,
+        bytes: &[0xEA, 0xA7, 0x10, 0xEB, 0x10, 0x1A, 0x4B, 0x01],
+    };
+    let sources = disassemble(&bank, 0x8000, 0x8007, None)
+        .iter()
+        .map(|line| line.source())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            "nop",
+            "lax $10",
+            ".byte $EB, $10 ; sbc #$10",
+            ".byte $1A ; nop",
+            ".byte $4B, $01 ; asr #$01",
+        ],
+        sources
+    );
+}
+
+#[test]
+fn test_range_stops_at_unreadable_bytes() {
+    // the JMP runs off the end of the bank
+    let bank = Bank {
+        base: 0x8000,
+        bytes: &[0xA9, 0x00, 0xE8, 0x4C, 0x00],
+    };
+    let lines = disassemble(&bank, 0x8000, 0xFFFF, None);
+    assert_eq!(3, lines.len());
+    assert_eq!(Some((Instruction::INX, Mode::Imp)), lines[1].instruction);
+    assert_eq!(0x8003, lines[2].addr);
+    assert_eq!(None, lines[2].instruction);
+    assert_eq!(".byte $4C, $00", lines[2].text);
+}
+
+#[test]
+fn test_effective_address_annotations() {
+    let mut memory = [0u8; 0x10000];
+    memory[0x0010] = 0x34;
+    memory[0x0011] = 0x12;
+    memory[0x0014] = 0x00;
+    memory[0x0015] = 0x03;
+    memory[0x0202] = 0xAA;
+    memory[0x0305] = 0xBB;
+    memory[0x1236] = 0xCC;
+    memory[0x02FF] = 0x00;
+    memory[0x0200] = 0xC0;
+    memory[0x0300] = 0xD0;
+    let registers = Some(IndexRegisters { x: 4, y: 2 });
+    let peek = |addr: u16| Some(memory[addr as usize]);
+    let annotation = |program: &[u8]| {
+        let peek = |addr: u16| match addr {
+            0x8000..=0x8002 => program.get(addr as usize - 0x8000).copied(),
+            _ => peek(addr),
+        };
+        disassemble_one(&peek, 0x8000, registers).annotation
+    };
+
+    assert_eq!(Some("= $34".to_string()), annotation(&[0xA5, 0x10]));
+    assert_eq!(Some("@ $0014 = $00".to_string()), annotation(&[0xB5, 0x10]));
+    assert_eq!(
+        Some("@ $0202 = $AA".to_string()),
+        annotation(&[0xB9, 0x00, 0x02])
+    );
+    assert_eq!(Some("@ $0300 = $D0".to_string()), annotation(&[0xA1, 0x10]));
+    assert_eq!(Some("@ $1236 = $CC".to_string()), annotation(&[0xB1, 0x10]));
+    // the pointer's high byte doesn't cross into the next page
+    assert_eq!(Some("= $C000".to_string()), annotation(&[0x6C, 0xFF, 0x02]));
+    assert_eq!(None, annotation(&[0x4C, 0x00, 0x02]));
+    assert_eq!(None, annotation(&[0xA9, 0x10]));
+}
+
+#[test]
+fn test_classified_bytes() {
+    let bytes = [
+        0xA9, 0x01, // lda #$01
+        0x4C, 0x00, 0xC0, // jmp $C000
+        0x01, 0x02, 0x03, // data
+        0xA9, 0x02, // lda #$02 but only the opcode was logged as code
+        0xFF, 0xFF, // never accessed
+    ];
+    let bank = Bank {
+        base: 0xC000,
+        bytes: &bytes,
+    };
+    let classify = |addr: u16| match addr {
+        0xC000..=0xC004 | 0xC008 => ByteKind::Code,
+        0xC005..=0xC007 | 0xC009 => ByteKind::Data,
+        _ => ByteKind::Unknown,
+    };
+
+    let lines = disassemble_classified(&bank, 0xC000, 0xC00B, &classify);
+    let text = lines.iter().map(|l| l.text.as_str()).collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            "lda #$01",
+            "jmp $C000",
+            ".byte $01, $02, $03",
+            ".byte $A9, $02",
+            ".byte $FF, $FF"
+        ],
+        text
+    );
+    assert_eq!(0xC005, lines[2].addr);
+    assert_eq!(None, lines[2].annotation);
+    assert_eq!(Some("not accessed".to_string()), lines[4].annotation);
+}
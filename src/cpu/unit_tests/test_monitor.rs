@@ -0,0 +1,315 @@
+use std::any::Any;
+
+use anyhow::{Result, anyhow};
+
+use crate::{
+    bus::Interruptor,
+    cpu::{
+        monitor::{InterruptKind, Monitor, NulMonitor},
+        *,
+    },
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Event {
+    Instruction(u16),
+    BusWrite(u16, u8, u8),
+    Interrupt(InterruptKind, u16),
+    DmaCycle(u16),
+    Cycle(usize),
+}
+
+#[derive(Default)]
+struct RecordingMonitor {
+    events: Vec<Event>,
+}
+
+impl RecordingMonitor {
+    fn interrupts(&self) -> Vec<Event> {
+        self.events
+            .iter()
+            .filter(|e| matches!(e, Event::Interrupt(_, _)))
+            .cloned()
+            .collect()
+    }
+}
+
+#[allow(unused_variables)]
+impl Monitor for RecordingMonitor {
+    fn new_instruction(
+        &mut self,
+        cycle: usize,
+        pc: u16,
+        sp: u8,
+        a: u8,
+        x: u8,
+        y: u8,
+        status: u8,
+    ) -> Result<()> {
+        self.events.push(Event::Instruction(pc));
+        Ok(())
+    }
+
+    fn fetch_instruction_byte(&mut self, byte: u8) -> Result<()> {
+        Ok(())
+    }
+
+    fn read_data_byte(&mut self, addr: u16, data: u8) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_data_byte(&mut self, addr: u16, old: u8, data: u8) -> Result<()> {
+        Ok(())
+    }
+
+    fn end_instruction(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn bus_write(&mut self, addr: u16, old: u8, data: u8) -> Result<()> {
+        self.events.push(Event::BusWrite(addr, old, data));
+        Ok(())
+    }
+
+    fn interrupt(&mut self, cycle: usize, kind: InterruptKind, handler: u16) -> Result<()> {
+        self.events.push(Event::Interrupt(kind, handler));
+        Ok(())
+    }
+
+    fn dma_cycle(&mut self, cycle: usize, halted_at: u16) -> Result<()> {
+        self.events.push(Event::DmaCycle(halted_at));
+        Ok(())
+    }
+
+    fn cycle(&mut self, cycle: usize) -> Result<()> {
+        self.events.push(Event::Cycle(cycle));
+        Ok(())
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/**
+ * Fails when the instruction at pc starts
+ */
+struct FailingMonitor {
+    pc: u16,
+}
+
+#[allow(unused_variables)]
+impl Monitor for FailingMonitor {
+    fn new_instruction(
+        &mut self,
+        cycle: usize,
+        pc: u16,
+        sp: u8,
+        a: u8,
+        x: u8,
+        y: u8,
+        status: u8,
+    ) -> Result<()> {
+        if pc == self.pc {
+            return Err(anyhow!("stop at ${pc:04X}"));
+        }
+        Ok(())
+    }
+
+    fn fetch_instruction_byte(&mut self, byte: u8) -> Result<()> {
+        Ok(())
+    }
+
+    fn read_data_byte(&mut self, addr: u16, data: u8) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_data_byte(&mut self, addr: u16, old: u8, data: u8) -> Result<()> {
+        Ok(())
+    }
+
+    fn end_instruction(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+fn create_monitored_configuration() -> (CPU, Rc<RefCell<Interruptor>>) {
+    let (mut cpu, _mem) = crate::cpu::create_test_configuration();
+    let interruptor = Rc::new(RefCell::new(Interruptor::new()));
+    cpu.add_device(interruptor.clone());
+
+    cpu.write_bus_byte(0xFFFC, 0x34);
+    cpu.write_bus_byte(0xFFFD, 0x12);
+    cpu.write_bus_byte(0xFFFA, 0x67);
+    cpu.write_bus_byte(0xFFFB, 0x45);
+    cpu.write_bus_byte(0xFFFE, 0xAB);
+    cpu.write_bus_byte(0xFFFF, 0x89);
+
+    // the NMI line is high until something pulls it low
+    interruptor.borrow_mut().flags = InterruptFlags::NMI;
+    cpu.add_monitor(Box::new(RecordingMonitor::default()));
+    (cpu, interruptor)
+}
+
+fn events(cpu: &mut CPU) -> Vec<Event> {
+    cpu.monitor_mut::<RecordingMonitor>()
+        .unwrap()
+        .events
+        .clone()
+}
+
+#[test]
+fn test_reset_and_brk_are_reported() {
+    let (mut cpu, _interruptor) = create_monitored_configuration();
+    cpu.write_bus_byte(0x1234, 0x00); // BRK
+
+    cpu.reset();
+    cpu.run_instruction();
+    cpu.run_instruction();
+
+    let monitor = cpu.monitor_mut::<RecordingMonitor>().unwrap();
+    assert_eq!(
+        vec![
+            Event::Interrupt(InterruptKind::RST, 0x1234),
+            Event::Interrupt(InterruptKind::BRK, 0x89AB)
+        ],
+        monitor.interrupts()
+    );
+}
+
+#[test]
+fn test_nmi_hijacking_brk_is_reported_as_nmi() {
+    let (mut cpu, interruptor) = create_monitored_configuration();
+    cpu.write_bus_byte(0x1234, 0x00); // BRK
+    cpu.reset();
+    cpu.run_instruction();
+
+    cpu.clock();
+    cpu.clock();
+    cpu.clock();
+    interruptor.borrow_mut().flags = InterruptFlags::empty();
+    cpu.clock(); // NMI detected at the end of the 4th cycle
+    while cpu.step != 0 {
+        cpu.clock();
+    }
+
+    let monitor = cpu.monitor_mut::<RecordingMonitor>().unwrap();
+    assert_eq!(
+        vec![
+            Event::Interrupt(InterruptKind::RST, 0x1234),
+            Event::Interrupt(InterruptKind::NMI, 0x4567)
+        ],
+        monitor.interrupts()
+    );
+}
+
+#[test]
+fn test_every_cycle_and_write_is_reported() {
+    let (mut cpu, _interruptor) = create_monitored_configuration();
+    cpu.write_bus_byte(0x1234, 0x20); // JSR $1240
+    cpu.write_bus_byte(0x1235, 0x40);
+    cpu.write_bus_byte(0x1236, 0x12);
+    cpu.reset();
+    cpu.run_instruction();
+    cpu.monitor_mut::<RecordingMonitor>()
+        .unwrap()
+        .events
+        .clear();
+
+    cpu.run_instruction();
+
+    // the stack pushes are writes too
+    assert_eq!(
+        vec![
+            Event::Instruction(0x1234),
+            Event::Cycle(7),
+            Event::Cycle(8),
+            Event::Cycle(9),
+            Event::BusWrite(0x01FD, 0x00, 0x12),
+            Event::Cycle(10),
+            Event::BusWrite(0x01FC, 0x00, 0x36),
+            Event::Cycle(11),
+            Event::Cycle(12),
+        ],
+        events(&mut cpu)
+    );
+}
+
+#[test]
+fn test_dma_cycles_are_reported() {
+    let (mut cpu, _interruptor) = create_monitored_configuration();
+    cpu.write_bus_byte(0x1234, 0xEA); // NOP
+    cpu.reset();
+    cpu.run_instruction();
+    cpu.monitor_mut::<RecordingMonitor>()
+        .unwrap()
+        .events
+        .clear();
+
+    cpu.set_rdy(false);
+    cpu.clock(); // halted on the opcode fetch
+    cpu.clock();
+    cpu.clock();
+    cpu.set_rdy(true);
+    cpu.clock(); // the fetch is repeated
+    assert_eq!(8, cpu.cycles());
+
+    assert_eq!(
+        vec![
+            Event::Instruction(0x1234),
+            Event::Cycle(7),
+            Event::DmaCycle(0x1234),
+            Event::DmaCycle(0x1234),
+            Event::DmaCycle(0x1234),
+        ],
+        events(&mut cpu)
+    );
+}
+
+#[test]
+fn test_monitor_error_halts_the_cpu() {
+    let (mut cpu, _interruptor) = create_monitored_configuration();
+    cpu.write_bus_byte(0x1234, 0xEA); // NOP
+    cpu.write_bus_byte(0x1235, 0xEA); // NOP
+    cpu.add_monitor(Box::new(FailingMonitor { pc: 0x1235 }));
+    cpu.reset();
+    cpu.run_instruction();
+    cpu.run_instruction();
+
+    cpu.clock();
+    assert!(cpu.monitor_error().is_some());
+    let cycles = cpu.cycles();
+    for _ in 0..10 {
+        cpu.clock();
+    }
+    assert_eq!(cycles, cpu.cycles());
+    assert_eq!(0x1236, cpu.pc);
+
+    let error = cpu.take_monitor_error().unwrap();
+    assert_eq!("stop at $1235", error.to_string());
+    cpu.clock();
+    assert_eq!(cycles + 1, cpu.cycles());
+    assert_eq!(0x1236, cpu.pc);
+    assert!(cpu.at_instruction_boundary());
+}
+
+#[test]
+fn test_monitors_can_be_added_and_removed() {
+    let (mut cpu, _interruptor) = create_monitored_configuration();
+    cpu.add_monitor(Box::new(FailingMonitor { pc: 0xFFFF }));
+    cpu.write_bus_byte(0x1234, 0xEA); // NOP
+    cpu.reset();
+    cpu.run_instruction();
+    cpu.run_instruction();
+    assert!(events(&mut cpu).contains(&Event::Instruction(0x1234)));
+
+    assert!(cpu.remove_monitor::<RecordingMonitor>().is_some());
+    assert!(cpu.monitor_mut::<RecordingMonitor>().is_none());
+    assert!(cpu.monitor_mut::<FailingMonitor>().is_some());
+    assert!(cpu.remove_monitor::<FailingMonitor>().is_some());
+    assert!(cpu.monitor.as_any().is::<NulMonitor>());
+}
@@ -0,0 +1,5 @@
+pub mod bus;
+pub mod cpu;
+pub mod nes;
+pub mod ram;
+pub mod save_state;
@@ -6,16 +6,14 @@ use cpal::{
 };
 use crossbeam_channel::bounded;
 use minifb::{Key, KeyRepeat, Scale, ScaleMode, Window, WindowOptions};
-use nes::{NES, controllers::JoyPad};
+use nes_rs::nes::{
+    NES,
+    controllers::{JoyPad, JoyPadButton},
+    ppu_events::{PpuEventLogger, PpuEvents, overlay_image},
+    ppu_viewer::Image,
+};
 use std::collections::HashSet;
-use std::{cell::RefCell, env, rc::Rc, time::Instant};
-
-use crate::nes::controllers::JoyPadButton;
-
-pub mod bus;
-pub mod cpu;
-pub mod nes;
-pub mod ram;
+use std::{cell::RefCell, env, fs, path::Path, rc::Rc, time::Instant};
 
 const NES_WIDTH: usize = 256;
 const NES_HEIGHT: usize = 240;
@@ -28,6 +26,92 @@ const PPU_CLOCK_SPEED: usize = 5369318;
 // for blip_buff to create downsamples during a frame
 const BLIP_BUFF_SIZE: usize = 30000;
 const VOLUME: f32 = 0.5;
+const PALETTE_KEYS: [Key; 8] = [
+    Key::Key0,
+    Key::Key1,
+    Key::Key2,
+    Key::Key3,
+    Key::Key4,
+    Key::Key5,
+    Key::Key6,
+    Key::Key7,
+];
+
+// given the screen and the pattern table palette
+type Render = Box<dyn Fn(&NES, &Image, u8) -> Image>;
+
+/**
+ * A PPU viewer in its own window, which its key opens and closes
+ */
+struct Viewer {
+    title: &'static str,
+    key: Key,
+    render: Render,
+    window: Option<Window>,
+}
+
+impl Viewer {
+    fn new(
+        title: &'static str,
+        key: Key,
+        render: impl Fn(&NES, &Image, u8) -> Image + 'static,
+    ) -> Self {
+        Self {
+            title,
+            key,
+            render: Box::new(render),
+            window: None,
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.window.is_some()
+    }
+
+    fn update(
+        &mut self,
+        nes: &NES,
+        screen: &Image,
+        keys_pressed: &HashSet<Key>,
+        palette: u8,
+    ) -> Result<()> {
+        if self.window.as_ref().is_some_and(|w| !w.is_open()) {
+            self.window = None;
+        }
+        let toggled = keys_pressed.contains(&self.key);
+        if toggled && self.window.is_some() {
+            self.window = None;
+            return Ok(());
+        }
+        if !toggled && self.window.is_none() {
+            return Ok(());
+        }
+
+        let image = (self.render)(nes, screen, palette);
+        let window = match &mut self.window {
+            Some(window) => window,
+            None => {
+                let opts = WindowOptions {
+                    scale: Scale::X2,
+                    ..WindowOptions::default()
+                };
+                self.window
+                    .insert(Window::new(self.title, image.width, image.height, opts)?)
+            }
+        };
+        window.update_with_buffer(&image.pixels, image.width, image.height)?;
+        Ok(())
+    }
+}
+
+fn pattern_tables_image(nes: &NES, palette: u8) -> Image {
+    let left = nes.pattern_table_image(0, palette);
+    let right = nes.pattern_table_image(1, palette);
+    let mut image = Image::new(left.width + right.width, left.height);
+    image.draw(0, 0, &left);
+    image.draw(left.width, 0, &right);
+    image
+}
 
 fn main() -> Result<()> {
     let audio_host = cpal::default_host();
@@ -77,7 +161,7 @@ where
         none: false,
     };
 
-    let mut screen_buffer = vec![0; NES_WIDTH * NES_HEIGHT];
+    let mut screen = Image::new(NES_WIDTH, NES_HEIGHT);
     let mut window = Window::new("NES RS", SCREEN_WIDTH, SCREEN_HEIGHT, opts)?;
 
     let mut nes = NES::new();
@@ -108,6 +192,27 @@ where
         audio_device.build_output_stream(stream_config, data_callback, err_callback, None)?;
     stream.play()?;
 
+    // events are only recorded while their viewer is open
+    let ppu_events = Rc::new(RefCell::new(None::<Rc<RefCell<PpuEvents>>>));
+    let viewer_events = ppu_events.clone();
+    let mut viewers = [
+        Viewer::new("Nametables", Key::F1, |nes, _, _| nes.nametables_image()),
+        Viewer::new("Pattern tables", Key::F2, |nes, _, palette| {
+            pattern_tables_image(nes, palette)
+        }),
+        Viewer::new("Sprites", Key::F3, |nes, _, _| nes.sprites_image()),
+        Viewer::new("Palettes", Key::F4, |nes, _, _| nes.palette_image()),
+        Viewer::new(
+            "PPU events",
+            Key::F6,
+            move |_, screen, _| match &*viewer_events.borrow() {
+                Some(events) => overlay_image(events.borrow().last_frame(), Some(screen)),
+                None => overlay_image(&[], Some(screen)),
+            },
+        ),
+    ];
+    let mut viewer_palette = 0;
+
     let mut muted = false;
     let start = Instant::now();
     let mut frame = 0.0;
@@ -118,8 +223,7 @@ where
 
         if let Some(p) = pixel_info {
             let color = ((p.r as u32) << 16) | ((p.g as u32) << 8) | (p.b as u32);
-            let (x, y) = (p.x as usize, p.y as usize);
-            screen_buffer[y * NES_WIDTH + x] = color;
+            screen.set_pixel(p.x as usize, p.y as usize, color);
         }
 
         if let Some(sample_float) = sample_opt {
@@ -140,7 +244,7 @@ where
         clocks += 1;
         if frame_complete {
             window
-                .update_with_buffer(&screen_buffer, NES_WIDTH, NES_HEIGHT)
+                .update_with_buffer(&screen.pixels, NES_WIDTH, NES_HEIGHT)
                 .unwrap();
 
             const DISPLAY_FRAME_RATE: bool = false;
@@ -161,7 +265,7 @@ where
                 }
             }
 
-            let keys: HashSet<Key> = HashSet::from_iter(window.get_keys().into_iter());
+            let keys: HashSet<Key> = HashSet::from_iter(window.get_keys());
 
             let joypad_input_1: u8 = check_keycode(&keys, Key::W, JoyPadButton::Up)
                 | check_keycode(&keys, Key::A, JoyPadButton::Left)
@@ -188,6 +292,43 @@ where
             if window.is_key_pressed(Key::R, KeyRepeat::No) {
                 nes.reset();
             }
+
+            // the viewers can be opened and closed, or the pattern table palette
+            // picked, from any of the windows
+            let mut keys_pressed =
+                HashSet::<Key>::from_iter(window.get_keys_pressed(KeyRepeat::No));
+            for viewer in &viewers {
+                if let Some(viewer_window) = &viewer.window {
+                    keys_pressed.extend(viewer_window.get_keys_pressed(KeyRepeat::No));
+                }
+            }
+            if let Some(palette) = PALETTE_KEYS.iter().position(|k| keys_pressed.contains(k)) {
+                viewer_palette = palette as u8;
+            }
+            for viewer in &mut viewers {
+                viewer.update(&nes, &screen, &keys_pressed, viewer_palette)?;
+            }
+            let events_open = viewers.iter().any(|v| v.key == Key::F6 && v.is_open());
+            let events_attached = ppu_events.borrow().is_some();
+            if events_open && !events_attached {
+                *ppu_events.borrow_mut() = Some(PpuEventLogger::attach(&mut nes, None));
+            } else if !events_open && events_attached {
+                PpuEventLogger::detach(&mut nes);
+                *ppu_events.borrow_mut() = None;
+            }
+
+            let state_path = Path::new(cartridge_name).with_extension("state");
+            if window.is_key_pressed(Key::F5, KeyRepeat::No) {
+                fs::write(&state_path, nes.save_state())?;
+            }
+
+            if window.is_key_pressed(Key::F9, KeyRepeat::No)
+                && state_path.exists()
+                && let Err(e) = nes.load_state(&fs::read(&state_path)?)
+            {
+                eprintln!("Couldn't load {}: {}", state_path.display(), e);
+                nes.reset();
+            }
         }
     }
 
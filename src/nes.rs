@@ -1,15 +1,30 @@
 mod apu;
+pub mod apu_inspector;
 mod cartridge;
+pub mod code_data_logger;
 pub mod controllers;
+pub mod debugger;
+pub mod developer_cartridge;
+pub mod gdb_server;
+pub mod mapper_inspector;
+pub mod memory_domains;
+mod mixer;
 mod ppu;
+pub mod ppu_events;
+pub mod ppu_viewer;
+pub mod profiler;
+pub mod symbols;
+pub mod trace_logger;
 
 use std::{cell::RefCell, rc::Rc};
 
-use crate::cpu::{CPU, CPUCycleType, CPUType};
+use crate::cpu::{CPU, CPUType};
 use crate::ram::RAM;
+use crate::save_state::{SaveState, SaveStateError, StateReader, StateWriter};
 use anyhow::Result;
 use apu::APU;
 use cartridge::{Cartridge, CartridgeCPUPort, CartridgePPUPort};
+use developer_cartridge::DeveloperCartridge;
 use ppu::PPU;
 
 use self::controllers::{Controller, NulController};
@@ -17,14 +32,15 @@ use self::ppu::PixelInfo;
 
 pub struct NES {
     cpu: Rc<RefCell<CPU>>,
+    ram: Rc<RefCell<RAM>>,
     apu: Rc<RefCell<APU>>,
     ppu: Rc<RefCell<PPU>>,
     cartridge_cpu_port: Rc<RefCell<CartridgeCPUPort>>,
     cartridge_ppu_port: Rc<RefCell<CartridgePPUPort>>,
+    developer_cartridge: Option<Rc<RefCell<DeveloperCartridge>>>,
     tick: u8,
     controller1: Rc<RefCell<dyn Controller>>,
     controller2: Rc<RefCell<dyn Controller>>,
-    last_cycle_type: CPUCycleType,
 }
 
 impl NES {
@@ -38,14 +54,14 @@ impl NES {
         // 0x0000 - 0x1FFFF "work" RAM (WRAM)
         // NES ram is physically only 0x0000 - 0x07FF, but it's then "mirrored" 3 more
         // times to 0x1FFF. "Mirroring" can be accomplished by masking off some bits
-        cpu.as_ref()
-            .borrow_mut()
-            .add_device(Rc::new(RefCell::new(RAM::new(0x0000, 0x1FFF, 0x07FF))));
+        let ram = Rc::new(RefCell::new(RAM::new(0x0000, 0x1FFF, 0x07FF)));
+        cpu.as_ref().borrow_mut().add_device(ram.clone());
         //0x2000 - 0x3FFF  PPU Registers from 0x2000 to 0x2007 and then mirrored with mask 0x0007
         cpu.borrow_mut().add_device(ppu.clone());
         //0x4000 - 0x4017  APU and IO registers
-        //0x4018 - 0x401F  APU and IO functionality that is disabled
         cpu.borrow_mut().add_device(apu.clone());
+        //0x4018 - 0x401F  APU and IO functionality that is disabled, or the
+        //                 developer cartridge's registers when it's attached
         //0x4020 - 0xFFFF  Cartridge space
         let cartridge_cpu_port = Rc::new(RefCell::new(CartridgeCPUPort::new(cartridge.clone())));
         cpu.as_ref()
@@ -57,20 +73,24 @@ impl NES {
         ppu.as_ref()
             .borrow_mut()
             .add_device(cartridge_ppu_port.clone());
+        ppu.as_ref()
+            .borrow_mut()
+            .add_fetch_observer(cartridge_ppu_port.clone());
 
         let controller1 = Rc::new(RefCell::new(NulController::new()));
         let controller2 = Rc::new(RefCell::new(NulController::new()));
 
         Self {
             cpu,
+            ram,
             apu,
             ppu,
             cartridge_cpu_port,
             cartridge_ppu_port,
+            developer_cartridge: None,
             tick: 0,
             controller1,
             controller2,
-            last_cycle_type: CPUCycleType::Read,
         }
     }
 
@@ -91,10 +111,13 @@ impl NES {
                 let mut apu_borrowed = self.apu.borrow_mut();
                 apu_borrowed.set_input_port1(input1);
                 apu_borrowed.set_input_port2(input2);
-                let sample = apu_borrowed.clock(self.last_cycle_type);
+                let sample = mixer::mix(
+                    apu_borrowed.clock(),
+                    self.cartridge_cpu_port.borrow().expansion_audio_clock(),
+                );
                 audio_sample = Some(sample);
             };
-            self.last_cycle_type = self.cpu.borrow_mut().clock();
+            self.cpu.borrow_mut().clock();
         }
 
         let (end_of_frame, pixelinfo) = self.ppu.borrow_mut().clock();
@@ -126,9 +149,60 @@ impl NES {
         self.controller2 = controller;
     }
 
+    /**
+     * The error a CPU monitor returned, which stopped the CPU. Taking it lets the CPU carry on.
+     */
+    pub fn take_monitor_error(&self) -> Option<anyhow::Error> {
+        self.cpu.borrow_mut().take_monitor_error()
+    }
+
     pub fn save_sram(&self) -> Result<()> {
         self.cartridge_cpu_port.borrow().save_sram()
     }
+
+    /**
+     * Captures the complete machine so that it can be resumed later with load_state.
+     * Controllers aren't included; they're owned by whoever plugged them in.
+     */
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.begin_section("NES");
+        writer.write_u8(self.tick);
+
+        self.cpu.borrow().save_state(&mut writer);
+        self.ram.borrow().save_state(&mut writer);
+        self.ppu.borrow().save_state(&mut writer);
+        self.apu.borrow().save_state(&mut writer);
+        self.cartridge_cpu_port.borrow().save_state(&mut writer);
+        writer.finish()
+    }
+
+    /**
+     * Restores a state made by save_state. The same cartridge must already be loaded.
+     * If an error is returned the machine may be partially restored and should be reset.
+     */
+    pub fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut reader = StateReader::new(data)?;
+        reader.expect_section("NES")?;
+        let tick = reader.read_u8()?;
+        if tick >= 3 {
+            Err(SaveStateError::InvalidValue("tick", tick as u32))?;
+        }
+        self.tick = tick;
+
+        self.cpu.borrow_mut().load_state(&mut reader)?;
+        self.ram.borrow_mut().load_state(&mut reader)?;
+        self.ppu.borrow_mut().load_state(&mut reader)?;
+        self.apu.borrow_mut().load_state(&mut reader)?;
+        self.cartridge_cpu_port.borrow().load_state(&mut reader)
+    }
+}
+
+/**
+ * Reads the PRG ROM out of an iNES file without loading the cartridge
+ */
+pub fn read_prg_rom(file_name: &str) -> Result<Vec<u8>> {
+    Cartridge::read_prg_rom(file_name)
 }
 
 impl Default for NES {
@@ -139,5 +213,22 @@ impl Default for NES {
 
 #[cfg(test)]
 mod integration_tests {
+    mod apu_inspector;
+    mod blargg;
+    mod code_data_logger;
+    mod debugger;
+    mod developer_cartridge;
+    mod fixtures;
+    mod gdb_server;
+    mod mapper_inspector;
+    mod memory_domains;
     mod nestest;
+    mod nmi_timing;
+    mod ppu_events;
+    mod ppu_viewer;
+    mod profiler;
+    mod save_state;
+    mod symbols;
+    mod test_rom;
+    mod trace_logger;
 }
@@ -1,22 +1,33 @@
 #![allow(clippy::upper_case_acronyms)]
 
-mod channels;
+pub mod channels;
+mod dma;
+#[cfg(test)]
+mod integration_tests;
 
 extern crate bitflags;
 
 use std::{cell::RefCell, ops::Not, rc::Rc};
 
+use anyhow::Result;
+
 use crate::{
     bus::{BusDevice, InterruptFlags},
-    cpu::{CPU, CPUCycleType},
+    cpu::CPU,
+    nes::apu_inspector::ApuChannels,
+    save_state::{SaveState, SaveStateError, StateReader, StateWriter},
 };
 
-use self::channels::{
-    Channel, dmc::DMCChannel, noise::NoiseChannel, pulse::PulseChannel, triangle::TriangleChannel,
+use self::{
+    channels::{
+        Channel, dmc::DMCChannel, noise::NoiseChannel, pulse::PulseChannel,
+        triangle::TriangleChannel,
+    },
+    dma::DMA,
 };
 
 const RANGE_START: u16 = 0x4000;
-const RANGE_END: u16 = 0x401F;
+const RANGE_END: u16 = 0x4017;
 const ADDR_MASK: u16 = 0x401F;
 
 pub struct APU {
@@ -29,8 +40,7 @@ pub struct APU {
     frame_counter: u16,
     frame_counter_reset_state: FrameCounterResetState,
 
-    oam_dma_state: OamDmaState,
-    oam_dma_data: u8,
+    dma: DMA,
 
     input_port1: u8,
     input_port2: u8,
@@ -45,6 +55,10 @@ pub struct APU {
     triangle_channel: TriangleChannel,
     noise_channel: NoiseChannel,
     dmc_channel: DMCChannel,
+
+    // for tools, and not saved. Mutes are the user's so they survive a reset
+    channel_outputs: [u8; 5],
+    muted_channels: [bool; 5],
 }
 
 impl APU {
@@ -58,8 +72,7 @@ impl APU {
             frame_counter: 15,
             frame_counter_reset_state: FrameCounterResetState::None,
 
-            oam_dma_state: OamDmaState::NoDma,
-            oam_dma_data: 0,
+            dma: DMA::new(),
 
             input_port1: 0,
             input_port2: 0,
@@ -74,29 +87,41 @@ impl APU {
             triangle_channel: TriangleChannel::new(),
             noise_channel: NoiseChannel::new(),
             dmc_channel: DMCChannel::new(),
+
+            channel_outputs: [0; 5],
+            muted_channels: [false; 5],
         }
     }
 
     #[must_use]
-    pub fn clock(&mut self, cpu_cycle_type: CPUCycleType) -> f32 {
+    pub fn clock(&mut self) -> f32 {
         self.cycle_type = !self.cycle_type;
 
         self.manage_input_ports();
 
-        self.manage_oam_dma(cpu_cycle_type);
+        if let Some(sample) = self.dma.clock(self.cycle_type, &self.cpu) {
+            self.dmc_channel.load_sample(sample);
+        }
         self.manage_frame_counter();
 
         let mut result = 0.0;
         match self.resetting_state {
             ResettingState::Ready => {
                 let cycle_type = self.cycle_type;
-                self.dmc_channel
-                    .manage_dma(cpu_cycle_type, self.cycle_type, &mut self.cpu);
+                if let Some(addr) = self.dmc_channel.dma_request() {
+                    self.dma.request_dmc(addr, &self.cpu);
+                }
                 self.sound_enable_register_high.set(
                     SoundEnableFlags::DMCInterrupt,
                     self.dmc_channel.memory_reader.irq_occurred,
                 );
-                let outputs = self.channel_set().map(|c| c.clock(cycle_type) as f32);
+                self.channel_outputs = self.channel_set().map(|c| c.clock(cycle_type));
+                let mut outputs = self.channel_outputs.map(|output| output as f32);
+                for (output, muted) in outputs.iter_mut().zip(self.muted_channels) {
+                    if muted {
+                        *output = 0.0;
+                    }
+                }
                 let pulse_out = if outputs[0] == 0.0 && outputs[1] == 0.0 {
                     0.0
                 } else {
@@ -127,8 +152,7 @@ impl APU {
 
     pub fn reset(&mut self) {
         self.resetting_state = ResettingState::WaitingForEnable;
-        self.oam_dma_state = OamDmaState::NoDma;
-        self.oam_dma_data = 0;
+        self.dma.reset();
         self.input_port1 = 0;
         self.input_port2 = 0;
         self.last_read = 0;
@@ -145,6 +169,7 @@ impl APU {
         self.triangle_channel = TriangleChannel::new();
         self.noise_channel = NoiseChannel::new();
         self.dmc_channel = DMCChannel::new();
+        self.channel_outputs = [0; 5];
 
         // not updated at reset
         // self.frame_counter = 0;
@@ -160,34 +185,31 @@ impl APU {
         self.input_port2 = value;
     }
 
-    fn manage_oam_dma(&mut self, cpu_cycle_type: CPUCycleType) {
-        match (self.oam_dma_state, cpu_cycle_type, self.cycle_type) {
-            (OamDmaState::NoDma, _, _) => (),
-            (OamDmaState::Requested, CPUCycleType::Read, _) => {
-                self.cpu.borrow_mut().set_rdy(false);
-                self.oam_dma_state = OamDmaState::Ready;
-            }
-            (OamDmaState::Requested, CPUCycleType::Write, _) => (),
-            (OamDmaState::Ready, _, APUCycleType::Get) => {
-                self.read_oam_dma(0);
-                self.oam_dma_state = OamDmaState::Executing(0);
-            }
-            (OamDmaState::Ready, _, APUCycleType::Put) => (),
-            (OamDmaState::Executing(offset), _, APUCycleType::Get) => {
-                self.read_oam_dma(offset as u16)
-            }
-            (OamDmaState::Executing(offset), _, APUCycleType::Put) => {
-                self.write_oam_dma();
-                if offset == 255 {
-                    self.cpu.borrow_mut().set_rdy(true);
-                    self.oam_dma_state = OamDmaState::NoDma;
-                } else {
-                    self.oam_dma_state = OamDmaState::Executing(offset + 1);
-                }
-            }
+    pub fn channels(&self) -> ApuChannels {
+        ApuChannels {
+            pulse1: self.pulse_channel1.state(),
+            pulse2: self.pulse_channel2.state(),
+            triangle: self.triangle_channel.state(),
+            noise: self.noise_channel.state(),
+            dmc: self.dmc_channel.state(),
         }
     }
 
+    /**
+     * The levels the channels output on the last clock, in register order
+     */
+    pub fn channel_outputs(&self) -> [u8; 5] {
+        self.channel_outputs
+    }
+
+    pub fn set_channel_muted(&mut self, channel: usize, muted: bool) {
+        self.muted_channels[channel] = muted;
+    }
+
+    pub fn is_channel_muted(&self, channel: usize) -> bool {
+        self.muted_channels[channel]
+    }
+
     fn manage_frame_counter(&mut self) {
         match self.frame_counter_reset_state {
             FrameCounterResetState::WaitingToReset(0) => {
@@ -276,18 +298,6 @@ impl APU {
         }
     }
 
-    fn read_oam_dma(&mut self, offset: u16) {
-        let addr = ((self.oam_dma_page as u16) << 8) | offset;
-        self.oam_dma_data = self.cpu.borrow_mut().read_bus_byte(addr);
-    }
-
-    fn write_oam_dma(&self) {
-        self.cpu
-            .as_ref()
-            .borrow_mut()
-            .write_bus_byte(0x2004, self.oam_dma_data);
-    }
-
     fn manage_input_ports(&mut self) {
         if self.input_port_ctrl & 0b00000001 != 0 {
             self.input_registers[0] = self.input_port1;
@@ -295,6 +305,26 @@ impl APU {
         }
     }
 
+    // what 0x4015 reads as
+    fn status(&self) -> u8 {
+        let channels: [&dyn Channel; 5] = [
+            &self.pulse_channel1,
+            &self.pulse_channel2,
+            &self.triangle_channel,
+            &self.noise_channel,
+            &self.dmc_channel,
+        ];
+        let status = channels.iter().fold(SoundEnableFlags::empty(), |f, c| {
+            f | if c.get_enabled() {
+                c.get_enabled_flag()
+            } else {
+                SoundEnableFlags::empty()
+            }
+        });
+
+        (status | self.sound_enable_register_high).bits() | (self.last_read & 0b00100000)
+    }
+
     fn bus_read_input_register(&mut self, input_no: usize) -> u8 {
         let result = self.input_registers[input_no] & 0b00000001;
         if self.input_port_ctrl & 0b00000001 == 0 {
@@ -325,22 +355,10 @@ impl BusDevice for APU {
     fn read(&mut self, addr: u16) -> u8 {
         if addr >= RANGE_START && addr <= RANGE_END {
             let physical = addr & ADDR_MASK;
-            let result = match physical {
+            match physical {
                 0x4000..=0x4013 => self.last_read,
                 0x4015 => {
-                    let status =
-                        self.channel_set()
-                            .iter()
-                            .fold(SoundEnableFlags::empty(), |f, c| {
-                                f | if c.get_enabled() {
-                                    c.get_enabled_flag()
-                                } else {
-                                    SoundEnableFlags::empty()
-                                }
-                            });
-
-                    let result = (status | self.sound_enable_register_high).bits()
-                        | (self.last_read & 0b00100000);
+                    let result = self.status();
                     self.set_frame_interrupt(false);
                     result
 
@@ -355,8 +373,7 @@ impl BusDevice for APU {
                     self.last_read
                 }
                 _ => 0xFF,
-            };
-            result
+            }
         } else {
             panic!("Address out of range in APU {}", addr)
         }
@@ -365,7 +382,7 @@ impl BusDevice for APU {
     fn write(&mut self, addr: u16, data: u8) -> u8 {
         if addr >= RANGE_START && addr <= RANGE_END {
             let physical = addr & ADDR_MASK;
-            let old = match physical {
+            match physical {
                 0x4000..=0x4013 => {
                     let channel_number = (physical >> 2) & 0b00000111;
                     let channel = &mut self.channel_set()[channel_number as usize];
@@ -376,8 +393,8 @@ impl BusDevice for APU {
                 }
                 0x4014 => {
                     let old = self.oam_dma_page;
-                    self.oam_dma_state = OamDmaState::Requested;
                     self.oam_dma_page = data;
+                    self.dma.request_oam(data);
                     old
                 }
                 0x4015 => {
@@ -420,13 +437,25 @@ impl BusDevice for APU {
                     old
                 }
                 _ => 0xFF,
-            };
-            old
+            }
         } else {
             panic!("Address out of range in APU {}", addr)
         }
     }
 
+    fn peek(&self, addr: u16) -> u8 {
+        match addr & ADDR_MASK {
+            0x4000..=0x4013 => self.last_read,
+            0x4015 => self.status(),
+            0x4016 => self.input_registers[0] & 0b00000001,
+            0x4017 => self.input_registers[1] & 0b00000001,
+            _ => 0xFF,
+        }
+    }
+
+    // the registers are all write only or change state when they're written
+    fn poke(&mut self, _addr: u16, _data: u8) {}
+
     fn bus_clock(&mut self) -> InterruptFlags {
         if self
             .sound_enable_register_high
@@ -439,12 +468,91 @@ impl BusDevice for APU {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
-enum OamDmaState {
-    NoDma,
-    Requested,
-    Ready,
-    Executing(u8),
+impl SaveState for APU {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("APU");
+        match self.resetting_state {
+            ResettingState::WaitingForEnable => writer.write_u8(0),
+            ResettingState::CountingDown(n) => {
+                writer.write_u8(1);
+                writer.write_u16(n);
+            }
+            ResettingState::Ready => writer.write_u8(2),
+        }
+        writer.write_bool(self.cycle_type == APUCycleType::Get);
+        writer.write_u8(self.last_read);
+
+        writer.write_u16(self.frame_counter);
+        match self.frame_counter_reset_state {
+            FrameCounterResetState::None => writer.write_u8(0),
+            FrameCounterResetState::WaitingToReset(n) => {
+                writer.write_u8(1);
+                writer.write_u8(n);
+            }
+        }
+
+        self.dma.save_state(writer);
+
+        writer.write_u8(self.input_port1);
+        writer.write_u8(self.input_port2);
+        writer.write_u8(self.input_port_ctrl);
+        writer.write_u8(self.oam_dma_page);
+        writer.write_bytes(&self.input_registers);
+        writer.write_u8(self.sound_enable_register_high.bits());
+        writer.write_u8(self.frame_counter_control.bits());
+
+        self.pulse_channel1.save_state(writer);
+        self.pulse_channel2.save_state(writer);
+        self.triangle_channel.save_state(writer);
+        self.noise_channel.save_state(writer);
+        self.dmc_channel.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("APU")?;
+        self.resetting_state = match reader.read_u8()? {
+            0 => ResettingState::WaitingForEnable,
+            1 => ResettingState::CountingDown(reader.read_u16()?),
+            2 => ResettingState::Ready,
+            n => Err(SaveStateError::InvalidValue(
+                "apu resetting state",
+                n as u32,
+            ))?,
+        };
+        self.cycle_type = if reader.read_bool()? {
+            APUCycleType::Get
+        } else {
+            APUCycleType::Put
+        };
+        self.last_read = reader.read_u8()?;
+
+        self.frame_counter = reader.read_u16()?;
+        self.frame_counter_reset_state = match reader.read_u8()? {
+            0 => FrameCounterResetState::None,
+            1 => FrameCounterResetState::WaitingToReset(reader.read_u8()?),
+            n => Err(SaveStateError::InvalidValue(
+                "frame counter reset state",
+                n as u32,
+            ))?,
+        };
+
+        self.dma.load_state(reader)?;
+
+        self.input_port1 = reader.read_u8()?;
+        self.input_port2 = reader.read_u8()?;
+        self.input_port_ctrl = reader.read_u8()?;
+        self.oam_dma_page = reader.read_u8()?;
+        reader.read_bytes_into(&mut self.input_registers)?;
+        self.sound_enable_register_high = SoundEnableFlags::from_bits_retain(reader.read_u8()?);
+        self.frame_counter_control = FrameCounterFlags::from_bits_retain(reader.read_u8()?);
+
+        self.pulse_channel1.load_state(reader)?;
+        self.pulse_channel2.load_state(reader)?;
+        self.triangle_channel.load_state(reader)?;
+        self.noise_channel.load_state(reader)?;
+        self.dmc_channel.load_state(reader)?;
+        Ok(())
+    }
 }
 
 bitflags::bitflags! {
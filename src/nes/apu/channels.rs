@@ -1,3 +1,10 @@
+use anyhow::Result;
+
+use crate::{
+    nes::apu_inspector::EnvelopeState,
+    save_state::{SaveState, StateReader, StateWriter},
+};
+
 use super::{APUCycleType, SoundEnableFlags};
 
 pub mod dmc;
@@ -65,6 +72,15 @@ impl Envelope {
         };
     }
 
+    fn state(&self) -> EnvelopeState {
+        EnvelopeState {
+            constant_volume: self.constant_volume,
+            looping: self.loop_enable,
+            volume: self.period_or_volume,
+            decay_level: self.decay_level,
+        }
+    }
+
     fn load_bits(&mut self, value: u8) {
         self.loop_enable = value & 0b00100000 != 0;
         self.constant_volume = value & 0b00010000 != 0;
@@ -165,3 +181,54 @@ impl LengthCounter {
         (self.period_index << 3) & 0b11111000
     }
 }
+
+impl SaveState for Envelope {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.start);
+        writer.write_u8(self.divider);
+        writer.write_bool(self.loop_enable);
+        writer.write_bool(self.constant_volume);
+        writer.write_u8(self.decay_level);
+        writer.write_u8(self.period_or_volume);
+        writer.write_u8(self.output);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.start = reader.read_bool()?;
+        self.divider = reader.read_u8()?;
+        self.loop_enable = reader.read_bool()?;
+        self.constant_volume = reader.read_bool()?;
+        self.decay_level = reader.read_u8()?;
+        self.period_or_volume = reader.read_u8()?;
+        self.output = reader.read_u8()?;
+        Ok(())
+    }
+}
+
+impl SaveState for FrequencyTimer {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.period);
+        writer.write_u16(self.value);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.period = reader.read_u16()?;
+        self.value = reader.read_u16()?;
+        Ok(())
+    }
+}
+
+impl SaveState for LengthCounter {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.period_index);
+        writer.write_u8(self.value);
+        writer.write_bool(self.halted);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.period_index = reader.read_u8()?;
+        self.value = reader.read_u8()?;
+        self.halted = reader.read_bool()?;
+        Ok(())
+    }
+}
@@ -1,8 +1,11 @@
-use std::{cell::RefCell, rc::Rc};
+use anyhow::Result;
 
 use crate::{
-    cpu::{CPU, CPUCycleType},
-    nes::apu::{APUCycleType, SoundEnableFlags},
+    nes::{
+        apu::{APUCycleType, SoundEnableFlags},
+        apu_inspector::DmcState,
+    },
+    save_state::{SaveState, StateReader, StateWriter},
 };
 
 use super::{Channel, FrequencyTimer};
@@ -24,19 +27,43 @@ impl DMCChannel {
         }
     }
 
-    pub fn manage_dma(
-        &mut self,
-        cpu_cyle_type: CPUCycleType,
-        apu_cycle_type: APUCycleType,
-        cpu: &mut Rc<RefCell<CPU>>,
-    ) {
-        if self.output_unit.sample_buffer.is_none() {
-            self.memory_reader.manage_dma(
-                cpu_cyle_type,
-                apu_cycle_type,
-                cpu,
-                &mut self.output_unit.sample_buffer,
-            )
+    pub fn state(&self) -> DmcState {
+        let reader = &self.memory_reader;
+        DmcState {
+            enabled: self.get_enabled(),
+            irq_enabled: reader.irq_enabled,
+            irq: reader.irq_occurred,
+            looping: reader.loop_enabled,
+            rate_index: self.period_index,
+            period: self.frequency_timer.period,
+            sample_address: reader.sample_address,
+            sample_length: reader.sample_length,
+            current_address: reader.current_address,
+            bytes_remaining: reader.samples_remaining,
+            output_level: self.output_unit.output,
+        }
+    }
+
+    /**
+     * The address of the next sample byte if the sample buffer needs filling
+     */
+    pub fn dma_request(&mut self) -> Option<u16> {
+        self.memory_reader.restart_if_needed();
+        if self.output_unit.sample_buffer.is_none() && self.memory_reader.samples_remaining != 0 {
+            Some(self.memory_reader.current_address)
+        } else {
+            None
+        }
+    }
+
+    /**
+     * Fills the sample buffer with a byte fetched by the DMA unit
+     */
+    pub fn load_sample(&mut self, sample: u8) {
+        // the channel may have been disabled while the byte was being fetched
+        if self.memory_reader.samples_remaining != 0 {
+            self.output_unit.sample_buffer = Some(sample);
+            self.memory_reader.advance();
         }
     }
 }
@@ -156,8 +183,6 @@ pub struct MemoryReader {
     current_address: u16,
     sample_length: u16,
     start: bool,
-    dma_state: DmcDmaState,
-    marked_not_ready: bool,
 }
 impl MemoryReader {
     fn new() -> Self {
@@ -170,66 +195,29 @@ impl MemoryReader {
             current_address: 0xC000,
             sample_length: 1,
             start: false,
-            dma_state: DmcDmaState::NoDma,
-            marked_not_ready: false,
         }
     }
 
-    fn manage_dma(
-        &mut self,
-        cpu_cycle_type: CPUCycleType,
-        apu_cycle_type: APUCycleType,
-        cpu: &mut Rc<RefCell<CPU>>,
-        sample: &mut Option<u8>,
-    ) {
+    fn restart_if_needed(&mut self) {
         if self.start {
             self.start = false;
             self.current_address = self.sample_address;
             self.samples_remaining = self.sample_length;
         }
+    }
 
-        if self.samples_remaining != 0
-            && cpu_cycle_type == CPUCycleType::Read
-            && self.dma_state == DmcDmaState::NoDma
-        {
-            self.dma_state = if cpu.borrow().is_rdy() {
-                DmcDmaState::Requested
-            } else {
-                DmcDmaState::Executing
-            }
-        };
-        match (self.dma_state, cpu_cycle_type, apu_cycle_type) {
-            (DmcDmaState::NoDma, _, _) => (),
-            (DmcDmaState::Requested, CPUCycleType::Read, _) => {
-                cpu.borrow_mut().set_rdy(false);
-                self.marked_not_ready = true;
-                self.dma_state = DmcDmaState::Executing;
-            }
-            (DmcDmaState::Executing, _, APUCycleType::Put) => {
-                let sample_buffer = cpu
-                    .as_ref()
-                    .borrow_mut()
-                    .read_bus_byte(self.current_address);
-                if self.current_address == 0xFFFF {
-                    self.current_address = 0x8000
-                } else {
-                    self.current_address += 1;
-                }
-                *sample = Some(sample_buffer);
-                self.samples_remaining -= 1;
-                if self.samples_remaining == 0 {
-                    self.start = self.loop_enabled;
-                    if !self.loop_enabled {
-                        self.irq_occurred = self.irq_enabled;
-                    }
-                }
-                if self.marked_not_ready {
-                    cpu.borrow_mut().set_rdy(true);
-                    self.marked_not_ready = false;
-                }
-                self.dma_state = DmcDmaState::NoDma;
+    fn advance(&mut self) {
+        if self.current_address == 0xFFFF {
+            self.current_address = 0x8000
+        } else {
+            self.current_address += 1;
+        }
+        self.samples_remaining -= 1;
+        if self.samples_remaining == 0 {
+            self.start = self.loop_enabled;
+            if !self.loop_enabled {
+                self.irq_occurred = self.irq_enabled;
             }
-            _ => (),
         }
     }
 
@@ -263,9 +251,56 @@ impl MemoryReader {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
-enum DmcDmaState {
-    NoDma,
-    Requested,
-    Executing,
+impl SaveState for DMCChannel {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("DMC");
+        writer.write_u8(self.period_index);
+
+        let reader = &self.memory_reader;
+        writer.write_bool(reader.irq_enabled);
+        writer.write_bool(reader.irq_occurred);
+        writer.write_bool(reader.loop_enabled);
+        writer.write_u16(reader.samples_remaining);
+        writer.write_u16(reader.sample_address);
+        writer.write_u16(reader.current_address);
+        writer.write_u16(reader.sample_length);
+        writer.write_bool(reader.start);
+
+        self.frequency_timer.save_state(writer);
+
+        let output = &self.output_unit;
+        writer.write_u8(output.bits_remaining);
+        writer.write_bool(output.silence);
+        writer.write_u8(output.shift_register);
+        writer.write_bool(output.sample_buffer.is_some());
+        writer.write_u8(output.sample_buffer.unwrap_or(0));
+        writer.write_u8(output.output);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("DMC")?;
+        self.period_index = reader.read_u8()?;
+
+        let memory_reader = &mut self.memory_reader;
+        memory_reader.irq_enabled = reader.read_bool()?;
+        memory_reader.irq_occurred = reader.read_bool()?;
+        memory_reader.loop_enabled = reader.read_bool()?;
+        memory_reader.samples_remaining = reader.read_u16()?;
+        memory_reader.sample_address = reader.read_u16()?;
+        memory_reader.current_address = reader.read_u16()?;
+        memory_reader.sample_length = reader.read_u16()?;
+        memory_reader.start = reader.read_bool()?;
+
+        self.frequency_timer.load_state(reader)?;
+
+        let output = &mut self.output_unit;
+        output.bits_remaining = reader.read_u8()?;
+        output.silence = reader.read_bool()?;
+        output.shift_register = reader.read_u8()?;
+        let has_sample = reader.read_bool()?;
+        let sample = reader.read_u8()?;
+        output.sample_buffer = if has_sample { Some(sample) } else { None };
+        output.output = reader.read_u8()?;
+        Ok(())
+    }
 }
@@ -1,4 +1,12 @@
-use crate::nes::apu::{APUCycleType, SoundEnableFlags};
+use anyhow::Result;
+
+use crate::{
+    nes::{
+        apu::{APUCycleType, SoundEnableFlags},
+        apu_inspector::NoiseState,
+    },
+    save_state::{SaveState, StateReader, StateWriter},
+};
 
 use super::{Channel, Envelope, FrequencyTimer, LengthCounter};
 
@@ -22,6 +30,18 @@ impl NoiseChannel {
             enabled: false,
         }
     }
+
+    pub fn state(&self) -> NoiseState {
+        NoiseState {
+            enabled: self.enabled,
+            short_mode: self.sequencer.mode,
+            period_index: self.sequencer.period_index,
+            period: self.frequency_timer.period,
+            envelope: self.envelope.state(),
+            length_counter: self.length_counter.value,
+            length_halted: self.length_counter.halted,
+        }
+    }
 }
 const NOISE_PERIOD_LOOKUP: [u16; 16] = [
     4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
@@ -132,3 +152,32 @@ impl NoiseSequencer {
         self.shift_register & 1 == 0
     }
 }
+
+impl SaveState for NoiseChannel {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("Noise");
+        self.envelope.save_state(writer);
+        self.length_counter.save_state(writer);
+        self.frequency_timer.save_state(writer);
+
+        writer.write_bool(self.sequencer.mode);
+        writer.write_u8(self.sequencer.period_index);
+        writer.write_u16(self.sequencer.shift_register);
+
+        writer.write_bool(self.enabled);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("Noise")?;
+        self.envelope.load_state(reader)?;
+        self.length_counter.load_state(reader)?;
+        self.frequency_timer.load_state(reader)?;
+
+        self.sequencer.mode = reader.read_bool()?;
+        self.sequencer.period_index = reader.read_u8()?;
+        self.sequencer.shift_register = reader.read_u16()?;
+
+        self.enabled = reader.read_bool()?;
+        Ok(())
+    }
+}
@@ -1,4 +1,12 @@
-use crate::nes::apu::{APUCycleType, SoundEnableFlags};
+use anyhow::Result;
+
+use crate::{
+    nes::{
+        apu::{APUCycleType, SoundEnableFlags},
+        apu_inspector::{PulseState, SweepState},
+    },
+    save_state::{SaveState, StateReader, StateWriter},
+};
 
 use super::{Channel, Envelope, FrequencyTimer, LengthCounter};
 
@@ -9,6 +17,7 @@ pub struct PulseChannel {
     frequency_timer: FrequencyTimer,
     length_counter: LengthCounter,
     sequencer: PulseSequencer,
+    has_sweep: bool,
 
     enabled: bool,
 }
@@ -20,10 +29,40 @@ impl PulseChannel {
             frequency_timer: FrequencyTimer::new(true),
             length_counter: LengthCounter::new(),
             sequencer: PulseSequencer::new(),
+            has_sweep: true,
 
             enabled: false,
         }
     }
+
+    /**
+     * Expansion audio pulses, e.g. MMC5's, are the same as the APU's minus the
+     * sweep unit. Writes to the sweep register are ignored and low periods aren't muted.
+     */
+    pub fn new_without_sweep() -> Self {
+        Self {
+            has_sweep: false,
+            ..Self::new(false)
+        }
+    }
+
+    pub fn state(&self) -> PulseState {
+        PulseState {
+            enabled: self.enabled,
+            duty: self.sequencer.duty_cycle,
+            period: self.frequency_timer.period,
+            envelope: self.envelope.state(),
+            sweep: self.has_sweep.then_some(SweepState {
+                enabled: self.sweep.enabled,
+                period: self.sweep.period,
+                negate: self.sweep.negative,
+                shift: self.sweep.shift_count,
+                muting: self.sweep.muting,
+            }),
+            length_counter: self.length_counter.value,
+            length_halted: self.length_counter.halted,
+        }
+    }
 }
 impl Channel for PulseChannel {
     fn set_register(&mut self, n: u8, value: u8) -> u8 {
@@ -35,7 +74,9 @@ impl Channel for PulseChannel {
                 self.length_counter.halted = self.envelope.loop_enable;
             }
             1 => {
-                self.sweep.load_bits(value);
+                if self.has_sweep {
+                    self.sweep.load_bits(value);
+                }
             }
             2 => {
                 self.frequency_timer.load_low_bits(value);
@@ -85,7 +126,10 @@ impl Channel for PulseChannel {
             self.sequencer.advance_position();
         }
 
-        if self.enabled && self.sweep.gate() && self.sequencer.gate() && self.length_counter.gate()
+        if self.enabled
+            && (!self.has_sweep || self.sweep.gate())
+            && self.sequencer.gate()
+            && self.length_counter.gate()
         {
             self.envelope.output
         } else {
@@ -98,7 +142,9 @@ impl Channel for PulseChannel {
     }
 
     fn half_frame_clock(&mut self) {
-        self.frequency_timer.period = self.sweep.half_frame_clock(self.frequency_timer.period);
+        if self.has_sweep {
+            self.frequency_timer.period = self.sweep.half_frame_clock(self.frequency_timer.period);
+        }
 
         self.length_counter.half_frame_clock();
     }
@@ -224,3 +270,54 @@ impl PulseSequencer {
         (self.duty_cycle << 6) & 0b11000000
     }
 }
+
+impl SaveState for PulseChannel {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("Pulse");
+        self.envelope.save_state(writer);
+
+        writer.write_bool(self.sweep.enabled);
+        writer.write_u8(self.sweep.period);
+        writer.write_bool(self.sweep.negative);
+        writer.write_u8(self.sweep.shift_count);
+        writer.write_bool(self.sweep.twos_complement);
+        writer.write_bool(self.sweep.muting);
+        writer.write_bool(self.sweep.start);
+        writer.write_u8(self.sweep.divider);
+
+        self.frequency_timer.save_state(writer);
+        self.length_counter.save_state(writer);
+
+        writer.write_u8(self.sequencer.duty_cycle);
+        writer.write_u8(self.sequencer.sequence);
+        writer.write_u8(self.sequencer.position);
+        writer.write_bool(self.sequencer.start);
+
+        writer.write_bool(self.enabled);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("Pulse")?;
+        self.envelope.load_state(reader)?;
+
+        self.sweep.enabled = reader.read_bool()?;
+        self.sweep.period = reader.read_u8()?;
+        self.sweep.negative = reader.read_bool()?;
+        self.sweep.shift_count = reader.read_u8()?;
+        self.sweep.twos_complement = reader.read_bool()?;
+        self.sweep.muting = reader.read_bool()?;
+        self.sweep.start = reader.read_bool()?;
+        self.sweep.divider = reader.read_u8()?;
+
+        self.frequency_timer.load_state(reader)?;
+        self.length_counter.load_state(reader)?;
+
+        self.sequencer.duty_cycle = reader.read_u8()?;
+        self.sequencer.sequence = reader.read_u8()?;
+        self.sequencer.position = reader.read_u8()?;
+        self.sequencer.start = reader.read_bool()?;
+
+        self.enabled = reader.read_bool()?;
+        Ok(())
+    }
+}
@@ -1,4 +1,12 @@
-use crate::nes::apu::{APUCycleType, SoundEnableFlags};
+use anyhow::Result;
+
+use crate::{
+    nes::{
+        apu::{APUCycleType, SoundEnableFlags},
+        apu_inspector::TriangleState,
+    },
+    save_state::{SaveState, StateReader, StateWriter},
+};
 
 use super::{Channel, FrequencyTimer, LengthCounter};
 
@@ -22,6 +30,18 @@ impl TriangleChannel {
             enabled: false,
         }
     }
+
+    pub fn state(&self) -> TriangleState {
+        TriangleState {
+            enabled: self.enabled,
+            period: self.frequency_timer.period,
+            linear_counter: self.linear_counter.count,
+            linear_reload: self.linear_counter.period,
+            control: self.linear_counter.control_flag,
+            length_counter: self.length_counter.value,
+            step: self.sequencer.output,
+        }
+    }
 }
 impl Channel for TriangleChannel {
     fn set_register(&mut self, n: u8, value: u8) -> u8 {
@@ -163,3 +183,38 @@ impl LinearCounter {
         self.count != 0
     }
 }
+
+impl SaveState for TriangleChannel {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("Triangle");
+        writer.write_bool(self.linear_counter.start);
+        writer.write_u8(self.linear_counter.period);
+        writer.write_u8(self.linear_counter.count);
+        writer.write_bool(self.linear_counter.control_flag);
+
+        self.length_counter.save_state(writer);
+        self.frequency_timer.save_state(writer);
+
+        writer.write_bool(self.sequencer.count_up);
+        writer.write_u8(self.sequencer.output);
+
+        writer.write_bool(self.enabled);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("Triangle")?;
+        self.linear_counter.start = reader.read_bool()?;
+        self.linear_counter.period = reader.read_u8()?;
+        self.linear_counter.count = reader.read_u8()?;
+        self.linear_counter.control_flag = reader.read_bool()?;
+
+        self.length_counter.load_state(reader)?;
+        self.frequency_timer.load_state(reader)?;
+
+        self.sequencer.count_up = reader.read_bool()?;
+        self.sequencer.output = reader.read_u8()?;
+
+        self.enabled = reader.read_bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+// source https://www.nesdev.org/wiki/DMA
+// The 2A03 has a single DMA unit that does both OAM and DMC transfers. It pulls RDY
+// low, which only halts the CPU on a read cycle. From then on every cycle belongs to
+// the DMA unit: reads happen on get cycles and writes on put cycles. A cycle with
+// nothing to transfer repeats the read the CPU was halted on, which is why reading
+// $2007 or the controllers while a DMC sample is being fetched can go wrong.
+
+use std::{cell::RefCell, rc::Rc};
+
+use anyhow::Result;
+
+use crate::{
+    cpu::CPU,
+    nes::apu::APUCycleType,
+    save_state::{SaveState, SaveStateError, StateReader, StateWriter},
+};
+
+const OAM_DATA_ADDR: u16 = 0x2004;
+const JOYPAD1_ADDR: u16 = 0x4016;
+const JOYPAD2_ADDR: u16 = 0x4017;
+
+pub struct DMA {
+    oam: OamDma,
+    oam_data: u8,
+    dmc: DmcDma,
+    // whether the last cycle on the bus was a read of the address the cpu is halted on
+    repeating_halted_read: bool,
+}
+
+impl DMA {
+    pub fn new() -> Self {
+        Self {
+            oam: OamDma::Idle,
+            oam_data: 0,
+            dmc: DmcDma::Idle,
+            repeating_halted_read: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /**
+     * Requested by the cpu writing $4014, so the cpu can't be borrowed to halt it
+     * until the next cycle's clock, which still comes before the cpu's next read
+     */
+    pub fn request_oam(&mut self, page: u8) {
+        self.oam = OamDma::Requested(page);
+    }
+
+    pub fn request_dmc(&mut self, addr: u16, cpu: &Rc<RefCell<CPU>>) {
+        if self.dmc == DmcDma::Idle {
+            self.dmc = DmcDma::Halting(addr);
+            cpu.borrow_mut().set_rdy(false);
+        }
+    }
+
+    /**
+     * Runs one cpu cycle. This has to happen before the cpu is clocked, so that the
+     * cpu sees RDY before it decides whether to halt. Returns the DMC sample byte
+     * when one is fetched.
+     */
+    pub fn clock(&mut self, cycle_type: APUCycleType, cpu: &Rc<RefCell<CPU>>) -> Option<u8> {
+        let mut cpu = cpu.borrow_mut();
+        if let OamDma::Requested(_) = self.oam {
+            cpu.set_rdy(false);
+        }
+        let Some(halted_at) = cpu.halted_at() else {
+            // if the cpu halts this cycle, it will be on a read of the halted address
+            self.repeating_halted_read = true;
+            return None;
+        };
+
+        if self.oam == OamDma::Idle && self.dmc == DmcDma::Idle {
+            // nothing left to do, so the cpu gets the bus back to repeat its read
+            cpu.set_rdy(true);
+            return None;
+        }
+
+        // the DMC needs a dummy cycle after the halt before it can fetch. If OAM DMA
+        // is running, both happen during OAM cycles and cost nothing
+        let dmc_ready = match self.dmc {
+            DmcDma::Halting(addr) => {
+                self.dmc = DmcDma::Ready(addr);
+                None
+            }
+            DmcDma::Ready(addr) => Some(addr),
+            DmcDma::Idle => None,
+        };
+        if let OamDma::Requested(page) = self.oam {
+            self.oam = OamDma::Get(page, 0);
+        }
+
+        let mut sample = None;
+        match (cycle_type, self.oam, dmc_ready) {
+            // the DMC takes priority over OAM, which then has to realign
+            (APUCycleType::Get, _, Some(addr)) => {
+                sample = Some(cpu.read_sample_byte(addr));
+                self.dmc = DmcDma::Idle;
+                self.repeating_halted_read = false;
+            }
+            (APUCycleType::Get, OamDma::Get(page, offset), _) => {
+                self.oam_data = cpu.read_bus_byte(u16::from_be_bytes([page, offset]));
+                self.oam = OamDma::Put(page, offset);
+                self.repeating_halted_read = false;
+            }
+            (APUCycleType::Put, OamDma::Put(page, offset), _) => {
+                cpu.write_bus_byte(OAM_DATA_ADDR, self.oam_data);
+                self.oam = if offset == 0xFF {
+                    OamDma::Idle
+                } else {
+                    OamDma::Get(page, offset + 1)
+                };
+                self.repeating_halted_read = false;
+            }
+            // dummy and alignment cycles
+            _ => self.repeat_halted_read(&mut cpu, halted_at),
+        }
+        sample
+    }
+
+    fn repeat_halted_read(&mut self, cpu: &mut CPU, addr: u16) {
+        // the controllers are only clocked at the start of a run of reads, so
+        // back to back reads only count once
+        let merged = self.repeating_halted_read && (addr == JOYPAD1_ADDR || addr == JOYPAD2_ADDR);
+        if !merged {
+            cpu.read_bus_byte(addr);
+        }
+        self.repeating_halted_read = true;
+    }
+}
+
+impl Default for DMA {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum OamDma {
+    Idle,
+    Requested(u8),
+    // page and offset of the next byte
+    Get(u8, u8),
+    Put(u8, u8),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum DmcDma {
+    Idle,
+    // waiting for the cpu to halt
+    Halting(u16),
+    // the dummy cycle is done, waiting for a get cycle
+    Ready(u16),
+}
+
+impl SaveState for DMA {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("DMA");
+        match self.oam {
+            OamDma::Idle => writer.write_u8(0),
+            OamDma::Requested(page) => {
+                writer.write_u8(1);
+                writer.write_u8(page);
+            }
+            OamDma::Get(page, offset) => {
+                writer.write_u8(2);
+                writer.write_u8(page);
+                writer.write_u8(offset);
+            }
+            OamDma::Put(page, offset) => {
+                writer.write_u8(3);
+                writer.write_u8(page);
+                writer.write_u8(offset);
+            }
+        }
+        writer.write_u8(self.oam_data);
+        match self.dmc {
+            DmcDma::Idle => writer.write_u8(0),
+            DmcDma::Halting(addr) => {
+                writer.write_u8(1);
+                writer.write_u16(addr);
+            }
+            DmcDma::Ready(addr) => {
+                writer.write_u8(2);
+                writer.write_u16(addr);
+            }
+        }
+        writer.write_bool(self.repeating_halted_read);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("DMA")?;
+        self.oam = match reader.read_u8()? {
+            0 => OamDma::Idle,
+            1 => OamDma::Requested(reader.read_u8()?),
+            2 => OamDma::Get(reader.read_u8()?, reader.read_u8()?),
+            3 => OamDma::Put(reader.read_u8()?, reader.read_u8()?),
+            n => Err(SaveStateError::InvalidValue("oam dma state", n as u32))?,
+        };
+        self.oam_data = reader.read_u8()?;
+        self.dmc = match reader.read_u8()? {
+            0 => DmcDma::Idle,
+            1 => DmcDma::Halting(reader.read_u16()?),
+            2 => DmcDma::Ready(reader.read_u16()?),
+            n => Err(SaveStateError::InvalidValue("dmc dma state", n as u32))?,
+        };
+        self.repeating_halted_read = reader.read_bool()?;
+        Ok(())
+    }
+}
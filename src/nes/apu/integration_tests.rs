@@ -0,0 +1,142 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::{APU, APUCycleType};
+
+use crate::{
+    bus::BusDevice,
+    cpu::{CPU, CPUType},
+    ram::RAM,
+};
+
+fn create_configuration(program: &[u8]) -> (Rc<RefCell<CPU>>, Rc<RefCell<APU>>) {
+    let cpu = Rc::new(RefCell::new(CPU::new(CPUType::RP2A03)));
+    let ram = Rc::new(RefCell::new(RAM::new(0x0000, 0x1FFF, 0x07FF)));
+    let apu = Rc::new(RefCell::new(APU::new(cpu.clone())));
+    cpu.borrow_mut().add_device(ram.clone());
+    cpu.borrow_mut().add_device(apu.clone());
+
+    ram.borrow_mut().raw()[0x0200..0x0200 + program.len()].copy_from_slice(program);
+    cpu.borrow_mut().reset_to(0x0200);
+    (cpu, apu)
+}
+
+fn clock(cpu: &Rc<RefCell<CPU>>, apu: &Rc<RefCell<APU>>) {
+    let _ = apu.borrow_mut().clock();
+    cpu.borrow_mut().clock();
+}
+
+fn run_until_released(cpu: &Rc<RefCell<CPU>>, apu: &Rc<RefCell<APU>>) -> u32 {
+    let mut cycles = 0;
+    while cpu.borrow().halted_at().is_some() {
+        clock(cpu, apu);
+        cycles += 1;
+    }
+    cycles
+}
+
+fn strobe_joypad1(apu: &Rc<RefCell<APU>>, buttons: u8) {
+    let mut apu = apu.borrow_mut();
+    apu.set_input_port1(buttons);
+    apu.write(0x4016, 1);
+    let _ = apu.clock();
+    apu.write(0x4016, 0);
+}
+
+#[test]
+fn test_dmc_dma_steals_cycles() {
+    for alignment in 0..=1 {
+        // NOPs
+        let (cpu, apu) = create_configuration(&[0xEA; 0x20]);
+        apu.borrow_mut().cycle_type = if alignment == 0 {
+            APUCycleType::Put
+        } else {
+            APUCycleType::Get
+        };
+
+        let cycles = cpu.borrow().cycles();
+        let _ = apu.borrow_mut().clock();
+        apu.borrow_mut().dma.request_dmc(0x0200, &cpu);
+        cpu.borrow_mut().clock();
+        assert!(cpu.borrow().halted_at().is_some());
+
+        // a dummy cycle, maybe an alignment cycle, the fetch and then the repeated read
+        assert_eq!(3 + alignment, run_until_released(&cpu, &apu));
+        assert_eq!(cycles + 1, cpu.borrow().cycles());
+    }
+}
+
+#[test]
+fn test_dmc_dma_during_oam_dma() {
+    let (cpu, apu) = create_configuration(&[0xEA; 0x20]);
+    apu.borrow_mut().cycle_type = APUCycleType::Get;
+    apu.borrow_mut().write(0x4014, 0x02);
+
+    let cycles = cpu.borrow().cycles();
+    clock(&cpu, &apu);
+    let mut stolen = 0;
+    while cpu.borrow().halted_at().is_some() {
+        if stolen == 100 {
+            apu.borrow_mut().dma.request_dmc(0x0200, &cpu);
+        }
+        clock(&cpu, &apu);
+        stolen += 1;
+    }
+
+    // the DMC fetch and realigning OAM DMA add 2 cycles
+    assert_eq!(512 + 1 + 2, stolen);
+    assert_eq!(cycles + 1, cpu.borrow().cycles());
+}
+
+#[test]
+fn test_dmc_dma_halts_on_read_cycle() {
+    // STA $00 writes on its last cycle
+    let (cpu, apu) = create_configuration(&[0x85, 0x00, 0xEA]);
+    clock(&cpu, &apu);
+    clock(&cpu, &apu);
+
+    let _ = apu.borrow_mut().clock();
+    apu.borrow_mut().dma.request_dmc(0x0200, &cpu);
+    cpu.borrow_mut().clock();
+    assert_eq!(None, cpu.borrow().halted_at());
+
+    // the NOP's opcode fetch is where it stops
+    clock(&cpu, &apu);
+    assert_eq!(Some(0x0202), cpu.borrow().halted_at());
+}
+
+#[test]
+fn test_dmc_dma_joypad_glitch() {
+    // LDA $4016, LDA $4016
+    let program = [0xAD, 0x16, 0x40, 0xAD, 0x16, 0x40];
+    let buttons = 0b0000_0101;
+
+    let (cpu, apu) = create_configuration(&program);
+    strobe_joypad1(&apu, buttons);
+    for _ in 0..4 {
+        clock(&cpu, &apu);
+    }
+    assert_eq!(1, cpu.borrow().a & 1);
+    for _ in 0..4 {
+        clock(&cpu, &apu);
+    }
+    assert_eq!(0, cpu.borrow().a & 1);
+
+    // the DMC fetch lands on the first read, which then happens twice
+    let (cpu, apu) = create_configuration(&program);
+    strobe_joypad1(&apu, buttons);
+    for _ in 0..3 {
+        clock(&cpu, &apu);
+    }
+    let _ = apu.borrow_mut().clock();
+    apu.borrow_mut().dma.request_dmc(0x0200, &cpu);
+    cpu.borrow_mut().clock();
+    assert_eq!(Some(0x4016), cpu.borrow().halted_at());
+    run_until_released(&cpu, &apu);
+
+    // the back to back dummy reads only clock the joypad once, but the repeated
+    // read after the fetch clocks it again, so one button is lost
+    for _ in 0..4 {
+        clock(&cpu, &apu);
+    }
+    assert_eq!(1, cpu.borrow().a & 1);
+}
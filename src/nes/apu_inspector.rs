@@ -0,0 +1,367 @@
+// What each of the APU's channels is doing, for tools: their registers and
+// internal counters, and what each contributed to the last sample. Channels can
+// also be muted, which leaves them out of the mix without affecting anything else.
+
+use std::fmt::Display;
+
+use super::NES;
+
+const CPU_CLOCK_SPEED: f32 = 1789773.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ApuChannel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
+}
+
+impl ApuChannel {
+    pub const ALL: [ApuChannel; 5] = [
+        ApuChannel::Pulse1,
+        ApuChannel::Pulse2,
+        ApuChannel::Triangle,
+        ApuChannel::Noise,
+        ApuChannel::Dmc,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ApuChannel::Pulse1 => "pulse1",
+            ApuChannel::Pulse2 => "pulse2",
+            ApuChannel::Triangle => "triangle",
+            ApuChannel::Noise => "noise",
+            ApuChannel::Dmc => "dmc",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.name() == name)
+    }
+
+    // where the channel is in the APU's registers and outputs
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct EnvelopeState {
+    pub constant_volume: bool,
+    pub looping: bool,
+    /**
+     * The constant volume, or the envelope's period
+     */
+    pub volume: u8,
+    pub decay_level: u8,
+}
+
+impl EnvelopeState {
+    /**
+     * The volume the channel plays at when it isn't silenced
+     */
+    pub fn output(&self) -> u8 {
+        if self.constant_volume {
+            self.volume
+        } else {
+            self.decay_level
+        }
+    }
+}
+
+impl Display for EnvelopeState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.constant_volume {
+            write!(f, "volume {:2}", self.volume)?;
+        } else {
+            write!(f, "decay {:2} period {:2}", self.decay_level, self.volume)?;
+        }
+        if self.looping {
+            write!(f, " loop")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SweepState {
+    pub enabled: bool,
+    pub period: u8,
+    pub negate: bool,
+    pub shift: u8,
+    /**
+     * The current or target period is out of range, which silences the channel
+     */
+    pub muting: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PulseState {
+    pub enabled: bool,
+    /**
+     * 0-3 for 12.5%, 25%, 50% and 75% (25% negated)
+     */
+    pub duty: u8,
+    pub period: u16,
+    pub envelope: EnvelopeState,
+    /**
+     * None for expansion audio pulses, which don't have one
+     */
+    pub sweep: Option<SweepState>,
+    pub length_counter: u8,
+    pub length_halted: bool,
+}
+
+impl PulseState {
+    pub fn frequency(&self) -> f32 {
+        CPU_CLOCK_SPEED / (16.0 * (self.period as f32 + 1.0))
+    }
+}
+
+impl Display for PulseState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} duty {} period ${:03X} ({:7.1} Hz) {} length {:3}",
+            on_off(self.enabled),
+            self.duty,
+            self.period,
+            self.frequency(),
+            self.envelope,
+            self.length_counter
+        )?;
+        if self.length_halted {
+            write!(f, " halted")?;
+        }
+        if let Some(sweep) = self.sweep
+            && sweep.enabled
+        {
+            let sign = if sweep.negate { '-' } else { '+' };
+            write!(f, " sweep {sign}{} every {}", sweep.shift, sweep.period)?;
+        }
+        if self.sweep.is_some_and(|s| s.muting) {
+            write!(f, " muted")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TriangleState {
+    pub enabled: bool,
+    pub period: u16,
+    pub linear_counter: u8,
+    pub linear_reload: u8,
+    /**
+     * Also halts the length counter
+     */
+    pub control: bool,
+    pub length_counter: u8,
+    /**
+     * Where the channel is in its 32 step sequence, as the level it outputs
+     */
+    pub step: u8,
+}
+
+impl TriangleState {
+    pub fn frequency(&self) -> f32 {
+        CPU_CLOCK_SPEED / (32.0 * (self.period as f32 + 1.0))
+    }
+}
+
+impl Display for TriangleState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} period ${:03X} ({:7.1} Hz) linear {:3}/{:3} length {:3} step {:2}",
+            on_off(self.enabled),
+            self.period,
+            self.frequency(),
+            self.linear_counter,
+            self.linear_reload,
+            self.length_counter,
+            self.step
+        )?;
+        if self.control {
+            write!(f, " control")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct NoiseState {
+    pub enabled: bool,
+    /**
+     * The short, metallic sounding 93 step sequence rather than the 32767 step one
+     */
+    pub short_mode: bool,
+    /**
+     * 0-15, which picks the period from a table
+     */
+    pub period_index: u8,
+    pub period: u16,
+    pub envelope: EnvelopeState,
+    pub length_counter: u8,
+    pub length_halted: bool,
+}
+
+impl Display for NoiseState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} period {:X} ({:4}) {} length {:3}",
+            on_off(self.enabled),
+            if self.short_mode { "short" } else { "long " },
+            self.period_index,
+            self.period,
+            self.envelope,
+            self.length_counter
+        )?;
+        if self.length_halted {
+            write!(f, " halted")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DmcState {
+    /**
+     * There are sample bytes left to play
+     */
+    pub enabled: bool,
+    pub irq_enabled: bool,
+    pub irq: bool,
+    pub looping: bool,
+    /**
+     * 0-15, which picks the period from a table
+     */
+    pub rate_index: u8,
+    pub period: u16,
+    pub sample_address: u16,
+    pub sample_length: u16,
+    pub current_address: u16,
+    pub bytes_remaining: u16,
+    /**
+     * The 7 bit level the channel is outputting
+     */
+    pub output_level: u8,
+}
+
+impl Display for DmcState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} rate {:X} ({:3}) sample ${:04X} length {:4} at ${:04X} remaining {:4} level {:3}",
+            on_off(self.enabled),
+            self.rate_index,
+            self.period,
+            self.sample_address,
+            self.sample_length,
+            self.current_address,
+            self.bytes_remaining,
+            self.output_level
+        )?;
+        for (set, name) in [
+            (self.looping, "loop"),
+            (self.irq_enabled, "irq-enabled"),
+            (self.irq, "irq"),
+        ] {
+            if set {
+                write!(f, " {name}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ApuChannels {
+    pub pulse1: PulseState,
+    pub pulse2: PulseState,
+    pub triangle: TriangleState,
+    pub noise: NoiseState,
+    pub dmc: DmcState,
+}
+
+impl Display for ApuChannels {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "pulse1   {}", self.pulse1)?;
+        writeln!(f, "pulse2   {}", self.pulse2)?;
+        writeln!(f, "triangle {}", self.triangle)?;
+        writeln!(f, "noise    {}", self.noise)?;
+        write!(f, "dmc      {}", self.dmc)
+    }
+}
+
+/**
+ * The level each channel was outputting for the last sample: 0-15, or 0-127 for the DMC.
+ * Muted channels still report theirs.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct ChannelSamples {
+    pub levels: [u8; 5],
+}
+
+impl ChannelSamples {
+    pub fn level(&self, channel: ApuChannel) -> u8 {
+        self.levels[channel.index()]
+    }
+
+    /**
+     * The channel on its own, through the APU's mixer so it's on the same 0.0 to 1.0
+     * scale as the mixed output. The mixer isn't linear, so the channels don't
+     * quite add up to the mix.
+     */
+    pub fn sample(&self, channel: ApuChannel) -> f32 {
+        let level = self.level(channel) as f32;
+        if level == 0.0 {
+            return 0.0;
+        }
+        match channel {
+            ApuChannel::Pulse1 | ApuChannel::Pulse2 => 95.88 / ((8128.0 / level) + 100.0),
+            ApuChannel::Triangle => tnd(level / 8227.0),
+            ApuChannel::Noise => tnd(level / 12241.0),
+            ApuChannel::Dmc => tnd(level / 22638.0),
+        }
+    }
+}
+
+fn tnd(weighted: f32) -> f32 {
+    159.79 / (1.0 / weighted + 100.0)
+}
+
+fn on_off(enabled: bool) -> &'static str {
+    if enabled { "on " } else { "off" }
+}
+
+impl NES {
+    pub fn apu_channels(&self) -> ApuChannels {
+        self.apu.borrow().channels()
+    }
+
+    /**
+     * What each channel put into the sample the last clock returned
+     */
+    pub fn channel_samples(&self) -> ChannelSamples {
+        ChannelSamples {
+            levels: self.apu.borrow().channel_outputs(),
+        }
+    }
+
+    /**
+     * Leaves the channel out of the mixed output. Soloing a channel is muting the
+     * others. Mutes last until they're cleared, across resets and save states.
+     */
+    pub fn set_channel_muted(&mut self, channel: ApuChannel, muted: bool) {
+        self.apu
+            .borrow_mut()
+            .set_channel_muted(channel.index(), muted);
+    }
+
+    pub fn is_channel_muted(&self, channel: ApuChannel) -> bool {
+        self.apu.borrow().is_channel_muted(channel.index())
+    }
+}
@@ -1,9 +1,12 @@
 mod mappers;
 mod memory_region;
 
+#[cfg(test)]
+mod unit_tests;
+
 use anyhow::Result;
 use std::{
-    cell::RefCell,
+    cell::{Ref, RefCell, RefMut},
     fs::File,
     io::{BufReader, BufWriter, Read, Write},
     path::{Path, PathBuf},
@@ -14,6 +17,11 @@ use thiserror::Error;
 use mappers::Mapper;
 
 use crate::bus::{BusDevice, InterruptFlags};
+use crate::nes::mapper_inspector::MapperDescription;
+use crate::nes::memory_domains::MemoryDomain;
+use crate::nes::mixer::ExpansionAudioSample;
+use crate::nes::ppu::{PpuFetchContext, PpuFetchObserver};
+use crate::save_state::{SaveState, SaveStateError, StateReader, StateWriter};
 
 use self::{
     mappers::NulMapper,
@@ -36,6 +44,8 @@ static SRAM_PAGE_SIZE: usize = 0x2000;
 static DEFAULT_SRAM_SIZE: usize = 0x8000;
 static PRG_ROM_PAGE_SIZE: usize = 0x4000;
 static CHR_ROM_PAGE_SIZE: usize = 0x2000;
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
 // NES VRAM is actually half this, 0x0800, but by doubling we can use some 4 screen
 // mappers without building more ram into the cart.
 // mirror_vram() takes care of making sure we don't see memory outside the range we should
@@ -45,72 +55,32 @@ static VRAM_SIZE: usize = 0x1000;
 impl Cartridge {
     pub fn load(file_name: &str) -> Result<Box<dyn Mapper>> {
         let file = File::open(file_name)?;
+        let file_size = file.metadata()?.len();
+        let core = CartridgeCore::load(file_name, BufReader::new(file), file_size)?;
 
-        let mut reader = BufReader::new(file);
-        let mut header = [0; 16];
+        let mapper_number = core.nes_header.mapper_number;
+        let submapper = core.nes_header.submapper;
+        mappers::get_mapper(mapper_number, submapper, core)
+    }
 
+    /**
+     * Reads just the PRG ROM, for looking at a cartridge without running it
+     */
+    pub fn read_prg_rom(file_name: &str) -> Result<Vec<u8>> {
+        let file = File::open(file_name)?;
+        let file_size = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let mut header = [0; HEADER_SIZE];
         reader.read_exact(&mut header)?;
-
         let nes_header = NesHeader::new(&header)?;
+        nes_header.check_file_size(file_size)?;
 
-        let rom_expansion_size = 0x2000;
-        let rom_expansion_vec = vec![0; rom_expansion_size];
-        let rom_expansion = MemoryRegion::new(
-            MemoryType::ROM_EXPANSION,
-            rom_expansion_vec,
-            0x4000,
-            0x5FFF,
-            true,
-        );
-
-        let mut sram_vec = vec![0; nes_header.sram_size];
-        if nes_header.sram_is_persistent {
-            Cartridge::load_sram(&mut sram_vec, file_name)?;
-        }
         if nes_header.has_trainer {
-            let mut trainer_ram = vec![0; 512];
-            reader.read_exact(&mut trainer_ram)?;
-            sram_vec[0x7000..(0x7000 + 512)].copy_from_slice(&trainer_ram[..512]);
-        }
-        let sram = MemoryRegion::new(MemoryType::SRAM, sram_vec, 0x6000, 0x7FFF, false);
-
-        let mut prg_rom_vec = vec![0; nes_header.prg_rom_size];
-        reader.read_exact(&mut prg_rom_vec)?;
-        let prg_rom = MemoryRegion::new(MemoryType::PRG_ROM, prg_rom_vec, 0x8000, 0xFFFF, true);
-
-        let mut chr_rom_vec = vec![0; nes_header.chr_rom_size];
-        if nes_header.chr_is_rom {
-            reader.read_exact(&mut chr_rom_vec)?;
+            reader.read_exact(&mut [0; TRAINER_SIZE])?;
         }
-        let chr_type = if nes_header.chr_is_rom {
-            MemoryType::CHR_ROM
-        } else {
-            MemoryType::CHR_RAM
-        };
-        let chr_ram =
-            MemoryRegion::new(chr_type, chr_rom_vec, 0x0000, 0x1FFF, nes_header.chr_is_rom);
-
-        // vram goes all the way to 0x3FFF even though palette ram occupies
-        // 0x3F00-0x3FFF. That's because the PPU 'shadow' reads
-        // vram even when reading palettes
-        let vram_vec = vec![0; VRAM_SIZE];
-        let mut vram = MemoryRegion::new(MemoryType::VRAM, vram_vec, 0x2000, 0x3FFF, false);
-        vram.set_bank_size_k(1);
-        vram.set_mirror_type(nes_header.mirror_type);
-
-        let mapper_number = nes_header.mapper_number;
-
-        let core = CartridgeCore {
-            nes_header,
-            cart_name: file_name.to_string(),
-            rom_expansion,
-            sram,
-            prg_rom,
-            chr_ram,
-            vram,
-        };
-
-        mappers::get_mapper(mapper_number, core)
+        let mut prg_rom = vec![0; nes_header.prg_rom_size];
+        reader.read_exact(&mut prg_rom)?;
+        Ok(prg_rom)
     }
 
     pub(crate) fn nul_cartridge() -> Box<dyn Mapper> {
@@ -144,25 +114,66 @@ impl Cartridge {
     }
 }
 
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum HeaderFormat {
+    INes,
+    Nes20,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    MultipleRegion,
+    Dendy,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem { ppu_type: u8, hardware_type: u8 },
+    Playchoice10,
+    Extended(u8),
+}
+
 pub struct NesHeader {
-    _ines_ver: u8,
+    format: HeaderFormat,
     mirror_type: MirrorType,
-    #[allow(dead_code)]
     sram_is_persistent: bool,
     chr_is_rom: bool,
     has_trainer: bool,
     prg_rom_size: usize,
     chr_rom_size: usize,
+    /**
+     * Total PRG RAM mapped at 0x6000-0x7FFF, volatile and battery backed combined
+     */
     sram_size: usize,
-    mapper_number: u8,
+    prg_ram_size: usize,
+    prg_nvram_size: usize,
+    chr_ram_size: usize,
+    chr_nvram_size: usize,
+    mapper_number: u16,
+    submapper: u8,
+    timing: Timing,
+    console_type: ConsoleType,
+    misc_rom_count: u8,
+    /**
+     * See https://www.nesdev.org/wiki/NES_2.0#Default_Expansion_Device
+     * 1 is the standard controller
+     */
+    expansion_device: u8,
 }
 impl NesHeader {
-    fn new(header: &[u8; 16]) -> Result<NesHeader> {
+    fn new(header: &[u8; HEADER_SIZE]) -> Result<NesHeader> {
         if header[0..4] != NES_TAG {
             Err(CartridgeError::UnrecognizedFileFormat)?;
         }
 
-        let _ines_ver = (header[7] >> 2) & 0x03;
+        let format = match (header[7] >> 2) & 0x03 {
+            0 => HeaderFormat::INes,
+            2 => HeaderFormat::Nes20,
+            _ => Err(CartridgeError::UnsupportedInesVersion)?,
+        };
 
         let four_screen = header[6] & 0x08 != 0;
         let has_trainer = header[6] & 0x04 != 0;
@@ -175,50 +186,184 @@ impl NesHeader {
             (true, _) => MirrorType::FourScreen,
         };
 
-        let prg_rom_size = (header[4] as usize) * PRG_ROM_PAGE_SIZE;
-        let mut chr_rom_size = (header[5] as usize) * CHR_ROM_PAGE_SIZE;
-        let chr_is_rom = if chr_rom_size == 0 {
-            chr_rom_size = CHR_ROM_PAGE_SIZE;
-            false
+        let mut result = Self {
+            format,
+            mirror_type,
+            sram_is_persistent,
+            chr_is_rom: true,
+            has_trainer,
+            prg_rom_size: 0,
+            chr_rom_size: 0,
+            sram_size: 0,
+            prg_ram_size: 0,
+            prg_nvram_size: 0,
+            chr_ram_size: 0,
+            chr_nvram_size: 0,
+            mapper_number: ((header[7] & 0xF0) | (header[6] >> 4)) as u16,
+            submapper: 0,
+            timing: Timing::Ntsc,
+            console_type: ConsoleType::Nes,
+            misc_rom_count: 0,
+            expansion_device: 1,
+        };
+
+        match format {
+            HeaderFormat::INes => result.parse_ines(header),
+            HeaderFormat::Nes20 => result.parse_nes20(header)?,
+        }
+
+        result.chr_is_rom = result.chr_rom_size != 0;
+        if !result.chr_is_rom {
+            // CHR RAM stands in for the CHR ROM
+            result.chr_rom_size =
+                (result.chr_ram_size + result.chr_nvram_size).max(CHR_ROM_PAGE_SIZE);
+        }
+
+        // mappers always see some memory at 0x6000-0x7FFF
+        result.sram_size = result.prg_ram_size + result.prg_nvram_size;
+        if result.sram_size == 0 {
+            result.sram_size = DEFAULT_SRAM_SIZE;
+        }
+
+        Ok(result)
+    }
+
+    fn parse_ines(&mut self, header: &[u8; HEADER_SIZE]) {
+        self.prg_rom_size = (header[4] as usize) * PRG_ROM_PAGE_SIZE;
+        self.chr_rom_size = (header[5] as usize) * CHR_ROM_PAGE_SIZE;
+
+        let prg_ram_size = (header[8] as usize) * SRAM_PAGE_SIZE;
+        if self.sram_is_persistent {
+            self.prg_nvram_size = prg_ram_size;
         } else {
-            true
+            self.prg_ram_size = prg_ram_size;
+        }
+    }
+
+    // https://www.nesdev.org/wiki/NES_2.0
+    fn parse_nes20(&mut self, header: &[u8; HEADER_SIZE]) -> Result<()> {
+        self.mapper_number |= ((header[8] & 0x0F) as u16) << 8;
+        self.submapper = header[8] >> 4;
+
+        self.prg_rom_size = NesHeader::rom_size(header[4], header[9] & 0x0F, PRG_ROM_PAGE_SIZE)?;
+        self.chr_rom_size = NesHeader::rom_size(header[5], header[9] >> 4, CHR_ROM_PAGE_SIZE)?;
+
+        self.prg_ram_size = NesHeader::ram_size(header[10] & 0x0F);
+        self.prg_nvram_size = NesHeader::ram_size(header[10] >> 4);
+        self.chr_ram_size = NesHeader::ram_size(header[11] & 0x0F);
+        self.chr_nvram_size = NesHeader::ram_size(header[11] >> 4);
+
+        self.timing = match header[12] & 0x03 {
+            0 => Timing::Ntsc,
+            1 => Timing::Pal,
+            2 => Timing::MultipleRegion,
+            3 => Timing::Dendy,
+            _ => unreachable!(),
         };
 
-        let mapper_number = (header[7] & 0xF0) | (header[6] >> 4);
+        self.console_type = match header[7] & 0x03 {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem {
+                ppu_type: header[13] & 0x0F,
+                hardware_type: header[13] >> 4,
+            },
+            2 => ConsoleType::Playchoice10,
+            3 => ConsoleType::Extended(header[13] & 0x0F),
+            _ => unreachable!(),
+        };
 
-        let mut sram_size = (header[8] as usize) * SRAM_PAGE_SIZE;
-        if sram_size == 0 {
-            sram_size = DEFAULT_SRAM_SIZE;
+        self.misc_rom_count = header[14] & 0x03;
+        self.expansion_device = header[15] & 0x3F;
+        Ok(())
+    }
+
+    /**
+     * When the high nibble is 0xF the low byte is an exponent and multiplier
+     * EEEEEE MM giving 2^E * (MM * 2 + 1) bytes. Otherwise the high nibble and
+     * low byte together count pages
+     */
+    fn rom_size(low: u8, high: u8, page_size: usize) -> Result<usize> {
+        if high == 0x0F {
+            let exponent = (low >> 2) as u32;
+            let multiplier = ((low & 0x03) as usize) * 2 + 1;
+            let size = 2usize
+                .checked_pow(exponent)
+                .and_then(|size| size.checked_mul(multiplier))
+                .ok_or(CartridgeError::InvalidRomSize(exponent, multiplier))?;
+            Ok(size)
+        } else {
+            Ok((((high as usize) << 8) | low as usize) * page_size)
         }
+    }
 
-        let chr_rom_ram = if chr_is_rom { "rom" } else { "ram" };
-        println!(
-            "sram_size {:#06x} | peristence {} | trainer {} | prg rom size {:#06x} | chr {} size {:#06x} | screen mirroring {:?} | mapper {}",
-            sram_size,
-            sram_is_persistent,
-            has_trainer,
-            prg_rom_size,
-            chr_rom_ram,
-            chr_rom_size,
-            mirror_type,
-            mapper_number
-        );
+    /**
+     * The header's sizes are only trusted as far as the file backs them up, so a
+     * bad header can't ask for more memory than the file has
+     */
+    fn check_file_size(&self, file_size: u64) -> Result<()> {
+        let trainer_size = if self.has_trainer { TRAINER_SIZE } else { 0 };
+        let chr_rom_size = if self.chr_is_rom {
+            self.chr_rom_size
+        } else {
+            0
+        };
+        // exponent sizes can add up past u64, which no file can hold
+        let rom_size = [trainer_size, self.prg_rom_size, chr_rom_size]
+            .into_iter()
+            .try_fold(HEADER_SIZE as u64, |total, size| {
+                total.checked_add(size as u64)
+            });
+        if rom_size.is_none_or(|rom_size| rom_size > file_size) {
+            let rom_size = rom_size.unwrap_or(u64::MAX);
+            Err(CartridgeError::RomSizeExceedsFile(rom_size, file_size))?;
+        }
+        Ok(())
+    }
 
-        if _ines_ver != 0 {
-            Err(CartridgeError::UnsupportedInesVersion)?;
+    /**
+     * What the header asks for that isn't emulated. The cartridge still runs, as
+     * an NTSC NES.
+     */
+    fn unsupported_features(&self) -> Vec<String> {
+        let mut result = Vec::new();
+        if matches!(self.timing, Timing::Pal | Timing::Dendy) {
+            result.push(format!("{:?} timing", self.timing));
         }
+        if self.console_type != ConsoleType::Nes {
+            result.push(format!("{:?} console", self.console_type));
+        }
+        result
+    }
 
-        Ok(Self {
-            _ines_ver,
-            mirror_type,
-            sram_is_persistent,
-            chr_is_rom,
-            has_trainer,
-            prg_rom_size,
-            chr_rom_size,
-            sram_size,
-            mapper_number,
-        })
+    /**
+     * RAM sizes are shift counts, 64 << shift bytes, with 0 meaning none
+     */
+    fn ram_size(shift: u8) -> usize {
+        if shift == 0 { 0 } else { 64 << shift }
+    }
+}
+
+impl std::fmt::Display for NesHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let chr_rom_ram = if self.chr_is_rom { "rom" } else { "ram" };
+        write!(
+            f,
+            "{:?} | sram_size {:#06x} | peristence {} | trainer {} | prg rom size {:#06x} | chr {} size {:#06x} | screen mirroring {:?} | mapper {}.{} | {:?} | {:?} | misc roms {} | expansion device {}",
+            self.format,
+            self.sram_size,
+            self.sram_is_persistent,
+            self.has_trainer,
+            self.prg_rom_size,
+            chr_rom_ram,
+            self.chr_rom_size,
+            self.mirror_type,
+            self.mapper_number,
+            self.submapper,
+            self.timing,
+            self.console_type,
+            self.misc_rom_count,
+            self.expansion_device,
+        )
     }
 }
 
@@ -233,7 +378,82 @@ pub struct CartridgeCore {
 }
 
 impl CartridgeCore {
-    fn read_cpu(&mut self, addr: u16) -> u8 {
+    /**
+     * Reads an iNES or NES 2.0 file. file_name is where the battery backed SRAM
+     * is saved next to.
+     */
+    fn load(file_name: &str, mut reader: impl Read, file_size: u64) -> Result<CartridgeCore> {
+        let mut header = [0; HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+
+        let nes_header = NesHeader::new(&header)?;
+        println!("{nes_header}");
+        nes_header.check_file_size(file_size)?;
+        for feature in nes_header.unsupported_features() {
+            eprintln!("{feature} isn't emulated, running as an NTSC NES");
+        }
+
+        let rom_expansion_size = 0x2000;
+        let rom_expansion_vec = vec![0; rom_expansion_size];
+        let rom_expansion = MemoryRegion::new(
+            MemoryType::ROM_EXPANSION,
+            rom_expansion_vec,
+            0x4000,
+            0x5FFF,
+            true,
+        );
+
+        let mut sram_vec = vec![0; nes_header.sram_size];
+        if nes_header.sram_is_persistent {
+            Cartridge::load_sram(&mut sram_vec, file_name)?;
+        }
+        if nes_header.has_trainer {
+            let mut trainer_ram = vec![0; TRAINER_SIZE];
+            reader.read_exact(&mut trainer_ram)?;
+            // the trainer lives at 0x7000, 0x1000 into the first sram bank
+            if sram_vec.len() < SRAM_PAGE_SIZE {
+                sram_vec.resize(SRAM_PAGE_SIZE, 0);
+            }
+            sram_vec[0x1000..(0x1000 + TRAINER_SIZE)].copy_from_slice(&trainer_ram);
+        }
+        let sram = MemoryRegion::new(MemoryType::SRAM, sram_vec, 0x6000, 0x7FFF, false);
+
+        let mut prg_rom_vec = vec![0; nes_header.prg_rom_size];
+        reader.read_exact(&mut prg_rom_vec)?;
+        let prg_rom = MemoryRegion::new(MemoryType::PRG_ROM, prg_rom_vec, 0x8000, 0xFFFF, true);
+
+        let mut chr_rom_vec = vec![0; nes_header.chr_rom_size];
+        if nes_header.chr_is_rom {
+            reader.read_exact(&mut chr_rom_vec)?;
+        }
+        let chr_type = if nes_header.chr_is_rom {
+            MemoryType::CHR_ROM
+        } else {
+            MemoryType::CHR_RAM
+        };
+        let chr_ram =
+            MemoryRegion::new(chr_type, chr_rom_vec, 0x0000, 0x1FFF, nes_header.chr_is_rom);
+
+        // vram goes all the way to 0x3FFF even though palette ram occupies
+        // 0x3F00-0x3FFF. That's because the PPU 'shadow' reads
+        // vram even when reading palettes
+        let vram_vec = vec![0; VRAM_SIZE];
+        let mut vram = MemoryRegion::new(MemoryType::VRAM, vram_vec, 0x2000, 0x3FFF, false);
+        vram.set_bank_size_k(1);
+        vram.set_mirror_type(nes_header.mirror_type);
+
+        Ok(CartridgeCore {
+            nes_header,
+            cart_name: file_name.to_string(),
+            rom_expansion,
+            sram,
+            prg_rom,
+            chr_ram,
+            vram,
+        })
+    }
+
+    fn read_cpu(&self, addr: u16) -> u8 {
         if self.sram.contains_addr(addr) {
             self.sram.read(addr)
         } else if self.prg_rom.contains_addr(addr) {
@@ -256,7 +476,7 @@ impl CartridgeCore {
         }
     }
 
-    fn read_ppu(&mut self, addr: u16) -> u8 {
+    fn read_ppu(&self, addr: u16) -> u8 {
         if self.chr_ram.contains_addr(addr) {
             self.chr_ram.read(addr)
         } else if self.vram.contains_addr(addr) {
@@ -275,6 +495,101 @@ impl CartridgeCore {
         }
     }
 
+    fn poke_cpu(&mut self, addr: u16, value: u8) {
+        if self.sram.contains_addr(addr) {
+            self.sram.poke(addr, value)
+        } else if self.prg_rom.contains_addr(addr) {
+            self.prg_rom.poke(addr, value)
+        } else if self.rom_expansion.contains_addr(addr) {
+            self.rom_expansion.poke(addr, value)
+        }
+    }
+
+    fn poke_ppu(&mut self, addr: u16, value: u8) {
+        if self.chr_ram.contains_addr(addr) {
+            self.chr_ram.poke(addr, value)
+        } else if self.vram.contains_addr(addr) {
+            self.vram.poke(addr, value)
+        }
+    }
+
+    fn memory(&self, domain: MemoryDomain) -> Option<&[u8]> {
+        match domain {
+            MemoryDomain::PrgRom => Some(&self.prg_rom.memory),
+            MemoryDomain::PrgRam => Some(&self.sram.memory),
+            MemoryDomain::Chr => Some(&self.chr_ram.memory),
+            MemoryDomain::Ciram => Some(&self.vram.memory),
+            _ => None,
+        }
+    }
+
+    fn memory_mut(&mut self, domain: MemoryDomain) -> Option<&mut [u8]> {
+        match domain {
+            MemoryDomain::PrgRom => Some(&mut self.prg_rom.memory),
+            MemoryDomain::PrgRam => Some(&mut self.sram.memory),
+            MemoryDomain::Chr => Some(&mut self.chr_ram.memory),
+            MemoryDomain::Ciram => Some(&mut self.vram.memory),
+            _ => None,
+        }
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.prg_rom.memory_offset(addr)
+    }
+
+    fn chr_rom_offset(&self, addr: u16) -> Option<usize> {
+        if self.nes_header.chr_is_rom {
+            self.chr_ram.memory_offset(addr)
+        } else {
+            None
+        }
+    }
+
+    fn prg_rom_size(&self) -> usize {
+        self.nes_header.prg_rom_size
+    }
+
+    fn chr_rom_size(&self) -> usize {
+        if self.nes_header.chr_is_rom {
+            self.nes_header.chr_rom_size
+        } else {
+            0
+        }
+    }
+
+    /**
+     * The banks and mirroring, which is all a mapper without registers of its own has
+     */
+    fn describe(&self) -> MapperDescription {
+        let nametables = self
+            .vram
+            .bank_mappings()
+            .into_iter()
+            .filter(|b| b.start < 0x3000)
+            .collect::<Vec<_>>();
+        let banks = nametables.iter().map(|b| b.bank).collect::<Vec<_>>();
+        let mirroring = match banks[..] {
+            [0, 1, 0, 1] => Some(MirrorType::Vertical),
+            [0, 0, 1, 1] => Some(MirrorType::Horizontal),
+            [0, 1, 2, 3] => Some(MirrorType::FourScreen),
+            [n, b, c, d] if n == b && n == c && n == d => Some(MirrorType::SingleScreen(n as u8)),
+            _ => None,
+        }
+        .filter(|_| nametables.iter().all(|b| !b.alternate));
+
+        MapperDescription {
+            mapper_number: self.nes_header.mapper_number,
+            submapper: self.nes_header.submapper,
+            prg_rom: self.prg_rom.bank_mappings(),
+            prg_ram: self.sram.bank_mappings(),
+            chr: self.chr_ram.bank_mappings(),
+            nametables,
+            mirroring,
+            irq: None,
+            registers: Vec::new(),
+        }
+    }
+
     fn save_sram(&self) -> Result<()> {
         if self.nes_header.sram_is_persistent {
             Cartridge::save_sram(&self.sram.memory, &self.cart_name)?;
@@ -284,6 +599,39 @@ impl CartridgeCore {
     }
 }
 
+impl SaveState for CartridgeCore {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("Cartridge");
+        writer.write_u16(self.nes_header.mapper_number);
+        writer.write_usize(self.nes_header.prg_rom_size);
+        writer.write_usize(self.nes_header.chr_rom_size);
+        self.rom_expansion.save_state(writer);
+        self.sram.save_state(writer);
+        self.prg_rom.save_state(writer);
+        self.chr_ram.save_state(writer);
+        self.vram.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("Cartridge")?;
+        let mapper_number = reader.read_u16()?;
+        let prg_rom_size = reader.read_usize()?;
+        let chr_rom_size = reader.read_usize()?;
+        if mapper_number != self.nes_header.mapper_number
+            || prg_rom_size != self.nes_header.prg_rom_size
+            || chr_rom_size != self.nes_header.chr_rom_size
+        {
+            Err(SaveStateError::CartridgeMismatch)?;
+        }
+        self.rom_expansion.load_state(reader)?;
+        self.sram.load_state(reader)?;
+        self.prg_rom.load_state(reader)?;
+        self.chr_ram.load_state(reader)?;
+        self.vram.load_state(reader)?;
+        Ok(())
+    }
+}
+
 pub struct CartridgeCPUPort {
     cartridge: Rc<RefCell<Box<dyn Mapper>>>,
 }
@@ -296,6 +644,44 @@ impl CartridgeCPUPort {
     pub fn save_sram(&self) -> Result<()> {
         self.cartridge.borrow().core().save_sram()
     }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        self.cartridge.borrow().save_state(writer);
+    }
+
+    pub fn load_state(&self, reader: &mut StateReader) -> Result<()> {
+        self.cartridge.borrow_mut().load_state(reader)
+    }
+
+    pub fn expansion_audio_clock(&self) -> Option<ExpansionAudioSample> {
+        self.cartridge.borrow_mut().expansion_audio_clock()
+    }
+
+    /**
+     * Where the CPU address is in PRG ROM with the current banks, if PRG ROM is mapped there
+     */
+    pub fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.cartridge.borrow().prg_rom_offset(addr)
+    }
+
+    pub fn prg_rom_size(&self) -> usize {
+        self.cartridge.borrow().prg_rom_size()
+    }
+
+    pub fn describe(&self) -> MapperDescription {
+        self.cartridge.borrow().describe()
+    }
+
+    /**
+     * One of the cartridge's memories, None for domains that aren't on the cartridge
+     */
+    pub fn memory(&self, domain: MemoryDomain) -> Option<Ref<'_, [u8]>> {
+        Ref::filter_map(self.cartridge.borrow(), |c| c.memory(domain)).ok()
+    }
+
+    pub fn memory_mut(&self, domain: MemoryDomain) -> Option<RefMut<'_, [u8]>> {
+        RefMut::filter_map(self.cartridge.borrow_mut(), |c| c.memory_mut(domain)).ok()
+    }
 }
 
 impl BusDevice for CartridgeCPUPort {
@@ -311,6 +697,14 @@ impl BusDevice for CartridgeCPUPort {
         (0x4020, 0xFFFF)
     }
 
+    fn peek(&self, addr: u16) -> u8 {
+        self.cartridge.borrow().peek_cpu(addr)
+    }
+
+    fn poke(&mut self, addr: u16, data: u8) {
+        self.cartridge.borrow_mut().poke_cpu(addr, data);
+    }
+
     fn bus_clock(&mut self) -> InterruptFlags {
         self.cartridge.borrow_mut().cpu_bus_clock()
     }
@@ -324,6 +718,20 @@ impl CartridgePPUPort {
     pub fn new(cartridge: Rc<RefCell<Box<dyn Mapper>>>) -> Self {
         Self { cartridge }
     }
+
+    /**
+     * Where the PPU address is in CHR ROM with the current banks. Always None for CHR RAM.
+     */
+    pub fn chr_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.cartridge.borrow().chr_rom_offset(addr)
+    }
+
+    /**
+     * 0 when the cartridge has CHR RAM
+     */
+    pub fn chr_rom_size(&self) -> usize {
+        self.cartridge.borrow().chr_rom_size()
+    }
 }
 
 impl BusDevice for CartridgePPUPort {
@@ -339,12 +747,26 @@ impl BusDevice for CartridgePPUPort {
         (0x0000, 0x3EFF)
     }
 
+    fn peek(&self, addr: u16) -> u8 {
+        self.cartridge.borrow().peek_ppu(addr)
+    }
+
+    fn poke(&mut self, addr: u16, data: u8) {
+        self.cartridge.borrow_mut().poke_ppu(addr, data);
+    }
+
     fn bus_clock(&mut self) -> crate::bus::InterruptFlags {
         self.cartridge.borrow_mut().ppu_bus_clock();
         InterruptFlags::empty()
     }
 }
 
+impl PpuFetchObserver for CartridgePPUPort {
+    fn ppu_fetch(&mut self, addr: u16, context: PpuFetchContext) {
+        self.cartridge.borrow_mut().ppu_fetch(addr, context);
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum CartridgeError {
     #[error("The file loaded in the cartridge wasn't recognized as having NES format")]
@@ -352,5 +774,9 @@ pub enum CartridgeError {
     #[error("The file loaded in the cartridge has an unsupported NES version")]
     UnsupportedInesVersion,
     #[error("The file loaded requires  mapper {0} which isn't supported yet")]
-    UnsupportedMapper(u8),
+    UnsupportedMapper(u16),
+    #[error("The file loaded declares a ROM of 2^{0} * {1} bytes, which is too big")]
+    InvalidRomSize(u32, usize),
+    #[error("The file loaded needs {0} bytes for the ROM its header declares, but only has {1}")]
+    RomSizeExceedsFile(u64, u64),
 }
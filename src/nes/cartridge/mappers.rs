@@ -1,13 +1,22 @@
 use anyhow::Result;
 
-use crate::bus::InterruptFlags;
+use crate::{
+    bus::InterruptFlags,
+    nes::{
+        mapper_inspector::{IrqState, MapperDescription, MapperRegister},
+        memory_domains::MemoryDomain,
+        mixer::ExpansionAudioSample,
+        ppu::PpuFetchContext,
+    },
+    save_state::{SaveState, StateReader, StateWriter},
+};
 
 use self::{
     axrom::AxRom, cnrom::CNRom, color_dreams::ColorDreams, hvc_un1rom::HvcUN1Rom, mmc1::MMC1,
-    mmc3::MMC3, mmc3_tqrom::MMC3TQRom, mmc3_tsxrom::MMC3TxSRom, namcot_108::Namcot108,
-    namcot_3425::Namcot3425, namcot_3443::Namcot3443, namcot_3446::Namcot3446,
-    namcot_3453::Namcot3453, nes_event::NesEvent, nrom::NRom, uxrom::UxRom,
-    uxrom_invert::UxRomInvert,
+    mmc3::MMC3, mmc3_tqrom::MMC3TQRom, mmc3_tsxrom::MMC3TxSRom, mmc5::MMC5, namco_163::Namco163,
+    namcot_108::Namcot108, namcot_3425::Namcot3425, namcot_3443::Namcot3443,
+    namcot_3446::Namcot3446, namcot_3453::Namcot3453, nes_event::NesEvent, nrom::NRom,
+    sunsoft_fme7::SunsoftFME7, uxrom::UxRom, uxrom_invert::UxRomInvert, vrc6::VRC6,
 };
 
 use super::{CartridgeCore, CartridgeError};
@@ -21,6 +30,8 @@ pub mod mmc3;
 pub mod mmc3_irq;
 pub mod mmc3_tqrom;
 pub mod mmc3_tsxrom;
+pub mod mmc5;
+pub mod namco_163;
 pub mod namcot_108;
 pub mod namcot_3425;
 pub mod namcot_3443;
@@ -28,35 +39,53 @@ pub mod namcot_3446;
 pub mod namcot_3453;
 pub mod nes_event;
 pub mod nrom;
+pub mod sunsoft_fme7;
+#[cfg(test)]
+mod test_cartridge;
 pub mod uxrom;
 pub mod uxrom_invert;
+pub mod vrc6;
+pub mod vrc_irq;
 
-pub fn get_mapper(mapper_number: u8, core: CartridgeCore) -> Result<Box<dyn Mapper>> {
-    let mapper: Box<dyn Mapper> = match mapper_number {
-        0 => Box::new(NRom::new(core)),
-        1 => Box::new(MMC1::new(core, false)),
-        2 => Box::new(UxRom::new(core)),
-        3 => Box::new(CNRom::new(core, false)),
-        4 => Box::new(MMC3::new(core)),
-        7 => Box::new(AxRom::new(core)),
-        11 => Box::new(ColorDreams::new(core)),
-        76 => Box::new(Namcot3446::new(core)),
-        88 => Box::new(Namcot3443::new(core)),
-        94 => Box::new(HvcUN1Rom::new(core)),
-        95 => Box::new(Namcot3425::new(core)),
-        105 => Box::new(NesEvent::new(core, 0b0100)),
-        118 => Box::new(MMC3TxSRom::new(core)),
-        119 => Box::new(MMC3TQRom::new(core)),
-        154 => Box::new(Namcot3453::new(core)),
-        155 => Box::new(MMC1::new(core, true)),
-        180 => Box::new(UxRomInvert::new(core)),
-        185 => Box::new(CNRom::new(core, true)),
-        206 => Box::new(Namcot108::new(core)),
+pub fn get_mapper(
+    mapper_number: u16,
+    submapper: u8,
+    core: CartridgeCore,
+) -> Result<Box<dyn Mapper>> {
+    let mapper: Box<dyn Mapper> = match (mapper_number, submapper) {
+        (0, _) => Box::new(NRom::new(core)),
+        // SEROM, SHROM, SH1ROM have a fixed 32k PRG ROM
+        (1, 5) => Box::new(MMC1::new(core, false, true)),
+        (1, _) => Box::new(MMC1::new(core, false, false)),
+        (2, _) => Box::new(UxRom::new(core)),
+        (3, _) => Box::new(CNRom::new(core, false)),
+        (4, _) => Box::new(MMC3::new(core)),
+        (5, _) => Box::new(MMC5::new(core)),
+        (7, _) => Box::new(AxRom::new(core)),
+        (11, _) => Box::new(ColorDreams::new(core)),
+        (19, _) => Box::new(Namco163::new(core)),
+        (24, _) => Box::new(VRC6::new(core, false)),
+        (26, _) => Box::new(VRC6::new(core, true)),
+        (69, _) => Box::new(SunsoftFME7::new(core)),
+        (76, _) => Box::new(Namcot3446::new(core)),
+        (88, _) => Box::new(Namcot3443::new(core)),
+        (94, _) => Box::new(HvcUN1Rom::new(core)),
+        (95, _) => Box::new(Namcot3425::new(core)),
+        (105, _) => Box::new(NesEvent::new(core, 0b0100)),
+        (118, _) => Box::new(MMC3TxSRom::new(core)),
+        (119, _) => Box::new(MMC3TQRom::new(core)),
+        (154, _) => Box::new(Namcot3453::new(core)),
+        (155, _) => Box::new(MMC1::new(core, true, false)),
+        (180, _) => Box::new(UxRomInvert::new(core)),
+        // submappers 4-7 say which bank value enables CHR
+        (185, 4..=7) => Box::new(CNRom::with_chr_enable_key(core, submapper & 0b11)),
+        (185, _) => Box::new(CNRom::new(core, true)),
+        (206, _) => Box::new(Namcot108::new(core)),
         _ => Err(CartridgeError::UnsupportedMapper(mapper_number))?,
     };
     Ok(mapper)
 }
-pub trait Mapper {
+pub trait Mapper: SaveState {
     fn cpu_bus_clock(&mut self) -> InterruptFlags;
     fn read_cpu(&mut self, addr: u16) -> u8;
     fn write_cpu(&mut self, addr: u16, value: u8) -> u8;
@@ -66,7 +95,106 @@ pub trait Mapper {
     fn write_ppu(&mut self, addr: u16, value: u8) -> u8;
 
     fn core(&self) -> &CartridgeCore;
+    fn core_mut(&mut self) -> &mut CartridgeCore;
+
+    /**
+     * What read_cpu would return, without changing anything
+     */
+    fn peek_cpu(&self, addr: u16) -> u8 {
+        self.core().read_cpu(addr)
+    }
+
+    /**
+     * Stores into RAM, or patches ROM, without configuring the mapper
+     */
+    fn poke_cpu(&mut self, addr: u16, value: u8) {
+        self.core_mut().poke_cpu(addr, value);
+    }
+
+    /**
+     * What read_ppu would return with the current banks, without changing anything
+     */
+    fn peek_ppu(&self, addr: u16) -> u8 {
+        self.core().read_ppu(addr)
+    }
+
+    fn poke_ppu(&mut self, addr: u16, value: u8) {
+        self.core_mut().poke_ppu(addr, value);
+    }
+
+    /**
+     * The whole of one of the cartridge's memories, e.g. all of PRG ROM
+     */
+    fn memory(&self, domain: MemoryDomain) -> Option<&[u8]> {
+        self.core().memory(domain)
+    }
+
+    fn memory_mut(&mut self, domain: MemoryDomain) -> Option<&mut [u8]> {
+        self.core_mut().memory_mut(domain)
+    }
+
+    /**
+     * Called just before each PPU read with what the PPU is fetching
+     */
+    fn ppu_fetch(&mut self, _addr: u16, _context: PpuFetchContext) {}
+
+    /**
+     * Called once per CPU cycle. Cartridges with their own sound chip return
+     * its output, which is mixed with the APU at that chip's level.
+     */
+    fn expansion_audio_clock(&mut self) -> Option<ExpansionAudioSample> {
+        None
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.core().prg_rom_offset(addr)
+    }
+
+    fn chr_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.core().chr_rom_offset(addr)
+    }
+
+    fn prg_rom_size(&self) -> usize {
+        self.core().prg_rom_size()
+    }
+
+    fn chr_rom_size(&self) -> usize {
+        self.core().chr_rom_size()
+    }
+
+    /**
+     * The mapper's own registers by name, as they were last written
+     */
+    fn registers(&self) -> Vec<MapperRegister> {
+        Vec::new()
+    }
+
+    fn irq_state(&self) -> Option<IrqState> {
+        None
+    }
+
+    /**
+     * Where the banks are, the mirroring, IRQ and registers, without changing anything
+     */
+    fn describe(&self) -> MapperDescription {
+        MapperDescription {
+            irq: self.irq_state(),
+            registers: self.registers(),
+            ..self.core().describe()
+        }
+    }
 }
+/**
+ * Registers kept in an array, named with the prefix and their index, e.g. chr0 to chr7
+ */
+fn numbered_registers<T: Copy + Into<u16>>(prefix: &str, values: &[T]) -> Vec<MapperRegister> {
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| MapperRegister::new(format!("{prefix}{i}"), *value))
+        .collect()
+}
+
 pub struct NulMapper {}
 
 impl Mapper for NulMapper {
@@ -93,4 +221,58 @@ impl Mapper for NulMapper {
     fn core(&self) -> &CartridgeCore {
         unreachable!()
     }
+
+    fn core_mut(&mut self) -> &mut CartridgeCore {
+        unreachable!()
+    }
+
+    fn peek_cpu(&self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn poke_cpu(&mut self, _addr: u16, _value: u8) {}
+
+    fn peek_ppu(&self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn poke_ppu(&mut self, _addr: u16, _value: u8) {}
+
+    fn memory(&self, _domain: MemoryDomain) -> Option<&[u8]> {
+        None
+    }
+
+    fn memory_mut(&mut self, _domain: MemoryDomain) -> Option<&mut [u8]> {
+        None
+    }
+
+    fn prg_rom_offset(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+
+    fn chr_rom_offset(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+
+    fn prg_rom_size(&self) -> usize {
+        0
+    }
+
+    fn chr_rom_size(&self) -> usize {
+        0
+    }
+
+    fn describe(&self) -> MapperDescription {
+        MapperDescription::default()
+    }
+}
+
+impl SaveState for NulMapper {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("NulMapper");
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("NulMapper")
+    }
 }
@@ -1,6 +1,12 @@
+use anyhow::Result;
+
 use crate::{
     bus::InterruptFlags,
-    nes::cartridge::{CartridgeCore, MirrorType},
+    nes::{
+        cartridge::{CartridgeCore, MirrorType},
+        mapper_inspector::MapperRegister,
+    },
+    save_state::{SaveState, StateReader, StateWriter},
 };
 
 use super::Mapper;
@@ -63,4 +69,27 @@ impl Mapper for AxRom {
     fn core(&self) -> &CartridgeCore {
         &self.core
     }
+
+    fn core_mut(&mut self) -> &mut CartridgeCore {
+        &mut self.core
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        vec![MapperRegister::new("nametable", self.mirror_mode)]
+    }
+}
+
+impl SaveState for AxRom {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("AxRom");
+        self.core.save_state(writer);
+        writer.write_u8(self.mirror_mode);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("AxRom")?;
+        self.core.load_state(reader)?;
+        self.mirror_mode = reader.read_u8()?;
+        Ok(())
+    }
 }
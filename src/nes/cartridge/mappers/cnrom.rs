@@ -1,6 +1,12 @@
+use anyhow::Result;
+
 use crate::{
     bus::InterruptFlags,
-    nes::cartridge::{CartridgeCore, Mapper},
+    nes::{
+        cartridge::{CartridgeCore, Mapper},
+        mapper_inspector::MapperRegister,
+    },
+    save_state::{SaveState, StateReader, StateWriter},
 };
 
 /**
@@ -10,6 +16,8 @@ use crate::{
 pub struct CNRom {
     core: CartridgeCore,
     remaining_junk_reads: u8,
+    chr_enable_key: Option<u8>,
+    chr_enabled: bool,
 }
 
 impl CNRom {
@@ -19,12 +27,31 @@ impl CNRom {
         Self {
             core,
             remaining_junk_reads,
+            chr_enable_key: None,
+            chr_enabled: true,
+        }
+    }
+
+    /**
+     * NES 2.0 mapper 185 carts say exactly which value written to the
+     * register enables CHR. Any other value disables it.
+     */
+    pub fn with_chr_enable_key(mut core: CartridgeCore, chr_enable_key: u8) -> Self {
+        core.chr_ram.set_bank_size_k(8);
+        Self {
+            core,
+            remaining_junk_reads: 0,
+            chr_enable_key: Some(chr_enable_key),
+            chr_enabled: false,
         }
     }
 
     fn configure(&mut self, _addr: u16, value: u8) -> u8 {
         let old = self.core.chr_ram.get_bank(0) as u8;
-        self.core.chr_ram.set_bank(0, value as i16);
+        match self.chr_enable_key {
+            Some(key) => self.chr_enabled = value & 0b11 == key,
+            None => self.core.chr_ram.set_bank(0, value as i16),
+        }
         old
     }
 }
@@ -45,6 +72,18 @@ impl Mapper for CNRom {
         if addr == 0x2007 && self.remaining_junk_reads > 0 {
             self.remaining_junk_reads -= 1;
             0xFF
+        } else if !self.chr_enabled && self.core.chr_ram.contains_addr(addr) {
+            0xFF
+        } else {
+            self.core.read_ppu(addr)
+        }
+    }
+
+    fn peek_ppu(&self, addr: u16) -> u8 {
+        if (addr == 0x2007 && self.remaining_junk_reads > 0)
+            || (!self.chr_enabled && self.core.chr_ram.contains_addr(addr))
+        {
+            0xFF
         } else {
             self.core.read_ppu(addr)
         }
@@ -63,4 +102,29 @@ impl Mapper for CNRom {
     fn core(&self) -> &CartridgeCore {
         &self.core
     }
+
+    fn core_mut(&mut self) -> &mut CartridgeCore {
+        &mut self.core
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        vec![MapperRegister::new("chr_enabled", self.chr_enabled)]
+    }
+}
+
+impl SaveState for CNRom {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("CNRom");
+        self.core.save_state(writer);
+        writer.write_u8(self.remaining_junk_reads);
+        writer.write_bool(self.chr_enabled);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("CNRom")?;
+        self.core.load_state(reader)?;
+        self.remaining_junk_reads = reader.read_u8()?;
+        self.chr_enabled = reader.read_bool()?;
+        Ok(())
+    }
 }
@@ -1,10 +1,17 @@
+use anyhow::Result;
+
 use crate::{
     bus::InterruptFlags,
-    nes::cartridge::{CartridgeCore, Mapper, MirrorType},
+    nes::{
+        cartridge::{CartridgeCore, Mapper, MirrorType},
+        mapper_inspector::MapperRegister,
+    },
+    save_state::{SaveState, StateReader, StateWriter},
 };
 
 /**
  * Mapper 1 (with force_sram_enable == false)
+ * Mapper 1 submapper 5 (with fixed_prg == true)
  * Mapper 155 (with force_sram_enable == true)
  */
 pub struct MMC1 {
@@ -12,6 +19,7 @@ pub struct MMC1 {
 
     sram_disabled: bool,
     force_sram_enable: bool,
+    fixed_prg: bool,
     control_reg: u8,
     sram_bank_reg: u8,
     chr_bank_0_reg: u8,
@@ -24,13 +32,14 @@ pub struct MMC1 {
 }
 
 impl MMC1 {
-    pub fn new(mut core: CartridgeCore, force_sram_enable: bool) -> Self {
+    pub fn new(mut core: CartridgeCore, force_sram_enable: bool, fixed_prg: bool) -> Self {
         core.sram.set_bank_size_k(8);
         let mut result = Self {
             core,
 
             sram_disabled: false,
             force_sram_enable,
+            fixed_prg,
             sram_bank_reg: 0,
             control_reg: 0b00001100,
             chr_bank_0_reg: 0,
@@ -89,6 +98,10 @@ impl MMC1 {
         self.core.vram.set_mirror_type(mirror_type);
 
         match self.prg_bank_mode() {
+            _ if self.fixed_prg => {
+                self.core.prg_rom.set_bank_size_k(32);
+                self.core.prg_rom.set_bank(0, 0);
+            }
             0..=1 => {
                 self.core.prg_rom.set_bank_size_k(32);
                 self.core
@@ -202,6 +215,14 @@ impl Mapper for MMC1 {
         }
     }
 
+    fn peek_cpu(&self, addr: u16) -> u8 {
+        if self.sram_disabled && self.core.sram.contains_addr(addr) {
+            0
+        } else {
+            self.core.read_cpu(addr)
+        }
+    }
+
     fn read_ppu(&mut self, addr: u16) -> u8 {
         self.core.read_ppu(addr)
     }
@@ -221,4 +242,57 @@ impl Mapper for MMC1 {
     fn core(&self) -> &CartridgeCore {
         &self.core
     }
+
+    fn core_mut(&mut self) -> &mut CartridgeCore {
+        &mut self.core
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        vec![
+            MapperRegister::new("control", self.control_reg),
+            MapperRegister::new("chr_bank_0", self.chr_bank_0_reg),
+            MapperRegister::new("chr_bank_1", self.chr_bank_1_reg),
+            MapperRegister::new("prg_bank", self.prg_bank_reg),
+            MapperRegister::new("sram_bank", self.sram_bank_reg),
+            MapperRegister::new("shift", self.shift_register),
+            MapperRegister::new("shift_count", self.shift_count),
+        ]
+    }
+}
+
+impl SaveState for MMC1 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("MMC1");
+        self.core.save_state(writer);
+        writer.write_bool(self.sram_disabled);
+        writer.write_bool(self.force_sram_enable);
+        writer.write_bool(self.fixed_prg);
+        writer.write_u8(self.control_reg);
+        writer.write_u8(self.sram_bank_reg);
+        writer.write_u8(self.chr_bank_0_reg);
+        writer.write_u8(self.chr_bank_1_reg);
+        writer.write_u8(self.prg_bank_reg);
+        writer.write_u8(self.shift_register);
+        writer.write_u8(self.shift_count);
+        writer.write_usize(self.cycle_count);
+        writer.write_usize(self.last_write_cycle);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("MMC1")?;
+        self.core.load_state(reader)?;
+        self.sram_disabled = reader.read_bool()?;
+        self.force_sram_enable = reader.read_bool()?;
+        self.fixed_prg = reader.read_bool()?;
+        self.control_reg = reader.read_u8()?;
+        self.sram_bank_reg = reader.read_u8()?;
+        self.chr_bank_0_reg = reader.read_u8()?;
+        self.chr_bank_1_reg = reader.read_u8()?;
+        self.prg_bank_reg = reader.read_u8()?;
+        self.shift_register = reader.read_u8()?;
+        self.shift_count = reader.read_u8()?;
+        self.cycle_count = reader.read_usize()?;
+        self.last_write_cycle = reader.read_usize()?;
+        Ok(())
+    }
 }
@@ -1,4 +1,10 @@
-use crate::bus::InterruptFlags;
+use anyhow::Result;
+
+use crate::{
+    bus::InterruptFlags,
+    nes::mapper_inspector::IrqState,
+    save_state::{SaveState, SaveStateError, StateReader, StateWriter},
+};
 
 const A12_SKIP_COUNT: u8 = 9;
 
@@ -54,6 +60,15 @@ impl MMC3Irq {
         }
     }
 
+    pub fn state(&self) -> IrqState {
+        IrqState {
+            counter: self.irq_count as u32,
+            latch: self.irq_latch as u32,
+            enabled: self.irq_enabled,
+            pending: self.irq_enabled && self.irq_occurred,
+        }
+    }
+
     pub fn check_a12(&mut self, addr: u16) {
         let a12_high = addr & 0b0001_0000_0000_0000 != 0;
         match (a12_high, self.a12_state) {
@@ -99,6 +114,37 @@ impl MMC3Irq {
     }
 }
 
+impl SaveState for MMC3Irq {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.irq_latch);
+        writer.write_u8(self.irq_count);
+        writer.write_bool(self.irq_reload);
+        writer.write_bool(self.irq_enabled);
+        writer.write_bool(self.irq_occurred);
+        match self.a12_state {
+            A12State::WasLow(n) => {
+                writer.write_u8(0);
+                writer.write_u8(n);
+            }
+            A12State::WasHigh => writer.write_u8(1),
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.irq_latch = reader.read_u8()?;
+        self.irq_count = reader.read_u8()?;
+        self.irq_reload = reader.read_bool()?;
+        self.irq_enabled = reader.read_bool()?;
+        self.irq_occurred = reader.read_bool()?;
+        self.a12_state = match reader.read_u8()? {
+            0 => A12State::WasLow(reader.read_u8()?),
+            1 => A12State::WasHigh,
+            n => Err(SaveStateError::InvalidValue("a12 state", n as u32))?,
+        };
+        Ok(())
+    }
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 enum A12State {
     WasLow(u8),
@@ -1,9 +1,15 @@
+use anyhow::Result;
+
 use crate::{
     bus::InterruptFlags,
-    nes::cartridge::{CartridgeCore, Mapper, MirrorType},
+    nes::{
+        cartridge::{CartridgeCore, Mapper, MirrorType},
+        mapper_inspector::{IrqState, MapperRegister},
+    },
+    save_state::{SaveState, StateReader, StateWriter},
 };
 
-use super::mmc3_irq::MMC3Irq;
+use super::{mmc3_irq::MMC3Irq, numbered_registers};
 /**
  * Mapper 119
  */
@@ -181,4 +187,52 @@ impl Mapper for MMC3TQRom {
     fn core(&self) -> &CartridgeCore {
         &self.core
     }
+
+    fn core_mut(&mut self) -> &mut CartridgeCore {
+        &mut self.core
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        let mut registers = vec![MapperRegister::new("bank_select", self.read_bank_select())];
+        registers.extend(numbered_registers("r", &self.registers));
+        registers.push(MapperRegister::new("mirroring", self.mirror_mode));
+        registers.push(MapperRegister::new(
+            "prg_ram_protect",
+            (u8::from(self.write_protection) << 7) | (u8::from(self.prg_ram_enable) << 6),
+        ));
+        registers
+    }
+
+    fn irq_state(&self) -> Option<IrqState> {
+        Some(self.mmc3_irq.state())
+    }
+}
+
+impl SaveState for MMC3TQRom {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("MMC3TQRom");
+        self.core.save_state(writer);
+        writer.write_u8(self.chr_bank_mode);
+        writer.write_u8(self.prg_bank_mode);
+        writer.write_usize(self.selected_register);
+        writer.write_bytes(&self.registers);
+        writer.write_u8(self.mirror_mode);
+        writer.write_bool(self.write_protection);
+        writer.write_bool(self.prg_ram_enable);
+        self.mmc3_irq.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("MMC3TQRom")?;
+        self.core.load_state(reader)?;
+        self.chr_bank_mode = reader.read_u8()?;
+        self.prg_bank_mode = reader.read_u8()?;
+        self.selected_register = reader.read_usize()?;
+        reader.read_bytes_into(&mut self.registers)?;
+        self.mirror_mode = reader.read_u8()?;
+        self.write_protection = reader.read_bool()?;
+        self.prg_ram_enable = reader.read_bool()?;
+        self.mmc3_irq.load_state(reader)?;
+        Ok(())
+    }
 }
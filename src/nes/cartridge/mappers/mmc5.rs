@@ -0,0 +1,789 @@
+#[cfg(test)]
+mod unit_tests;
+
+use anyhow::Result;
+
+use crate::{
+    bus::InterruptFlags,
+    nes::{
+        apu::{
+            APUCycleType,
+            channels::{Channel, pulse::PulseChannel},
+        },
+        cartridge::{CartridgeCore, Mapper},
+        mapper_inspector::{IrqState, MapperRegister},
+        mixer::{ExpansionAudioChip, ExpansionAudioSample},
+        ppu::{PpuFetchContext, PpuFetchKind},
+    },
+    save_state::{SaveState, SaveStateError, StateReader, StateWriter},
+};
+
+use super::numbered_registers;
+
+const EXRAM_SIZE: usize = 0x400;
+const PRG_RAM_BANK_SIZE: usize = 0x2000;
+const ATTRIBUTE_OFFSET: u16 = 0x3C0;
+// the audio length counters and envelopes are clocked at a fixed ~240Hz
+const FRAME_CLOCK_PERIOD: u16 = 7457;
+// the PPU is considered idle once it hasn't read anything for this many CPU cycles
+const PPU_IDLE_CPU_CYCLES: u8 = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum NametableSource {
+    Vram(u8),
+    ExRam,
+    Fill,
+}
+
+impl NametableSource {
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0b11 {
+            0 => NametableSource::Vram(0),
+            1 => NametableSource::Vram(1),
+            2 => NametableSource::ExRam,
+            _ => NametableSource::Fill,
+        }
+    }
+}
+
+/**
+ * Mapper 5
+ */
+pub struct MMC5 {
+    core: CartridgeCore,
+
+    prg_mode: u8,
+    chr_mode: u8,
+    prg_ram_protect: [u8; 2],
+    exram_mode: u8,
+    nametable_mapping: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+
+    prg_ram_bank: u8,
+    prg_banks: [u8; 4],
+    // (is rom, bank) for each 8K page from 0x8000
+    prg_pages: [(bool, i16); 4],
+
+    chr_banks: [u16; 12],
+    chr_upper_bits: u8,
+    last_chr_write_a: bool,
+    // 1K banks for each page of pattern memory
+    chr_map_a: [i16; 8],
+    chr_map_b: [i16; 8],
+    fetch_kind: PpuFetchKind,
+    sprite_size_large: bool,
+
+    exram: Vec<u8>,
+    ex_attribute: u8,
+
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    in_frame: bool,
+    scanline_counter: u8,
+    last_ppu_addr: u16,
+    ppu_addr_match_count: u8,
+    ppu_idle_cycles: u8,
+
+    multiplicand: u8,
+    multiplier: u8,
+
+    pulse_channel1: PulseChannel,
+    pulse_channel2: PulseChannel,
+    audio_cycle_type: APUCycleType,
+    frame_clock_counter: u16,
+    pcm_read_mode: bool,
+    pcm_irq_enabled: bool,
+    pcm_irq_pending: bool,
+    pcm_level: u8,
+}
+
+impl MMC5 {
+    pub fn new(mut core: CartridgeCore) -> Self {
+        core.prg_rom.set_bank_size_k(8);
+        core.sram.set_bank_size_k(8);
+        core.chr_ram.set_bank_size_k(1);
+        let mut result = Self {
+            core,
+
+            prg_mode: 3,
+            chr_mode: 0,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            nametable_mapping: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+
+            prg_ram_bank: 0,
+            prg_banks: [0, 0, 0, 0xFF],
+            prg_pages: [(true, 0); 4],
+
+            chr_banks: [0; 12],
+            chr_upper_bits: 0,
+            last_chr_write_a: true,
+            chr_map_a: [0; 8],
+            chr_map_b: [0; 8],
+            fetch_kind: PpuFetchKind::Data,
+            sprite_size_large: false,
+
+            exram: vec![0; EXRAM_SIZE],
+            ex_attribute: 0,
+
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            in_frame: false,
+            scanline_counter: 0,
+            last_ppu_addr: 0,
+            ppu_addr_match_count: 0,
+            ppu_idle_cycles: 0,
+
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+
+            pulse_channel1: PulseChannel::new_without_sweep(),
+            pulse_channel2: PulseChannel::new_without_sweep(),
+            audio_cycle_type: APUCycleType::Get,
+            frame_clock_counter: 0,
+            pcm_read_mode: false,
+            pcm_irq_enabled: false,
+            pcm_irq_pending: false,
+            pcm_level: 0,
+        };
+        result.reconfigure_prg_banks();
+        result.reconfigure_chr_banks();
+        result.reconfigure_nametables();
+        result
+    }
+
+    fn configure(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x5000..=0x5003 => {
+                self.pulse_channel1.set_register((addr & 0b11) as u8, value);
+            }
+            0x5004..=0x5007 => {
+                self.pulse_channel2.set_register((addr & 0b11) as u8, value);
+            }
+            0x5010 => {
+                self.pcm_read_mode = value & 0b1 != 0;
+                self.pcm_irq_enabled = value & 0b1000_0000 != 0;
+            }
+            0x5011 if !self.pcm_read_mode && value != 0 => self.pcm_level = value,
+            0x5015 => {
+                self.pulse_channel1.set_enabled(value & 0b01 != 0);
+                self.pulse_channel2.set_enabled(value & 0b10 != 0);
+            }
+            0x5100 => {
+                self.prg_mode = value & 0b11;
+                self.reconfigure_prg_banks();
+            }
+            0x5101 => {
+                self.chr_mode = value & 0b11;
+                self.reconfigure_chr_banks();
+            }
+            0x5102 => self.prg_ram_protect[0] = value & 0b11,
+            0x5103 => self.prg_ram_protect[1] = value & 0b11,
+            0x5104 => self.exram_mode = value & 0b11,
+            0x5105 => {
+                self.nametable_mapping = value;
+                self.reconfigure_nametables();
+            }
+            0x5106 => self.fill_tile = value,
+            0x5107 => self.fill_attribute = value & 0b11,
+            0x5113 => self.prg_ram_bank = value & 0b0111_1111,
+            0x5114..=0x5117 => {
+                self.prg_banks[(addr - 0x5114) as usize] = value;
+                self.reconfigure_prg_banks();
+            }
+            0x5120..=0x512B => {
+                let index = (addr - 0x5120) as usize;
+                self.chr_banks[index] = ((self.chr_upper_bits as u16) << 8) | value as u16;
+                self.last_chr_write_a = index < 8;
+                self.reconfigure_chr_banks();
+            }
+            0x5130 => self.chr_upper_bits = value & 0b11,
+            0x5203 => self.irq_compare = value,
+            0x5204 => self.irq_enabled = value & 0b1000_0000 != 0,
+            0x5205 => self.multiplicand = value,
+            0x5206 => self.multiplier = value,
+            0x5C00..=0x5FFF => {
+                let index = (addr - 0x5C00) as usize;
+                match self.exram_mode {
+                    // only writable while rendering, otherwise 0 is written instead
+                    0 | 1 => self.exram[index] = if self.in_frame { value } else { 0 },
+                    2 => self.exram[index] = value,
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn read_register(&mut self, addr: u16) -> u8 {
+        let result = self.peek_register(addr);
+        match addr {
+            0x5010 => self.pcm_irq_pending = false,
+            0x5204 => self.irq_pending = false,
+            _ => (),
+        }
+        result
+    }
+
+    fn peek_register(&self, addr: u16) -> u8 {
+        match addr {
+            0x5010 => {
+                (if self.pcm_irq_pending { 0b1000_0000 } else { 0 })
+                    | if self.pcm_read_mode { 0b1 } else { 0 }
+            }
+            0x5015 => {
+                (if self.pulse_channel1.get_enabled() {
+                    0b01
+                } else {
+                    0
+                }) | if self.pulse_channel2.get_enabled() {
+                    0b10
+                } else {
+                    0
+                }
+            }
+            0x5204 => {
+                (if self.irq_pending { 0b1000_0000 } else { 0 })
+                    | if self.in_frame { 0b0100_0000 } else { 0 }
+            }
+            0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => self.exram[(addr - 0x5C00) as usize],
+            _ => 0,
+        }
+    }
+
+    fn reconfigure_prg_banks(&mut self) {
+        let rom_or_ram = |value: u8| (value & 0b1000_0000 != 0, (value & 0b0111_1111) as i16);
+        // 0x5117 always maps ROM
+        let last = (true, (self.prg_banks[3] & 0b0111_1111) as i16);
+        self.prg_pages = match self.prg_mode {
+            0 => {
+                let base = last.1 & !0b11;
+                [
+                    (true, base),
+                    (true, base + 1),
+                    (true, base + 2),
+                    (true, base + 3),
+                ]
+            }
+            1 => {
+                let (rom, bank) = rom_or_ram(self.prg_banks[1]);
+                let bank = bank & !0b1;
+                let last_base = last.1 & !0b1;
+                [
+                    (rom, bank),
+                    (rom, bank + 1),
+                    (true, last_base),
+                    (true, last_base + 1),
+                ]
+            }
+            2 => {
+                let (rom, bank) = rom_or_ram(self.prg_banks[1]);
+                let bank = bank & !0b1;
+                [
+                    (rom, bank),
+                    (rom, bank + 1),
+                    rom_or_ram(self.prg_banks[2]),
+                    last,
+                ]
+            }
+            _ => [
+                rom_or_ram(self.prg_banks[0]),
+                rom_or_ram(self.prg_banks[1]),
+                rom_or_ram(self.prg_banks[2]),
+                last,
+            ],
+        };
+
+        for (page, (rom, bank)) in self.prg_pages.iter().enumerate() {
+            if *rom {
+                self.core.prg_rom.set_bank(page, *bank);
+            }
+        }
+    }
+
+    fn reconfigure_chr_banks(&mut self) {
+        let banks = self.chr_banks.map(|b| b as i16);
+        for page in 0..8 {
+            let i = page as i16;
+            (self.chr_map_a[page], self.chr_map_b[page]) = match self.chr_mode {
+                0 => (banks[7] * 8 + i, banks[11] * 8 + i),
+                1 => {
+                    let a = if page < 4 { banks[3] } else { banks[7] };
+                    (a * 4 + (i & 0b11), banks[11] * 4 + (i & 0b11))
+                }
+                2 => (
+                    banks[page | 1] * 2 + (i & 0b1),
+                    banks[8 + ((page >> 1) & 0b1) * 2 + 1] * 2 + (i & 0b1),
+                ),
+                _ => (banks[page], banks[8 + (page & 0b11)]),
+            };
+        }
+    }
+
+    fn reconfigure_nametables(&mut self) {
+        for quadrant in 0..4 {
+            if let NametableSource::Vram(bank) = self.nametable_source(quadrant) {
+                self.core.vram.set_bank(quadrant, bank as i16);
+                self.core.vram.set_bank(quadrant + 4, bank as i16);
+            }
+        }
+    }
+
+    fn nametable_source(&self, quadrant: usize) -> NametableSource {
+        NametableSource::from_bits(self.nametable_mapping >> (quadrant * 2))
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [0b10, 0b01]
+    }
+
+    fn read_prg_ram(&mut self, bank: i16, addr: u16) -> u8 {
+        if self.core.sram.memory.is_empty() {
+            return 0;
+        }
+        self.core.sram.set_bank(0, bank);
+        self.core.sram.read(0x6000 | (addr & 0x1FFF))
+    }
+
+    // the same as read_prg_ram, without switching the bank
+    fn prg_ram_index(&self, bank: i16, addr: u16) -> Option<usize> {
+        let memory = &self.core.sram.memory;
+        if memory.is_empty() {
+            return None;
+        }
+        Some((bank as usize * PRG_RAM_BANK_SIZE + (addr & 0x1FFF) as usize) % memory.len())
+    }
+
+    fn write_prg_ram(&mut self, bank: i16, addr: u16, value: u8) -> u8 {
+        if self.core.sram.memory.is_empty() {
+            return 0;
+        }
+        self.core.sram.set_bank(0, bank);
+        if self.prg_ram_writable() {
+            self.core.sram.write(0x6000 | (addr & 0x1FFF), value)
+        } else {
+            self.core.sram.read(0x6000 | (addr & 0x1FFF))
+        }
+    }
+
+    fn use_chr_set_a(&self) -> bool {
+        match self.fetch_kind {
+            _ if !self.sprite_size_large => true,
+            PpuFetchKind::SpritePattern => true,
+            PpuFetchKind::Data => self.last_chr_write_a,
+            _ => false,
+        }
+    }
+
+    fn map_chr(&mut self, addr: u16) {
+        let page = (addr >> 10) as usize;
+        if self.exram_mode == 1 && self.fetch_kind == PpuFetchKind::BackgroundPattern {
+            // extended attributes select a 4K bank per tile
+            let bank_4k = ((self.chr_upper_bits as i16) << 6) | (self.ex_attribute & 0x3F) as i16;
+            self.core
+                .chr_ram
+                .set_bank(page, bank_4k * 4 + (page & 0b11) as i16);
+        } else if self.use_chr_set_a() {
+            self.core.chr_ram.set_bank(page, self.chr_map_a[page]);
+        } else {
+            self.core.chr_ram.set_bank(page, self.chr_map_b[page]);
+        }
+    }
+
+    fn read_nametable(&mut self, addr: u16) -> u8 {
+        let offset = addr & 0x3FF;
+        if self.exram_mode == 1 && (0x2000..=0x2FFF).contains(&addr) {
+            match self.fetch_kind {
+                PpuFetchKind::Nametable => self.ex_attribute = self.exram[offset as usize],
+                PpuFetchKind::Attribute => return (self.ex_attribute >> 6) * 0x55,
+                _ => (),
+            }
+        }
+        self.peek_nametable(addr)
+    }
+
+    fn peek_nametable(&self, addr: u16) -> u8 {
+        let quadrant = ((addr >> 10) & 0b11) as usize;
+        let offset = addr & 0x3FF;
+        match self.nametable_source(quadrant) {
+            NametableSource::Vram(_) => self.core.read_ppu(addr),
+            NametableSource::ExRam if self.exram_mode <= 1 => self.exram[offset as usize],
+            NametableSource::ExRam => 0,
+            NametableSource::Fill if offset >= ATTRIBUTE_OFFSET => self.fill_attribute * 0x55,
+            NametableSource::Fill => self.fill_tile,
+        }
+    }
+
+    fn detect_scanline(&mut self, addr: u16) {
+        self.ppu_idle_cycles = 0;
+        if (0x2000..=0x2FFF).contains(&addr) && addr == self.last_ppu_addr {
+            self.ppu_addr_match_count += 1;
+            // the PPU reads the same nametable byte three times at the end of each scanline
+            if self.ppu_addr_match_count == 2 {
+                if self.in_frame {
+                    self.scanline_counter = self.scanline_counter.wrapping_add(1);
+                    if self.scanline_counter == self.irq_compare {
+                        self.irq_pending = true;
+                    }
+                } else {
+                    // a new frame clears an IRQ left pending from the last one
+                    self.in_frame = true;
+                    self.scanline_counter = 0;
+                    self.irq_pending = false;
+                }
+            }
+        } else {
+            self.ppu_addr_match_count = 0;
+        }
+        self.last_ppu_addr = addr;
+    }
+
+    fn clock_audio(&mut self) {
+        self.audio_cycle_type = !self.audio_cycle_type;
+        self.frame_clock_counter += 1;
+        if self.frame_clock_counter >= FRAME_CLOCK_PERIOD {
+            self.frame_clock_counter = 0;
+            for channel in [&mut self.pulse_channel1, &mut self.pulse_channel2] {
+                channel.quarter_frame_clock();
+                channel.half_frame_clock();
+            }
+        }
+    }
+}
+
+impl Mapper for MMC5 {
+    fn read_cpu(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x5000..=0x5FFF => self.read_register(addr),
+            0x6000..=0x7FFF => self.read_prg_ram(self.prg_ram_bank as i16, addr),
+            0x8000..=0xFFFF => {
+                if addr == 0xFFFA || addr == 0xFFFB {
+                    // the NMI vector fetch means the frame is over
+                    self.in_frame = false;
+                }
+                let (rom, bank) = self.prg_pages[((addr - 0x8000) >> 13) as usize];
+                let value = if rom {
+                    self.core.read_cpu(addr)
+                } else {
+                    self.read_prg_ram(bank, addr)
+                };
+                if self.pcm_read_mode && addr <= 0xBFFF {
+                    if value == 0 {
+                        self.pcm_irq_pending = true;
+                    } else {
+                        self.pcm_level = value;
+                    }
+                }
+                value
+            }
+            _ => self.core.read_cpu(addr),
+        }
+    }
+    fn write_cpu(&mut self, addr: u16, value: u8) -> u8 {
+        match addr {
+            0x5000..=0x5FFF => {
+                self.configure(addr, value);
+                0
+            }
+            0x6000..=0x7FFF => self.write_prg_ram(self.prg_ram_bank as i16, addr, value),
+            0x8000..=0xDFFF => {
+                let (rom, bank) = self.prg_pages[((addr - 0x8000) >> 13) as usize];
+                if rom {
+                    self.core.read_cpu(addr)
+                } else {
+                    self.write_prg_ram(bank, addr, value)
+                }
+            }
+            _ => self.core.read_cpu(addr),
+        }
+    }
+
+    fn peek_cpu(&self, addr: u16) -> u8 {
+        let prg_ram = |bank, addr| {
+            self.prg_ram_index(bank, addr)
+                .map_or(0, |index| self.core.sram.memory[index])
+        };
+        match addr {
+            0x5000..=0x5FFF => self.peek_register(addr),
+            0x6000..=0x7FFF => prg_ram(self.prg_ram_bank as i16, addr),
+            0x8000..=0xFFFF => match self.prg_pages[((addr - 0x8000) >> 13) as usize] {
+                (true, _) => self.core.read_cpu(addr),
+                (false, bank) => prg_ram(bank, addr),
+            },
+            _ => self.core.read_cpu(addr),
+        }
+    }
+    fn poke_cpu(&mut self, addr: u16, value: u8) {
+        let prg_ram_bank = match addr {
+            0x5C00..=0x5FFF => {
+                self.exram[(addr - 0x5C00) as usize] = value;
+                return;
+            }
+            0x5000..=0x5BFF => return,
+            0x6000..=0x7FFF => self.prg_ram_bank as i16,
+            0x8000..=0xFFFF => match self.prg_pages[((addr - 0x8000) >> 13) as usize] {
+                (true, _) => return self.core.poke_cpu(addr, value),
+                (false, bank) => bank,
+            },
+            _ => return self.core.poke_cpu(addr, value),
+        };
+        if let Some(index) = self.prg_ram_index(prg_ram_bank, addr) {
+            self.core.sram.memory[index] = value;
+        }
+    }
+
+    fn read_ppu(&mut self, addr: u16) -> u8 {
+        self.detect_scanline(addr);
+        if addr < 0x2000 {
+            self.map_chr(addr);
+            self.core.read_ppu(addr)
+        } else {
+            self.read_nametable(addr)
+        }
+    }
+    // CHR is peeked with the banks of the last fetch
+    fn peek_ppu(&self, addr: u16) -> u8 {
+        if addr < 0x2000 {
+            self.core.read_ppu(addr)
+        } else {
+            self.peek_nametable(addr)
+        }
+    }
+    fn poke_ppu(&mut self, addr: u16, value: u8) {
+        if addr < 0x2000 {
+            return self.core.poke_ppu(addr, value);
+        }
+        let offset = (addr & 0x3FF) as usize;
+        match self.nametable_source(((addr >> 10) & 0b11) as usize) {
+            NametableSource::Vram(_) => self.core.poke_ppu(addr, value),
+            NametableSource::ExRam => self.exram[offset] = value,
+            NametableSource::Fill => (),
+        }
+    }
+
+    fn write_ppu(&mut self, addr: u16, value: u8) -> u8 {
+        if addr < 0x2000 {
+            self.fetch_kind = PpuFetchKind::Data;
+            self.map_chr(addr);
+            return self.core.write_ppu(addr, value);
+        }
+        let quadrant = ((addr >> 10) & 0b11) as usize;
+        let offset = (addr & 0x3FF) as usize;
+        match self.nametable_source(quadrant) {
+            NametableSource::Vram(_) => self.core.write_ppu(addr, value),
+            NametableSource::ExRam if self.exram_mode <= 1 => {
+                let old = self.exram[offset];
+                self.exram[offset] = value;
+                old
+            }
+            _ => 0,
+        }
+    }
+
+    fn ppu_fetch(&mut self, _addr: u16, context: PpuFetchContext) {
+        self.fetch_kind = context.kind;
+        self.sprite_size_large = context.sprite_size_large;
+    }
+
+    fn cpu_bus_clock(&mut self) -> InterruptFlags {
+        if self.ppu_idle_cycles < PPU_IDLE_CPU_CYCLES {
+            self.ppu_idle_cycles += 1;
+            if self.ppu_idle_cycles == PPU_IDLE_CPU_CYCLES {
+                self.in_frame = false;
+                self.last_ppu_addr = 0;
+                self.ppu_addr_match_count = 0;
+            }
+        }
+
+        if (self.irq_pending && self.irq_enabled) || (self.pcm_irq_pending && self.pcm_irq_enabled)
+        {
+            InterruptFlags::IRQ
+        } else {
+            InterruptFlags::empty()
+        }
+    }
+
+    fn ppu_bus_clock(&mut self) {}
+
+    fn expansion_audio_clock(&mut self) -> Option<ExpansionAudioSample> {
+        self.clock_audio();
+        let cycle_type = self.audio_cycle_type;
+        let pulse1 = self.pulse_channel1.clock(cycle_type) as f32;
+        let pulse2 = self.pulse_channel2.clock(cycle_type) as f32;
+        let pulse_out = if pulse1 == 0.0 && pulse2 == 0.0 {
+            0.0
+        } else {
+            95.88 / ((8128.0 / (pulse1 + pulse2)) + 100.0)
+        };
+
+        // PCM is 8 bits, mixed like the 7 bit DMC output
+        let pcm = (self.pcm_level >> 1) as f32;
+        let pcm_out = if pcm == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / (pcm / 22638.0) + 100.0)
+        };
+        Some(ExpansionAudioSample::new(
+            ExpansionAudioChip::Mmc5,
+            pulse_out + pcm_out,
+        ))
+    }
+
+    fn core(&self) -> &CartridgeCore {
+        &self.core
+    }
+
+    fn core_mut(&mut self) -> &mut CartridgeCore {
+        &mut self.core
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        let mut registers = vec![
+            MapperRegister::new("prg_mode", self.prg_mode),
+            MapperRegister::new("chr_mode", self.chr_mode),
+            MapperRegister::new("prg_ram_protect_1", self.prg_ram_protect[0]),
+            MapperRegister::new("prg_ram_protect_2", self.prg_ram_protect[1]),
+            MapperRegister::new("exram_mode", self.exram_mode),
+            MapperRegister::new("nametable_mapping", self.nametable_mapping),
+            MapperRegister::new("fill_tile", self.fill_tile),
+            MapperRegister::new("fill_attribute", self.fill_attribute),
+            MapperRegister::new("prg_ram_bank", self.prg_ram_bank),
+        ];
+        registers.extend(numbered_registers("prg", &self.prg_banks));
+        registers.extend(numbered_registers("chr", &self.chr_banks));
+        registers.push(MapperRegister::new("chr_upper_bits", self.chr_upper_bits));
+        registers.push(MapperRegister::new("multiplicand", self.multiplicand));
+        registers.push(MapperRegister::new("multiplier", self.multiplier));
+        registers
+    }
+
+    fn irq_state(&self) -> Option<IrqState> {
+        Some(IrqState {
+            counter: self.scanline_counter as u32,
+            latch: self.irq_compare as u32,
+            enabled: self.irq_enabled,
+            pending: self.irq_pending,
+        })
+    }
+}
+
+impl SaveState for MMC5 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("MMC5");
+        self.core.save_state(writer);
+
+        writer.write_u8(self.prg_mode);
+        writer.write_u8(self.chr_mode);
+        writer.write_bytes(&self.prg_ram_protect);
+        writer.write_u8(self.exram_mode);
+        writer.write_u8(self.nametable_mapping);
+        writer.write_u8(self.fill_tile);
+        writer.write_u8(self.fill_attribute);
+
+        writer.write_u8(self.prg_ram_bank);
+        writer.write_bytes(&self.prg_banks);
+
+        for bank in self.chr_banks {
+            writer.write_u16(bank);
+        }
+        writer.write_u8(self.chr_upper_bits);
+        writer.write_bool(self.last_chr_write_a);
+        writer.write_u8(self.fetch_kind as u8);
+        writer.write_bool(self.sprite_size_large);
+
+        writer.write_bytes(&self.exram);
+        writer.write_u8(self.ex_attribute);
+
+        writer.write_u8(self.irq_compare);
+        writer.write_bool(self.irq_enabled);
+        writer.write_bool(self.irq_pending);
+        writer.write_bool(self.in_frame);
+        writer.write_u8(self.scanline_counter);
+        writer.write_u16(self.last_ppu_addr);
+        writer.write_u8(self.ppu_addr_match_count);
+        writer.write_u8(self.ppu_idle_cycles);
+
+        writer.write_u8(self.multiplicand);
+        writer.write_u8(self.multiplier);
+
+        self.pulse_channel1.save_state(writer);
+        self.pulse_channel2.save_state(writer);
+        writer.write_bool(self.audio_cycle_type == APUCycleType::Put);
+        writer.write_u16(self.frame_clock_counter);
+        writer.write_bool(self.pcm_read_mode);
+        writer.write_bool(self.pcm_irq_enabled);
+        writer.write_bool(self.pcm_irq_pending);
+        writer.write_u8(self.pcm_level);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("MMC5")?;
+        self.core.load_state(reader)?;
+
+        self.prg_mode = reader.read_u8()?;
+        self.chr_mode = reader.read_u8()?;
+        reader.read_bytes_into(&mut self.prg_ram_protect)?;
+        self.exram_mode = reader.read_u8()?;
+        self.nametable_mapping = reader.read_u8()?;
+        self.fill_tile = reader.read_u8()?;
+        self.fill_attribute = reader.read_u8()?;
+
+        self.prg_ram_bank = reader.read_u8()?;
+        reader.read_bytes_into(&mut self.prg_banks)?;
+
+        for bank in self.chr_banks.iter_mut() {
+            *bank = reader.read_u16()?;
+        }
+        self.chr_upper_bits = reader.read_u8()?;
+        self.last_chr_write_a = reader.read_bool()?;
+        self.fetch_kind = match reader.read_u8()? {
+            0 => PpuFetchKind::Nametable,
+            1 => PpuFetchKind::Attribute,
+            2 => PpuFetchKind::BackgroundPattern,
+            3 => PpuFetchKind::SpritePattern,
+            4 => PpuFetchKind::Data,
+            n => Err(SaveStateError::InvalidValue("MMC5 fetch kind", n as u32))?,
+        };
+        self.sprite_size_large = reader.read_bool()?;
+
+        reader.read_bytes_into(&mut self.exram)?;
+        self.ex_attribute = reader.read_u8()?;
+
+        self.irq_compare = reader.read_u8()?;
+        self.irq_enabled = reader.read_bool()?;
+        self.irq_pending = reader.read_bool()?;
+        self.in_frame = reader.read_bool()?;
+        self.scanline_counter = reader.read_u8()?;
+        self.last_ppu_addr = reader.read_u16()?;
+        self.ppu_addr_match_count = reader.read_u8()?;
+        self.ppu_idle_cycles = reader.read_u8()?;
+
+        self.multiplicand = reader.read_u8()?;
+        self.multiplier = reader.read_u8()?;
+
+        self.pulse_channel1.load_state(reader)?;
+        self.pulse_channel2.load_state(reader)?;
+        self.audio_cycle_type = if reader.read_bool()? {
+            APUCycleType::Put
+        } else {
+            APUCycleType::Get
+        };
+        self.frame_clock_counter = reader.read_u16()?;
+        self.pcm_read_mode = reader.read_bool()?;
+        self.pcm_irq_enabled = reader.read_bool()?;
+        self.pcm_irq_pending = reader.read_bool()?;
+        self.pcm_level = reader.read_u8()?;
+
+        self.reconfigure_prg_banks();
+        self.reconfigure_chr_banks();
+        self.reconfigure_nametables();
+        Ok(())
+    }
+}
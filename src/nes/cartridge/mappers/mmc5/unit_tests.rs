@@ -0,0 +1,304 @@
+use crate::{
+    bus::InterruptFlags,
+    nes::{
+        cartridge::mappers::{Mapper, mmc5::MMC5, test_cartridge::numbered_cartridge},
+        ppu::{PpuFetchContext, PpuFetchKind},
+    },
+};
+
+fn create_mmc5() -> MMC5 {
+    MMC5::new(numbered_cartridge(5, 128, 128, 64))
+}
+
+fn write(mmc5: &mut MMC5, registers: &[(u16, u8)]) {
+    for (addr, value) in registers {
+        mmc5.write_cpu(*addr, *value);
+    }
+}
+
+// the 8K bank at each page of 0x8000-0xFFFF
+fn prg_banks(mmc5: &mut MMC5) -> [u8; 4] {
+    [0x8000, 0xA000, 0xC000, 0xE000].map(|addr| mmc5.read_cpu(addr) / 8)
+}
+
+// the 1K bank at each page of pattern memory
+fn chr_banks(mmc5: &mut MMC5) -> [u8; 8] {
+    std::array::from_fn(|page| mmc5.read_ppu(page as u16 * 0x400))
+}
+
+fn fetch(mmc5: &mut MMC5, addr: u16, kind: PpuFetchKind, sprite_size_large: bool) -> u8 {
+    mmc5.ppu_fetch(
+        addr,
+        PpuFetchContext {
+            kind,
+            sprite_size_large,
+            rendering_enabled: true,
+            scan_line: 0,
+            dot: 0,
+        },
+    );
+    mmc5.read_ppu(addr)
+}
+
+// the PPU reads the same nametable byte three times at the end of a scanline
+fn scanline(mmc5: &mut MMC5) {
+    mmc5.read_ppu(0x0000);
+    for _ in 0..3 {
+        mmc5.read_ppu(0x2000);
+    }
+}
+
+#[test]
+fn test_prg_modes() {
+    let mut mmc5 = create_mmc5();
+    // 0x5117 starts at the last bank
+    assert_eq!(15, prg_banks(&mut mmc5)[3]);
+
+    write(&mut mmc5, &[(0x5100, 0), (0x5117, 0x85)]);
+    assert_eq!([4, 5, 6, 7], prg_banks(&mut mmc5));
+
+    write(&mut mmc5, &[(0x5100, 1), (0x5115, 0x87), (0x5117, 0x8B)]);
+    assert_eq!([6, 7, 10, 11], prg_banks(&mut mmc5));
+
+    write(
+        &mut mmc5,
+        &[(0x5100, 2), (0x5115, 0x84), (0x5116, 0x89), (0x5117, 0x8F)],
+    );
+    assert_eq!([4, 5, 9, 15], prg_banks(&mut mmc5));
+
+    write(
+        &mut mmc5,
+        &[
+            (0x5100, 3),
+            (0x5114, 0x81),
+            (0x5115, 0x82),
+            (0x5116, 0x83),
+            (0x5117, 0x04),
+        ],
+    );
+    // 0x5117 is always ROM
+    assert_eq!([1, 2, 3, 4], prg_banks(&mut mmc5));
+}
+
+#[test]
+fn test_prg_ram() {
+    let mut mmc5 = create_mmc5();
+    write(&mut mmc5, &[(0x5113, 3)]);
+
+    // writes are ignored until both protect registers are set
+    mmc5.write_cpu(0x6000, 0x42);
+    assert_eq!(0, mmc5.core.sram.memory[3 * 0x2000]);
+    write(&mut mmc5, &[(0x5102, 0b10), (0x5103, 0b01)]);
+    mmc5.write_cpu(0x6000, 0x42);
+    assert_eq!(0x42, mmc5.core.sram.memory[3 * 0x2000]);
+
+    // RAM banked into 0x8000
+    write(&mut mmc5, &[(0x5100, 3), (0x5114, 0x02)]);
+    mmc5.write_cpu(0x8001, 0x99);
+    assert_eq!(0x99, mmc5.read_cpu(0x8001));
+    write(&mut mmc5, &[(0x5113, 2)]);
+    assert_eq!(0x99, mmc5.read_cpu(0x6001));
+}
+
+#[test]
+fn test_chr_modes() {
+    let mut mmc5 = create_mmc5();
+
+    write(&mut mmc5, &[(0x5101, 0), (0x5127, 3)]);
+    assert_eq!([24, 25, 26, 27, 28, 29, 30, 31], chr_banks(&mut mmc5));
+
+    write(&mut mmc5, &[(0x5101, 1), (0x5123, 1), (0x5127, 2)]);
+    assert_eq!([4, 5, 6, 7, 8, 9, 10, 11], chr_banks(&mut mmc5));
+
+    write(
+        &mut mmc5,
+        &[
+            (0x5101, 2),
+            (0x5121, 1),
+            (0x5123, 2),
+            (0x5125, 3),
+            (0x5127, 4),
+        ],
+    );
+    assert_eq!([2, 3, 4, 5, 6, 7, 8, 9], chr_banks(&mut mmc5));
+
+    write(&mut mmc5, &[(0x5101, 3)]);
+    for i in 0..8 {
+        write(&mut mmc5, &[(0x5120 + i, 10 + i as u8)]);
+    }
+    assert_eq!([10, 11, 12, 13, 14, 15, 16, 17], chr_banks(&mut mmc5));
+}
+
+#[test]
+fn test_chr_sets_for_large_sprites() {
+    let mut mmc5 = create_mmc5();
+    write(&mut mmc5, &[(0x5101, 3)]);
+    for i in 0..8 {
+        write(&mut mmc5, &[(0x5120 + i, 10 + i as u8)]);
+    }
+    for i in 0..4 {
+        write(&mut mmc5, &[(0x5128 + i, 40 + i as u8)]);
+    }
+
+    // 8x8 sprites only use set A
+    assert_eq!(
+        15,
+        fetch(&mut mmc5, 0x1400, PpuFetchKind::BackgroundPattern, false)
+    );
+
+    // 8x16 sprites use set A, and the background set B
+    assert_eq!(
+        15,
+        fetch(&mut mmc5, 0x1400, PpuFetchKind::SpritePattern, true)
+    );
+    assert_eq!(
+        41,
+        fetch(&mut mmc5, 0x1400, PpuFetchKind::BackgroundPattern, true)
+    );
+
+    // the CPU sees the set last written
+    assert_eq!(41, fetch(&mut mmc5, 0x1400, PpuFetchKind::Data, true));
+    write(&mut mmc5, &[(0x5125, 20)]);
+    assert_eq!(20, fetch(&mut mmc5, 0x1400, PpuFetchKind::Data, true));
+}
+
+#[test]
+fn test_multiplier() {
+    let mut mmc5 = create_mmc5();
+    // 0xFF * 0xFF
+    assert_eq!(0x01, mmc5.read_cpu(0x5205));
+    assert_eq!(0xFE, mmc5.read_cpu(0x5206));
+
+    write(&mut mmc5, &[(0x5205, 200), (0x5206, 150)]);
+    assert_eq!(0x30, mmc5.read_cpu(0x5205));
+    assert_eq!(0x75, mmc5.read_cpu(0x5206));
+}
+
+#[test]
+fn test_scanline_irq() {
+    let mut mmc5 = create_mmc5();
+    write(&mut mmc5, &[(0x5203, 2), (0x5204, 0x80)]);
+
+    // the first scanline starts the frame, and counts as 0
+    scanline(&mut mmc5);
+    scanline(&mut mmc5);
+    assert_eq!(InterruptFlags::empty(), mmc5.cpu_bus_clock());
+    assert_eq!(0b0100_0000, mmc5.read_cpu(0x5204));
+
+    scanline(&mut mmc5);
+    assert_eq!(InterruptFlags::IRQ, mmc5.cpu_bus_clock());
+
+    // reading the status acknowledges it
+    assert_eq!(0b1100_0000, mmc5.read_cpu(0x5204));
+    assert_eq!(InterruptFlags::empty(), mmc5.cpu_bus_clock());
+
+    // the frame ends once the PPU stops reading
+    mmc5.cpu_bus_clock();
+    mmc5.cpu_bus_clock();
+    assert_eq!(0, mmc5.read_cpu(0x5204));
+}
+
+#[test]
+fn test_scanline_irq_disabled() {
+    let mut mmc5 = create_mmc5();
+    write(&mut mmc5, &[(0x5203, 1)]);
+
+    scanline(&mut mmc5);
+    scanline(&mut mmc5);
+    // pending, but not asserted until it's enabled
+    assert_eq!(InterruptFlags::empty(), mmc5.cpu_bus_clock());
+    assert_eq!(0b1000_0000, mmc5.peek_cpu(0x5204) & 0b1000_0000);
+
+    write(&mut mmc5, &[(0x5204, 0x80)]);
+    assert_eq!(InterruptFlags::IRQ, mmc5.cpu_bus_clock());
+}
+
+#[test]
+fn test_scanline_irq_cleared_by_next_frame() {
+    let mut mmc5 = create_mmc5();
+    write(&mut mmc5, &[(0x5203, 1)]);
+    scanline(&mut mmc5);
+    scanline(&mut mmc5);
+    assert_eq!(0b1000_0000, mmc5.peek_cpu(0x5204) & 0b1000_0000);
+
+    // left unacknowledged through the end of the frame
+    for _ in 0..3 {
+        mmc5.cpu_bus_clock();
+    }
+    assert_eq!(0b1000_0000, mmc5.peek_cpu(0x5204));
+
+    scanline(&mut mmc5);
+    assert_eq!(0b0100_0000, mmc5.peek_cpu(0x5204));
+    write(&mut mmc5, &[(0x5204, 0x80)]);
+    assert_eq!(InterruptFlags::empty(), mmc5.cpu_bus_clock());
+}
+
+#[test]
+fn test_exram_as_nametable() {
+    let mut mmc5 = create_mmc5();
+    // the first nametable from ExRAM
+    write(&mut mmc5, &[(0x5104, 0), (0x5105, 0b10)]);
+
+    // outside of rendering 0 is written instead, and the CPU can't read it back
+    write(&mut mmc5, &[(0x5C10, 0x42)]);
+    assert_eq!(0, mmc5.read_ppu(0x2010));
+
+    scanline(&mut mmc5);
+    write(&mut mmc5, &[(0x5C10, 0x42)]);
+    assert_eq!(0x42, mmc5.read_ppu(0x2010));
+    assert_eq!(0, mmc5.read_cpu(0x5C10));
+}
+
+#[test]
+fn test_exram_as_ram() {
+    let mut mmc5 = create_mmc5();
+    write(&mut mmc5, &[(0x5104, 2), (0x5C00, 0x42), (0x5FFF, 0x43)]);
+    assert_eq!(0x42, mmc5.read_cpu(0x5C00));
+    assert_eq!(0x43, mmc5.read_cpu(0x5FFF));
+
+    // read only
+    write(&mut mmc5, &[(0x5104, 3), (0x5C00, 0x55)]);
+    assert_eq!(0x42, mmc5.read_cpu(0x5C00));
+
+    // and not a nametable in either mode
+    write(&mut mmc5, &[(0x5105, 0b10)]);
+    assert_eq!(0, mmc5.read_ppu(0x2000));
+}
+
+#[test]
+fn test_fill_mode() {
+    let mut mmc5 = create_mmc5();
+    write(&mut mmc5, &[(0x5105, 0xFF), (0x5106, 0x33), (0x5107, 2)]);
+
+    assert_eq!(0x33, mmc5.read_ppu(0x2000));
+    assert_eq!(0x33, mmc5.read_ppu(0x2C00));
+    assert_eq!(0xAA, mmc5.read_ppu(0x23C0));
+}
+
+#[test]
+fn test_extended_attributes() {
+    let mut mmc5 = create_mmc5();
+    write(&mut mmc5, &[(0x5104, 1)]);
+    // palette 3 and 4K CHR bank 5 for the first tile
+    mmc5.poke_cpu(0x5C00, 0b1100_0101);
+
+    fetch(&mut mmc5, 0x2000, PpuFetchKind::Nametable, false);
+    assert_eq!(
+        0xFF,
+        fetch(&mut mmc5, 0x23C0, PpuFetchKind::Attribute, false)
+    );
+    assert_eq!(
+        20,
+        fetch(&mut mmc5, 0x0010, PpuFetchKind::BackgroundPattern, false)
+    );
+    assert_eq!(
+        23,
+        fetch(&mut mmc5, 0x0C10, PpuFetchKind::BackgroundPattern, false)
+    );
+
+    // sprites use the normal banks
+    assert_eq!(
+        0,
+        fetch(&mut mmc5, 0x0010, PpuFetchKind::SpritePattern, false)
+    );
+}
@@ -0,0 +1,443 @@
+#[cfg(test)]
+mod unit_tests;
+
+use anyhow::Result;
+
+use crate::{
+    bus::InterruptFlags,
+    nes::{
+        cartridge::{CartridgeCore, Mapper},
+        mapper_inspector::{IrqState, MapperRegister},
+        mixer::{ExpansionAudioChip, ExpansionAudioSample},
+    },
+    save_state::{SaveState, StateReader, StateWriter},
+};
+
+use super::numbered_registers;
+
+const SOUND_RAM_SIZE: usize = 0x80;
+const CHANNEL_REGISTERS_START: usize = 0x40;
+const CHANNEL_COUNT: usize = 8;
+// each channel is updated in turn, one every 15 CPU cycles
+const CHANNEL_UPDATE_CYCLES: u8 = 15;
+const IRQ_COUNTER_MAX: u16 = 0x7FFF;
+// values at or above this in a CHR or nametable register select CIRAM instead of CHR ROM
+const CIRAM_SELECT: u8 = 0xE0;
+const NAMETABLE_SIZE: usize = 0x400;
+
+/**
+ * Mapper 19
+ */
+pub struct Namco163 {
+    core: CartridgeCore,
+    chr_registers: [u8; 8],
+    nametable_registers: [u8; 4],
+    prg_registers: [u8; 3],
+    sound_disabled: bool,
+    // CIRAM can't be selected for 0x0000-0x0FFF and 0x1000-0x1FFF respectively
+    ciram_disabled: [bool; 2],
+    write_protect: u8,
+
+    irq_counter: u16,
+    irq_enabled: bool,
+
+    sound_ram: [u8; SOUND_RAM_SIZE],
+    sound_address: u8,
+    auto_increment: bool,
+    update_cycle: u8,
+    current_channel: usize,
+    channel_outputs: [f32; CHANNEL_COUNT],
+}
+
+impl Namco163 {
+    pub fn new(mut core: CartridgeCore) -> Self {
+        core.chr_ram.set_bank_size_k(1);
+        core.prg_rom.set_bank_size_k(8);
+        let mut result = Self {
+            core,
+            chr_registers: [0; 8],
+            nametable_registers: [CIRAM_SELECT; 4],
+            prg_registers: [0; 3],
+            sound_disabled: false,
+            ciram_disabled: [false; 2],
+            write_protect: 0,
+
+            irq_counter: 0,
+            irq_enabled: false,
+
+            sound_ram: [0; SOUND_RAM_SIZE],
+            sound_address: 0,
+            auto_increment: false,
+            update_cycle: 0,
+            current_channel: CHANNEL_COUNT - 1,
+            channel_outputs: [0.0; CHANNEL_COUNT],
+        };
+        result.reconfigure_banks();
+        result
+    }
+
+    fn configure(&mut self, addr: u16, value: u8) -> u8 {
+        match addr {
+            0x8000..=0xBFFF => {
+                let index = ((addr - 0x8000) >> 11) as usize;
+                let old = self.chr_registers[index];
+                self.chr_registers[index] = value;
+                self.reconfigure_banks();
+                old
+            }
+            0xC000..=0xDFFF => {
+                let index = ((addr - 0xC000) >> 11) as usize;
+                let old = self.nametable_registers[index];
+                self.nametable_registers[index] = value;
+                self.reconfigure_banks();
+                old
+            }
+            0xE000..=0xE7FF => {
+                let old = self.prg_registers[0] | if self.sound_disabled { 0b0100_0000 } else { 0 };
+                self.prg_registers[0] = value & 0b0011_1111;
+                self.sound_disabled = value & 0b0100_0000 != 0;
+                self.reconfigure_banks();
+                old
+            }
+            0xE800..=0xEFFF => {
+                let old = self.prg_registers[1]
+                    | if self.ciram_disabled[0] {
+                        0b0100_0000
+                    } else {
+                        0
+                    }
+                    | if self.ciram_disabled[1] {
+                        0b1000_0000
+                    } else {
+                        0
+                    };
+                self.prg_registers[1] = value & 0b0011_1111;
+                self.ciram_disabled[0] = value & 0b0100_0000 != 0;
+                self.ciram_disabled[1] = value & 0b1000_0000 != 0;
+                self.reconfigure_banks();
+                old
+            }
+            0xF000..=0xF7FF => {
+                let old = self.prg_registers[2];
+                self.prg_registers[2] = value & 0b0011_1111;
+                self.reconfigure_banks();
+                old
+            }
+            0xF800..=0xFFFF => {
+                let old = self.sound_address | if self.auto_increment { 0b1000_0000 } else { 0 };
+                // the same register also controls PRG RAM write protection
+                self.write_protect = value;
+                self.sound_address = value & 0b0111_1111;
+                self.auto_increment = value & 0b1000_0000 != 0;
+                old
+            }
+            _ => unreachable!("Invalid register {}", addr),
+        }
+    }
+
+    fn reconfigure_banks(&mut self) {
+        for (page, bank) in self.chr_registers.into_iter().enumerate() {
+            self.core.chr_ram.set_bank(page, bank as i16);
+        }
+        for (page, bank) in self.nametable_registers.into_iter().enumerate() {
+            if bank >= CIRAM_SELECT {
+                self.core.vram.set_bank(page, (bank & 0b1) as i16);
+                self.core.vram.set_bank(page + 4, (bank & 0b1) as i16);
+            }
+        }
+
+        self.core.prg_rom.set_bank(0, self.prg_registers[0] as i16);
+        self.core.prg_rom.set_bank(1, self.prg_registers[1] as i16);
+        self.core.prg_rom.set_bank(2, self.prg_registers[2] as i16);
+        self.core.prg_rom.set_bank(3, -1);
+    }
+
+    fn sram_writable(&self, addr: u16) -> bool {
+        let segment = (addr - 0x6000) >> 11;
+        self.write_protect & 0b1111_0000 == 0b0100_0000 && self.write_protect & (1 << segment) == 0
+    }
+
+    fn read_sound_data(&mut self) -> u8 {
+        let result = self.sound_ram[self.sound_address as usize];
+        self.advance_sound_address();
+        result
+    }
+
+    fn write_sound_data(&mut self, value: u8) -> u8 {
+        let old = self.sound_ram[self.sound_address as usize];
+        self.sound_ram[self.sound_address as usize] = value;
+        self.advance_sound_address();
+        old
+    }
+
+    fn advance_sound_address(&mut self) {
+        if self.auto_increment {
+            self.sound_address = (self.sound_address + 1) & 0b0111_1111;
+        }
+    }
+
+    /**
+     * The CIRAM page selected by a CHR or nametable register, if it selects one
+     */
+    fn ciram_page(&self, addr: u16) -> Option<usize> {
+        let (register, disabled) = if addr < 0x2000 {
+            let page = (addr >> 10) as usize;
+            (self.chr_registers[page], self.ciram_disabled[page / 4])
+        } else {
+            (
+                self.nametable_registers[((addr >> 10) & 0b11) as usize],
+                false,
+            )
+        };
+        (register >= CIRAM_SELECT && !disabled).then_some((register & 0b1) as usize)
+    }
+
+    fn read_pattern_or_nametable(&self, addr: u16) -> u8 {
+        let offset = addr as usize % NAMETABLE_SIZE;
+        match self.ciram_page(addr) {
+            Some(page) if addr < 0x2000 => self.core.vram.memory[page * NAMETABLE_SIZE + offset],
+            Some(_) => self.core.read_ppu(addr),
+            None if addr < 0x2000 => self.core.read_ppu(addr),
+            None => {
+                // CHR ROM used as a nametable
+                let bank = self.nametable_registers[((addr >> 10) & 0b11) as usize] as usize;
+                let memory = &self.core.chr_ram.memory;
+                memory[(bank * NAMETABLE_SIZE + offset) % memory.len()]
+            }
+        }
+    }
+
+    fn write_pattern_or_nametable(&mut self, addr: u16, value: u8) -> u8 {
+        let offset = addr as usize % NAMETABLE_SIZE;
+        match self.ciram_page(addr) {
+            Some(page) if addr < 0x2000 => {
+                let index = page * NAMETABLE_SIZE + offset;
+                let old = self.core.vram.memory[index];
+                self.core.vram.memory[index] = value;
+                old
+            }
+            Some(_) => self.core.write_ppu(addr, value),
+            None if addr < 0x2000 => self.core.write_ppu(addr, value),
+            None => self.read_pattern_or_nametable(addr),
+        }
+    }
+
+    fn enabled_channel_count(&self) -> usize {
+        ((self.sound_ram[0x7F] >> 4) & 0b111) as usize + 1
+    }
+
+    fn update_channel(&mut self, channel: usize) {
+        let base = CHANNEL_REGISTERS_START + channel * 8;
+        let registers = &mut self.sound_ram[base..base + 8];
+
+        let frequency =
+            registers[0] as u32 | (registers[2] as u32) << 8 | ((registers[4] & 0b11) as u32) << 16;
+        let length = 256 - (registers[4] & 0b1111_1100) as u32;
+        let mut phase =
+            registers[1] as u32 | (registers[3] as u32) << 8 | (registers[5] as u32) << 16;
+        phase = (phase + frequency) % (length << 16);
+        registers[1] = phase as u8;
+        registers[3] = (phase >> 8) as u8;
+        registers[5] = (phase >> 16) as u8;
+
+        let sample_address = (((phase >> 16) + registers[6] as u32) & 0xFF) as usize;
+        let volume = (registers[7] & 0b1111) as f32;
+        let sample = (self.sound_ram[sample_address >> 1] >> ((sample_address & 1) * 4)) & 0b1111;
+        self.channel_outputs[channel] = (sample as f32 - 8.0) * volume;
+    }
+}
+
+impl Mapper for Namco163 {
+    fn read_cpu(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x4800..=0x4FFF => self.read_sound_data(),
+            _ => self.peek_cpu(addr),
+        }
+    }
+    fn peek_cpu(&self, addr: u16) -> u8 {
+        match addr {
+            0x4800..=0x4FFF => self.sound_ram[self.sound_address as usize],
+            0x5000..=0x57FF => self.irq_counter as u8,
+            0x5800..=0x5FFF => {
+                (self.irq_counter >> 8) as u8 | if self.irq_enabled { 0b1000_0000 } else { 0 }
+            }
+            _ => self.core.read_cpu(addr),
+        }
+    }
+    fn write_cpu(&mut self, addr: u16, value: u8) -> u8 {
+        match addr {
+            0x4800..=0x4FFF => self.write_sound_data(value),
+            0x5000..=0x57FF => {
+                let old = self.irq_counter as u8;
+                self.irq_counter = (self.irq_counter & 0xFF00) | value as u16;
+                old
+            }
+            0x5800..=0x5FFF => {
+                let old =
+                    (self.irq_counter >> 8) as u8 | if self.irq_enabled { 0b1000_0000 } else { 0 };
+                self.irq_counter =
+                    (self.irq_counter & 0x00FF) | ((value & 0b0111_1111) as u16) << 8;
+                self.irq_enabled = value & 0b1000_0000 != 0;
+                old
+            }
+            0x6000..=0x7FFF if !self.sram_writable(addr) => self.core.read_cpu(addr),
+            0x8000..=0xFFFF => self.configure(addr, value),
+            _ => self.core.write_cpu(addr, value),
+        }
+    }
+
+    fn poke_cpu(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4800..=0x4FFF => self.sound_ram[self.sound_address as usize] = value,
+            // the IRQ counter
+            0x5000..=0x5FFF => {}
+            _ => self.core.poke_cpu(addr, value),
+        }
+    }
+
+    fn read_ppu(&mut self, addr: u16) -> u8 {
+        self.read_pattern_or_nametable(addr)
+    }
+    fn write_ppu(&mut self, addr: u16, value: u8) -> u8 {
+        self.write_pattern_or_nametable(addr, value)
+    }
+
+    fn peek_ppu(&self, addr: u16) -> u8 {
+        self.read_pattern_or_nametable(addr)
+    }
+    fn poke_ppu(&mut self, addr: u16, value: u8) {
+        let offset = addr as usize % NAMETABLE_SIZE;
+        match self.ciram_page(addr) {
+            Some(page) if addr < 0x2000 => {
+                self.core.vram.memory[page * NAMETABLE_SIZE + offset] = value;
+            }
+            Some(_) => self.core.poke_ppu(addr, value),
+            None if addr < 0x2000 => self.core.poke_ppu(addr, value),
+            None => {
+                let bank = self.nametable_registers[((addr >> 10) & 0b11) as usize] as usize;
+                let memory = &mut self.core.chr_ram.memory;
+                let index = (bank * NAMETABLE_SIZE + offset) % memory.len();
+                memory[index] = value;
+            }
+        }
+    }
+
+    fn cpu_bus_clock(&mut self) -> InterruptFlags {
+        if self.irq_enabled && self.irq_counter < IRQ_COUNTER_MAX {
+            self.irq_counter += 1;
+        }
+        if self.irq_enabled && self.irq_counter == IRQ_COUNTER_MAX {
+            InterruptFlags::IRQ
+        } else {
+            InterruptFlags::empty()
+        }
+    }
+
+    fn ppu_bus_clock(&mut self) {}
+
+    fn expansion_audio_clock(&mut self) -> Option<ExpansionAudioSample> {
+        self.update_cycle += 1;
+        if self.update_cycle >= CHANNEL_UPDATE_CYCLES {
+            self.update_cycle = 0;
+            let enabled_count = self.enabled_channel_count();
+            self.current_channel = if self.current_channel <= CHANNEL_COUNT - enabled_count {
+                CHANNEL_COUNT - 1
+            } else {
+                self.current_channel - 1
+            };
+            self.update_channel(self.current_channel);
+        }
+
+        if self.sound_disabled {
+            return None;
+        }
+        // the hardware multiplexes the enabled channels, which averages out to this
+        let enabled_count = self.enabled_channel_count();
+        let total: f32 = self.channel_outputs[CHANNEL_COUNT - enabled_count..]
+            .iter()
+            .sum();
+        Some(ExpansionAudioSample::new(
+            ExpansionAudioChip::Namco163,
+            total / enabled_count as f32,
+        ))
+    }
+
+    fn core(&self) -> &CartridgeCore {
+        &self.core
+    }
+
+    fn core_mut(&mut self) -> &mut CartridgeCore {
+        &mut self.core
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        let mut registers = numbered_registers("chr", &self.chr_registers);
+        registers.extend(numbered_registers("nametable", &self.nametable_registers));
+        registers.extend(numbered_registers("prg", &self.prg_registers));
+        registers.push(MapperRegister::new("write_protect", self.write_protect));
+        registers.push(MapperRegister::new("sound_address", self.sound_address));
+        registers
+    }
+
+    fn irq_state(&self) -> Option<IrqState> {
+        Some(IrqState {
+            counter: self.irq_counter as u32,
+            latch: IRQ_COUNTER_MAX as u32,
+            enabled: self.irq_enabled,
+            pending: self.irq_enabled && self.irq_counter == IRQ_COUNTER_MAX,
+        })
+    }
+}
+
+impl SaveState for Namco163 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("Namco163");
+        self.core.save_state(writer);
+        writer.write_bytes(&self.chr_registers);
+        writer.write_bytes(&self.nametable_registers);
+        writer.write_bytes(&self.prg_registers);
+        writer.write_bool(self.sound_disabled);
+        writer.write_bool(self.ciram_disabled[0]);
+        writer.write_bool(self.ciram_disabled[1]);
+        writer.write_u8(self.write_protect);
+
+        writer.write_u16(self.irq_counter);
+        writer.write_bool(self.irq_enabled);
+
+        writer.write_bytes(&self.sound_ram);
+        writer.write_u8(self.sound_address);
+        writer.write_bool(self.auto_increment);
+        writer.write_u8(self.update_cycle);
+        writer.write_usize(self.current_channel);
+        for output in self.channel_outputs {
+            writer.write_i16(output as i16);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("Namco163")?;
+        self.core.load_state(reader)?;
+        reader.read_bytes_into(&mut self.chr_registers)?;
+        reader.read_bytes_into(&mut self.nametable_registers)?;
+        reader.read_bytes_into(&mut self.prg_registers)?;
+        self.sound_disabled = reader.read_bool()?;
+        self.ciram_disabled[0] = reader.read_bool()?;
+        self.ciram_disabled[1] = reader.read_bool()?;
+        self.write_protect = reader.read_u8()?;
+
+        self.irq_counter = reader.read_u16()?;
+        self.irq_enabled = reader.read_bool()?;
+
+        reader.read_bytes_into(&mut self.sound_ram)?;
+        self.sound_address = reader.read_u8()?;
+        self.auto_increment = reader.read_bool()?;
+        self.update_cycle = reader.read_u8()?;
+        self.current_channel = reader.read_usize()? % CHANNEL_COUNT;
+        for output in self.channel_outputs.iter_mut() {
+            *output = reader.read_i16()? as f32;
+        }
+
+        self.reconfigure_banks();
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+use crate::{
+    bus::InterruptFlags,
+    nes::cartridge::mappers::{Mapper, namco_163::Namco163, test_cartridge::numbered_cartridge},
+};
+
+fn create_namco_163() -> Namco163 {
+    Namco163::new(numbered_cartridge(19, 128, 128, 8))
+}
+
+// writes consecutive sound RAM bytes through the auto-incrementing data port
+fn write_sound_ram(namco: &mut Namco163, addr: u8, values: &[u8]) {
+    namco.write_cpu(0xF800, 0b1000_0000 | addr);
+    for value in values {
+        namco.write_cpu(0x4800, *value);
+    }
+}
+
+// a channel playing sample 0xF at full volume, its volume register also sets the channel count
+fn setup_channel(namco: &mut Namco163, channel: u8, enabled_channels: u8) {
+    write_sound_ram(
+        namco,
+        0x40 + channel * 8,
+        &[0, 0, 0, 0, 0, 0, 0, ((enabled_channels - 1) << 4) | 0x0F],
+    );
+}
+
+fn output_after(namco: &mut Namco163, cycles: usize) -> f32 {
+    let mut output = 0.0;
+    for _ in 0..cycles {
+        output = namco.expansion_audio_clock().unwrap().output;
+    }
+    output
+}
+
+#[test]
+fn test_irq_counter_overflow() {
+    let mut namco = create_namco_163();
+    namco.write_cpu(0x5000, 0xFD);
+    namco.write_cpu(0x5800, 0xFF);
+    // bit 7 of the high byte is the enable, not part of the counter
+    assert_eq!(0x7FFD, namco.irq_counter);
+
+    assert_eq!(InterruptFlags::empty(), namco.cpu_bus_clock());
+    assert_eq!(InterruptFlags::IRQ, namco.cpu_bus_clock());
+
+    // the counter stops at 0x7FFF rather than wrapping
+    assert_eq!(InterruptFlags::IRQ, namco.cpu_bus_clock());
+    assert_eq!(0xFF, namco.read_cpu(0x5000));
+    assert_eq!(0xFF, namco.read_cpu(0x5800));
+
+    // disabling acknowledges it
+    namco.write_cpu(0x5800, 0x7F);
+    assert_eq!(InterruptFlags::empty(), namco.cpu_bus_clock());
+}
+
+#[test]
+fn test_irq_counter_disabled() {
+    let mut namco = create_namco_163();
+    namco.write_cpu(0x5000, 0xFE);
+    namco.write_cpu(0x5800, 0x7F);
+
+    assert_eq!(InterruptFlags::empty(), namco.cpu_bus_clock());
+    assert_eq!(0x7FFE, namco.irq_counter);
+}
+
+#[test]
+fn test_sound_ram_auto_increment() {
+    let mut namco = create_namco_163();
+    write_sound_ram(&mut namco, 0x7E, &[1, 2, 3]);
+    // the address wraps around after 0x7F
+    assert_eq!([1, 2], namco.sound_ram[0x7E..]);
+    assert_eq!(3, namco.sound_ram[0]);
+
+    namco.write_cpu(0xF800, 0b1000_0000 | 0x7F);
+    assert_eq!(2, namco.read_cpu(0x4800));
+    assert_eq!(3, namco.read_cpu(0x4800));
+
+    // without auto-increment the address stays put
+    namco.write_cpu(0xF800, 0x10);
+    namco.write_cpu(0x4800, 4);
+    namco.write_cpu(0x4800, 5);
+    assert_eq!(5, namco.read_cpu(0x4800));
+    assert_eq!(5, namco.read_cpu(0x4800));
+    assert_eq!(0, namco.sound_ram[0x11]);
+}
+
+#[test]
+fn test_single_channel_output() {
+    let mut namco = create_namco_163();
+    write_sound_ram(&mut namco, 0, &[0x0F]);
+    setup_channel(&mut namco, 0, 1);
+    setup_channel(&mut namco, 7, 1);
+
+    // with one channel enabled only channel 7 plays, updated every 15 cycles
+    assert_eq!(0.0, output_after(&mut namco, 14));
+    assert_eq!(105.0, output_after(&mut namco, 1));
+    assert_eq!(105.0, output_after(&mut namco, 8 * 15));
+}
+
+#[test]
+fn test_eight_channel_output() {
+    let mut namco = create_namco_163();
+    write_sound_ram(&mut namco, 0, &[0x0F]);
+    setup_channel(&mut namco, 0, 8);
+    setup_channel(&mut namco, 7, 8);
+
+    // the channels are updated counting down from 6, and averaged over all 8
+    assert_eq!(0.0, output_after(&mut namco, 6 * 15));
+    assert_eq!(105.0 / 8.0, output_after(&mut namco, 15));
+    assert_eq!(210.0 / 8.0, output_after(&mut namco, 15));
+    assert_eq!(210.0 / 8.0, output_after(&mut namco, 8 * 15));
+}
+
+#[test]
+fn test_sound_disabled() {
+    let mut namco = create_namco_163();
+    namco.write_cpu(0xE000, 0b0100_0000);
+    assert_eq!(None, namco.expansion_audio_clock());
+}
@@ -1,8 +1,16 @@
+use anyhow::Result;
+
 use crate::{
     bus::InterruptFlags,
-    nes::cartridge::{CartridgeCore, Mapper, MirrorType},
+    nes::{
+        cartridge::{CartridgeCore, Mapper, MirrorType},
+        mapper_inspector::MapperRegister,
+    },
+    save_state::{SaveState, StateReader, StateWriter},
 };
 
+use super::numbered_registers;
+
 /**
  * Mapper 154
  */
@@ -134,4 +142,34 @@ impl Mapper for Namcot3453 {
     fn core(&self) -> &CartridgeCore {
         &self.core
     }
+
+    fn core_mut(&mut self) -> &mut CartridgeCore {
+        &mut self.core
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        let mut registers = vec![MapperRegister::new("bank_select", self.read_bank_select())];
+        registers.extend(numbered_registers("r", &self.registers));
+        registers.push(MapperRegister::new("mirroring", self.mirror_mode));
+        registers
+    }
+}
+
+impl SaveState for Namcot3453 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("Namcot3453");
+        self.core.save_state(writer);
+        writer.write_usize(self.selected_register);
+        writer.write_u8(self.mirror_mode);
+        writer.write_bytes(&self.registers);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("Namcot3453")?;
+        self.core.load_state(reader)?;
+        self.selected_register = reader.read_usize()?;
+        self.mirror_mode = reader.read_u8()?;
+        reader.read_bytes_into(&mut self.registers)?;
+        Ok(())
+    }
 }
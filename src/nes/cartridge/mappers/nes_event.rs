@@ -1,6 +1,12 @@
+use anyhow::Result;
+
 use crate::{
     bus::InterruptFlags,
-    nes::cartridge::{CartridgeCore, Mapper, MirrorType},
+    nes::{
+        cartridge::{CartridgeCore, Mapper, MirrorType},
+        mapper_inspector::{IrqState, MapperRegister},
+    },
+    save_state::{SaveState, SaveStateError, StateReader, StateWriter},
 };
 
 /**
@@ -187,6 +193,14 @@ impl Mapper for NesEvent {
         }
     }
 
+    fn peek_cpu(&self, addr: u16) -> u8 {
+        if self.sram_disabled && self.core.sram.contains_addr(addr) {
+            0
+        } else {
+            self.core.read_cpu(addr)
+        }
+    }
+
     fn read_ppu(&mut self, addr: u16) -> u8 {
         self.core.read_ppu(addr)
     }
@@ -217,6 +231,80 @@ impl Mapper for NesEvent {
     fn core(&self) -> &CartridgeCore {
         &self.core
     }
+
+    fn core_mut(&mut self) -> &mut CartridgeCore {
+        &mut self.core
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        vec![
+            MapperRegister::new("control", self.control_reg),
+            MapperRegister::new("prg_chip_select", self.prg_chip_select),
+            MapperRegister::new("prg_bank_a", self.prg_bank_reg_a),
+            MapperRegister::new("prg_bank_b", self.prg_bank_reg_b),
+            MapperRegister::new("shift", self.shift_register),
+            MapperRegister::new("shift_count", self.shift_count),
+        ]
+    }
+
+    fn irq_state(&self) -> Option<IrqState> {
+        Some(IrqState {
+            counter: self.irq_count,
+            latch: self.irq_counter_target,
+            enabled: self.irq_counter_enabled,
+            pending: self.irq_occurred,
+        })
+    }
+}
+
+impl SaveState for NesEvent {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("NesEvent");
+        self.core.save_state(writer);
+        writer.write_bool(self.sram_disabled);
+        writer.write_u8(self.control_reg);
+        writer.write_bool(self.irq_counter_enabled);
+        writer.write_bool(self.irq_occurred);
+        writer.write_u8(self.prg_chip_select);
+        writer.write_u8(self.prg_bank_reg_a);
+        writer.write_u8(self.prg_bank_reg_b);
+        writer.write_u8(self.shift_register);
+        writer.write_u8(self.shift_count);
+        writer.write_usize(self.cycle_count);
+        writer.write_usize(self.last_write_cycle);
+        writer.write_u32(self.irq_count);
+        writer.write_u32(self.irq_counter_target);
+        writer.write_u8(match self.initialized {
+            InitState::NotStarted => 0,
+            InitState::Started => 1,
+            InitState::Initialized => 2,
+        });
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("NesEvent")?;
+        self.core.load_state(reader)?;
+        self.sram_disabled = reader.read_bool()?;
+        self.control_reg = reader.read_u8()?;
+        self.irq_counter_enabled = reader.read_bool()?;
+        self.irq_occurred = reader.read_bool()?;
+        self.prg_chip_select = reader.read_u8()?;
+        self.prg_bank_reg_a = reader.read_u8()?;
+        self.prg_bank_reg_b = reader.read_u8()?;
+        self.shift_register = reader.read_u8()?;
+        self.shift_count = reader.read_u8()?;
+        self.cycle_count = reader.read_usize()?;
+        self.last_write_cycle = reader.read_usize()?;
+        self.irq_count = reader.read_u32()?;
+        self.irq_counter_target = reader.read_u32()?;
+        self.initialized = match reader.read_u8()? {
+            0 => InitState::NotStarted,
+            1 => InitState::Started,
+            2 => InitState::Initialized,
+            n => Err(SaveStateError::InvalidValue("init state", n as u32))?,
+        };
+        Ok(())
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -1,6 +1,9 @@
+use anyhow::Result;
+
 use crate::{
     bus::InterruptFlags,
     nes::cartridge::{CartridgeCore, Mapper},
+    save_state::{SaveState, StateReader, StateWriter},
 };
 
 /**
@@ -40,4 +43,21 @@ impl Mapper for NRom {
     fn core(&self) -> &CartridgeCore {
         &self.core
     }
+
+    fn core_mut(&mut self) -> &mut CartridgeCore {
+        &mut self.core
+    }
+}
+
+impl SaveState for NRom {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("NRom");
+        self.core.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("NRom")?;
+        self.core.load_state(reader)?;
+        Ok(())
+    }
 }
@@ -0,0 +1,504 @@
+#[cfg(test)]
+mod unit_tests;
+
+use anyhow::Result;
+
+use crate::{
+    bus::InterruptFlags,
+    nes::{
+        cartridge::{CartridgeCore, Mapper, MirrorType},
+        mapper_inspector::{IrqState, MapperRegister},
+        mixer::{ExpansionAudioChip, ExpansionAudioSample},
+    },
+    save_state::{SaveState, StateReader, StateWriter},
+};
+
+use super::numbered_registers;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+// the 5B's tone, noise and envelope generators run at 1/16th of the CPU clock
+const AUDIO_DIVIDER: u8 = 16;
+const ENVELOPE_STEPS: u8 = 32;
+// each of the 32 output levels is 1.5dB apart
+const DB_PER_LEVEL: f32 = 1.5;
+
+/**
+ * Mapper 69
+ */
+pub struct SunsoftFME7 {
+    core: CartridgeCore,
+    command: u8,
+    chr_registers: [u8; 8],
+    prg_registers: [u8; 3],
+    // 0x6000-0x7FFF
+    ram_register: u8,
+    mirror_mode: u8,
+
+    irq_enabled: bool,
+    irq_counter_enabled: bool,
+    irq_counter: u16,
+    irq_occurred: bool,
+
+    audio: Sunsoft5BAudio,
+}
+
+impl SunsoftFME7 {
+    pub fn new(mut core: CartridgeCore) -> Self {
+        core.chr_ram.set_bank_size_k(1);
+        core.prg_rom.set_bank_size_k(8);
+        let mut result = Self {
+            core,
+            command: 0,
+            chr_registers: [0; 8],
+            prg_registers: [0; 3],
+            ram_register: 0,
+            mirror_mode: 0,
+
+            irq_enabled: false,
+            irq_counter_enabled: false,
+            irq_counter: 0,
+            irq_occurred: false,
+
+            audio: Sunsoft5BAudio::new(),
+        };
+        result.reconfigure_banks();
+        result
+    }
+
+    fn configure(&mut self, addr: u16, value: u8) -> u8 {
+        match addr {
+            0x8000..=0x9FFF => {
+                let old = self.command;
+                self.command = value & 0b1111;
+                old
+            }
+            0xA000..=0xBFFF => self.write_parameter(value),
+            0xC000..=0xDFFF => self.audio.select_register(value),
+            0xE000..=0xFFFF => self.audio.write_register(value),
+            _ => unreachable!("Invalid register {}", addr),
+        }
+    }
+
+    fn write_parameter(&mut self, value: u8) -> u8 {
+        let old = match self.command {
+            0..=7 => {
+                let index = self.command as usize;
+                std::mem::replace(&mut self.chr_registers[index], value)
+            }
+            8 => std::mem::replace(&mut self.ram_register, value),
+            9..=0xB => {
+                let index = (self.command - 9) as usize;
+                std::mem::replace(&mut self.prg_registers[index], value & 0b0011_1111)
+            }
+            0xC => std::mem::replace(&mut self.mirror_mode, value & 0b11),
+            0xD => {
+                let old = (if self.irq_enabled { 0b1 } else { 0 })
+                    | if self.irq_counter_enabled {
+                        0b1000_0000
+                    } else {
+                        0
+                    };
+                self.irq_enabled = value & 0b1 != 0;
+                self.irq_counter_enabled = value & 0b1000_0000 != 0;
+                self.irq_occurred = false;
+                old
+            }
+            0xE => {
+                let old = self.irq_counter as u8;
+                self.irq_counter = (self.irq_counter & 0xFF00) | value as u16;
+                old
+            }
+            _ => {
+                let old = (self.irq_counter >> 8) as u8;
+                self.irq_counter = (self.irq_counter & 0x00FF) | (value as u16) << 8;
+                old
+            }
+        };
+        self.reconfigure_banks();
+        old
+    }
+
+    fn reconfigure_banks(&mut self) {
+        for (page, bank) in self.chr_registers.into_iter().enumerate() {
+            self.core.chr_ram.set_bank(page, bank as i16);
+        }
+
+        self.core.prg_rom.set_bank(0, self.prg_registers[0] as i16);
+        self.core.prg_rom.set_bank(1, self.prg_registers[1] as i16);
+        self.core.prg_rom.set_bank(2, self.prg_registers[2] as i16);
+        self.core.prg_rom.set_bank(3, -1);
+        self.core
+            .sram
+            .set_bank(0, (self.ram_register & 0b0011_1111) as i16);
+
+        if self.core.nes_header.mirror_type != MirrorType::FourScreen {
+            let mirror_type = match self.mirror_mode {
+                0 => MirrorType::Vertical,
+                1 => MirrorType::Horizontal,
+                2 => MirrorType::SingleScreen(0),
+                _ => MirrorType::SingleScreen(1),
+            };
+            self.core.vram.set_mirror_type(mirror_type);
+        }
+    }
+
+    fn ram_selected(&self) -> bool {
+        self.ram_register & 0b0100_0000 != 0
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_register & 0b1000_0000 != 0
+    }
+
+    fn read_rom_at_6000(&self, addr: u16) -> u8 {
+        let bank = (self.ram_register & 0b0011_1111) as usize;
+        let memory = &self.core.prg_rom.memory;
+        memory[(bank * PRG_BANK_SIZE + (addr & 0x1FFF) as usize) % memory.len()]
+    }
+}
+
+impl Mapper for SunsoftFME7 {
+    fn read_cpu(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if !self.ram_selected() => self.read_rom_at_6000(addr),
+            0x6000..=0x7FFF if !self.ram_enabled() => 0,
+            _ => self.core.read_cpu(addr),
+        }
+    }
+    fn write_cpu(&mut self, addr: u16, value: u8) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if !self.ram_selected() || !self.ram_enabled() => 0,
+            0x8000..=0xFFFF => self.configure(addr, value),
+            _ => self.core.write_cpu(addr, value),
+        }
+    }
+
+    fn peek_cpu(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if !self.ram_selected() => self.read_rom_at_6000(addr),
+            0x6000..=0x7FFF if !self.ram_enabled() => 0,
+            _ => self.core.read_cpu(addr),
+        }
+    }
+
+    fn read_ppu(&mut self, addr: u16) -> u8 {
+        self.core.read_ppu(addr)
+    }
+    fn write_ppu(&mut self, addr: u16, value: u8) -> u8 {
+        self.core.write_ppu(addr, value)
+    }
+
+    fn cpu_bus_clock(&mut self) -> InterruptFlags {
+        if self.irq_counter_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xFFFF && self.irq_enabled {
+                self.irq_occurred = true;
+            }
+        }
+
+        if self.irq_occurred {
+            InterruptFlags::IRQ
+        } else {
+            InterruptFlags::empty()
+        }
+    }
+
+    fn ppu_bus_clock(&mut self) {}
+
+    fn expansion_audio_clock(&mut self) -> Option<ExpansionAudioSample> {
+        Some(ExpansionAudioSample::new(
+            ExpansionAudioChip::Sunsoft5B,
+            self.audio.clock(),
+        ))
+    }
+
+    fn core(&self) -> &CartridgeCore {
+        &self.core
+    }
+
+    fn core_mut(&mut self) -> &mut CartridgeCore {
+        &mut self.core
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        let mut registers = vec![MapperRegister::new("command", self.command)];
+        registers.extend(numbered_registers("chr", &self.chr_registers));
+        registers.push(MapperRegister::new("ram", self.ram_register));
+        registers.extend(numbered_registers("prg", &self.prg_registers));
+        registers.push(MapperRegister::new("mirroring", self.mirror_mode));
+        registers
+    }
+
+    // the counter counts down while it's enabled, and fires when it wraps if the IRQ is
+    fn irq_state(&self) -> Option<IrqState> {
+        Some(IrqState {
+            counter: self.irq_counter as u32,
+            latch: 0,
+            enabled: self.irq_enabled && self.irq_counter_enabled,
+            pending: self.irq_occurred,
+        })
+    }
+}
+
+impl SaveState for SunsoftFME7 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("SunsoftFME7");
+        self.core.save_state(writer);
+        writer.write_u8(self.command);
+        writer.write_bytes(&self.chr_registers);
+        writer.write_bytes(&self.prg_registers);
+        writer.write_u8(self.ram_register);
+        writer.write_u8(self.mirror_mode);
+        writer.write_bool(self.irq_enabled);
+        writer.write_bool(self.irq_counter_enabled);
+        writer.write_u16(self.irq_counter);
+        writer.write_bool(self.irq_occurred);
+        self.audio.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("SunsoftFME7")?;
+        self.core.load_state(reader)?;
+        self.command = reader.read_u8()?;
+        reader.read_bytes_into(&mut self.chr_registers)?;
+        reader.read_bytes_into(&mut self.prg_registers)?;
+        self.ram_register = reader.read_u8()?;
+        self.mirror_mode = reader.read_u8()?;
+        self.irq_enabled = reader.read_bool()?;
+        self.irq_counter_enabled = reader.read_bool()?;
+        self.irq_counter = reader.read_u16()?;
+        self.irq_occurred = reader.read_bool()?;
+        self.audio.load_state(reader)?;
+        self.reconfigure_banks();
+        Ok(())
+    }
+}
+
+/**
+ * The 5B is a YM2149F: three square wave channels with a shared noise
+ * generator and envelope, all with a logarithmic volume scale.
+ */
+struct Sunsoft5BAudio {
+    selected_register: u8,
+    registers: [u8; 16],
+    divider: u8,
+
+    tone_counters: [u16; 3],
+    tone_outputs: [bool; 3],
+
+    noise_counter: u8,
+    // noise runs at half the rate of the tone generators
+    noise_half_step: bool,
+    noise_shift_register: u32,
+
+    envelope_counter: u16,
+    envelope_step: u8,
+    envelope_attack: bool,
+    envelope_holding: bool,
+
+    levels: [f32; ENVELOPE_STEPS as usize],
+}
+
+impl Sunsoft5BAudio {
+    fn new() -> Self {
+        let mut levels = [0.0; ENVELOPE_STEPS as usize];
+        for (level, amplitude) in levels.iter_mut().enumerate().skip(1) {
+            let attenuation = (ENVELOPE_STEPS - 1 - level as u8) as f32 * DB_PER_LEVEL;
+            *amplitude = 10.0_f32.powf(-attenuation / 20.0);
+        }
+        Self {
+            selected_register: 0,
+            registers: [0; 16],
+            divider: 0,
+
+            tone_counters: [0; 3],
+            tone_outputs: [false; 3],
+
+            noise_counter: 0,
+            noise_half_step: false,
+            noise_shift_register: 1,
+
+            envelope_counter: 0,
+            envelope_step: 0,
+            envelope_attack: false,
+            envelope_holding: false,
+
+            levels,
+        }
+    }
+
+    fn select_register(&mut self, value: u8) -> u8 {
+        std::mem::replace(&mut self.selected_register, value & 0b1111)
+    }
+
+    fn write_register(&mut self, value: u8) -> u8 {
+        let index = self.selected_register as usize;
+        let old = std::mem::replace(&mut self.registers[index], value);
+        if index == 0xD {
+            self.restart_envelope();
+        }
+        old
+    }
+
+    fn tone_period(&self, channel: usize) -> u16 {
+        (self.registers[channel * 2] as u16
+            | ((self.registers[channel * 2 + 1] & 0b1111) as u16) << 8)
+            .max(1)
+    }
+
+    fn noise_period(&self) -> u8 {
+        (self.registers[6] & 0b1_1111).max(1)
+    }
+
+    fn envelope_period(&self) -> u16 {
+        (self.registers[0xB] as u16 | (self.registers[0xC] as u16) << 8).max(1)
+    }
+
+    fn envelope_shape(&self) -> u8 {
+        self.registers[0xD] & 0b1111
+    }
+
+    fn restart_envelope(&mut self) {
+        self.envelope_counter = 0;
+        self.envelope_step = 0;
+        self.envelope_attack = self.envelope_shape() & 0b0100 != 0;
+        self.envelope_holding = false;
+    }
+
+    fn envelope_level(&self) -> u8 {
+        if self.envelope_attack {
+            self.envelope_step
+        } else {
+            ENVELOPE_STEPS - 1 - self.envelope_step
+        }
+    }
+
+    fn clock_envelope(&mut self) {
+        if self.envelope_holding {
+            return;
+        }
+        self.envelope_counter += 1;
+        if self.envelope_counter < self.envelope_period() {
+            return;
+        }
+        self.envelope_counter = 0;
+        self.envelope_step += 1;
+        if self.envelope_step < ENVELOPE_STEPS {
+            return;
+        }
+
+        let shape = self.envelope_shape();
+        let continue_cycle = shape & 0b1000 != 0;
+        let alternate = shape & 0b0010 != 0;
+        let hold = shape & 0b0001 != 0;
+        if !continue_cycle {
+            // drop to silence and stay there
+            self.envelope_step = ENVELOPE_STEPS - 1;
+            self.envelope_attack = false;
+            self.envelope_holding = true;
+        } else if hold {
+            self.envelope_step = ENVELOPE_STEPS - 1;
+            if alternate {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            self.envelope_holding = true;
+        } else {
+            self.envelope_step = 0;
+            if alternate {
+                self.envelope_attack = !self.envelope_attack;
+            }
+        }
+    }
+
+    fn clock_noise(&mut self) {
+        self.noise_half_step = !self.noise_half_step;
+        if self.noise_half_step {
+            return;
+        }
+        self.noise_counter += 1;
+        if self.noise_counter >= self.noise_period() {
+            self.noise_counter = 0;
+            let feedback = (self.noise_shift_register ^ (self.noise_shift_register >> 3)) & 1;
+            self.noise_shift_register = (self.noise_shift_register >> 1) | (feedback << 16);
+        }
+    }
+
+    fn clock(&mut self) -> f32 {
+        self.divider += 1;
+        if self.divider >= AUDIO_DIVIDER {
+            self.divider = 0;
+            for channel in 0..3 {
+                self.tone_counters[channel] += 1;
+                if self.tone_counters[channel] >= self.tone_period(channel) {
+                    self.tone_counters[channel] = 0;
+                    self.tone_outputs[channel] = !self.tone_outputs[channel];
+                }
+            }
+            self.clock_noise();
+            self.clock_envelope();
+        }
+
+        let mixer = self.registers[7];
+        let noise_output = self.noise_shift_register & 1 != 0;
+        (0..3)
+            .map(|channel| {
+                let tone_disabled = mixer & (1 << channel) != 0;
+                let noise_disabled = mixer & (1 << (channel + 3)) != 0;
+                if (self.tone_outputs[channel] || tone_disabled) && (noise_output || noise_disabled)
+                {
+                    let volume = self.registers[8 + channel];
+                    let level = if volume & 0b1_0000 != 0 {
+                        self.envelope_level()
+                    } else if volume & 0b1111 == 0 {
+                        0
+                    } else {
+                        // 4 bit volumes are 3dB apart so line up with every other level
+                        (volume & 0b1111) * 2 + 1
+                    };
+                    self.levels[level as usize]
+                } else {
+                    0.0
+                }
+            })
+            .sum()
+    }
+}
+
+impl SaveState for Sunsoft5BAudio {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("Sunsoft5B");
+        writer.write_u8(self.selected_register);
+        writer.write_bytes(&self.registers);
+        writer.write_u8(self.divider);
+        for channel in 0..3 {
+            writer.write_u16(self.tone_counters[channel]);
+            writer.write_bool(self.tone_outputs[channel]);
+        }
+        writer.write_u8(self.noise_counter);
+        writer.write_bool(self.noise_half_step);
+        writer.write_u32(self.noise_shift_register);
+        writer.write_u16(self.envelope_counter);
+        writer.write_u8(self.envelope_step);
+        writer.write_bool(self.envelope_attack);
+        writer.write_bool(self.envelope_holding);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("Sunsoft5B")?;
+        self.selected_register = reader.read_u8()?;
+        reader.read_bytes_into(&mut self.registers)?;
+        self.divider = reader.read_u8()?;
+        for channel in 0..3 {
+            self.tone_counters[channel] = reader.read_u16()?;
+            self.tone_outputs[channel] = reader.read_bool()?;
+        }
+        self.noise_counter = reader.read_u8()?;
+        self.noise_half_step = reader.read_bool()?;
+        self.noise_shift_register = reader.read_u32()?;
+        self.envelope_counter = reader.read_u16()?;
+        self.envelope_step = reader.read_u8()?;
+        self.envelope_attack = reader.read_bool()?;
+        self.envelope_holding = reader.read_bool()?;
+        Ok(())
+    }
+}
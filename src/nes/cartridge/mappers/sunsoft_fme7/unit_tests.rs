@@ -0,0 +1,179 @@
+use crate::{
+    bus::InterruptFlags,
+    nes::cartridge::mappers::{
+        Mapper, sunsoft_fme7::SunsoftFME7, test_cartridge::numbered_cartridge,
+    },
+};
+
+// the audio is clocked once every 16 CPU cycles
+const AUDIO_TICK: usize = 16;
+
+fn create_fme7() -> SunsoftFME7 {
+    SunsoftFME7::new(numbered_cartridge(69, 128, 128, 8))
+}
+
+fn write_parameters(fme7: &mut SunsoftFME7, parameters: &[(u8, u8)]) {
+    for (command, value) in parameters {
+        fme7.write_cpu(0x8000, *command);
+        fme7.write_cpu(0xA000, *value);
+    }
+}
+
+fn write_audio(fme7: &mut SunsoftFME7, registers: &[(u8, u8)]) {
+    for (register, value) in registers {
+        fme7.write_cpu(0xC000, *register);
+        fme7.write_cpu(0xE000, *value);
+    }
+}
+
+// the output at the end of each audio tick
+fn audio_ticks(fme7: &mut SunsoftFME7, ticks: usize) -> Vec<f32> {
+    (0..ticks * AUDIO_TICK)
+        .map(|_| fme7.expansion_audio_clock().unwrap().output)
+        .skip(AUDIO_TICK - 1)
+        .step_by(AUDIO_TICK)
+        .collect()
+}
+
+#[test]
+fn test_prg_banks() {
+    let mut fme7 = create_fme7();
+    write_parameters(&mut fme7, &[(9, 3), (0xA, 4), (0xB, 5)]);
+    assert_eq!(
+        [24, 32, 40, 120],
+        [0x8000, 0xA000, 0xC000, 0xE000].map(|addr| fme7.read_cpu(addr))
+    );
+
+    // ROM at 0x6000 unless RAM is selected
+    write_parameters(&mut fme7, &[(8, 2)]);
+    assert_eq!(16, fme7.read_cpu(0x6000));
+    write_parameters(&mut fme7, &[(8, 0b0100_0000)]);
+    assert_eq!(0, fme7.read_cpu(0x6000));
+    fme7.write_cpu(0x6000, 0x42);
+    assert_eq!(0, fme7.read_cpu(0x6000));
+
+    write_parameters(&mut fme7, &[(8, 0b1100_0000)]);
+    fme7.write_cpu(0x6000, 0x42);
+    assert_eq!(0x42, fme7.read_cpu(0x6000));
+}
+
+#[test]
+fn test_irq_counter() {
+    let mut fme7 = create_fme7();
+    write_parameters(&mut fme7, &[(0xE, 0x01), (0xF, 0x01), (0xD, 0x81)]);
+
+    // the IRQ fires when the 16 bit counter wraps from 0 to 0xFFFF
+    for _ in 0..0x0101 {
+        assert_eq!(InterruptFlags::empty(), fme7.cpu_bus_clock());
+    }
+    assert_eq!(0, fme7.irq_counter);
+    assert_eq!(InterruptFlags::IRQ, fme7.cpu_bus_clock());
+    assert_eq!(0xFFFF, fme7.irq_counter);
+
+    // it keeps counting, and stays pending until the control is written
+    assert_eq!(InterruptFlags::IRQ, fme7.cpu_bus_clock());
+    assert_eq!(0xFFFE, fme7.irq_counter);
+    write_parameters(&mut fme7, &[(0xD, 0x81)]);
+    assert_eq!(InterruptFlags::empty(), fme7.cpu_bus_clock());
+}
+
+#[test]
+fn test_irq_counter_without_irq() {
+    let mut fme7 = create_fme7();
+    write_parameters(&mut fme7, &[(0xE, 0x01), (0xF, 0x00), (0xD, 0x80)]);
+
+    // counts and wraps, but doesn't fire
+    for _ in 0..3 {
+        assert_eq!(InterruptFlags::empty(), fme7.cpu_bus_clock());
+    }
+    assert_eq!(0xFFFE, fme7.irq_counter);
+}
+
+#[test]
+fn test_irq_counter_disabled() {
+    let mut fme7 = create_fme7();
+    write_parameters(&mut fme7, &[(0xE, 0x00), (0xF, 0x00), (0xD, 0x01)]);
+
+    assert_eq!(InterruptFlags::empty(), fme7.cpu_bus_clock());
+    assert_eq!(0, fme7.irq_counter);
+}
+
+#[test]
+fn test_envelope_attack() {
+    let mut fme7 = create_fme7();
+    let levels = fme7.audio.levels;
+    // channel A at the envelope's volume, with tone and noise off
+    write_audio(
+        &mut fme7,
+        &[(7, 0b11_1111), (8, 0x10), (0xB, 1), (0xC, 0), (0xD, 0b0100)],
+    );
+
+    let outputs = audio_ticks(&mut fme7, 33);
+    assert_eq!(levels[1], outputs[0]);
+    assert_eq!(levels[16], outputs[15]);
+    assert_eq!(1.0, outputs[30]);
+    // without continue it drops to silence and stays there
+    assert_eq!(0.0, outputs[31]);
+    assert_eq!(0.0, outputs[32]);
+}
+
+#[test]
+fn test_envelope_triangle() {
+    let mut fme7 = create_fme7();
+    let levels = fme7.audio.levels;
+    write_audio(
+        &mut fme7,
+        &[(7, 0b11_1111), (8, 0x10), (0xB, 2), (0xC, 0), (0xD, 0b1110)],
+    );
+
+    // each step lasts 2 ticks, and alternate reverses direction at the end
+    let outputs = audio_ticks(&mut fme7, 130);
+    assert_eq!(levels[1], outputs[1]);
+    assert_eq!(1.0, outputs[61]);
+    assert_eq!(1.0, outputs[63]);
+    assert_eq!(levels[30], outputs[65]);
+    assert_eq!(0.0, outputs[125]);
+    assert_eq!(0.0, outputs[127]);
+    assert_eq!(levels[1], outputs[129]);
+}
+
+#[test]
+fn test_envelope_hold() {
+    let mut fme7 = create_fme7();
+    write_audio(
+        &mut fme7,
+        &[(7, 0b11_1111), (8, 0x10), (0xB, 1), (0xC, 0), (0xD, 0b1011)],
+    );
+
+    // decays, then jumps back up and holds
+    let outputs = audio_ticks(&mut fme7, 40);
+    assert_eq!(1.0, fme7.audio.levels[31]);
+    assert_eq!(0.0, outputs[30]);
+    assert_eq!(vec![1.0; 9], outputs[31..]);
+}
+
+#[test]
+fn test_noise() {
+    let mut fme7 = create_fme7();
+    // channel A at full volume, with only noise
+    write_audio(&mut fme7, &[(6, 1), (7, 0b11_0111), (8, 0x0F)]);
+
+    // the 17 bit LFSR shifts every other tick, so the first 1 takes 17 shifts to come back out
+    let outputs = audio_ticks(&mut fme7, 40);
+    assert_eq!(1.0, outputs[0]);
+    assert!(outputs[1..33].iter().all(|output| *output == 0.0));
+    assert_eq!(1.0, outputs[33]);
+
+    // and it doesn't get stuck
+    let outputs = audio_ticks(&mut fme7, 2000);
+    let ones = outputs.iter().filter(|output| **output == 1.0).count();
+    assert!((600..1400).contains(&ones), "{ones}");
+}
+
+#[test]
+fn test_tone_and_noise_disabled() {
+    let mut fme7 = create_fme7();
+    write_audio(&mut fme7, &[(7, 0b11_1111), (8, 0x0F), (9, 0x0F)]);
+
+    assert_eq!(vec![2.0; 4], audio_ticks(&mut fme7, 4));
+}
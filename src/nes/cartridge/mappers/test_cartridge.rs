@@ -0,0 +1,51 @@
+// Cartridges built in memory for the mappers' unit tests
+
+use crate::nes::cartridge::CartridgeCore;
+
+const PRG_ROM_UNIT_K: usize = 16;
+const CHR_ROM_UNIT_K: usize = 8;
+
+/**
+ * A NES 2.0 cartridge where every byte of PRG and CHR ROM is the number of the
+ * 1K bank it's in, so a read shows which bank is mapped there. Without CHR ROM
+ * it has 8K of CHR RAM.
+ */
+pub(super) fn numbered_cartridge(
+    mapper_number: u16,
+    prg_rom_k: usize,
+    chr_rom_k: usize,
+    prg_ram_k: usize,
+) -> CartridgeCore {
+    let mut data = vec![
+        b'N',
+        b'E',
+        b'S',
+        0x1A,
+        (prg_rom_k / PRG_ROM_UNIT_K) as u8,
+        (chr_rom_k / CHR_ROM_UNIT_K) as u8,
+        ((mapper_number & 0x0F) << 4) as u8,
+        (mapper_number & 0xF0) as u8 | 0x08,
+        (mapper_number >> 8) as u8,
+        0,
+        ram_shift(prg_ram_k),
+        if chr_rom_k == 0 { ram_shift(8) } else { 0 },
+        0,
+        0,
+        0,
+        0,
+    ];
+    for k in 0..prg_rom_k + chr_rom_k {
+        let bank = if k < prg_rom_k { k } else { k - prg_rom_k };
+        data.extend([bank as u8; 0x400]);
+    }
+    CartridgeCore::load("test.nes", data.as_slice(), data.len() as u64).unwrap()
+}
+
+// NES 2.0 RAM sizes are 64 << shift bytes
+fn ram_shift(size_k: usize) -> u8 {
+    if size_k == 0 {
+        0
+    } else {
+        (size_k * 1024 / 64).trailing_zeros() as u8
+    }
+}
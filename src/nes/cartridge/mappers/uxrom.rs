@@ -1,6 +1,9 @@
+use anyhow::Result;
+
 use crate::{
     bus::InterruptFlags,
     nes::cartridge::{CartridgeCore, Mapper},
+    save_state::{SaveState, StateReader, StateWriter},
 };
 
 /**
@@ -53,4 +56,21 @@ impl Mapper for UxRom {
     fn core(&self) -> &CartridgeCore {
         &self.core
     }
+
+    fn core_mut(&mut self) -> &mut CartridgeCore {
+        &mut self.core
+    }
+}
+
+impl SaveState for UxRom {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("UxRom");
+        self.core.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("UxRom")?;
+        self.core.load_state(reader)?;
+        Ok(())
+    }
 }
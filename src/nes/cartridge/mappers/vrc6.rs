@@ -0,0 +1,462 @@
+#[cfg(test)]
+mod unit_tests;
+
+use anyhow::Result;
+
+use crate::{
+    bus::InterruptFlags,
+    nes::{
+        cartridge::{CartridgeCore, Mapper, MirrorType},
+        mapper_inspector::{IrqState, MapperRegister},
+        mixer::{ExpansionAudioChip, ExpansionAudioSample},
+    },
+    save_state::{SaveState, StateReader, StateWriter},
+};
+
+use super::{numbered_registers, vrc_irq::VrcIrq};
+
+const PULSE_STEPS: u8 = 16;
+const SAW_STEPS: u8 = 14;
+
+/**
+ * Mapper 24 and 26
+ */
+pub struct VRC6 {
+    core: CartridgeCore,
+    // mapper 26 swaps the two low address lines
+    swap_address_lines: bool,
+    prg_registers: [u8; 2],
+    chr_registers: [u8; 8],
+    ppu_banking: u8,
+    vrc_irq: VrcIrq,
+
+    pulse_channel1: Vrc6Pulse,
+    pulse_channel2: Vrc6Pulse,
+    saw_channel: Vrc6Saw,
+    // 0x9003
+    frequency_control: u8,
+}
+
+impl VRC6 {
+    pub fn new(mut core: CartridgeCore, swap_address_lines: bool) -> Self {
+        core.chr_ram.set_bank_size_k(1);
+        core.prg_rom.set_bank_size_k(8);
+        let mut result = Self {
+            core,
+            swap_address_lines,
+            prg_registers: [0; 2],
+            chr_registers: [0; 8],
+            ppu_banking: 0,
+            vrc_irq: VrcIrq::new(),
+
+            pulse_channel1: Vrc6Pulse::new(),
+            pulse_channel2: Vrc6Pulse::new(),
+            saw_channel: Vrc6Saw::new(),
+            frequency_control: 0,
+        };
+        result.reconfigure_banks();
+        result
+    }
+
+    fn configure(&mut self, addr: u16, value: u8) -> u8 {
+        let addr = if self.swap_address_lines {
+            (addr & 0xF000) | ((addr & 0b01) << 1) | ((addr & 0b10) >> 1)
+        } else {
+            addr & 0xF003
+        };
+        match addr {
+            0x8000..=0x8003 => self.set_prg_register(0, value & 0b0000_1111),
+            0x9000..=0x9002 => self.pulse_channel1.set_register(addr & 0b11, value),
+            0x9003 => {
+                let old = self.frequency_control;
+                self.frequency_control = value & 0b111;
+                old
+            }
+            0xA000..=0xA002 => self.pulse_channel2.set_register(addr & 0b11, value),
+            0xA003 => 0,
+            0xB000..=0xB002 => self.saw_channel.set_register(addr & 0b11, value),
+            0xB003 => {
+                let old = self.ppu_banking;
+                self.ppu_banking = value;
+                self.reconfigure_banks();
+                old
+            }
+            0xC000..=0xC003 => self.set_prg_register(1, value & 0b0001_1111),
+            0xD000..=0xD003 => self.set_chr_register((addr & 0b11) as usize, value),
+            0xE000..=0xE003 => self.set_chr_register(4 + (addr & 0b11) as usize, value),
+            0xF000 => self.vrc_irq.set_latch(value),
+            0xF001 => self.vrc_irq.set_control(value),
+            0xF002 => self.vrc_irq.acknowledge(),
+            0xF003 => 0,
+            _ => unreachable!("Invalid register {}", addr),
+        }
+    }
+
+    fn set_prg_register(&mut self, index: usize, value: u8) -> u8 {
+        let old = self.prg_registers[index];
+        self.prg_registers[index] = value;
+        self.reconfigure_banks();
+        old
+    }
+
+    fn set_chr_register(&mut self, index: usize, value: u8) -> u8 {
+        let old = self.chr_registers[index];
+        self.chr_registers[index] = value;
+        self.reconfigure_banks();
+        old
+    }
+
+    fn reconfigure_banks(&mut self) {
+        let prg_16k = self.prg_registers[0] as i16 * 2;
+        self.core.prg_rom.set_bank(0, prg_16k);
+        self.core.prg_rom.set_bank(1, prg_16k + 1);
+        self.core.prg_rom.set_bank(2, self.prg_registers[1] as i16);
+        self.core.prg_rom.set_bank(3, -1);
+
+        let r = self.chr_registers.map(|b| b as i16);
+        let chr_banks = match self.ppu_banking & 0b11 {
+            0 => r,
+            // 2K banks
+            1 => [
+                r[0] * 2,
+                r[0] * 2 + 1,
+                r[1] * 2,
+                r[1] * 2 + 1,
+                r[2] * 2,
+                r[2] * 2 + 1,
+                r[3] * 2,
+                r[3] * 2 + 1,
+            ],
+            // 1K banks then 2K banks
+            _ => [
+                r[0],
+                r[1],
+                r[2],
+                r[3],
+                r[4] * 2,
+                r[4] * 2 + 1,
+                r[5] * 2,
+                r[5] * 2 + 1,
+            ],
+        };
+        for (page, bank) in chr_banks.into_iter().enumerate() {
+            self.core.chr_ram.set_bank(page, bank);
+        }
+
+        if self.core.nes_header.mirror_type != MirrorType::FourScreen {
+            let mirror_type = match (self.ppu_banking >> 2) & 0b11 {
+                0 => MirrorType::Vertical,
+                1 => MirrorType::Horizontal,
+                2 => MirrorType::SingleScreen(0),
+                _ => MirrorType::SingleScreen(1),
+            };
+            self.core.vram.set_mirror_type(mirror_type);
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.ppu_banking & 0b1000_0000 != 0
+    }
+
+    fn frequency_shift(&self) -> Option<u8> {
+        match self.frequency_control {
+            n if n & 0b001 != 0 => None,
+            n if n & 0b100 != 0 => Some(8),
+            n if n & 0b010 != 0 => Some(4),
+            _ => Some(0),
+        }
+    }
+}
+
+impl Mapper for VRC6 {
+    fn read_cpu(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram_enabled() => 0,
+            _ => self.core.read_cpu(addr),
+        }
+    }
+    fn write_cpu(&mut self, addr: u16, value: u8) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram_enabled() => 0,
+            0x8000..=0xFFFF => self.configure(addr, value),
+            _ => self.core.write_cpu(addr, value),
+        }
+    }
+
+    fn peek_cpu(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram_enabled() => 0,
+            _ => self.core.read_cpu(addr),
+        }
+    }
+
+    fn read_ppu(&mut self, addr: u16) -> u8 {
+        self.core.read_ppu(addr)
+    }
+    fn write_ppu(&mut self, addr: u16, value: u8) -> u8 {
+        self.core.write_ppu(addr, value)
+    }
+
+    fn cpu_bus_clock(&mut self) -> InterruptFlags {
+        self.vrc_irq.cpu_bus_clock()
+    }
+
+    fn ppu_bus_clock(&mut self) {}
+
+    fn expansion_audio_clock(&mut self) -> Option<ExpansionAudioSample> {
+        if let Some(shift) = self.frequency_shift() {
+            self.pulse_channel1.clock(shift);
+            self.pulse_channel2.clock(shift);
+            self.saw_channel.clock(shift);
+        }
+        let output =
+            self.pulse_channel1.output() + self.pulse_channel2.output() + self.saw_channel.output();
+        Some(ExpansionAudioSample::new(
+            ExpansionAudioChip::Vrc6,
+            output as f32,
+        ))
+    }
+
+    fn core(&self) -> &CartridgeCore {
+        &self.core
+    }
+
+    fn core_mut(&mut self) -> &mut CartridgeCore {
+        &mut self.core
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        let mut registers = numbered_registers("prg", &self.prg_registers);
+        registers.extend(numbered_registers("chr", &self.chr_registers));
+        registers.push(MapperRegister::new("ppu_banking", self.ppu_banking));
+        registers.push(MapperRegister::new(
+            "frequency_control",
+            self.frequency_control,
+        ));
+        registers
+    }
+
+    fn irq_state(&self) -> Option<IrqState> {
+        Some(self.vrc_irq.state())
+    }
+}
+
+impl SaveState for VRC6 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("VRC6");
+        self.core.save_state(writer);
+        writer.write_bytes(&self.prg_registers);
+        writer.write_bytes(&self.chr_registers);
+        writer.write_u8(self.ppu_banking);
+        self.vrc_irq.save_state(writer);
+        self.pulse_channel1.save_state(writer);
+        self.pulse_channel2.save_state(writer);
+        self.saw_channel.save_state(writer);
+        writer.write_u8(self.frequency_control);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("VRC6")?;
+        self.core.load_state(reader)?;
+        reader.read_bytes_into(&mut self.prg_registers)?;
+        reader.read_bytes_into(&mut self.chr_registers)?;
+        self.ppu_banking = reader.read_u8()?;
+        self.vrc_irq.load_state(reader)?;
+        self.pulse_channel1.load_state(reader)?;
+        self.pulse_channel2.load_state(reader)?;
+        self.saw_channel.load_state(reader)?;
+        self.frequency_control = reader.read_u8()?;
+        self.reconfigure_banks();
+        Ok(())
+    }
+}
+
+struct Vrc6Pulse {
+    volume: u8,
+    duty: u8,
+    ignore_duty: bool,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+}
+
+impl Vrc6Pulse {
+    fn new() -> Self {
+        Self {
+            volume: 0,
+            duty: 0,
+            ignore_duty: false,
+            period: 0,
+            enabled: false,
+            timer: 0,
+            step: 0,
+        }
+    }
+
+    fn set_register(&mut self, n: u16, value: u8) -> u8 {
+        match n {
+            0 => {
+                let old = self.volume | (self.duty << 4) | if self.ignore_duty { 0x80 } else { 0 };
+                self.volume = value & 0b1111;
+                self.duty = (value >> 4) & 0b111;
+                self.ignore_duty = value & 0b1000_0000 != 0;
+                old
+            }
+            1 => {
+                let old = self.period as u8;
+                self.period = (self.period & 0x0F00) | value as u16;
+                old
+            }
+            2 => {
+                let old = (self.period >> 8) as u8 | if self.enabled { 0x80 } else { 0 };
+                self.period = (self.period & 0x00FF) | ((value & 0b1111) as u16) << 8;
+                self.enabled = value & 0b1000_0000 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                }
+                old
+            }
+            _ => unreachable!("Invalid register {}", n),
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = (self.step + 1) % PULSE_STEPS;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.ignore_duty || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+impl SaveState for Vrc6Pulse {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("Vrc6Pulse");
+        writer.write_u8(self.volume);
+        writer.write_u8(self.duty);
+        writer.write_bool(self.ignore_duty);
+        writer.write_u16(self.period);
+        writer.write_bool(self.enabled);
+        writer.write_u16(self.timer);
+        writer.write_u8(self.step);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("Vrc6Pulse")?;
+        self.volume = reader.read_u8()?;
+        self.duty = reader.read_u8()?;
+        self.ignore_duty = reader.read_bool()?;
+        self.period = reader.read_u16()?;
+        self.enabled = reader.read_bool()?;
+        self.timer = reader.read_u16()?;
+        self.step = reader.read_u8()?;
+        Ok(())
+    }
+}
+
+struct Vrc6Saw {
+    rate: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+    accumulator: u8,
+}
+
+impl Vrc6Saw {
+    fn new() -> Self {
+        Self {
+            rate: 0,
+            period: 0,
+            enabled: false,
+            timer: 0,
+            step: 0,
+            accumulator: 0,
+        }
+    }
+
+    fn set_register(&mut self, n: u16, value: u8) -> u8 {
+        match n {
+            0 => {
+                let old = self.rate;
+                self.rate = value & 0b0011_1111;
+                old
+            }
+            1 => {
+                let old = self.period as u8;
+                self.period = (self.period & 0x0F00) | value as u16;
+                old
+            }
+            2 => {
+                let old = (self.period >> 8) as u8 | if self.enabled { 0x80 } else { 0 };
+                self.period = (self.period & 0x00FF) | ((value & 0b1111) as u16) << 8;
+                self.enabled = value & 0b1000_0000 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+                old
+            }
+            _ => unreachable!("Invalid register {}", n),
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            // the accumulator only advances on every other step
+            self.step += 1;
+            if self.step == SAW_STEPS {
+                self.step = 0;
+                self.accumulator = 0;
+            } else if self.step & 1 == 0 {
+                self.accumulator = self.accumulator.wrapping_add(self.rate);
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+impl SaveState for Vrc6Saw {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("Vrc6Saw");
+        writer.write_u8(self.rate);
+        writer.write_u16(self.period);
+        writer.write_bool(self.enabled);
+        writer.write_u16(self.timer);
+        writer.write_u8(self.step);
+        writer.write_u8(self.accumulator);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("Vrc6Saw")?;
+        self.rate = reader.read_u8()?;
+        self.period = reader.read_u16()?;
+        self.enabled = reader.read_bool()?;
+        self.timer = reader.read_u16()?;
+        self.step = reader.read_u8()?;
+        self.accumulator = reader.read_u8()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+use crate::nes::cartridge::mappers::{Mapper, test_cartridge::numbered_cartridge, vrc6::VRC6};
+
+fn create_vrc6(swap_address_lines: bool) -> VRC6 {
+    VRC6::new(numbered_cartridge(24, 128, 128, 8), swap_address_lines)
+}
+
+fn write(vrc6: &mut VRC6, registers: &[(u16, u8)]) {
+    for (addr, value) in registers {
+        vrc6.write_cpu(*addr, *value);
+    }
+}
+
+fn outputs(vrc6: &mut VRC6, cycles: usize) -> Vec<f32> {
+    (0..cycles)
+        .map(|_| vrc6.expansion_audio_clock().unwrap().output)
+        .collect()
+}
+
+#[test]
+fn test_prg_banks() {
+    let mut vrc6 = create_vrc6(false);
+    write(&mut vrc6, &[(0x8000, 2), (0xC000, 9)]);
+
+    // 16K at 0x8000, 8K at 0xC000, and the last 8K fixed
+    assert_eq!(32, vrc6.read_cpu(0x8000));
+    assert_eq!(40, vrc6.read_cpu(0xA000));
+    assert_eq!(72, vrc6.read_cpu(0xC000));
+    assert_eq!(120, vrc6.read_cpu(0xE000));
+}
+
+#[test]
+fn test_chr_banks() {
+    let mut vrc6 = create_vrc6(false);
+    for i in 0..4 {
+        write(
+            &mut vrc6,
+            &[(0xD000 + i, 10 + i as u8), (0xE000 + i, 20 + i as u8)],
+        );
+    }
+    let chr_banks = |vrc6: &mut VRC6| -> [u8; 8] {
+        std::array::from_fn(|page| vrc6.read_ppu(page as u16 * 0x400))
+    };
+
+    assert_eq!([10, 11, 12, 13, 20, 21, 22, 23], chr_banks(&mut vrc6));
+
+    write(&mut vrc6, &[(0xB003, 0b01)]);
+    assert_eq!([20, 21, 22, 23, 24, 25, 26, 27], chr_banks(&mut vrc6));
+
+    write(&mut vrc6, &[(0xB003, 0b10)]);
+    assert_eq!([10, 11, 12, 13, 40, 41, 42, 43], chr_banks(&mut vrc6));
+}
+
+#[test]
+fn test_swapped_address_lines() {
+    let mut vrc6 = create_vrc6(true);
+    // 0xB001 is the saw's high period and enable on mapper 26
+    write(&mut vrc6, &[(0xB000, 42), (0xB001, 0x80)]);
+    assert!(vrc6.saw_channel.enabled);
+
+    write(&mut vrc6, &[(0xD001, 5), (0xD002, 6)]);
+    assert_eq!([0, 6, 5, 0], vrc6.chr_registers[..4]);
+}
+
+#[test]
+fn test_prg_ram_enable() {
+    let mut vrc6 = create_vrc6(false);
+    write(&mut vrc6, &[(0x6000, 0x42)]);
+    assert_eq!(0, vrc6.read_cpu(0x6000));
+
+    write(&mut vrc6, &[(0xB003, 0x80), (0x6000, 0x42)]);
+    assert_eq!(0x42, vrc6.read_cpu(0x6000));
+}
+
+#[test]
+fn test_saw_accumulator() {
+    let mut vrc6 = create_vrc6(false);
+    write(&mut vrc6, &[(0xB000, 42), (0xB001, 0), (0xB002, 0x80)]);
+
+    // the rate is added on every other step, and the accumulator resets after 14
+    assert_eq!(
+        vec![0, 5, 5, 10, 10, 15, 15, 21, 21, 26, 26, 31, 31, 0, 0, 5],
+        outputs(&mut vrc6, 16)
+            .into_iter()
+            .map(|output| output as u8)
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_saw_accumulator_overflow() {
+    let mut vrc6 = create_vrc6(false);
+    write(&mut vrc6, &[(0xB000, 0x3F), (0xB001, 0), (0xB002, 0x80)]);
+
+    // rates above 42 wrap the 8 bit accumulator
+    let samples = outputs(&mut vrc6, 13);
+    assert_eq!(31.0, samples[7]);
+    assert_eq!(7.0, samples[9]);
+    assert_eq!(15.0, samples[11]);
+}
+
+#[test]
+fn test_saw_period() {
+    let mut vrc6 = create_vrc6(false);
+    write(&mut vrc6, &[(0xB000, 42), (0xB001, 3), (0xB002, 0x80)]);
+    let samples = outputs(&mut vrc6, 20);
+
+    // the first step is immediate, then every 4 cycles
+    assert_eq!(0.0, samples[3]);
+    assert_eq!(5.0, samples[4]);
+    assert_eq!(5.0, samples[11]);
+    assert_eq!(10.0, samples[12]);
+
+    // disabling resets the accumulator
+    write(&mut vrc6, &[(0xB002, 0)]);
+    assert_eq!(0.0, vrc6.expansion_audio_clock().unwrap().output);
+}
+
+#[test]
+fn test_pulse_duty() {
+    let mut vrc6 = create_vrc6(false);
+    write(&mut vrc6, &[(0x9000, 0x3F), (0x9001, 0), (0x9002, 0x80)]);
+
+    // duty 3 is on for 4 of 16 steps
+    let samples = outputs(&mut vrc6, 32);
+    assert_eq!(8, samples.iter().filter(|output| **output == 15.0).count());
+    assert_eq!(24, samples.iter().filter(|output| **output == 0.0).count());
+
+    // and ignoring the duty is always on
+    write(&mut vrc6, &[(0xA000, 0x85), (0xA002, 0x80)]);
+    assert!(outputs(&mut vrc6, 16).iter().all(|output| *output >= 5.0));
+}
+
+#[test]
+fn test_halt() {
+    let mut vrc6 = create_vrc6(false);
+    write(
+        &mut vrc6,
+        &[(0x9003, 1), (0xB000, 42), (0xB001, 0), (0xB002, 0x80)],
+    );
+    assert!(outputs(&mut vrc6, 16).iter().all(|output| *output == 0.0));
+
+    write(&mut vrc6, &[(0x9003, 0)]);
+    assert_eq!(5.0, outputs(&mut vrc6, 2)[1]);
+}
@@ -0,0 +1,136 @@
+#[cfg(test)]
+mod unit_tests;
+
+use anyhow::Result;
+
+use crate::{
+    bus::InterruptFlags,
+    nes::mapper_inspector::IrqState,
+    save_state::{SaveState, StateReader, StateWriter},
+};
+
+// the prescaler counts down by 3 each CPU cycle to approximate one PPU scanline
+const PRESCALER_PERIOD: i16 = 341;
+const PRESCALER_STEP: i16 = 3;
+
+/**
+ * Core IRQ logic shared by the Konami VRC mappers
+ */
+pub struct VrcIrq {
+    irq_latch: u8,
+    irq_count: u8,
+    prescaler: i16,
+    irq_enabled: bool,
+    enable_after_ack: bool,
+    cycle_mode: bool,
+    irq_occurred: bool,
+}
+
+impl VrcIrq {
+    pub fn new() -> Self {
+        Self {
+            irq_latch: 0,
+            irq_count: 0,
+            prescaler: PRESCALER_PERIOD,
+            irq_enabled: false,
+            enable_after_ack: false,
+            cycle_mode: false,
+            irq_occurred: false,
+        }
+    }
+
+    pub fn set_latch(&mut self, value: u8) -> u8 {
+        let old = self.irq_latch;
+        self.irq_latch = value;
+        old
+    }
+
+    pub fn set_control(&mut self, value: u8) -> u8 {
+        let old = self.read_control();
+        self.enable_after_ack = value & 0b001 != 0;
+        self.irq_enabled = value & 0b010 != 0;
+        self.cycle_mode = value & 0b100 != 0;
+        if self.irq_enabled {
+            self.irq_count = self.irq_latch;
+            self.prescaler = PRESCALER_PERIOD;
+        }
+        self.irq_occurred = false;
+        old
+    }
+
+    pub fn acknowledge(&mut self) -> u8 {
+        let old = self.read_control();
+        self.irq_occurred = false;
+        self.irq_enabled = self.enable_after_ack;
+        old
+    }
+
+    pub fn state(&self) -> IrqState {
+        IrqState {
+            counter: self.irq_count as u32,
+            latch: self.irq_latch as u32,
+            enabled: self.irq_enabled,
+            pending: self.irq_occurred,
+        }
+    }
+
+    fn read_control(&self) -> u8 {
+        (if self.enable_after_ack { 0b001 } else { 0 })
+            | (if self.irq_enabled { 0b010 } else { 0 })
+            | (if self.cycle_mode { 0b100 } else { 0 })
+    }
+
+    fn clock_counter(&mut self) {
+        if self.irq_count == 0xFF {
+            self.irq_count = self.irq_latch;
+            self.irq_occurred = true;
+        } else {
+            self.irq_count += 1;
+        }
+    }
+
+    pub fn cpu_bus_clock(&mut self) -> InterruptFlags {
+        if self.irq_enabled {
+            if self.cycle_mode {
+                self.clock_counter();
+            } else {
+                self.prescaler -= PRESCALER_STEP;
+                if self.prescaler <= 0 {
+                    self.prescaler += PRESCALER_PERIOD;
+                    self.clock_counter();
+                }
+            }
+        }
+
+        if self.irq_occurred {
+            InterruptFlags::IRQ
+        } else {
+            InterruptFlags::empty()
+        }
+    }
+}
+
+impl SaveState for VrcIrq {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("VrcIrq");
+        writer.write_u8(self.irq_latch);
+        writer.write_u8(self.irq_count);
+        writer.write_i16(self.prescaler);
+        writer.write_bool(self.irq_enabled);
+        writer.write_bool(self.enable_after_ack);
+        writer.write_bool(self.cycle_mode);
+        writer.write_bool(self.irq_occurred);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("VrcIrq")?;
+        self.irq_latch = reader.read_u8()?;
+        self.irq_count = reader.read_u8()?;
+        self.prescaler = reader.read_i16()?;
+        self.irq_enabled = reader.read_bool()?;
+        self.enable_after_ack = reader.read_bool()?;
+        self.cycle_mode = reader.read_bool()?;
+        self.irq_occurred = reader.read_bool()?;
+        Ok(())
+    }
+}
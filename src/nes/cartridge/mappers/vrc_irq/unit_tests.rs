@@ -0,0 +1,86 @@
+use crate::{bus::InterruptFlags, nes::cartridge::mappers::vrc_irq::VrcIrq};
+
+// the number of CPU cycles until the next IRQ, up to a limit
+fn cycles_until_irq(vrc_irq: &mut VrcIrq, limit: usize) -> Option<usize> {
+    (1..=limit).find(|_| vrc_irq.cpu_bus_clock() == InterruptFlags::IRQ)
+}
+
+#[test]
+fn test_cycle_mode() {
+    let mut vrc_irq = VrcIrq::new();
+    vrc_irq.set_latch(0xFD);
+    vrc_irq.set_control(0b110);
+
+    assert_eq!(Some(3), cycles_until_irq(&mut vrc_irq, 1000));
+    // still pending until it's acknowledged
+    assert_eq!(InterruptFlags::IRQ, vrc_irq.cpu_bus_clock());
+}
+
+#[test]
+fn test_scanline_mode() {
+    let mut vrc_irq = VrcIrq::new();
+    vrc_irq.set_latch(0xFD);
+    vrc_irq.set_control(0b010);
+
+    // the prescaler makes 3 scanlines take 341 CPU cycles
+    assert_eq!(Some(341), cycles_until_irq(&mut vrc_irq, 1000));
+    vrc_irq.set_control(0b010);
+    assert_eq!(Some(341), cycles_until_irq(&mut vrc_irq, 1000));
+
+    // a single scanline is 113 or 114 cycles
+    vrc_irq.set_latch(0xFF);
+    vrc_irq.set_control(0b010);
+    assert_eq!(Some(114), cycles_until_irq(&mut vrc_irq, 1000));
+    vrc_irq.acknowledge();
+    vrc_irq.set_control(0b010);
+    assert_eq!(Some(114), cycles_until_irq(&mut vrc_irq, 1000));
+}
+
+#[test]
+fn test_disabled() {
+    let mut vrc_irq = VrcIrq::new();
+    vrc_irq.set_latch(0xFF);
+    vrc_irq.set_control(0b100);
+
+    assert_eq!(None, cycles_until_irq(&mut vrc_irq, 1000));
+}
+
+#[test]
+fn test_acknowledge_with_enable() {
+    let mut vrc_irq = VrcIrq::new();
+    vrc_irq.set_latch(0xFE);
+    vrc_irq.set_control(0b111);
+    assert_eq!(Some(2), cycles_until_irq(&mut vrc_irq, 1000));
+
+    // acknowledging returns the control register, and keeps counting from the latch
+    assert_eq!(0b111, vrc_irq.acknowledge());
+    assert!(!vrc_irq.state().pending);
+    assert_eq!(Some(2), cycles_until_irq(&mut vrc_irq, 1000));
+}
+
+#[test]
+fn test_acknowledge_without_enable() {
+    let mut vrc_irq = VrcIrq::new();
+    vrc_irq.set_latch(0xFE);
+    vrc_irq.set_control(0b110);
+    assert_eq!(Some(2), cycles_until_irq(&mut vrc_irq, 1000));
+
+    assert_eq!(0b110, vrc_irq.acknowledge());
+    assert!(!vrc_irq.state().enabled);
+    assert_eq!(None, cycles_until_irq(&mut vrc_irq, 1000));
+}
+
+#[test]
+fn test_control_reloads_counter() {
+    let mut vrc_irq = VrcIrq::new();
+    vrc_irq.set_latch(0xF0);
+    vrc_irq.set_control(0b110);
+    vrc_irq.cpu_bus_clock();
+    vrc_irq.cpu_bus_clock();
+    assert_eq!(0xF2, vrc_irq.state().counter);
+
+    vrc_irq.set_latch(0xFE);
+    assert_eq!(0xF2, vrc_irq.state().counter);
+    vrc_irq.set_control(0b110);
+    assert_eq!(0xFE, vrc_irq.state().counter);
+}
@@ -1,6 +1,13 @@
 #[cfg(test)]
 mod unit_tests;
 
+use anyhow::Result;
+
+use crate::{
+    nes::mapper_inspector::BankMapping,
+    save_state::{SaveState, SaveStateError, StateReader, StateWriter},
+};
+
 use super::MirrorType;
 
 const MAX_BANKS: usize = 8;
@@ -45,7 +52,14 @@ impl MemoryRegion {
             alternate_bank_count: 0,
             bank_map: [(0, false); MAX_BANKS],
         };
-        result.set_bank_size(result.get_address_size().min(result.memory.len()));
+        // memory smaller than a page is mirrored across it, so there are never
+        // more pages than the bank map holds
+        let address_size = result.get_address_size();
+        result.set_bank_size(
+            address_size
+                .min(result.memory.len())
+                .max(address_size / MAX_BANKS),
+        );
         result
     }
 
@@ -73,6 +87,18 @@ impl MemoryRegion {
         old
     }
 
+    /**
+     * Writes even when the memory is write protected, e.g. to patch ROM
+     */
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        let (index, alternate) = self.convert(addr);
+        if alternate {
+            self.alternate_memory[index] = value;
+        } else {
+            self.memory[index] = value;
+        }
+    }
+
     fn get_address_size(&self) -> usize {
         self.end_address as usize - self.start_address as usize + 1
     }
@@ -145,6 +171,28 @@ impl MemoryRegion {
         self.bank_map[page].0
     }
 
+    /**
+     * Where each page of the region is mapped with the current banks
+     */
+    pub fn bank_mappings(&self) -> Vec<BankMapping> {
+        if self.memory.is_empty() {
+            return Vec::new();
+        }
+        (0..self.page_count)
+            .map(|page| {
+                let start = self.start_address + (page * self.bank_size) as u16;
+                let (offset, alternate) = self.convert(start);
+                BankMapping {
+                    start,
+                    end: start + (self.bank_size - 1) as u16,
+                    bank: offset / self.bank_size,
+                    offset,
+                    alternate,
+                }
+            })
+            .collect()
+    }
+
     fn convert(&self, addr: u16) -> (usize, bool) {
         let raw_index = (addr - self.start_address) as usize;
         let page = raw_index / self.bank_size;
@@ -166,7 +214,8 @@ impl MemoryRegion {
             } else {
                 self.bank_count
             };
-            (bank.0 as usize % bank_count) * self.bank_size
+            // memory smaller than a bank has no whole bank, it mirrors from the start
+            (bank.0 as usize % bank_count.max(1)) * self.bank_size
         } else {
             self.memory
                 .len()
@@ -181,9 +230,89 @@ impl MemoryRegion {
         }
     }
 
+    // a size the region could have been given: whole pages, no more than there's room for
+    fn is_valid_bank_size(&self, bank_size: usize) -> bool {
+        let address_size = self.get_address_size();
+        bank_size != 0
+            && address_size.is_multiple_of(bank_size)
+            && address_size / bank_size <= MAX_BANKS
+    }
+
+    // a bank convert can find with the current bank size. Positive banks wrap,
+    // but need memory to wrap around; negative ones count back from the end of memory.
+    fn is_valid_bank(&self, (bank, alternate): (i16, bool)) -> bool {
+        let (bank_count, memory_size) = if alternate {
+            (self.alternate_bank_count, self.alternate_memory.len())
+        } else {
+            (self.bank_count, self.memory.len())
+        };
+        if bank >= 0 {
+            memory_size > 0
+        } else {
+            bank.unsigned_abs() as usize <= bank_count && memory_size > 0
+        }
+    }
+
     pub fn contains_addr(&self, addr: u16) -> bool {
         self.start_address <= addr && addr <= self.end_address
     }
+
+    /**
+     * Where addr is in memory with the current banks. None when addr is outside
+     * the region or mapped to the alternate memory.
+     */
+    pub fn memory_offset(&self, addr: u16) -> Option<usize> {
+        if !self.contains_addr(addr) || self.memory.is_empty() {
+            return None;
+        }
+        match self.convert(addr) {
+            (offset, false) => Some(offset),
+            (_, true) => None,
+        }
+    }
+}
+
+/**
+ * Only memory that can change is captured. ROM contents come from the
+ * cartridge file and are expected to be identical when a state is loaded.
+ */
+impl SaveState for MemoryRegion {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_usize(self.bank_size);
+        for (bank, alternate) in self.bank_map {
+            writer.write_i16(bank);
+            writer.write_bool(alternate);
+        }
+        if !self.write_protect {
+            writer.write_bytes(&self.memory);
+        }
+        if !self.alternate_write_protect {
+            writer.write_bytes(&self.alternate_memory);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        let bank_size = reader.read_usize()?;
+        if !self.is_valid_bank_size(bank_size) {
+            Err(SaveStateError::InvalidValue("bank size", bank_size as u32))?;
+        }
+        self.set_bank_size(bank_size);
+        for page in 0..MAX_BANKS {
+            let entry = (reader.read_i16()?, reader.read_bool()?);
+            // only the pages the region has are ever converted
+            if page < self.page_count && !self.is_valid_bank(entry) {
+                Err(SaveStateError::InvalidValue("bank", entry.0 as u32))?;
+            }
+            self.bank_map[page] = entry;
+        }
+        if !self.write_protect {
+            reader.read_bytes_into(&mut self.memory)?;
+        }
+        if !self.alternate_write_protect {
+            reader.read_bytes_into(&mut self.alternate_memory)?;
+        }
+        Ok(())
+    }
 }
 
 fn k_to_usize(k: u16) -> usize {
@@ -1,6 +1,9 @@
-use crate::nes::cartridge::{
-    MirrorType,
-    memory_region::{MemoryRegion, MemoryType},
+use crate::{
+    nes::cartridge::{
+        MirrorType,
+        memory_region::{MemoryRegion, MemoryType},
+    },
+    save_state::{SaveState, StateReader, StateWriter},
 };
 
 #[test]
@@ -76,3 +79,68 @@ fn test_with_bank_set() {
     assert_eq!(0x3000, ram.convert(0x3800).0);
     assert_eq!(0x37FF, ram.convert(0x3FFF).0);
 }
+
+#[test]
+fn test_bank_mappings() {
+    let vec = vec![0; 0x8000];
+    let mut rom = MemoryRegion::new(MemoryType::PRG_ROM, vec, 0x8000, 0xFFFF, true);
+    rom.set_bank_size_k(8);
+    rom.set_bank(0, 2);
+    rom.set_bank(3, -1);
+
+    let banks = rom
+        .bank_mappings()
+        .iter()
+        .map(|b| (b.start, b.end, b.bank, b.offset))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            (0x8000, 0x9FFF, 2, 0x4000),
+            (0xA000, 0xBFFF, 0, 0x0000),
+            (0xC000, 0xDFFF, 0, 0x0000),
+            (0xE000, 0xFFFF, 3, 0x6000),
+        ],
+        banks
+    );
+}
+
+// a state for an 8K RAM region with 2K banks, with the given bank size and first bank
+fn ram_state(bank_size: usize, bank: i16) -> Vec<u8> {
+    let mut writer = StateWriter::new();
+    writer.write_usize(bank_size);
+    writer.write_i16(bank);
+    writer.write_bool(false);
+    for _ in 1..8 {
+        writer.write_i16(0);
+        writer.write_bool(false);
+    }
+    writer.write_bytes(&[0; 0x2000]);
+    writer.finish()
+}
+
+fn load_ram_state(data: &[u8]) -> anyhow::Result<MemoryRegion> {
+    let mut ram = MemoryRegion::new(MemoryType::SRAM, vec![0; 0x2000], 0x6000, 0x7FFF, false);
+    ram.load_state(&mut StateReader::new(data)?)?;
+    Ok(ram)
+}
+
+#[test]
+fn test_load_state() {
+    let mut ram = load_ram_state(&ram_state(0x800, -1)).unwrap();
+    ram.write(0x6000, 0x42);
+    assert_eq!(0x42, ram.memory[0x1800]);
+
+    // positive banks wrap around
+    let ram = load_ram_state(&ram_state(0x800, 5)).unwrap();
+    assert_eq!(Some(0x0800), ram.memory_offset(0x6000));
+}
+
+#[test]
+fn test_load_state_rejects_invalid_banks() {
+    for (bank_size, bank) in [(0, 0), (0x300, 0), (0x200, 0), (0x4000, 0), (0x800, -5)] {
+        assert!(
+            load_ram_state(&ram_state(bank_size, bank)).is_err(),
+            "bank size {bank_size:#x} bank {bank}"
+        );
+    }
+}
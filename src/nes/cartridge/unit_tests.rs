@@ -0,0 +1,210 @@
+use crate::nes::cartridge::{
+    CartridgeCore, ConsoleType, HeaderFormat, MirrorType, NesHeader, Timing,
+};
+
+fn header(bytes: [u8; 12]) -> [u8; 16] {
+    let mut result = [0; 16];
+    result[0..4].copy_from_slice(&[b'N', b'E', b'S', 0x1A]);
+    result[4..16].copy_from_slice(&bytes);
+    result
+}
+
+#[test]
+fn test_ines_header() {
+    let header =
+        NesHeader::new(&header([0x02, 0x01, 0x43, 0x10, 0x00, 0, 0, 0, 0, 0, 0, 0])).unwrap();
+
+    assert_eq!(HeaderFormat::INes, header.format);
+    assert_eq!(0x14, header.mapper_number);
+    assert_eq!(0, header.submapper);
+    assert_eq!(0x8000, header.prg_rom_size);
+    assert_eq!(0x2000, header.chr_rom_size);
+    assert!(header.chr_is_rom);
+    assert!(header.sram_is_persistent);
+    assert!(!header.has_trainer);
+    assert_eq!(MirrorType::Vertical, header.mirror_type);
+    assert_eq!(0x8000, header.sram_size);
+    assert_eq!(Timing::Ntsc, header.timing);
+    assert_eq!(ConsoleType::Nes, header.console_type);
+}
+
+#[test]
+fn test_ines_chr_ram() {
+    let header =
+        NesHeader::new(&header([0x01, 0x00, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0, 0])).unwrap();
+
+    assert!(!header.chr_is_rom);
+    assert_eq!(0x2000, header.chr_rom_size);
+    assert_eq!(0x2000, header.sram_size);
+    assert_eq!(0x2000, header.prg_ram_size);
+    assert_eq!(0, header.prg_nvram_size);
+}
+
+#[test]
+fn test_unsupported_version() {
+    assert!(NesHeader::new(&header([0x01, 0x01, 0x00, 0x04, 0, 0, 0, 0, 0, 0, 0, 0])).is_err());
+    assert!(NesHeader::new(&[0; 16]).is_err());
+}
+
+#[test]
+fn test_nes20_mapper_and_submapper() {
+    let header = NesHeader::new(&header([
+        0x08, 0x10, 0x52, 0x48, 0x31, 0x00, 0x70, 0x07, 0x01, 0x00, 0x00, 0x01,
+    ]))
+    .unwrap();
+
+    assert_eq!(HeaderFormat::Nes20, header.format);
+    assert_eq!(0x145, header.mapper_number);
+    assert_eq!(3, header.submapper);
+    assert_eq!(8 * 0x4000, header.prg_rom_size);
+    assert_eq!(16 * 0x2000, header.chr_rom_size);
+    assert!(header.chr_is_rom);
+    assert_eq!(0, header.prg_ram_size);
+    assert_eq!(0x2000, header.prg_nvram_size);
+    assert_eq!(0x2000, header.sram_size);
+    assert_eq!(0x2000, header.chr_ram_size);
+    assert_eq!(0, header.chr_nvram_size);
+    assert_eq!(Timing::Pal, header.timing);
+    assert_eq!(1, header.expansion_device);
+}
+
+#[test]
+fn test_nes20_large_rom_sizes() {
+    // MSB nibbles select 0x102 PRG pages and 0x200 CHR pages
+    let header = NesHeader::new(&header([
+        0x02, 0x00, 0x00, 0x08, 0x00, 0x21, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ]))
+    .unwrap();
+
+    assert_eq!(0x102 * 0x4000, header.prg_rom_size);
+    assert_eq!(0x200 * 0x2000, header.chr_rom_size);
+}
+
+#[test]
+fn test_nes20_exponent_multiplier_sizes() {
+    // PRG 2^10 * 3, CHR 2^7 * 1
+    let header = NesHeader::new(&header([
+        0b00101001, 0b00011100, 0x00, 0x08, 0x00, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ]))
+    .unwrap();
+
+    assert_eq!(1024 * 3, header.prg_rom_size);
+    assert_eq!(128, header.chr_rom_size);
+}
+
+#[test]
+fn test_nes20_chr_ram_and_prg_ram() {
+    let header = NesHeader::new(&header([
+        0x02, 0x00, 0x00, 0x08, 0x00, 0x00, 0x07, 0x09, 0x03, 0x00, 0x00, 0x00,
+    ]))
+    .unwrap();
+
+    assert_eq!(0x2000, header.prg_ram_size);
+    assert_eq!(0, header.prg_nvram_size);
+    assert!(!header.chr_is_rom);
+    assert_eq!(0x8000, header.chr_ram_size);
+    // CHR RAM takes the place of CHR ROM
+    assert_eq!(0x8000, header.chr_rom_size);
+    assert_eq!(Timing::Dendy, header.timing);
+}
+
+#[test]
+fn test_nes20_no_prg_ram_still_maps_sram() {
+    let header = NesHeader::new(&header([0x02, 0x01, 0x00, 0x08, 0, 0, 0, 0, 0, 0, 0, 0])).unwrap();
+
+    assert_eq!(0, header.prg_ram_size);
+    assert_eq!(0, header.prg_nvram_size);
+    assert_eq!(0x8000, header.sram_size);
+}
+
+#[test]
+fn test_nes20_console_types() {
+    let vs = NesHeader::new(&header([
+        0x02, 0x01, 0x00, 0x09, 0, 0, 0, 0, 0, 0x23, 0, 0x02,
+    ]))
+    .unwrap();
+    assert_eq!(
+        ConsoleType::VsSystem {
+            ppu_type: 3,
+            hardware_type: 2
+        },
+        vs.console_type
+    );
+    assert_eq!(2, vs.expansion_device);
+
+    let playchoice =
+        NesHeader::new(&header([0x02, 0x01, 0x00, 0x0A, 0, 0, 0, 0, 0, 0, 0, 0])).unwrap();
+    assert_eq!(ConsoleType::Playchoice10, playchoice.console_type);
+
+    let extended =
+        NesHeader::new(&header([0x02, 0x01, 0x00, 0x0B, 0, 0, 0, 0, 0, 0x03, 0, 0])).unwrap();
+    assert_eq!(ConsoleType::Extended(3), extended.console_type);
+}
+
+#[test]
+fn test_nes20_exponent_multiplier_overflow() {
+    // 2^63 * 7
+    assert!(
+        NesHeader::new(&header([
+            0xFF, 0x00, 0x00, 0x08, 0x00, 0x0F, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ]))
+        .is_err()
+    );
+}
+
+#[test]
+fn test_rom_size_exceeds_file() {
+    let nes_header =
+        NesHeader::new(&header([0x02, 0x01, 0x04, 0x00, 0, 0, 0, 0, 0, 0, 0, 0])).unwrap();
+    // the header, a trainer, 32K of PRG and 8K of CHR
+    let size = 16 + 512 + 0x8000 + 0x2000;
+    assert!(nes_header.check_file_size(size).is_ok());
+    assert!(nes_header.check_file_size(size + 100).is_ok());
+    assert!(nes_header.check_file_size(size - 1).is_err());
+
+    // 2^63 bytes of PRG
+    let huge = NesHeader::new(&header([
+        0xFC, 0x00, 0x00, 0x08, 0x00, 0x0F, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ]))
+    .unwrap();
+    assert!(huge.check_file_size(0x10000).is_err());
+
+    // 2^63 bytes each of PRG and CHR add up past u64
+    let huger = NesHeader::new(&header([
+        0xFC, 0xFC, 0x00, 0x08, 0x00, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ]))
+    .unwrap();
+    assert!(huger.check_file_size(0x10000).is_err());
+    assert!(huger.check_file_size(u64::MAX).is_err());
+}
+
+#[test]
+fn test_tiny_prg_ram_is_mirrored() {
+    // 16K of PRG ROM, 8K of CHR ROM, and 128 bytes of PRG RAM
+    let mut data = header([0x01, 0x01, 0x00, 0x08, 0, 0, 0x01, 0, 0, 0, 0, 0]).to_vec();
+    data.resize(16 + 0x4000 + 0x2000, 0);
+    let mut core = CartridgeCore::load("test.nes", data.as_slice(), data.len() as u64).unwrap();
+
+    for addr in 0x6000..0x6080 {
+        core.write_cpu(addr, addr as u8);
+    }
+    for addr in 0x6000..=0x7FFF {
+        assert_eq!((addr & 0x7F) as u8, core.read_cpu(addr), "{addr:#06x}");
+    }
+}
+
+#[test]
+fn test_unsupported_features() {
+    let ntsc = NesHeader::new(&header([0x02, 0x01, 0x00, 0x08, 0, 0, 0, 0, 0, 0, 0, 0])).unwrap();
+    assert!(ntsc.unsupported_features().is_empty());
+
+    let pal_vs =
+        NesHeader::new(&header([0x02, 0x01, 0x00, 0x09, 0, 0, 0, 0, 0x01, 0, 0, 0])).unwrap();
+    assert_eq!(
+        vec![
+            "Pal timing".to_string(),
+            "VsSystem { ppu_type: 0, hardware_type: 0 } console".to_string()
+        ],
+        pal_vs.unsupported_features()
+    );
+}
@@ -0,0 +1,460 @@
+// Records which PRG ROM bytes run as code or are read as data, and which CHR ROM
+// bytes are drawn, in FCEUX's .cdl format. Bytes are logged by where they are in
+// the ROM, after the mapper's banking, not by the address they were seen at.
+// source https://fceux.com/web/help/CodeDataLogger.html
+
+use std::{any::Any, cell::RefCell, fmt::Display, fs, path::Path, rc::Rc};
+
+use anyhow::Result;
+use thiserror::Error;
+
+use crate::{
+    cpu::{
+        decode::decode,
+        disassembler::{ByteKind, Line, MemorySource, disassemble_classified},
+        instructions::{Instruction, Mode},
+        monitor::{InterruptKind, Monitor},
+    },
+    nes::{
+        NES,
+        cartridge::{CartridgeCPUPort, CartridgePPUPort},
+        ppu::{PpuFetchContext, PpuFetchKind, PpuFetchObserver},
+    },
+};
+
+bitflags::bitflags! {
+    /**
+     * One per PRG ROM byte, laid out as FCEUX does: xPdcAADC
+     */
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub struct PrgFlags: u8 {
+        const Code = 0x01;
+        const Data = 0x02;
+        /**
+         * Which 8k of 0x8000-0xFFFF the byte was mapped into when it was last logged
+         */
+        const Window = 0x0C;
+        /**
+         * The target of a JMP ($nnnn)
+         */
+        const IndirectCode = 0x10;
+        /**
+         * Read through a pointer, e.g. LDA ($nn),Y
+         */
+        const IndirectData = 0x20;
+        /**
+         * Played by the DMC
+         */
+        const Pcm = 0x40;
+    }
+}
+
+bitflags::bitflags! {
+    /**
+     * One per CHR ROM byte
+     */
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub struct ChrFlags: u8 {
+        const Drawn = 0x01;
+        /**
+         * Read by the CPU through 0x2007
+         */
+        const Read = 0x02;
+    }
+}
+
+const WINDOW_SHIFT: u8 = 2;
+const WINDOW_SIZE: usize = 0x2000;
+const PRG_ROM_START: u16 = 0x8000;
+const PATTERN_TABLES_END: u16 = 0x1FFF;
+const NMI_VECTOR: u16 = 0xFFFA;
+const RESET_VECTOR: u16 = 0xFFFC;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CodeDataLog {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+}
+
+impl CodeDataLog {
+    pub fn new(prg_rom_size: usize, chr_rom_size: usize) -> Self {
+        Self {
+            prg: vec![0; prg_rom_size],
+            chr: vec![0; chr_rom_size],
+        }
+    }
+
+    /**
+     * Reads a .cdl file, which is the PRG ROM flags followed by the CHR ROM flags
+     */
+    pub fn from_bytes(bytes: &[u8], prg_rom_size: usize) -> Result<Self> {
+        if bytes.len() < prg_rom_size {
+            Err(CodeDataLogError::TooShort(bytes.len(), prg_rom_size))?;
+        }
+        let (prg, chr) = bytes.split_at(prg_rom_size);
+        Ok(Self {
+            prg: prg.to_vec(),
+            chr: chr.to_vec(),
+        })
+    }
+
+    pub fn load(path: &Path, prg_rom_size: usize) -> Result<Self> {
+        Self::from_bytes(&fs::read(path)?, prg_rom_size)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [&self.prg[..], &self.chr[..]].concat()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn prg_rom_size(&self) -> usize {
+        self.prg.len()
+    }
+
+    pub fn chr_rom_size(&self) -> usize {
+        self.chr.len()
+    }
+
+    pub fn prg(&self, offset: usize) -> PrgFlags {
+        PrgFlags::from_bits_retain(self.prg.get(offset).copied().unwrap_or(0))
+    }
+
+    pub fn chr(&self, offset: usize) -> ChrFlags {
+        ChrFlags::from_bits_retain(self.chr.get(offset).copied().unwrap_or(0))
+    }
+
+    /**
+     * Logs the PRG ROM byte at offset, which the CPU saw at addr
+     */
+    pub fn log_prg(&mut self, offset: usize, addr: u16, flags: PrgFlags) {
+        let Some(byte) = self.prg.get_mut(offset) else {
+            return;
+        };
+        let mut logged = PrgFlags::from_bits_retain(*byte) | flags;
+        if addr >= PRG_ROM_START {
+            let window = ((addr - PRG_ROM_START) as usize / WINDOW_SIZE) as u8;
+            logged.remove(PrgFlags::Window);
+            logged |= PrgFlags::from_bits_retain(window << WINDOW_SHIFT);
+        }
+        *byte = logged.bits();
+    }
+
+    pub fn log_chr(&mut self, offset: usize, flags: ChrFlags) {
+        if let Some(byte) = self.chr.get_mut(offset) {
+            *byte |= flags.bits();
+        }
+    }
+
+    /**
+     * The address the PRG ROM byte was last seen at, if it was ever logged
+     */
+    pub fn address(&self, offset: usize) -> Option<u16> {
+        let flags = self.prg(offset);
+        if !flags.intersects(PrgFlags::Code | PrgFlags::Data | PrgFlags::Pcm) {
+            return None;
+        }
+        let window = ((flags & PrgFlags::Window).bits() >> WINDOW_SHIFT) as usize;
+        Some(PRG_ROM_START + (window * WINDOW_SIZE + offset % WINDOW_SIZE) as u16)
+    }
+
+    pub fn classify(&self, offset: usize) -> ByteKind {
+        let flags = self.prg(offset);
+        if flags.contains(PrgFlags::Code) {
+            ByteKind::Code
+        } else if flags.intersects(PrgFlags::Data | PrgFlags::IndirectData | PrgFlags::Pcm) {
+            ByteKind::Data
+        } else {
+            ByteKind::Unknown
+        }
+    }
+
+    /**
+     * Disassembles the PRG ROM bytes from start up to but not including end, which
+     * appear at base in the CPU's address space. Only logged code becomes instructions.
+     */
+    pub fn disassemble(&self, prg_rom: &[u8], start: usize, end: usize, base: u16) -> Vec<Line> {
+        let end = end.min(prg_rom.len());
+        if start >= end {
+            return Vec::new();
+        }
+        let memory = |addr: u16| -> Option<u8> {
+            let offset = start + addr.checked_sub(base)? as usize;
+            (offset < end).then(|| prg_rom[offset])
+        };
+        let classify = |addr: u16| self.classify(start + addr.wrapping_sub(base) as usize);
+        let last = base.saturating_add((end - start - 1) as u16);
+        disassemble_classified(&memory as &dyn MemorySource, base, last, &classify)
+    }
+
+    pub fn summary(&self) -> Summary {
+        let count = |flags: PrgFlags| {
+            self.prg
+                .iter()
+                .filter(|b| PrgFlags::from_bits_retain(**b).intersects(flags))
+                .count()
+        };
+        Summary {
+            prg_bytes: self.prg.len(),
+            code: count(PrgFlags::Code),
+            data: count(PrgFlags::Data | PrgFlags::Pcm),
+            logged: count(PrgFlags::Code | PrgFlags::Data | PrgFlags::Pcm),
+            chr_bytes: self.chr.len(),
+            drawn: self
+                .chr
+                .iter()
+                .filter(|b| *b & ChrFlags::Drawn.bits() != 0)
+                .count(),
+            read: self
+                .chr
+                .iter()
+                .filter(|b| *b & ChrFlags::Read.bits() != 0)
+                .count(),
+        }
+    }
+}
+
+/**
+ * How much of the ROM has been logged. A byte can be both code and data.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Summary {
+    pub prg_bytes: usize,
+    pub code: usize,
+    pub data: usize,
+    pub logged: usize,
+    pub chr_bytes: usize,
+    pub drawn: usize,
+    pub read: usize,
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let percent = |n: usize, of: usize| {
+            if of == 0 {
+                0.0
+            } else {
+                n as f64 * 100.0 / of as f64
+            }
+        };
+        write!(
+            f,
+            "PRG ROM: {} of {} bytes logged ({:.1}%), {} code, {} data",
+            self.logged,
+            self.prg_bytes,
+            percent(self.logged, self.prg_bytes),
+            self.code,
+            self.data
+        )?;
+        if self.chr_bytes != 0 {
+            write!(
+                f,
+                "\nCHR ROM: {} of {} bytes drawn ({:.1}%), {} read",
+                self.drawn,
+                self.chr_bytes,
+                percent(self.drawn, self.chr_bytes),
+                self.read
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub struct CodeDataLogger {}
+
+impl CodeDataLogger {
+    /**
+     * Starts a new log of the loaded cartridge
+     */
+    pub fn attach(nes: &mut NES) -> Rc<RefCell<CodeDataLog>> {
+        let log = CodeDataLog::new(
+            nes.cartridge_cpu_port.borrow().prg_rom_size(),
+            nes.cartridge_ppu_port.borrow().chr_rom_size(),
+        );
+        Self::attach_log(nes, log)
+    }
+
+    /**
+     * Carries on logging into an existing log, e.g. one loaded from a .cdl file
+     */
+    pub fn attach_with(nes: &mut NES, log: CodeDataLog) -> Result<Rc<RefCell<CodeDataLog>>> {
+        let prg_rom_size = nes.cartridge_cpu_port.borrow().prg_rom_size();
+        let chr_rom_size = nes.cartridge_ppu_port.borrow().chr_rom_size();
+        if log.prg_rom_size() != prg_rom_size || log.chr_rom_size() != chr_rom_size {
+            Err(CodeDataLogError::SizeMismatch(
+                log.prg_rom_size(),
+                log.chr_rom_size(),
+                prg_rom_size,
+                chr_rom_size,
+            ))?;
+        }
+        Ok(Self::attach_log(nes, log))
+    }
+
+    pub fn detach(nes: &mut NES) {
+        let monitor = nes.cpu.borrow_mut().remove_monitor::<CodeDataMonitor>();
+        if let Some(mut monitor) = monitor
+            && let Some(monitor) = monitor.as_any().downcast_mut::<CodeDataMonitor>()
+        {
+            nes.ppu
+                .borrow_mut()
+                .remove_fetch_observer(&monitor.ppu_observer);
+        }
+    }
+
+    fn attach_log(nes: &mut NES, log: CodeDataLog) -> Rc<RefCell<CodeDataLog>> {
+        let log = Rc::new(RefCell::new(log));
+        let ppu_observer: Rc<RefCell<dyn PpuFetchObserver>> = Rc::new(RefCell::new(ChrObserver {
+            log: log.clone(),
+            cartridge: nes.cartridge_ppu_port.clone(),
+        }));
+        nes.ppu
+            .borrow_mut()
+            .add_fetch_observer(ppu_observer.clone());
+        nes.cpu.borrow_mut().add_monitor(Box::new(CodeDataMonitor {
+            log: log.clone(),
+            cartridge: nes.cartridge_cpu_port.clone(),
+            ppu_observer,
+            pc: 0,
+            fetched: 0,
+            mode: Mode::Imp,
+            indirect_jump: false,
+            after_indirect_jump: false,
+        }));
+        log
+    }
+}
+
+struct CodeDataMonitor {
+    log: Rc<RefCell<CodeDataLog>>,
+    cartridge: Rc<RefCell<CartridgeCPUPort>>,
+    // kept to be removed from the PPU on detach
+    ppu_observer: Rc<RefCell<dyn PpuFetchObserver>>,
+    pc: u16,
+    // how many bytes of the current instruction have been fetched
+    fetched: u16,
+    mode: Mode,
+    indirect_jump: bool,
+    after_indirect_jump: bool,
+}
+
+impl CodeDataMonitor {
+    fn log(&self, addr: u16, flags: PrgFlags) {
+        if let Some(offset) = self.cartridge.borrow().prg_rom_offset(addr) {
+            self.log.borrow_mut().log_prg(offset, addr, flags);
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl Monitor for CodeDataMonitor {
+    fn new_instruction(
+        &mut self,
+        cycle: usize,
+        pc: u16,
+        sp: u8,
+        a: u8,
+        x: u8,
+        y: u8,
+        status: u8,
+    ) -> Result<()> {
+        self.pc = pc;
+        self.fetched = 0;
+        self.after_indirect_jump = std::mem::take(&mut self.indirect_jump);
+        Ok(())
+    }
+
+    fn fetch_instruction_byte(&mut self, byte: u8) -> Result<()> {
+        if self.fetched == 0 {
+            let (instruction, mode, _, _) = decode(byte);
+            self.mode = mode;
+            self.indirect_jump = instruction == Instruction::JMP && mode == Mode::AbsInd;
+        }
+        let mut flags = PrgFlags::Code;
+        if self.after_indirect_jump {
+            flags |= PrgFlags::IndirectCode;
+        }
+        self.log(self.pc.wrapping_add(self.fetched), flags);
+        self.fetched += 1;
+        Ok(())
+    }
+
+    fn read_data_byte(&mut self, addr: u16, data: u8) -> Result<()> {
+        // the pointer itself is in zero page, so anything read from ROM is the target
+        let flags = match self.mode {
+            Mode::IndX | Mode::IndY => PrgFlags::Data | PrgFlags::IndirectData,
+            _ => PrgFlags::Data,
+        };
+        self.log(addr, flags);
+        Ok(())
+    }
+
+    fn write_data_byte(&mut self, addr: u16, old: u8, data: u8) -> Result<()> {
+        Ok(())
+    }
+
+    fn end_instruction(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn interrupt(&mut self, cycle: usize, kind: InterruptKind, handler: u16) -> Result<()> {
+        // the handler isn't what the jump was going to
+        self.indirect_jump = false;
+        let vector = match kind {
+            InterruptKind::NMI => NMI_VECTOR,
+            InterruptKind::RST => RESET_VECTOR,
+            InterruptKind::IRQ | InterruptKind::BRK => IRQ_VECTOR,
+        };
+        self.log(vector, PrgFlags::Data);
+        self.log(vector + 1, PrgFlags::Data);
+        Ok(())
+    }
+
+    fn sample_read(&mut self, addr: u16, data: u8) -> Result<()> {
+        self.log(addr, PrgFlags::Pcm);
+        Ok(())
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+struct ChrObserver {
+    log: Rc<RefCell<CodeDataLog>>,
+    cartridge: Rc<RefCell<CartridgePPUPort>>,
+}
+
+impl PpuFetchObserver for ChrObserver {
+    fn ppu_fetch(&mut self, addr: u16, context: PpuFetchContext) {
+        if addr > PATTERN_TABLES_END {
+            return;
+        }
+        let flags = match context.kind {
+            PpuFetchKind::BackgroundPattern | PpuFetchKind::SpritePattern
+                if context.rendering_enabled =>
+            {
+                ChrFlags::Drawn
+            }
+            PpuFetchKind::Data => ChrFlags::Read,
+            _ => return,
+        };
+        if let Some(offset) = self.cartridge.borrow().chr_rom_offset(addr) {
+            self.log.borrow_mut().log_chr(offset, flags);
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum CodeDataLogError {
+    #[error("The code/data log has {0:#x} bytes, but the PRG ROM alone has {1:#x}")]
+    TooShort(usize, usize),
+    #[error(
+        "The code/data log is for {0:#x} bytes of PRG ROM and {1:#x} of CHR ROM, but the cartridge has {2:#x} and {3:#x}"
+    )]
+    SizeMismatch(usize, usize, usize, usize),
+}
@@ -0,0 +1,758 @@
+pub mod condition;
+
+#[cfg(test)]
+mod unit_tests;
+
+use std::{any::Any, cell::RefCell, fmt::Display, mem, rc::Rc};
+
+use anyhow::Result;
+use thiserror::Error;
+
+use crate::{
+    cpu::{
+        disassembler::{IndexRegisters, Line, disassemble_one},
+        flags::StatusFlags,
+        monitor::Monitor,
+    },
+    nes::{
+        NES,
+        ppu::{PixelInfo, PpuFetchContext, PpuFetchObserver},
+        symbols::Labels,
+    },
+};
+
+use self::condition::{Condition, ConditionContext, Variable};
+
+const JSR_OPCODE: u8 = 0x20;
+const RTS_OPCODE: u8 = 0x60;
+const RTI_OPCODE: u8 = 0x40;
+
+bitflags::bitflags! {
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub struct Access: u8 {
+        const Read = 0b001;
+        const Write = 0b010;
+        const Execute = 0b100;
+    }
+}
+
+impl Display for Access {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (flag, c) in [
+            (Access::Read, 'r'),
+            (Access::Write, 'w'),
+            (Access::Execute, 'x'),
+        ] {
+            write!(f, "{}", if self.contains(flag) { c } else { '-' })?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AddressSpace {
+    Cpu,
+    Ppu,
+}
+
+#[derive(Clone, Debug)]
+pub struct Breakpoint {
+    pub id: usize,
+    pub space: AddressSpace,
+    pub start: u16,
+    pub end: u16,
+    pub access: Access,
+    pub condition: Option<Condition>,
+    pub enabled: bool,
+}
+
+impl Breakpoint {
+    fn contains(&self, space: AddressSpace, addr: u16) -> bool {
+        self.enabled && self.space == space && self.start <= addr && addr <= self.end
+    }
+}
+
+impl Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let space = match self.space {
+            AddressSpace::Cpu => "cpu",
+            AddressSpace::Ppu => "ppu",
+        };
+        write!(
+            f,
+            "#{} {} {} ${:04X}",
+            self.id, space, self.access, self.start
+        )?;
+        if self.end != self.start {
+            write!(f, "-${:04X}", self.end)?;
+        }
+        if let Some(condition) = &self.condition {
+            write!(f, " if {}", condition)?;
+        }
+        if !self.enabled {
+            write!(f, " (disabled)")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StopReason {
+    /**
+     * An execution breakpoint, before the instruction runs
+     */
+    Breakpoint(usize),
+    /**
+     * A read or write watchpoint, after the instruction that made the access
+     */
+    Watchpoint {
+        id: usize,
+        access: Access,
+        addr: u16,
+        value: u8,
+    },
+    Step,
+    ScanLine(i16),
+    Jammed,
+    /**
+     * A monitor returned an error, see CPU::monitor_error
+     */
+    MonitorError,
+}
+
+impl Display for StopReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopReason::Breakpoint(id) => write!(f, "breakpoint #{id}"),
+            StopReason::Watchpoint {
+                id,
+                access,
+                addr,
+                value,
+            } => write!(
+                f,
+                "watchpoint #{id}, {access} ${addr:04X} value ${value:02X}"
+            ),
+            StopReason::Step => write!(f, "step"),
+            StopReason::ScanLine(line) => write!(f, "scanline {line}"),
+            StopReason::Jammed => write!(f, "the CPU jammed"),
+            StopReason::MonitorError => write!(f, "a CPU monitor failed"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RunMode {
+    Continue,
+    /**
+     * Stop before the next instruction, which may be an interrupt handler
+     */
+    StepInto,
+    /**
+     * Like step into, except subroutine calls are run until they return
+     */
+    StepOver,
+    /**
+     * Run until the current subroutine or interrupt handler returns
+     */
+    StepOut,
+    ToScanLine(i16),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Registers {
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    pub status: StatusFlags,
+    pub cycle: usize,
+    pub scan_line: i16,
+    pub dot: u16,
+}
+
+impl Display for Registers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} SP:{:02X} P:{:02X} ",
+            self.pc,
+            self.a,
+            self.x,
+            self.y,
+            self.sp,
+            self.status.bits()
+        )?;
+        for (i, name) in "NV-BDIZC".chars().enumerate() {
+            let set = self.status.bits() & (0x80 >> i) != 0;
+            let c = match name {
+                '-' => '-',
+                _ if set => name,
+                _ => name.to_ascii_lowercase(),
+            };
+            write!(f, "{c}")?;
+        }
+        write!(
+            f,
+            " CYC:{} PPU:{:3},{:3}",
+            self.cycle, self.scan_line, self.dot
+        )
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum State {
+    Paused,
+    Running(RunMode),
+}
+
+/**
+ * Pauses a NES at breakpoints and watchpoints, and steps through it an
+ * instruction at a time. Once attached, drive the NES through the
+ * debugger's clock instead of its own.
+ */
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    next_id: usize,
+    watch_log: Rc<RefCell<WatchLog>>,
+    ppu_observer: Rc<RefCell<dyn PpuFetchObserver>>,
+    state: State,
+    stop_reason: Option<StopReason>,
+    // a watchpoint that hit, waiting for the instruction to finish
+    pending_stop: Option<StopReason>,
+    // don't stop again at the instruction the debugger was resumed from
+    resumed_at_boundary: bool,
+    resume_pc: u16,
+    resume_sp: u8,
+    step_over_jsr: bool,
+    last_opcode: u8,
+    last_scan_line: i16,
+    resumed_jammed: bool,
+    labels: Option<Labels>,
+}
+
+impl Debugger {
+    /**
+     * Starts paused. Any monitor already on the CPU keeps working.
+     */
+    pub fn attach(nes: &mut NES) -> Self {
+        let watch_log = Rc::new(RefCell::new(WatchLog::default()));
+        let ppu_observer: Rc<RefCell<dyn PpuFetchObserver>> = watch_log.clone();
+        nes.ppu
+            .borrow_mut()
+            .add_fetch_observer(ppu_observer.clone());
+
+        nes.cpu.borrow_mut().add_monitor(Box::new(DebugMonitor {
+            watch_log: watch_log.clone(),
+        }));
+
+        Self {
+            breakpoints: Vec::new(),
+            next_id: 1,
+            watch_log,
+            ppu_observer,
+            state: State::Paused,
+            stop_reason: None,
+            pending_stop: None,
+            resumed_at_boundary: false,
+            resume_pc: 0,
+            resume_sp: 0,
+            step_over_jsr: false,
+            last_opcode: 0,
+            last_scan_line: 0,
+            resumed_jammed: false,
+            labels: None,
+        }
+    }
+
+    /**
+     * Leaves any other monitors on the CPU in place
+     */
+    pub fn detach(self, nes: &mut NES) {
+        nes.ppu
+            .borrow_mut()
+            .remove_fetch_observer(&self.ppu_observer);
+
+        nes.cpu.borrow_mut().remove_monitor::<DebugMonitor>();
+    }
+
+    /**
+     * Stops before the instruction at pc runs
+     */
+    pub fn add_breakpoint(&mut self, pc: u16, condition: Option<Condition>) -> usize {
+        self.add(AddressSpace::Cpu, pc, pc, Access::Execute, condition)
+    }
+
+    /**
+     * Stops when an address in the range is accessed. Execute watchpoints stop before
+     * the instruction, reads and writes stop after the instruction that made them.
+     * PPU reads include rendering fetches, and their value isn't known.
+     */
+    pub fn add_watchpoint(
+        &mut self,
+        space: AddressSpace,
+        start: u16,
+        end: u16,
+        access: Access,
+        condition: Option<Condition>,
+    ) -> Result<usize> {
+        if start > end {
+            Err(DebuggerError::InvalidRange(start, end))?;
+        }
+        if access.is_empty() {
+            Err(DebuggerError::NoAccess)?;
+        }
+        if space == AddressSpace::Ppu && access.contains(Access::Execute) {
+            Err(DebuggerError::PpuExecute)?;
+        }
+        Ok(self.add(space, start, end, access, condition))
+    }
+
+    fn add(
+        &mut self,
+        space: AddressSpace,
+        start: u16,
+        end: u16,
+        access: Access,
+        condition: Option<Condition>,
+    ) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.breakpoints.push(Breakpoint {
+            id,
+            space,
+            start,
+            end,
+            access,
+            condition,
+            enabled: true,
+        });
+        self.update_watch_log();
+        id
+    }
+
+    pub fn remove(&mut self, id: usize) -> Result<()> {
+        let index = self.index_of(id)?;
+        self.breakpoints.remove(index);
+        self.update_watch_log();
+        Ok(())
+    }
+
+    pub fn set_enabled(&mut self, id: usize, enabled: bool) -> Result<()> {
+        let index = self.index_of(id)?;
+        self.breakpoints[index].enabled = enabled;
+        self.update_watch_log();
+        Ok(())
+    }
+
+    pub fn set_condition(&mut self, id: usize, condition: Option<Condition>) -> Result<()> {
+        let index = self.index_of(id)?;
+        self.breakpoints[index].condition = condition;
+        Ok(())
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    fn index_of(&self, id: usize) -> Result<usize> {
+        let index = self
+            .breakpoints
+            .iter()
+            .position(|b| b.id == id)
+            .ok_or(DebuggerError::NoSuchBreakpoint(id))?;
+        Ok(index)
+    }
+
+    // the monitor and PPU observer only need to know about reads and writes
+    fn update_watch_log(&mut self) {
+        self.watch_log.borrow_mut().watchpoints = self
+            .breakpoints
+            .iter()
+            .filter(|b| b.enabled && b.access.intersects(Access::Read | Access::Write))
+            .map(|b| (b.id, b.space, b.start, b.end, b.access))
+            .collect();
+    }
+
+    pub fn registers(&self, nes: &NES) -> Registers {
+        let cpu = nes.cpu.borrow();
+        let ppu = nes.ppu.borrow();
+        Registers {
+            pc: cpu.pc,
+            a: cpu.a,
+            x: cpu.x,
+            y: cpu.y,
+            sp: cpu.sp,
+            status: cpu.status,
+            cycle: cpu.cycles,
+            scan_line: ppu.scan_line(),
+            dot: ppu.dot(),
+        }
+    }
+
+    /**
+     * Sets PC, A, X, Y, SP and P from registers. Only for while the debugger is paused.
+     */
+    pub fn set_registers(&self, nes: &NES, registers: &Registers) {
+        let mut cpu = nes.cpu.borrow_mut();
+        cpu.pc = registers.pc;
+        cpu.a = registers.a;
+        cpu.x = registers.x;
+        cpu.y = registers.y;
+        cpu.sp = registers.sp;
+        cpu.status = registers.status;
+    }
+
+    /**
+     * Evaluates an expression, e.g. to show a value while paused
+     */
+    pub fn evaluate(&self, nes: &NES, condition: &Condition) -> i64 {
+        condition.evaluate(&NesContext { nes, access: None })
+    }
+
+    /**
+     * Names addresses in disassembly and stop reports
+     */
+    pub fn set_labels(&mut self, labels: Labels) {
+        self.labels = Some(labels);
+    }
+
+    pub fn labels(&self) -> Option<&Labels> {
+        self.labels.as_ref()
+    }
+
+    /**
+     * e.g. `player_update+3`, if there's a symbol for addr
+     */
+    pub fn label(&self, addr: u16) -> Option<String> {
+        self.labels.as_ref().and_then(|labels| labels.label(addr))
+    }
+
+    /**
+     * Disassembles count instructions from addr. The line at PC is annotated
+     * with the address it accesses.
+     */
+    pub fn disassemble(&self, nes: &NES, addr: u16, count: usize) -> Vec<Line> {
+        let cpu = nes.cpu.borrow();
+        let registers = IndexRegisters { x: cpu.x, y: cpu.y };
+        let memory = |addr| Some(nes.peek_cpu(addr));
+        let mut lines = Vec::new();
+        let mut addr = addr;
+        while lines.len() < count {
+            let registers = (addr == cpu.pc).then_some(registers);
+            let mut line = disassemble_one(&memory, addr, registers);
+            if line.is_empty() {
+                break;
+            }
+            if let Some(labels) = &self.labels {
+                line.label_operand(&|addr| labels.label(addr));
+            }
+            addr = addr.wrapping_add(line.len());
+            lines.push(line);
+        }
+        lines
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state == State::Paused
+    }
+
+    /**
+     * Why the debugger last paused
+     */
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        self.stop_reason
+    }
+
+    pub fn pause(&mut self) {
+        self.state = State::Paused;
+        self.stop_reason = None;
+    }
+
+    pub fn resume(&mut self, nes: &NES, mode: RunMode) {
+        let cpu = nes.cpu.borrow();
+        self.state = State::Running(mode);
+        self.stop_reason = None;
+        self.pending_stop = None;
+        self.resumed_at_boundary = nes.tick == 0 && cpu.at_instruction_boundary();
+        self.resume_pc = cpu.pc;
+        self.resume_sp = cpu.sp;
+        self.step_over_jsr = self.resumed_at_boundary && nes.peek_cpu(cpu.pc) == JSR_OPCODE;
+        self.last_opcode = 0;
+        self.last_scan_line = nes.ppu.borrow().scan_line();
+        self.resumed_jammed = cpu.is_jammed();
+    }
+
+    /**
+     * Clocks the NES like NES::clock does, unless the debugger is paused or
+     * pauses before the clock. Then None is returned.
+     */
+    pub fn clock(&mut self, nes: &mut NES) -> Option<(bool, Option<PixelInfo>, Option<f32>)> {
+        let State::Running(mode) = self.state else {
+            return None;
+        };
+
+        if nes.tick == 0
+            && nes.cpu.borrow().at_instruction_boundary()
+            && let Some(reason) = self.check_instruction_boundary(nes, mode)
+        {
+            self.stop(reason);
+            return None;
+        }
+
+        let result = nes.clock();
+
+        self.check_watchpoints(nes);
+        let scan_line = nes.ppu.borrow().scan_line();
+        if mode == RunMode::ToScanLine(scan_line) && self.last_scan_line != scan_line {
+            self.stop(StopReason::ScanLine(scan_line));
+        }
+        self.last_scan_line = scan_line;
+        if !self.resumed_jammed && nes.cpu.borrow().is_jammed() {
+            self.stop(StopReason::Jammed);
+        }
+        if nes.cpu.borrow().monitor_error().is_some() {
+            self.stop(StopReason::MonitorError);
+        }
+
+        Some(result)
+    }
+
+    /**
+     * Resumes and clocks until the debugger pauses again
+     */
+    pub fn run(&mut self, nes: &mut NES, mode: RunMode) -> StopReason {
+        self.resume(nes, mode);
+        while self.clock(nes).is_some() {}
+        self.stop_reason.unwrap_or(StopReason::Step)
+    }
+
+    fn stop(&mut self, reason: StopReason) {
+        self.state = State::Paused;
+        self.stop_reason = Some(reason);
+    }
+
+    fn check_instruction_boundary(&mut self, nes: &NES, mode: RunMode) -> Option<StopReason> {
+        let cpu = nes.cpu.borrow();
+        let pc = cpu.pc;
+        let sp = cpu.sp;
+        let interrupt_pending = cpu.interrupt_pending();
+        drop(cpu);
+
+        let starting = mem::take(&mut self.resumed_at_boundary);
+        if !starting {
+            if let Some(reason) = self.pending_stop.take() {
+                return Some(reason);
+            }
+
+            let returned = (self.last_opcode == RTS_OPCODE || self.last_opcode == RTI_OPCODE)
+                && sp > self.resume_sp;
+            let stepped = match mode {
+                RunMode::StepInto => true,
+                RunMode::StepOver if self.step_over_jsr => {
+                    pc == self.resume_pc.wrapping_add(3) && sp == self.resume_sp
+                }
+                RunMode::StepOver => true,
+                RunMode::StepOut => returned,
+                RunMode::Continue | RunMode::ToScanLine(_) => false,
+            };
+            if stepped {
+                return Some(StopReason::Step);
+            }
+
+            // an interrupt is about to run instead of the instruction at pc
+            if !interrupt_pending {
+                let context = NesContext { nes, access: None };
+                let hit = self.breakpoints.iter().find(|b| {
+                    b.access.contains(Access::Execute)
+                        && b.contains(AddressSpace::Cpu, pc)
+                        && b.condition.as_ref().is_none_or(|c| c.is_true(&context))
+                });
+                if let Some(breakpoint) = hit {
+                    return Some(StopReason::Breakpoint(breakpoint.id));
+                }
+            }
+        }
+
+        self.last_opcode = if interrupt_pending {
+            0
+        } else {
+            nes.peek_cpu(pc)
+        };
+        None
+    }
+
+    fn check_watchpoints(&mut self, nes: &NES) {
+        let hits = mem::take(&mut self.watch_log.borrow_mut().hits);
+        for hit in hits {
+            let Some(breakpoint) = self.breakpoints.iter().find(|b| b.id == hit.id) else {
+                continue;
+            };
+            let context = NesContext {
+                nes,
+                access: Some((hit.addr, hit.value)),
+            };
+            let triggered = breakpoint
+                .condition
+                .as_ref()
+                .is_none_or(|c| c.is_true(&context));
+            if triggered && self.pending_stop.is_none() {
+                self.pending_stop = Some(StopReason::Watchpoint {
+                    id: hit.id,
+                    access: hit.access,
+                    addr: hit.addr,
+                    value: hit.value,
+                });
+            }
+        }
+    }
+}
+
+struct NesContext<'a> {
+    nes: &'a NES,
+    access: Option<(u16, u8)>,
+}
+
+impl ConditionContext for NesContext<'_> {
+    fn variable(&self, variable: Variable) -> i64 {
+        let cpu = self.nes.cpu.borrow();
+        let flag = |flag| cpu.status.contains(flag) as i64;
+        match variable {
+            Variable::A => cpu.a as i64,
+            Variable::X => cpu.x as i64,
+            Variable::Y => cpu.y as i64,
+            Variable::SP => cpu.sp as i64,
+            Variable::PC => cpu.pc as i64,
+            Variable::P => cpu.status.bits() as i64,
+            Variable::Carry => flag(StatusFlags::Carry),
+            Variable::Zero => flag(StatusFlags::Zero),
+            Variable::InterruptDisable => flag(StatusFlags::InterruptDisable),
+            Variable::Decimal => flag(StatusFlags::Decimal),
+            Variable::Overflow => flag(StatusFlags::Overflow),
+            Variable::Negative => flag(StatusFlags::Negative),
+            Variable::Cycle => cpu.cycles as i64,
+            Variable::ScanLine => self.nes.ppu.borrow().scan_line() as i64,
+            Variable::Dot => self.nes.ppu.borrow().dot() as i64,
+            Variable::Address => self.access.map_or(0, |(addr, _)| addr as i64),
+            Variable::Value => self.access.map_or(0, |(_, value)| value as i64),
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.nes.peek_cpu(addr)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct WatchHit {
+    id: usize,
+    access: Access,
+    addr: u16,
+    value: u8,
+}
+
+/**
+ * Shared by the CPU monitor and PPU observer, which record the accesses
+ * that hit a watchpoint for the debugger to check after each clock
+ */
+#[derive(Default)]
+struct WatchLog {
+    watchpoints: Vec<(usize, AddressSpace, u16, u16, Access)>,
+    hits: Vec<WatchHit>,
+}
+
+impl WatchLog {
+    fn access(&mut self, space: AddressSpace, access: Access, addr: u16, value: u8) {
+        for (id, watch_space, start, end, watch_access) in &self.watchpoints {
+            if *watch_space == space
+                && watch_access.contains(access)
+                && *start <= addr
+                && addr <= *end
+            {
+                self.hits.push(WatchHit {
+                    id: *id,
+                    access,
+                    addr,
+                    value,
+                });
+            }
+        }
+    }
+}
+
+impl PpuFetchObserver for WatchLog {
+    fn ppu_fetch(&mut self, addr: u16, _context: PpuFetchContext) {
+        self.access(AddressSpace::Ppu, Access::Read, addr, 0);
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.access(AddressSpace::Ppu, Access::Write, addr, data);
+    }
+}
+
+/**
+ * Records the CPU's reads and writes in the watch log. Writes are seen on
+ * the bus, so stack pushes and DMA writes hit watchpoints too.
+ */
+struct DebugMonitor {
+    watch_log: Rc<RefCell<WatchLog>>,
+}
+
+#[allow(unused_variables)]
+impl Monitor for DebugMonitor {
+    fn new_instruction(
+        &mut self,
+        cycle: usize,
+        pc: u16,
+        sp: u8,
+        a: u8,
+        x: u8,
+        y: u8,
+        status: u8,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    fn fetch_instruction_byte(&mut self, byte: u8) -> Result<()> {
+        Ok(())
+    }
+
+    fn read_data_byte(&mut self, addr: u16, data: u8) -> Result<()> {
+        self.watch_log
+            .borrow_mut()
+            .access(AddressSpace::Cpu, Access::Read, addr, data);
+        Ok(())
+    }
+
+    fn write_data_byte(&mut self, addr: u16, old: u8, data: u8) -> Result<()> {
+        Ok(())
+    }
+
+    fn bus_write(&mut self, addr: u16, old: u8, data: u8) -> Result<()> {
+        self.watch_log
+            .borrow_mut()
+            .access(AddressSpace::Cpu, Access::Write, addr, data);
+        Ok(())
+    }
+
+    fn end_instruction(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum DebuggerError {
+    #[error("There's no breakpoint #{0}")]
+    NoSuchBreakpoint(usize),
+    #[error("${0:04X}-${1:04X} isn't a valid address range")]
+    InvalidRange(u16, u16),
+    #[error("A watchpoint needs at least one of read, write or execute")]
+    NoAccess,
+    #[error("Nothing is executed from the PPU's address space")]
+    PpuExecute,
+}
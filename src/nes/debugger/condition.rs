@@ -0,0 +1,398 @@
+use std::fmt::Display;
+
+use anyhow::Result;
+use thiserror::Error;
+
+/**
+ * Something a condition can look at. Registers and flags are read from the CPU,
+ * the rest describe where the PPU is and, for watchpoints, the access that hit.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Variable {
+    A,
+    X,
+    Y,
+    SP,
+    PC,
+    P,
+    Carry,
+    Zero,
+    InterruptDisable,
+    Decimal,
+    Overflow,
+    Negative,
+    Cycle,
+    ScanLine,
+    Dot,
+    Address,
+    Value,
+}
+
+impl Variable {
+    fn from_name(name: &str) -> Option<Self> {
+        use Variable::*;
+
+        let variable = match name.to_ascii_uppercase().as_str() {
+            "A" => A,
+            "X" => X,
+            "Y" => Y,
+            "SP" | "S" => SP,
+            "PC" => PC,
+            "P" => P,
+            "C" => Carry,
+            "Z" => Zero,
+            "I" => InterruptDisable,
+            "D" => Decimal,
+            "V" => Overflow,
+            "N" => Negative,
+            "CYCLE" => Cycle,
+            "SCANLINE" => ScanLine,
+            "DOT" => Dot,
+            "ADDRESS" => Address,
+            "VALUE" => Value,
+            _ => return None,
+        };
+        Some(variable)
+    }
+}
+
+/**
+ * Where a condition gets its values from
+ */
+pub trait ConditionContext {
+    fn variable(&self, variable: Variable) -> i64;
+    /**
+     * Reads a byte from the CPU's address space without side effects
+     */
+    fn peek(&self, addr: u16) -> u8;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Operator {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Add,
+    Subtract,
+}
+
+impl Operator {
+    fn precedence(self) -> u8 {
+        use Operator::*;
+
+        match self {
+            Or => 1,
+            And => 2,
+            BitOr => 3,
+            BitXor => 4,
+            BitAnd => 5,
+            Equal | NotEqual => 6,
+            Less | LessOrEqual | Greater | GreaterOrEqual => 7,
+            Add | Subtract => 8,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        use Operator::*;
+
+        match self {
+            Or => "||",
+            And => "&&",
+            BitOr => "|",
+            BitXor => "^",
+            BitAnd => "&",
+            Equal => "==",
+            NotEqual => "!=",
+            Less => "<",
+            LessOrEqual => "<=",
+            Greater => ">",
+            GreaterOrEqual => ">=",
+            Add => "+",
+            Subtract => "-",
+        }
+    }
+
+    fn apply(self, left: i64, right: i64) -> i64 {
+        use Operator::*;
+
+        match self {
+            Or => (left != 0 || right != 0) as i64,
+            And => (left != 0 && right != 0) as i64,
+            BitOr => left | right,
+            BitXor => left ^ right,
+            BitAnd => left & right,
+            Equal => (left == right) as i64,
+            NotEqual => (left != right) as i64,
+            Less => (left < right) as i64,
+            LessOrEqual => (left <= right) as i64,
+            Greater => (left > right) as i64,
+            GreaterOrEqual => (left >= right) as i64,
+            Add => left.wrapping_add(right),
+            Subtract => left.wrapping_sub(right),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Expression {
+    Number(i64),
+    Variable(Variable),
+    // a byte in the CPU's address space, written [addr]
+    Memory(Box<Expression>),
+    Not(Box<Expression>),
+    Negate(Box<Expression>),
+    Binary(Operator, Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    fn evaluate(&self, context: &dyn ConditionContext) -> i64 {
+        match self {
+            Expression::Number(n) => *n,
+            Expression::Variable(v) => context.variable(*v),
+            Expression::Memory(addr) => context.peek(addr.evaluate(context) as u16) as i64,
+            Expression::Not(e) => (e.evaluate(context) == 0) as i64,
+            Expression::Negate(e) => e.evaluate(context).wrapping_neg(),
+            Expression::Binary(op, left, right) => {
+                // && and || short circuit so [addr] isn't read needlessly
+                let left = left.evaluate(context);
+                match op {
+                    Operator::And if left == 0 => 0,
+                    Operator::Or if left != 0 => 1,
+                    _ => op.apply(left, right.evaluate(context)),
+                }
+            }
+        }
+    }
+}
+
+/**
+ * A parsed breakpoint condition, e.g. `A == $40 && [$00F0] != 0`.
+ * Numbers are decimal, or hex with a $ or 0x prefix, or binary with a %
+ * prefix. Operators follow C's precedence.
+ */
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Condition {
+    text: String,
+    expression: Expression,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Self> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+        };
+        let expression = parser.expression(0)?;
+        if let Some(token) = parser.tokens.get(parser.position) {
+            Err(ConditionError::UnexpectedToken(token.to_string()))?;
+        }
+        Ok(Self {
+            text: text.trim().to_string(),
+            expression,
+        })
+    }
+
+    /**
+     * Anything other than 0 is true
+     */
+    pub fn is_true(&self, context: &dyn ConditionContext) -> bool {
+        self.evaluate(context) != 0
+    }
+
+    pub fn evaluate(&self, context: &dyn ConditionContext) -> i64 {
+        self.expression.evaluate(context)
+    }
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Operator(Operator),
+    Not,
+    Minus,
+    OpenParen,
+    CloseParen,
+    OpenBracket,
+    CloseBracket,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{n}"),
+            Token::Name(name) => write!(f, "{name}"),
+            Token::Operator(op) => write!(f, "{}", op.symbol()),
+            Token::Not => write!(f, "!"),
+            Token::Minus => write!(f, "-"),
+            Token::OpenParen => write!(f, "("),
+            Token::CloseParen => write!(f, ")"),
+            Token::OpenBracket => write!(f, "["),
+            Token::CloseBracket => write!(f, "]"),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let (token, length) = match (c, next) {
+            (' ' | '\t', _) => {
+                i += 1;
+                continue;
+            }
+            ('|', Some('|')) => (Token::Operator(Operator::Or), 2),
+            ('&', Some('&')) => (Token::Operator(Operator::And), 2),
+            ('=', Some('=')) => (Token::Operator(Operator::Equal), 2),
+            ('!', Some('=')) => (Token::Operator(Operator::NotEqual), 2),
+            ('<', Some('=')) => (Token::Operator(Operator::LessOrEqual), 2),
+            ('>', Some('=')) => (Token::Operator(Operator::GreaterOrEqual), 2),
+            ('|', _) => (Token::Operator(Operator::BitOr), 1),
+            ('^', _) => (Token::Operator(Operator::BitXor), 1),
+            ('&', _) => (Token::Operator(Operator::BitAnd), 1),
+            ('<', _) => (Token::Operator(Operator::Less), 1),
+            ('>', _) => (Token::Operator(Operator::Greater), 1),
+            ('+', _) => (Token::Operator(Operator::Add), 1),
+            ('-', _) => (Token::Minus, 1),
+            ('!', _) => (Token::Not, 1),
+            ('(', _) => (Token::OpenParen, 1),
+            (')', _) => (Token::CloseParen, 1),
+            ('[', _) => (Token::OpenBracket, 1),
+            (']', _) => (Token::CloseBracket, 1),
+            _ if c.is_ascii_alphanumeric() || c == '$' || c == '%' || c == '_' => {
+                let length = chars[i + 1..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+                    .count()
+                    + 1;
+                let word = chars[i..i + length].iter().collect::<String>();
+                let token = if c.is_ascii_digit() || c == '$' || c == '%' {
+                    Token::Number(parse_number(&word)?)
+                } else {
+                    Token::Name(word)
+                };
+                (token, length)
+            }
+            _ => Err(ConditionError::UnexpectedToken(c.to_string()))?,
+        };
+        tokens.push(token);
+        i += length;
+    }
+    Ok(tokens)
+}
+
+fn parse_number(word: &str) -> Result<i64> {
+    let (digits, radix) = if let Some(hex) = word.strip_prefix('$') {
+        (hex, 16)
+    } else if let Some(hex) = word.strip_prefix("0x") {
+        (hex, 16)
+    } else if let Some(binary) = word.strip_prefix('%') {
+        (binary, 2)
+    } else {
+        (word, 10)
+    };
+    let number = i64::from_str_radix(digits, radix)
+        .map_err(|_| ConditionError::InvalidNumber(word.to_string()))?;
+    Ok(number)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Result<Token> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or(ConditionError::UnexpectedEnd)?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<()> {
+        let token = self.next()?;
+        if token != expected {
+            Err(ConditionError::UnexpectedToken(token.to_string()))?;
+        }
+        Ok(())
+    }
+
+    fn peek_operator(&self) -> Option<Operator> {
+        match self.tokens.get(self.position) {
+            Some(Token::Operator(op)) => Some(*op),
+            Some(Token::Minus) => Some(Operator::Subtract),
+            _ => None,
+        }
+    }
+
+    // precedence climbing, only operators that bind tighter than min_precedence are taken
+    fn expression(&mut self, min_precedence: u8) -> Result<Expression> {
+        let mut left = self.unary()?;
+        while let Some(op) = self.peek_operator() {
+            if op.precedence() <= min_precedence {
+                break;
+            }
+            self.position += 1;
+            let right = self.expression(op.precedence())?;
+            left = Expression::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expression> {
+        let expression = match self.next()? {
+            Token::Number(n) => Expression::Number(n),
+            Token::Name(name) => Expression::Variable(
+                Variable::from_name(&name).ok_or(ConditionError::UnknownName(name))?,
+            ),
+            Token::Not => Expression::Not(Box::new(self.unary()?)),
+            Token::Minus => Expression::Negate(Box::new(self.unary()?)),
+            Token::OpenParen => {
+                let inner = self.expression(0)?;
+                self.expect(Token::CloseParen)?;
+                inner
+            }
+            Token::OpenBracket => {
+                let addr = self.expression(0)?;
+                self.expect(Token::CloseBracket)?;
+                Expression::Memory(Box::new(addr))
+            }
+            token => Err(ConditionError::UnexpectedToken(token.to_string()))?,
+        };
+        Ok(expression)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ConditionError {
+    #[error("The condition ended unexpectedly")]
+    UnexpectedEnd,
+    #[error("Unexpected '{0}' in the condition")]
+    UnexpectedToken(String),
+    #[error("'{0}' isn't a valid number")]
+    InvalidNumber(String),
+    #[error("'{0}' isn't a register, flag or other known name")]
+    UnknownName(String),
+}
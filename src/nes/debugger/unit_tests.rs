@@ -0,0 +1,90 @@
+use super::condition::{Condition, ConditionContext, Variable};
+
+struct TestContext {
+    a: u8,
+    x: u8,
+    status: u8,
+    memory: [u8; 0x100],
+}
+
+impl TestContext {
+    fn new() -> Self {
+        Self {
+            a: 0,
+            x: 0,
+            status: 0,
+            memory: [0; 0x100],
+        }
+    }
+}
+
+impl ConditionContext for TestContext {
+    fn variable(&self, variable: Variable) -> i64 {
+        match variable {
+            Variable::A => self.a as i64,
+            Variable::X => self.x as i64,
+            Variable::P => self.status as i64,
+            Variable::Carry => (self.status & 1) as i64,
+            _ => 0,
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.memory[addr as usize & 0xFF]
+    }
+}
+
+fn evaluate(text: &str, context: &TestContext) -> i64 {
+    Condition::parse(text).unwrap().evaluate(context)
+}
+
+#[test]
+fn test_numbers() {
+    let context = TestContext::new();
+    assert_eq!(42, evaluate("42", &context));
+    assert_eq!(0xC0, evaluate("$c0", &context));
+    assert_eq!(0xC0, evaluate("0xC0", &context));
+    assert_eq!(5, evaluate("%101", &context));
+    assert_eq!(-3, evaluate("-3", &context));
+}
+
+#[test]
+fn test_precedence() {
+    let context = TestContext::new();
+    assert_eq!(7, evaluate("1 + 2 | 4", &context));
+    assert_eq!(1, evaluate("1 + 1 == 2", &context));
+    assert_eq!(1, evaluate("1 == 1 && 2 == 2 || 0", &context));
+    assert_eq!(0, evaluate("1 == (1 && 0)", &context));
+    assert_eq!(1, evaluate("3 & 1 == 1", &context));
+    assert_eq!(4, evaluate("10 - 4 - 2", &context));
+}
+
+#[test]
+fn test_variables_and_memory() {
+    let mut context = TestContext::new();
+    context.a = 0x40;
+    context.x = 2;
+    context.status = 0x01;
+    context.memory[0xF2] = 0x99;
+    assert_eq!(1, evaluate("a == $40 && C", &context));
+    assert_eq!(1, evaluate("[$F0 + X] == $99", &context));
+    assert_eq!(1, evaluate("!Z", &context));
+    assert_eq!(0x41, evaluate("A | P", &context));
+}
+
+#[test]
+fn test_display_shows_the_text() {
+    let condition = Condition::parse("  A == $40 ").unwrap();
+    assert_eq!("A == $40", condition.to_string());
+}
+
+#[test]
+fn test_errors() {
+    assert!(Condition::parse("").is_err());
+    assert!(Condition::parse("A ==").is_err());
+    assert!(Condition::parse("(A == 1").is_err());
+    assert!(Condition::parse("A == 1)").is_err());
+    assert!(Condition::parse("Q == 1").is_err());
+    assert!(Condition::parse("$XYZ").is_err());
+    assert!(Condition::parse("A @ 1").is_err());
+}
@@ -0,0 +1,144 @@
+// Registers in the $4018-$401F range, which a real NES leaves unused, for
+// homebrew run in CI: print text, stop with an exit code and time code in CPU
+// cycles. They're only there when the developer cartridge is attached.
+//
+// $4018 write  prints the byte as a character
+// $4019 write  exits with the byte as the exit code
+// $401A write  starts the stopwatch
+// $401B write  stops the stopwatch and prints the cycles since it started
+// $401C-$401F  read the last stopwatch count, least significant byte first
+
+use std::{cell::RefCell, io::Write, rc::Rc};
+
+use crate::bus::{BusDevice, InterruptFlags};
+
+use super::NES;
+
+const RANGE_START: u16 = 0x4018;
+const RANGE_END: u16 = 0x401F;
+const PRINT: u16 = 0x4018;
+const EXIT: u16 = 0x4019;
+const STOPWATCH_START: u16 = 0x401A;
+const STOPWATCH_STOP: u16 = 0x401B;
+const STOPWATCH_COUNT: u16 = 0x401C;
+
+pub struct DeveloperCartridge {
+    writer: Box<dyn Write>,
+    exit_code: Option<u8>,
+    cycles: u64,
+    stopwatch_started: Option<u64>,
+    last_stopwatch: Option<u64>,
+}
+
+impl DeveloperCartridge {
+    pub fn new(writer: Box<dyn Write>) -> Self {
+        Self {
+            writer,
+            exit_code: None,
+            cycles: 0,
+            stopwatch_started: None,
+            last_stopwatch: None,
+        }
+    }
+
+    /**
+     * Set once the program writes to $4019. The registers ignore writes after
+     * that, so nothing more is printed while the frontend stops.
+     */
+    pub fn exit_code(&self) -> Option<u8> {
+        self.exit_code
+    }
+
+    /**
+     * The cycles between the last start and stop of the stopwatch
+     */
+    pub fn last_stopwatch(&self) -> Option<u64> {
+        self.last_stopwatch
+    }
+
+    fn stopwatch_byte(&self, addr: u16) -> u8 {
+        let count = u32::try_from(self.last_stopwatch.unwrap_or(0)).unwrap_or(u32::MAX);
+        count.to_le_bytes()[(addr - STOPWATCH_COUNT) as usize]
+    }
+}
+
+impl BusDevice for DeveloperCartridge {
+    fn get_address_range(&self) -> (u16, u16) {
+        (RANGE_START, RANGE_END)
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        self.peek(addr)
+    }
+
+    fn write(&mut self, addr: u16, data: u8) -> u8 {
+        if self.exit_code.is_some() {
+            return 0;
+        }
+        // the program has no way to hear about a failed write, so they're dropped
+        match addr {
+            PRINT => {
+                let _ = self.writer.write_all(&[data]);
+            }
+            EXIT => {
+                self.exit_code = Some(data);
+                let _ = self.writer.flush();
+            }
+            STOPWATCH_START => self.stopwatch_started = Some(self.cycles),
+            STOPWATCH_STOP => {
+                if let Some(started) = self.stopwatch_started.take() {
+                    let elapsed = self.cycles - started;
+                    self.last_stopwatch = Some(elapsed);
+                    let _ = writeln!(self.writer, "stopwatch: {elapsed} cycles");
+                }
+            }
+            _ => {}
+        }
+        0
+    }
+
+    fn bus_clock(&mut self) -> InterruptFlags {
+        self.cycles += 1;
+        InterruptFlags::empty()
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            STOPWATCH_COUNT..=RANGE_END => self.stopwatch_byte(addr),
+            _ => 0,
+        }
+    }
+
+    // the registers are all write only or only change when the program writes them
+    fn poke(&mut self, _addr: u16, _data: u8) {}
+}
+
+impl NES {
+    /**
+     * Puts the developer registers at $4018-$401F, printing to writer. Attaching
+     * again swaps the writer and keeps the same registers.
+     */
+    pub fn attach_developer_cartridge(
+        &mut self,
+        writer: Box<dyn Write>,
+    ) -> Rc<RefCell<DeveloperCartridge>> {
+        if let Some(cartridge) = &self.developer_cartridge {
+            cartridge.borrow_mut().writer = writer;
+            return cartridge.clone();
+        }
+        let cartridge = Rc::new(RefCell::new(DeveloperCartridge::new(writer)));
+        self.cpu.borrow_mut().add_device(cartridge.clone());
+        self.developer_cartridge = Some(cartridge.clone());
+        cartridge
+    }
+
+    /**
+     * The exit code the program asked for through the developer cartridge, if
+     * it's attached and the program has finished
+     */
+    pub fn developer_exit_code(&self) -> Option<u8> {
+        self.developer_cartridge
+            .as_ref()
+            .and_then(|c| c.borrow().exit_code())
+    }
+}
@@ -0,0 +1,633 @@
+// A stub for gdb's remote serial protocol, so gdb, lldb and front ends built on
+// them can debug the running NES over TCP. It's built on the Debugger, which does
+// the breakpoints, watchpoints and stepping. Only all-stop mode with the one
+// thread is supported, and packets without a handler get the empty reply that
+// tells the client they aren't supported.
+
+#[cfg(test)]
+mod unit_tests;
+
+use std::{
+    collections::HashMap,
+    io::{ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use anyhow::Result;
+use thiserror::Error;
+
+use crate::{
+    cpu::flags::StatusFlags,
+    nes::{
+        NES,
+        debugger::{Access, AddressSpace, Debugger, RunMode, StopReason},
+        ppu::PixelInfo,
+    },
+};
+
+const INTERRUPT: u8 = 0x03;
+const PACKET_SIZE: usize = 0x4000;
+// how often the connection is checked for an interrupt while running, in PPU clocks
+const POLL_INTERVAL: usize = 30000;
+
+// what the debugger returns for a clock: None while paused
+type Clocked = Option<(bool, Option<PixelInfo>, Option<f32>)>;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGABRT: u8 = 6;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.nes-rs.6502">
+    <reg name="a" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="x" bitsize="8" type="uint8"/>
+    <reg name="y" bitsize="8" type="uint8"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="p" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+/**
+ * What the NES should do after a packet
+ */
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Action {
+    Reply(String),
+    Resume(RunMode),
+    /**
+     * Carry on running without the client
+     */
+    Detach,
+    /**
+     * Stop emulating
+     */
+    Kill,
+}
+
+/**
+ * The breakpoint types of the Z and z packets
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum BreakpointType {
+    Software,
+    Hardware,
+    Write,
+    Read,
+    Access,
+}
+
+/**
+ * Handles gdb's packets against the debugger. It doesn't know about the
+ * connection, which GdbServer looks after.
+ */
+pub struct GdbStub {
+    pub debugger: Debugger,
+    // the debugger's id for each breakpoint gdb set
+    breakpoints: HashMap<(BreakpointType, u16, u16), usize>,
+    interrupted: bool,
+}
+
+impl GdbStub {
+    /**
+     * Attaches a debugger to the NES, which starts paused
+     */
+    pub fn attach(nes: &mut NES) -> Self {
+        Self {
+            debugger: Debugger::attach(nes),
+            breakpoints: HashMap::new(),
+            interrupted: false,
+        }
+    }
+
+    /**
+     * Pauses the NES at the client's request
+     */
+    pub fn interrupt(&mut self) {
+        self.debugger.pause();
+        self.interrupted = true;
+    }
+
+    pub fn resume(&mut self, nes: &NES, mode: RunMode) {
+        self.debugger.resume(nes, mode);
+        self.interrupted = false;
+    }
+
+    pub fn handle(&mut self, nes: &mut NES, packet: &str) -> Result<Action> {
+        let reply = |text: &str| Ok(Action::Reply(text.to_string()));
+        let (command, arguments) = packet.split_at(packet.len().min(1));
+
+        match command {
+            "?" => Ok(Action::Reply(self.stop_reply())),
+            "g" => Ok(Action::Reply(self.read_registers(nes))),
+            "G" => {
+                self.write_registers(nes, arguments)?;
+                reply("OK")
+            }
+            "p" => {
+                let register = parse_hex(arguments)?;
+                let bytes = self.register_bytes(nes);
+                let (start, len) = register_bytes_at(register)?;
+                Ok(Action::Reply(to_hex(&bytes[start..start + len])))
+            }
+            "P" => {
+                let (register, value) = arguments.split_once('=').ok_or(invalid(packet))?;
+                let mut bytes = self.register_bytes(nes);
+                let (start, len) = register_bytes_at(parse_hex(register)?)?;
+                let value = from_hex(value)?;
+                if value.len() != len {
+                    Err(invalid(packet))?;
+                }
+                bytes[start..start + len].copy_from_slice(&value);
+                self.set_register_bytes(nes, &bytes);
+                reply("OK")
+            }
+            "m" => {
+                let (addr, len) = parse_range(arguments)?;
+                let bytes = (0..len.min(PACKET_SIZE / 2))
+                    .map(|i| nes.peek_cpu(addr.wrapping_add(i as u16)))
+                    .collect::<Vec<_>>();
+                Ok(Action::Reply(to_hex(&bytes)))
+            }
+            "M" => {
+                let (range, data) = arguments.split_once(':').ok_or(invalid(packet))?;
+                let (addr, len) = parse_range(range)?;
+                let data = from_hex(data)?;
+                if data.len() != len {
+                    Err(invalid(packet))?;
+                }
+                for (i, byte) in data.into_iter().enumerate() {
+                    nes.poke_cpu(addr.wrapping_add(i as u16), byte);
+                }
+                reply("OK")
+            }
+            "c" | "s" => {
+                if !arguments.is_empty() {
+                    self.set_pc(nes, parse_hex(arguments)?);
+                }
+                let mode = match command {
+                    "c" => RunMode::Continue,
+                    _ => RunMode::StepInto,
+                };
+                Ok(Action::Resume(mode))
+            }
+            "Z" | "z" => {
+                let mut parts = arguments.split(',');
+                let (Some(kind), Some(addr), Some(len)) =
+                    (parts.next(), parts.next(), parts.next())
+                else {
+                    Err(invalid(packet))?
+                };
+                let kind = match kind {
+                    "0" => BreakpointType::Software,
+                    "1" => BreakpointType::Hardware,
+                    "2" => BreakpointType::Write,
+                    "3" => BreakpointType::Read,
+                    "4" => BreakpointType::Access,
+                    _ => return reply(""),
+                };
+                let addr = parse_hex(addr)?;
+                // breakpoints give the instruction size, which doesn't matter
+                let len = match kind {
+                    BreakpointType::Software | BreakpointType::Hardware => 1,
+                    _ => parse_hex::<u16>(len)?.max(1),
+                };
+                if command == "Z" {
+                    self.add_breakpoint(kind, addr, len)?;
+                } else if let Some(id) = self.breakpoints.remove(&(kind, addr, len)) {
+                    self.debugger.remove(id)?;
+                }
+                reply("OK")
+            }
+            "v" => self.handle_v(packet),
+            "q" => Ok(Action::Reply(self.query(packet)?)),
+            "Q" if packet == "QStartNoAckMode" => reply("OK"),
+            "H" | "T" => reply("OK"),
+            "D" => {
+                self.remove_breakpoints()?;
+                Ok(Action::Detach)
+            }
+            "k" => Ok(Action::Kill),
+            _ => reply(""),
+        }
+    }
+
+    /**
+     * Why the NES is paused, in gdb's terms
+     */
+    pub fn stop_reply(&self) -> String {
+        let signal = match self.debugger.stop_reason() {
+            Some(StopReason::Watchpoint { id, addr, .. }) => {
+                let kind = self
+                    .breakpoints
+                    .iter()
+                    .find(|(_, breakpoint)| **breakpoint == id)
+                    .map(|((kind, _, _), _)| *kind);
+                let name = match kind {
+                    Some(BreakpointType::Read) => "rwatch",
+                    Some(BreakpointType::Access) => "awatch",
+                    _ => "watch",
+                };
+                return format!("T{SIGTRAP:02x}{name}:{addr:04x};");
+            }
+            Some(StopReason::Breakpoint(_)) => return format!("T{SIGTRAP:02x}swbreak:;"),
+            Some(StopReason::Jammed) => SIGILL,
+            Some(StopReason::MonitorError) => SIGABRT,
+            Some(StopReason::Step) | Some(StopReason::ScanLine(_)) => SIGTRAP,
+            None if self.interrupted => SIGINT,
+            // not started yet
+            None => SIGTRAP,
+        };
+        format!("S{signal:02x}")
+    }
+
+    fn handle_v(&self, packet: &str) -> Result<Action> {
+        if packet == "vCont?" {
+            return Ok(Action::Reply("vCont;c;C;s;S".to_string()));
+        }
+        let Some(actions) = packet.strip_prefix("vCont;") else {
+            return Ok(Action::Reply(String::new()));
+        };
+        // there's only one thread, so the first action is the one for it
+        let action = actions.split(';').next().unwrap_or_default();
+        let mode = match action.chars().next() {
+            Some('c' | 'C') => RunMode::Continue,
+            Some('s' | 'S') => RunMode::StepInto,
+            _ => Err(invalid(packet))?,
+        };
+        Ok(Action::Resume(mode))
+    }
+
+    fn query(&self, packet: &str) -> Result<String> {
+        let (name, arguments) = packet.split_once(':').unwrap_or((packet, ""));
+        let reply = match name {
+            "qSupported" => {
+                format!("PacketSize={PACKET_SIZE:x};qXfer:features:read+;QStartNoAckMode+;swbreak+")
+            }
+            "qXfer" => {
+                let Some(range) = arguments.strip_prefix("features:read:target.xml:") else {
+                    return Ok("E00".to_string());
+                };
+                let (offset, len) = range.split_once(',').ok_or(invalid(packet))?;
+                let offset = parse_hex::<usize>(offset)?.min(TARGET_XML.len());
+                let end = (offset + parse_hex::<usize>(len)?).min(TARGET_XML.len());
+                let more = if end < TARGET_XML.len() { 'm' } else { 'l' };
+                format!("{more}{}", &TARGET_XML[offset..end])
+            }
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        };
+        Ok(reply)
+    }
+
+    fn add_breakpoint(&mut self, kind: BreakpointType, addr: u16, len: u16) -> Result<()> {
+        if self.breakpoints.contains_key(&(kind, addr, len)) {
+            return Ok(());
+        }
+        let end = addr.saturating_add(len - 1);
+        let id = match kind {
+            BreakpointType::Software | BreakpointType::Hardware => {
+                self.debugger.add_breakpoint(addr, None)
+            }
+            BreakpointType::Write => {
+                self.debugger
+                    .add_watchpoint(AddressSpace::Cpu, addr, end, Access::Write, None)?
+            }
+            BreakpointType::Read => {
+                self.debugger
+                    .add_watchpoint(AddressSpace::Cpu, addr, end, Access::Read, None)?
+            }
+            BreakpointType::Access => self.debugger.add_watchpoint(
+                AddressSpace::Cpu,
+                addr,
+                end,
+                Access::Read | Access::Write,
+                None,
+            )?,
+        };
+        self.breakpoints.insert((kind, addr, len), id);
+        Ok(())
+    }
+
+    // so that nothing stops the NES once the client has gone
+    fn remove_breakpoints(&mut self) -> Result<()> {
+        for (_, id) in self.breakpoints.drain() {
+            self.debugger.remove(id)?;
+        }
+        Ok(())
+    }
+
+    // a, x, y, sp, pc (little endian) and p, the order of the target description
+    fn register_bytes(&self, nes: &NES) -> [u8; 7] {
+        let r = self.debugger.registers(nes);
+        let [pc_low, pc_high] = r.pc.to_le_bytes();
+        [r.a, r.x, r.y, r.sp, pc_low, pc_high, r.status.bits()]
+    }
+
+    fn set_register_bytes(&self, nes: &NES, bytes: &[u8; 7]) {
+        let mut registers = self.debugger.registers(nes);
+        registers.a = bytes[0];
+        registers.x = bytes[1];
+        registers.y = bytes[2];
+        registers.sp = bytes[3];
+        registers.pc = u16::from_le_bytes([bytes[4], bytes[5]]);
+        registers.status = StatusFlags::from_bits_retain(bytes[6]);
+        self.debugger.set_registers(nes, &registers);
+    }
+
+    fn read_registers(&self, nes: &NES) -> String {
+        to_hex(&self.register_bytes(nes))
+    }
+
+    fn write_registers(&self, nes: &NES, hex: &str) -> Result<()> {
+        let bytes: [u8; 7] = from_hex(hex)?.try_into().map_err(|_| invalid(hex))?;
+        self.set_register_bytes(nes, &bytes);
+        Ok(())
+    }
+
+    fn set_pc(&self, nes: &NES, pc: u16) {
+        let mut registers = self.debugger.registers(nes);
+        registers.pc = pc;
+        self.debugger.set_registers(nes, &registers);
+    }
+}
+
+/**
+ * Serves one gdb connection. Drive the NES through the server's clock, and
+ * call serve_while_paused before each clock.
+ */
+pub struct GdbServer {
+    pub stub: GdbStub,
+    stream: TcpStream,
+    // bytes read but not handled yet
+    received: Vec<u8>,
+    no_ack: bool,
+    // whether the client is waiting to hear why the NES stopped
+    awaiting_stop: bool,
+    clocks: usize,
+    connected: bool,
+}
+
+impl GdbServer {
+    /**
+     * Waits for a client to connect, e.g. to "127.0.0.1:2345"
+     */
+    pub fn accept(nes: &mut NES, addr: impl ToSocketAddrs) -> Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+        Self::new(nes, stream)
+    }
+
+    pub fn new(nes: &mut NES, stream: TcpStream) -> Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stub: GdbStub::attach(nes),
+            stream,
+            received: Vec::new(),
+            no_ack: false,
+            awaiting_stop: false,
+            clocks: 0,
+            connected: true,
+        })
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /**
+     * Answers the client's packets while the NES is paused, until one of them
+     * resumes it. Returns false when the client kills the NES or disconnects
+     * while it's paused, or when it stops with no client left to resume it.
+     */
+    pub fn serve_while_paused(&mut self, nes: &mut NES) -> Result<bool> {
+        if !self.stub.debugger.is_paused() {
+            return Ok(true);
+        }
+        if !self.connected {
+            return Ok(false);
+        }
+        if self.awaiting_stop {
+            self.awaiting_stop = false;
+            let reply = self.stub.stop_reply();
+            self.send(&reply)?;
+        }
+        self.stream.set_nonblocking(false)?;
+        loop {
+            let Some(packet) = self.next_packet()? else {
+                self.connected = false;
+                return Ok(false);
+            };
+            let Some(packet) = packet else {
+                // an interrupt while already paused
+                continue;
+            };
+            // errors go to the client rather than ending the session
+            let action = self
+                .stub
+                .handle(nes, &packet)
+                .unwrap_or_else(|_| Action::Reply("E01".to_string()));
+            match action {
+                Action::Reply(reply) => {
+                    self.send(&reply)?;
+                    if packet == "QStartNoAckMode" {
+                        self.no_ack = true;
+                    }
+                }
+                Action::Resume(mode) => {
+                    self.stub.resume(nes, mode);
+                    self.awaiting_stop = true;
+                    return Ok(true);
+                }
+                Action::Detach => {
+                    self.send("OK")?;
+                    self.stub.resume(nes, RunMode::Continue);
+                    self.connected = false;
+                    return Ok(true);
+                }
+                Action::Kill => {
+                    self.connected = false;
+                    return Ok(false);
+                }
+            }
+        }
+    }
+
+    /**
+     * Clocks the NES through the debugger, pausing it if the client asks to.
+     * A client that goes away while the NES runs is treated as detached.
+     */
+    pub fn clock(&mut self, nes: &mut NES) -> Result<Clocked> {
+        let result = self.stub.debugger.clock(nes);
+        self.clocks += 1;
+        if self.connected && self.clocks.is_multiple_of(POLL_INTERVAL) && self.interrupted()? {
+            self.stub.interrupt();
+        }
+        if !self.connected && !self.stub.breakpoints.is_empty() {
+            self.stub.remove_breakpoints()?;
+        }
+        Ok(result)
+    }
+
+    // checks for an interrupt without waiting
+    fn interrupted(&mut self) -> Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut buffer = [0; 64];
+        match self.stream.read(&mut buffer) {
+            Ok(0) => self.connected = false,
+            Ok(count) => self.received.extend_from_slice(&buffer[..count]),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) if e.kind() == ErrorKind::ConnectionReset => self.connected = false,
+            Err(e) => Err(e)?,
+        }
+        if !self.connected {
+            return Ok(false);
+        }
+        self.stream.set_nonblocking(false)?;
+        if let Some(i) = self.received.iter().position(|b| *b == INTERRUPT) {
+            self.received.remove(i);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    // None when the connection closed, Some(None) for an interrupt
+    fn next_packet(&mut self) -> Result<Option<Option<String>>> {
+        loop {
+            match parse_packet(&self.received) {
+                Parsed::Packet(packet, used) => {
+                    self.received.drain(..used);
+                    if !self.no_ack {
+                        self.stream.write_all(b"+")?;
+                    }
+                    return Ok(Some(Some(packet)));
+                }
+                Parsed::Interrupt(used) => {
+                    self.received.drain(..used);
+                    return Ok(Some(None));
+                }
+                Parsed::BadChecksum(used) => {
+                    self.received.drain(..used);
+                    self.stream.write_all(b"-")?;
+                }
+                Parsed::Incomplete => {
+                    let mut buffer = [0; 4096];
+                    let count = self.stream.read(&mut buffer)?;
+                    if count == 0 {
+                        return Ok(None);
+                    }
+                    self.received.extend_from_slice(&buffer[..count]);
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> Result<()> {
+        self.stream.write_all(frame_packet(data).as_bytes())?;
+        Ok(())
+    }
+}
+
+#[derive(PartialEq, Eq, Debug)]
+enum Parsed {
+    /**
+     * A packet's data, and how many bytes it took up with anything skipped before it
+     */
+    Packet(String, usize),
+    Interrupt(usize),
+    BadChecksum(usize),
+    Incomplete,
+}
+
+fn parse_packet(bytes: &[u8]) -> Parsed {
+    // acknowledgements and anything else between packets are skipped
+    let Some(start) = bytes.iter().position(|b| *b == b'$' || *b == INTERRUPT) else {
+        return Parsed::Incomplete;
+    };
+    if bytes[start] == INTERRUPT {
+        return Parsed::Interrupt(start + 1);
+    }
+    let Some(end) = bytes[start..]
+        .iter()
+        .position(|b| *b == b'#')
+        .map(|i| start + i)
+    else {
+        return Parsed::Incomplete;
+    };
+    if bytes.len() < end + 3 {
+        return Parsed::Incomplete;
+    }
+    let data = &bytes[start + 1..end];
+    let expected = std::str::from_utf8(&bytes[end + 1..end + 3])
+        .ok()
+        .and_then(|c| u8::from_str_radix(c, 16).ok());
+    if expected != Some(checksum(data)) {
+        return Parsed::BadChecksum(end + 3);
+    }
+    Parsed::Packet(String::from_utf8_lossy(data).to_string(), end + 3)
+}
+
+fn frame_packet(data: &str) -> String {
+    // replies never contain the characters that would need escaping
+    format!("${data}#{:02x}", checksum(data.as_bytes()))
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, b| sum.wrapping_add(*b))
+}
+
+// the register's position in register_bytes
+fn register_bytes_at(register: usize) -> Result<(usize, usize)> {
+    match register {
+        0..=3 => Ok((register, 1)),
+        4 => Ok((4, 2)),
+        5 => Ok((6, 1)),
+        _ => Err(GdbError::NoSuchRegister(register))?,
+    }
+}
+
+fn parse_hex<T: TryFrom<u64>>(text: &str) -> Result<T> {
+    let number = u64::from_str_radix(text, 16)
+        .ok()
+        .and_then(|n| T::try_from(n).ok())
+        .ok_or_else(|| invalid(text))?;
+    Ok(number)
+}
+
+fn parse_range(text: &str) -> Result<(u16, usize)> {
+    let (addr, len) = text.split_once(',').ok_or(invalid(text))?;
+    Ok((parse_hex(addr)?, parse_hex(len)?))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(text: &str) -> Result<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        Err(invalid(text))?;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| parse_hex(&text[i..i + 2]))
+        .collect()
+}
+
+fn invalid(text: &str) -> GdbError {
+    GdbError::InvalidPacket(text.to_string())
+}
+
+#[derive(Error, Debug)]
+pub enum GdbError {
+    #[error("Invalid packet '{0}'")]
+    InvalidPacket(String),
+    #[error("There's no register {0}")]
+    NoSuchRegister(usize),
+}
@@ -0,0 +1,29 @@
+use crate::nes::gdb_server::{Parsed, frame_packet, from_hex, parse_packet, to_hex};
+
+#[test]
+fn test_framing() {
+    assert_eq!("$OK#9a", frame_packet("OK"));
+    assert_eq!("$#00", frame_packet(""));
+}
+
+#[test]
+fn test_parsing() {
+    assert_eq!(Parsed::Packet("g".to_string(), 5), parse_packet(b"$g#67"));
+    // acknowledgements before a packet are skipped
+    assert_eq!(
+        Parsed::Packet("mc000,4".to_string(), 12),
+        parse_packet(b"+$mc000,4#c0")
+    );
+    assert_eq!(Parsed::Interrupt(1), parse_packet(b"\x03$g#67"));
+    assert_eq!(Parsed::BadChecksum(5), parse_packet(b"$g#00"));
+    assert_eq!(Parsed::Incomplete, parse_packet(b"$g#6"));
+    assert_eq!(Parsed::Incomplete, parse_packet(b"+"));
+}
+
+#[test]
+fn test_hex() {
+    assert_eq!("00a9ff", to_hex(&[0x00, 0xA9, 0xFF]));
+    assert_eq!(vec![0x00, 0xA9, 0xFF], from_hex("00a9FF").unwrap());
+    assert!(from_hex("0").is_err());
+    assert!(from_hex("zz").is_err());
+}
@@ -0,0 +1,105 @@
+use super::fixtures::nes_with_program;
+use crate::nes::NES;
+use crate::nes::apu_inspector::ApuChannel;
+
+// pulse 1 at a constant full volume, and the triangle
+const PROGRAM: &str = "
+        lda #%00000101
+        sta $4015
+        lda #%10111111
+        sta $4000
+        lda #$FD
+        sta $4002
+        lda #$08
+        sta $4003
+        lda #$FF
+        sta $4008
+        lda #$7E
+        sta $400A
+        lda #$00
+        sta $400B
+    loop:
+        jmp loop
+";
+
+// the sum of the samples over a frame, and the same per channel
+fn run_frame(nes: &mut NES) -> (f32, [f32; 5]) {
+    let mut mixed = 0.0;
+    let mut channels = [0.0; 5];
+    loop {
+        let (frame_complete, _, sample) = nes.clock();
+        if let Some(sample) = sample {
+            mixed += sample;
+            let samples = nes.channel_samples();
+            for (total, channel) in channels.iter_mut().zip(ApuChannel::ALL) {
+                *total += samples.sample(channel);
+            }
+        }
+        if frame_complete {
+            return (mixed, channels);
+        }
+    }
+}
+
+#[test]
+fn test_channel_state() {
+    let mut nes = nes_with_program(PROGRAM);
+    run_frame(&mut nes);
+
+    let channels = nes.apu_channels();
+    let pulse = channels.pulse1;
+    assert!(pulse.enabled);
+    assert_eq!(2, pulse.duty);
+    assert_eq!(0x0FD, pulse.period);
+    assert!((pulse.frequency() - 440.0).abs() < 1.0);
+    assert!(pulse.envelope.constant_volume);
+    assert_eq!(15, pulse.envelope.output());
+    assert!(pulse.length_halted);
+    assert_eq!(Some(false), pulse.sweep.map(|s| s.enabled));
+
+    assert!(!channels.pulse2.enabled);
+    assert!(channels.triangle.enabled);
+    assert_eq!(0x07E, channels.triangle.period);
+    assert_eq!(0x7F, channels.triangle.linear_reload);
+    assert!(channels.triangle.control);
+    assert!(!channels.noise.enabled);
+    assert!(!channels.dmc.enabled);
+    assert_eq!(0xC000, channels.dmc.sample_address);
+
+    assert!(channels.to_string().starts_with(
+        "pulse1   on  duty 2 period $0FD (  440.4 Hz) volume 15 loop length 254 halted\n"
+    ));
+}
+
+#[test]
+fn test_channel_samples() {
+    let mut nes = nes_with_program(PROGRAM);
+    run_frame(&mut nes);
+    let (mixed, channels) = run_frame(&mut nes);
+
+    assert!(mixed > 0.0);
+    assert!(channels[0] > 0.0);
+    assert!(channels[2] > 0.0);
+    assert_eq!([0.0; 2], [channels[1], channels[3]]);
+    // pulses and the others are mixed separately, so one of each adds up
+    assert!((mixed - channels.iter().sum::<f32>()).abs() < 0.001 * mixed);
+}
+
+#[test]
+fn test_muted_channels() {
+    let mut nes = nes_with_program(PROGRAM);
+    run_frame(&mut nes);
+    let (_, unmuted) = run_frame(&mut nes);
+
+    nes.set_channel_muted(ApuChannel::Triangle, true);
+    assert!(nes.is_channel_muted(ApuChannel::Triangle));
+    let (mixed, channels) = run_frame(&mut nes);
+    // muted channels still report what they'd play
+    assert!(channels[2] > 0.0);
+    assert!((mixed - channels[0]).abs() < 0.001 * unmuted[0]);
+
+    nes.set_channel_muted(ApuChannel::Pulse1, true);
+    nes.reset();
+    assert!(nes.is_channel_muted(ApuChannel::Pulse1));
+    assert_eq!(0.0, run_frame(&mut nes).0);
+}
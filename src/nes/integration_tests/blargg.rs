@@ -0,0 +1,92 @@
+// blargg's accuracy suites from https://github.com/christopherpow/nes-test-roms
+// They aren't distributed with this crate. Copy the suite directories into
+// resources/test/blargg and run `cargo test --release -- --ignored blargg`
+
+use super::test_rom::{assert_legacy_test_rom_passes, assert_test_rom_passes};
+
+// generous, the longest running suites take around 30 seconds of emulated time
+const MAX_FRAMES: usize = 60 * 60;
+
+#[test]
+#[ignore = "needs the cpu_instrs ROM in resources/test/blargg"]
+fn test_cpu_instrs() {
+    assert_test_rom_passes(
+        "resources/test/blargg/cpu_instrs/cpu_instrs.nes",
+        MAX_FRAMES,
+    );
+}
+
+#[test]
+#[ignore = "needs the instr_timing ROM in resources/test/blargg"]
+fn test_instr_timing() {
+    assert_test_rom_passes(
+        "resources/test/blargg/instr_timing/instr_timing.nes",
+        MAX_FRAMES,
+    );
+}
+
+#[test]
+#[ignore = "needs the cpu_interrupts_v2 ROM in resources/test/blargg"]
+fn test_cpu_interrupts() {
+    assert_test_rom_passes(
+        "resources/test/blargg/cpu_interrupts_v2/cpu_interrupts.nes",
+        MAX_FRAMES,
+    );
+}
+
+#[test]
+#[ignore = "needs the ppu_vbl_nmi ROM in resources/test/blargg"]
+fn test_ppu_vbl_nmi() {
+    assert_test_rom_passes(
+        "resources/test/blargg/ppu_vbl_nmi/ppu_vbl_nmi.nes",
+        MAX_FRAMES,
+    );
+}
+
+// these predate the 0x6000 protocol, so only report a result code at 0xF8
+#[test]
+#[ignore = "needs the sprite_hit ROMs in resources/test/blargg"]
+fn test_sprite_hit() {
+    for rom in [
+        "01.basics",
+        "02.alignment",
+        "03.corners",
+        "04.flip",
+        "05.left_clip",
+        "06.right_edge",
+        "07.screen_bottom",
+        "08.double_height",
+        "09.timing_basics",
+        "10.timing_order",
+        "11.edge_timing",
+    ] {
+        assert_legacy_test_rom_passes(
+            &format!("resources/test/blargg/sprite_hit_tests_2005.10.05/{rom}.nes"),
+            MAX_FRAMES,
+        );
+    }
+}
+
+#[test]
+#[ignore = "needs the apu_test ROM in resources/test/blargg"]
+fn test_apu_test() {
+    assert_test_rom_passes("resources/test/blargg/apu_test/apu_test.nes", MAX_FRAMES);
+}
+
+#[test]
+#[ignore = "needs the mmc3_test ROMs in resources/test/blargg"]
+fn test_mmc3_test() {
+    for rom in [
+        "1-clocking",
+        "2-details",
+        "3-A12_clocking",
+        "4-scanline_timing",
+        "5-MMC3",
+        "6-MMC3_alt",
+    ] {
+        assert_test_rom_passes(
+            &format!("resources/test/blargg/mmc3_test_2/rom_singles/{rom}.nes"),
+            MAX_FRAMES,
+        );
+    }
+}
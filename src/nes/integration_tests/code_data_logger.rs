@@ -0,0 +1,113 @@
+use super::fixtures::{NESTEST, load_nestest};
+use crate::nes::code_data_logger::{ChrFlags, CodeDataLog, CodeDataLogger, PrgFlags};
+use crate::nes::read_prg_rom;
+
+const NESTEST_CYCLES: usize = 26560;
+
+fn log_automated_nestest() -> CodeDataLog {
+    let mut nes = load_nestest();
+    let log = CodeDataLogger::attach(&mut nes);
+    nes.cpu.borrow_mut().reset_to(0xC000);
+    while nes.cpu.borrow().cycles < NESTEST_CYCLES {
+        nes.clock();
+    }
+    log.borrow().clone()
+}
+
+#[test]
+fn test_logs_code_by_rom_offset() {
+    let log = log_automated_nestest();
+    assert_eq!(0x4000, log.prg_rom_size());
+    assert_eq!(0x2000, log.chr_rom_size());
+
+    // the 16k PRG ROM is mirrored, and nestest runs from the upper copy
+    assert!(log.prg(0x0000).contains(PrgFlags::Code));
+    assert_eq!(Some(0xC000), log.address(0x0000));
+    // the operand of JMP $C5F5
+    assert!(log.prg(0x0002).contains(PrgFlags::Code));
+    // DB7B JMP ($0200) goes to DB7E
+    assert!(log.prg(0x1B7E).contains(PrgFlags::IndirectCode));
+    assert!(!log.prg(0x1B7B).contains(PrgFlags::IndirectCode));
+}
+
+#[test]
+fn test_logs_data_by_rom_offset() {
+    let log = log_automated_nestest();
+
+    // NOP $A9A9 reads from the lower copy of a byte that also runs as code in the upper
+    let flags = log.prg(0x29A9);
+    assert!(flags.contains(PrgFlags::Data | PrgFlags::Code));
+    assert!([Some(0xA9A9), Some(0xE9A9)].contains(&log.address(0x29A9)));
+
+    // the reset vector
+    assert!(log.prg(0x3FFC).contains(PrgFlags::Data));
+    assert!(log.prg(0x3FFD).contains(PrgFlags::Data));
+    assert!(log.prg(0x3FFE).is_empty());
+}
+
+#[test]
+fn test_logs_drawn_tiles() {
+    let mut nes = load_nestest();
+    let log = CodeDataLogger::attach(&mut nes);
+    nes.reset();
+    let mut frames = 0;
+    while frames < 10 {
+        if nes.clock().0 {
+            frames += 1;
+        }
+    }
+
+    // nestest's menu is drawn with the background pattern table at 0x0000
+    let summary = log.borrow().summary();
+    assert!(summary.drawn > 0);
+    assert!(summary.code > 0);
+    assert!((0..0x2000).any(|i| log.borrow().chr(i).contains(ChrFlags::Drawn)));
+}
+
+#[test]
+fn test_file_round_trip() {
+    let log = log_automated_nestest();
+    let bytes = log.to_bytes();
+    assert_eq!(0x6000, bytes.len());
+    assert_eq!(log, CodeDataLog::from_bytes(&bytes, 0x4000).unwrap());
+    assert!(CodeDataLog::from_bytes(&bytes[..0x3000], 0x4000).is_err());
+}
+
+#[test]
+fn test_attach_with_checks_sizes() {
+    let mut nes = load_nestest();
+    assert!(CodeDataLogger::attach_with(&mut nes, CodeDataLog::new(0x8000, 0x2000)).is_err());
+
+    let log = CodeDataLogger::attach_with(&mut nes, log_automated_nestest()).unwrap();
+    assert!(log.borrow().prg(0x0000).contains(PrgFlags::Code));
+}
+
+#[test]
+fn test_detach() {
+    let mut nes = load_nestest();
+    let log = CodeDataLogger::attach(&mut nes);
+    CodeDataLogger::detach(&mut nes);
+    nes.cpu.borrow_mut().reset_to(0xC000);
+    for _ in 0..1000 {
+        nes.clock();
+    }
+    assert_eq!(0, log.borrow().summary().logged);
+}
+
+#[test]
+fn test_disassemble_separates_code_from_data() {
+    let log = log_automated_nestest();
+    let prg_rom = read_prg_rom(NESTEST).unwrap();
+
+    let lines = log.disassemble(&prg_rom, 0x0000, 0x4000, 0xC000);
+    assert_eq!("jmp $C5F5", lines[0].text);
+    assert_eq!(0xC003, lines[1].addr);
+    assert!(lines[1].instruction.is_none());
+
+    let vectors = lines.iter().find(|l| l.addr == 0xFFFC).unwrap();
+    assert_eq!(".byte $04, $C0", vectors.text);
+    assert_eq!(None, vectors.annotation);
+    let last = lines.last().unwrap();
+    assert_eq!(0xFFFE, last.addr);
+    assert_eq!(Some("not accessed".to_string()), last.annotation);
+}
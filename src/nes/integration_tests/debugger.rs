@@ -0,0 +1,205 @@
+use super::fixtures::{automated_nestest, nestest_menu};
+use crate::cpu::monitor::NulMonitor;
+use crate::nes::NES;
+use crate::nes::debugger::condition::Condition;
+use crate::nes::debugger::{Access, AddressSpace, Debugger, RunMode, StopReason};
+
+fn pc(nes: &NES) -> u16 {
+    nes.cpu.borrow().pc
+}
+
+#[test]
+fn test_starts_paused() {
+    let mut nes = automated_nestest();
+    let mut debugger = Debugger::attach(&mut nes);
+    assert!(debugger.is_paused());
+    assert!(debugger.clock(&mut nes).is_none());
+}
+
+#[test]
+fn test_breakpoint() {
+    let mut nes = automated_nestest();
+    let mut debugger = Debugger::attach(&mut nes);
+    let id = debugger.add_breakpoint(0xC5FD, None);
+
+    assert_eq!(
+        StopReason::Breakpoint(id),
+        debugger.run(&mut nes, RunMode::Continue)
+    );
+    assert_eq!(0xC5FD, pc(&nes));
+
+    // resuming doesn't stop at the same breakpoint again straight away
+    let other = debugger.add_breakpoint(0xC72D, None);
+    assert_eq!(
+        StopReason::Breakpoint(other),
+        debugger.run(&mut nes, RunMode::Continue)
+    );
+}
+
+#[test]
+fn test_conditional_breakpoint() {
+    let mut nes = automated_nestest();
+    let mut debugger = Debugger::attach(&mut nes);
+    debugger.add_breakpoint(0xC5F7, Some(Condition::parse("X != 0").unwrap()));
+    let id = debugger.add_breakpoint(0xC5F9, Some(Condition::parse("X == 0").unwrap()));
+
+    assert_eq!(
+        StopReason::Breakpoint(id),
+        debugger.run(&mut nes, RunMode::Continue)
+    );
+    assert_eq!(0xC5F9, pc(&nes));
+}
+
+#[test]
+fn test_disabled_and_removed_breakpoints() {
+    let mut nes = automated_nestest();
+    let mut debugger = Debugger::attach(&mut nes);
+    let disabled = debugger.add_breakpoint(0xC5F5, None);
+    let removed = debugger.add_breakpoint(0xC5F7, None);
+    let id = debugger.add_breakpoint(0xC5F9, None);
+    debugger.set_enabled(disabled, false).unwrap();
+    debugger.remove(removed).unwrap();
+    assert!(debugger.remove(removed).is_err());
+
+    assert_eq!(
+        StopReason::Breakpoint(id),
+        debugger.run(&mut nes, RunMode::Continue)
+    );
+}
+
+#[test]
+fn test_watch_write() {
+    let mut nes = automated_nestest();
+    let mut debugger = Debugger::attach(&mut nes);
+    let id = debugger
+        .add_watchpoint(AddressSpace::Cpu, 0x0010, 0x0011, Access::Write, None)
+        .unwrap();
+
+    // stops after STX $10
+    assert_eq!(
+        StopReason::Watchpoint {
+            id,
+            access: Access::Write,
+            addr: 0x0010,
+            value: 0
+        },
+        debugger.run(&mut nes, RunMode::Continue)
+    );
+    assert_eq!(0xC5FB, pc(&nes));
+}
+
+#[test]
+fn test_conditional_watch() {
+    let mut nes = automated_nestest();
+    let mut debugger = Debugger::attach(&mut nes);
+    let condition = Condition::parse("ADDRESS == $11").unwrap();
+    let id = debugger
+        .add_watchpoint(
+            AddressSpace::Cpu,
+            0x0000,
+            0x00FF,
+            Access::Write,
+            Some(condition),
+        )
+        .unwrap();
+
+    let reason = debugger.run(&mut nes, RunMode::Continue);
+    assert!(matches!(reason, StopReason::Watchpoint { id: i, addr: 0x0011, .. } if i == id));
+    assert_eq!(0xC5FD, pc(&nes));
+}
+
+#[test]
+fn test_ppu_watch() {
+    // the menu is drawn into the first name table
+    let mut nes = nestest_menu();
+    let mut debugger = Debugger::attach(&mut nes);
+    let id = debugger
+        .add_watchpoint(AddressSpace::Ppu, 0x2000, 0x23FF, Access::Write, None)
+        .unwrap();
+
+    let reason = debugger.run(&mut nes, RunMode::Continue);
+    assert!(matches!(reason, StopReason::Watchpoint { id: i, .. } if i == id));
+}
+
+#[test]
+fn test_invalid_watchpoints() {
+    let mut nes = automated_nestest();
+    let mut debugger = Debugger::attach(&mut nes);
+    assert!(
+        debugger
+            .add_watchpoint(AddressSpace::Cpu, 0x10, 0x0F, Access::Read, None)
+            .is_err()
+    );
+    assert!(
+        debugger
+            .add_watchpoint(AddressSpace::Ppu, 0x2000, 0x2000, Access::Execute, None)
+            .is_err()
+    );
+    assert!(
+        debugger
+            .add_watchpoint(AddressSpace::Cpu, 0x10, 0x10, Access::empty(), None)
+            .is_err()
+    );
+}
+
+#[test]
+fn test_step_into_and_over() {
+    let mut nes = automated_nestest();
+    let mut debugger = Debugger::attach(&mut nes);
+    debugger.add_breakpoint(0xC5FD, None);
+    debugger.run(&mut nes, RunMode::Continue);
+
+    let state = nes.save_state();
+    assert_eq!(StopReason::Step, debugger.run(&mut nes, RunMode::StepInto));
+    assert_eq!(0xC72D, pc(&nes));
+
+    nes.load_state(&state).unwrap();
+    assert_eq!(StopReason::Step, debugger.run(&mut nes, RunMode::StepOver));
+    assert_eq!(0xC600, pc(&nes));
+
+    // not a JSR, so the same as step into
+    assert_eq!(StopReason::Step, debugger.run(&mut nes, RunMode::StepOver));
+    assert_ne!(0xC600, pc(&nes));
+}
+
+#[test]
+fn test_step_out() {
+    let mut nes = automated_nestest();
+    let mut debugger = Debugger::attach(&mut nes);
+    debugger.add_breakpoint(0xC72D, None);
+    debugger.run(&mut nes, RunMode::Continue);
+    let sp = nes.cpu.borrow().sp;
+
+    assert_eq!(StopReason::Step, debugger.run(&mut nes, RunMode::StepOut));
+    assert_eq!(0xC600, pc(&nes));
+    assert_eq!(sp.wrapping_add(2), nes.cpu.borrow().sp);
+}
+
+#[test]
+fn test_run_to_scan_line() {
+    let mut nes = automated_nestest();
+    let mut debugger = Debugger::attach(&mut nes);
+
+    assert_eq!(
+        StopReason::ScanLine(100),
+        debugger.run(&mut nes, RunMode::ToScanLine(100))
+    );
+    let registers = debugger.registers(&nes);
+    assert_eq!(100, registers.scan_line);
+    assert_eq!(0, registers.dot);
+}
+
+#[test]
+fn test_detach_restores_monitor() {
+    let mut nes = automated_nestest();
+    let debugger = Debugger::attach(&mut nes);
+    debugger.detach(&mut nes);
+    assert!(
+        nes.cpu
+            .borrow_mut()
+            .monitor
+            .as_any()
+            .downcast_mut::<NulMonitor>()
+            .is_some()
+    );
+}
@@ -0,0 +1,65 @@
+use super::fixtures::{SharedBuffer, nes_with_program};
+
+// prints "OK", times a loop, keeps the stopwatch's low byte in $00 and exits with 3
+const PROGRAM: &str = "
+        lda #$4F
+        sta $4018
+        lda #$4B
+        sta $4018
+        lda #$0A
+        sta $4018
+        sta $401A
+        ldx #10
+    delay:
+        dex
+        bne delay
+        sta $401B
+        lda $401C
+        sta $00
+        lda #3
+        sta $4019
+        lda #$58
+        sta $4018
+    forever:
+        jmp forever
+";
+
+#[test]
+fn test_developer_cartridge() {
+    let mut nes = nes_with_program(PROGRAM);
+    let output = SharedBuffer::default();
+    let cartridge = nes.attach_developer_cartridge(Box::new(output.clone()));
+
+    let mut clocks = 0;
+    while nes.developer_exit_code().is_none() && clocks < 10000 {
+        nes.clock();
+        clocks += 1;
+    }
+    for _ in 0..100 {
+        nes.clock();
+    }
+
+    assert_eq!(Some(3), nes.developer_exit_code());
+    // the loop, then the sta that stops the stopwatch
+    let cycles = 2 + 10 * 2 + 9 * 3 + 2 + 4;
+    assert_eq!(Some(cycles), cartridge.borrow().last_stopwatch());
+    assert_eq!(cycles as u8, nes.peek_cpu(0x0000));
+    assert_eq!(cycles as u8, nes.peek_cpu(0x401C));
+    assert_eq!(0, nes.peek_cpu(0x401D));
+    // nothing is printed after exiting
+    assert_eq!(
+        format!("OK\nstopwatch: {cycles} cycles\n"),
+        String::from_utf8(output.0.take()).unwrap()
+    );
+}
+
+#[test]
+fn test_not_attached() {
+    let mut nes = nes_with_program(PROGRAM);
+    for _ in 0..10000 {
+        nes.clock();
+    }
+
+    assert_eq!(None, nes.developer_exit_code());
+    assert_eq!(0, nes.peek_cpu(0x0000));
+}
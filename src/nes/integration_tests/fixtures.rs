@@ -0,0 +1,62 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::nes::NES;
+use crate::nes::memory_domains::MemoryDomain;
+
+pub(super) const NESTEST: &str = "resources/test/nestest.nes";
+
+/**
+ * nestest's cartridge, loaded but not reset yet
+ */
+pub(super) fn load_nestest() -> NES {
+    let mut nes = NES::new();
+    nes.load_cartridge(NESTEST.to_string()).unwrap();
+    nes
+}
+
+/**
+ * nestest after a reset, which draws its menu and waits for input with NMIs enabled
+ */
+pub(super) fn nestest_menu() -> NES {
+    let mut nes = load_nestest();
+    nes.reset();
+    nes
+}
+
+/**
+ * nestest's automated mode, which runs every test from 0xC000 without needing the PPU
+ */
+pub(super) fn automated_nestest() -> NES {
+    let nes = load_nestest();
+    nes.cpu.borrow_mut().reset_to(0xC000);
+    nes
+}
+
+/**
+ * Runs a program assembled over nestest's PRG ROM at 0xC000
+ */
+pub(super) fn nes_with_program(program: &str) -> NES {
+    let nes = load_nestest();
+    nes.assemble_into(MemoryDomain::PrgRom, 0, 0xC000, program)
+        .unwrap();
+    nes.cpu.borrow_mut().reset_to(0xC000);
+    nes
+}
+
+/**
+ * Collects what's written to it, e.g. a trace, while the test keeps a handle to read it
+ */
+#[derive(Clone, Default)]
+pub(super) struct SharedBuffer(pub(super) Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+use super::fixtures::automated_nestest;
+use crate::nes::NES;
+use crate::nes::gdb_server::{Action, GdbServer, GdbStub};
+
+fn reply(stub: &mut GdbStub, nes: &mut NES, packet: &str) -> String {
+    match stub.handle(nes, packet).unwrap() {
+        Action::Reply(reply) => reply,
+        action => panic!("{action:?}"),
+    }
+}
+
+fn run(stub: &mut GdbStub, nes: &mut NES, packet: &str) {
+    let Action::Resume(mode) = stub.handle(nes, packet).unwrap() else {
+        panic!("{packet} didn't resume");
+    };
+    stub.resume(nes, mode);
+    while stub.debugger.clock(nes).is_some() {}
+}
+
+#[test]
+fn test_registers() {
+    let mut nes = automated_nestest();
+    let mut stub = GdbStub::attach(&mut nes);
+    // a, x, y, sp, pc and p
+    assert_eq!("000000fd00c034", reply(&mut stub, &mut nes, "g"));
+    assert_eq!("00c0", reply(&mut stub, &mut nes, "p4"));
+
+    assert_eq!("OK", reply(&mut stub, &mut nes, "P0=42"));
+    assert_eq!("42", reply(&mut stub, &mut nes, "p0"));
+    assert_eq!("OK", reply(&mut stub, &mut nes, "G010203f000c1e5"));
+    let registers = stub.debugger.registers(&nes);
+    assert_eq!(
+        (0x01, 0x02, 0x03, 0xF0, 0xC100, 0xE5),
+        (
+            registers.a,
+            registers.x,
+            registers.y,
+            registers.sp,
+            registers.pc,
+            registers.status.bits()
+        )
+    );
+    assert!(stub.handle(&mut nes, "p9").is_err());
+    assert!(stub.handle(&mut nes, "G0102").is_err());
+}
+
+#[test]
+fn test_memory() {
+    let mut nes = automated_nestest();
+    let mut stub = GdbStub::attach(&mut nes);
+    assert_eq!("4cf5c5", reply(&mut stub, &mut nes, "mc000,3"));
+    assert_eq!("OK", reply(&mut stub, &mut nes, "M10,2:abcd"));
+    assert_eq!("abcd", reply(&mut stub, &mut nes, "m10,2"));
+    // registers read without side effects
+    assert_eq!(4, reply(&mut stub, &mut nes, "m1ffe,4").len() / 2);
+    // writes patch ROM rather than reaching the mapper
+    assert_eq!("OK", reply(&mut stub, &mut nes, "Mc000,1:ea"));
+    assert_eq!("eaf5c5", reply(&mut stub, &mut nes, "mc000,3"));
+}
+
+#[test]
+fn test_breakpoints_and_stepping() {
+    let mut nes = automated_nestest();
+    let mut stub = GdbStub::attach(&mut nes);
+    assert_eq!("S05", reply(&mut stub, &mut nes, "?"));
+
+    assert_eq!("OK", reply(&mut stub, &mut nes, "Z0,c5f5,1"));
+    run(&mut stub, &mut nes, "c");
+    assert_eq!(0xC5F5, stub.debugger.registers(&nes).pc);
+    assert_eq!("T05swbreak:;", stub.stop_reply());
+
+    // C5F5 LDX #$00, C5F7 STX $00
+    run(&mut stub, &mut nes, "vCont;s:1");
+    assert_eq!(0xC5F7, stub.debugger.registers(&nes).pc);
+    assert_eq!("S05", stub.stop_reply());
+
+    assert_eq!("OK", reply(&mut stub, &mut nes, "Z2,0,1"));
+    run(&mut stub, &mut nes, "c");
+    assert_eq!(0xC5F9, stub.debugger.registers(&nes).pc);
+    assert_eq!("T05watch:0000;", stub.stop_reply());
+
+    assert_eq!("OK", reply(&mut stub, &mut nes, "z2,0,1"));
+    assert_eq!("OK", reply(&mut stub, &mut nes, "z0,c5f5,1"));
+    assert!(stub.debugger.breakpoints().is_empty());
+}
+
+#[test]
+fn test_queries() {
+    let mut nes = automated_nestest();
+    let mut stub = GdbStub::attach(&mut nes);
+    assert!(
+        reply(&mut stub, &mut nes, "qSupported:multiprocess+").contains("qXfer:features:read+")
+    );
+    let xml = reply(&mut stub, &mut nes, "qXfer:features:read:target.xml:0,1000");
+    assert!(xml.starts_with("l<?xml"));
+    assert!(xml.contains(r#"<reg name="pc" bitsize="16""#));
+    assert!(reply(&mut stub, &mut nes, "qXfer:features:read:target.xml:0,10").starts_with('m'));
+    assert_eq!("vCont;c;C;s;S", reply(&mut stub, &mut nes, "vCont?"));
+    assert_eq!("", reply(&mut stub, &mut nes, "qUnknownThing"));
+    assert_eq!(Action::Kill, stub.handle(&mut nes, "k").unwrap());
+}
+
+// sends a packet and returns the reply, skipping acknowledgements
+fn exchange(stream: &mut TcpStream, packet: &[u8]) -> String {
+    stream.write_all(packet).unwrap();
+    let mut received = Vec::new();
+    let mut byte = [0];
+    loop {
+        stream.read_exact(&mut byte).unwrap();
+        received.push(byte[0]);
+        if received.len() >= 3 && received[received.len() - 3] == b'#' {
+            break;
+        }
+    }
+    let text = String::from_utf8(received).unwrap();
+    let start = text.find('$').unwrap();
+    text[start + 1..text.len() - 3].to_string()
+}
+
+fn packet(data: &str) -> Vec<u8> {
+    let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+    format!("${data}#{checksum:02x}").into_bytes()
+}
+
+#[test]
+fn test_over_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = thread::spawn(move || {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        assert_eq!("S05", exchange(&mut stream, &packet("?")));
+        assert_eq!("OK", exchange(&mut stream, &packet("QStartNoAckMode")));
+        assert_eq!("OK", exchange(&mut stream, &packet("Z0,c5f5,1")));
+        assert_eq!("T05swbreak:;", exchange(&mut stream, &packet("c")));
+        assert_eq!("000000fdf5c5", &exchange(&mut stream, &packet("g"))[..12]);
+        assert_eq!("OK", exchange(&mut stream, &packet("z0,c5f5,1")));
+
+        // nestest loops forever once it's done, until it's interrupted
+        let mut continue_and_interrupt = packet("c");
+        continue_and_interrupt.push(0x03);
+        assert_eq!("S02", exchange(&mut stream, &continue_and_interrupt));
+        stream.write_all(&packet("k")).unwrap();
+    });
+
+    let (stream, _) = listener.accept().unwrap();
+    let mut nes = automated_nestest();
+    let mut server = GdbServer::new(&mut nes, stream).unwrap();
+    while server.serve_while_paused(&mut nes).unwrap() {
+        server.clock(&mut nes).unwrap();
+    }
+    assert!(!server.is_connected());
+    client.join().unwrap();
+}
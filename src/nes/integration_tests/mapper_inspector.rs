@@ -0,0 +1,94 @@
+use super::fixtures::load_nestest;
+use crate::cpu::assembler::assemble;
+use crate::nes::NES;
+use crate::nes::mapper_inspector::{IrqState, MirrorType};
+
+const PRG_SIZE: usize = 0x10000;
+const CHR_SIZE: usize = 0x4000;
+
+// switches $8000 to bank 3, mirrors horizontally and sets up the scanline IRQ
+const PROGRAM: &str = "
+        lda #6
+        sta $8000
+        lda #3
+        sta $8001
+        lda #1
+        sta $A000
+        lda #32
+        sta $C000
+        sta $E001
+    forever:
+        jmp forever
+";
+
+fn run_mmc3_rom() -> NES {
+    let code = assemble(PROGRAM, 0xE000).unwrap();
+    let mut prg = vec![0xEA; PRG_SIZE];
+    prg[PRG_SIZE - 0x2000..PRG_SIZE - 0x2000 + code.len()].copy_from_slice(&code);
+    // NMI, reset and IRQ vectors
+    prg[PRG_SIZE - 6..].copy_from_slice(&[0x00, 0xE0, 0x00, 0xE0, 0x00, 0xE0]);
+    let mut rom = vec![
+        b'N', b'E', b'S', 0x1A, 4, 2, 0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    rom.extend(prg);
+    rom.extend(vec![0; CHR_SIZE]);
+
+    let path = std::env::temp_dir().join(format!("nes-rs-mmc3-{}.nes", std::process::id()));
+    std::fs::write(&path, rom).unwrap();
+    let mut nes = NES::new();
+    nes.load_cartridge(path.to_string_lossy().to_string())
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    nes.reset();
+    for _ in 0..1000 {
+        nes.clock();
+    }
+    nes
+}
+
+#[test]
+fn test_nrom() {
+    let nes = load_nestest();
+    let description = nes.mapper_description();
+
+    assert_eq!(0, description.mapper_number);
+    // 16K of PRG ROM shows up twice
+    assert_eq!(2, description.prg_rom.len());
+    assert!(description.prg_rom.iter().all(|b| b.offset == 0));
+    assert_eq!(Some(MirrorType::Horizontal), description.mirroring);
+    assert_eq!(None, description.irq);
+    assert!(description.registers.is_empty());
+}
+
+#[test]
+fn test_mmc3() {
+    let nes = run_mmc3_rom();
+    let description = nes.mapper_description();
+
+    assert_eq!(4, description.mapper_number);
+    assert_eq!(4, description.prg_rom.len());
+    assert_eq!(3, description.prg_bank_at(0x8000).unwrap().bank);
+    assert_eq!(1, description.prg_bank_at(0xA000).unwrap().bank);
+    assert_eq!(6, description.prg_bank_at(0xC000).unwrap().bank);
+    assert_eq!(7, description.prg_bank_at(0xFFFF).unwrap().bank);
+    assert_eq!(8, description.chr.len());
+    assert_eq!(1, description.chr_bank_at(0x0400).unwrap().bank);
+    assert_eq!(Some(MirrorType::Horizontal), description.mirroring);
+    assert_eq!(
+        Some(IrqState {
+            counter: 0,
+            latch: 32,
+            enabled: true,
+            pending: false,
+        }),
+        description.irq
+    );
+    assert_eq!(Some(6), description.register("bank_select"));
+    assert_eq!(Some(3), description.register("r6"));
+    assert_eq!(Some(1), description.register("mirroring"));
+
+    let text = description.to_string();
+    assert!(text.contains("    $8000-$9FFF bank   3 ($06000)\n"));
+    assert!(text.contains("irq counter 0 latch 32 enabled"));
+    assert!(text.contains("registers bank_select $06, r0 $00"));
+}
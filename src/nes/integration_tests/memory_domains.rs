@@ -0,0 +1,101 @@
+use super::fixtures::nestest_menu;
+use crate::nes::memory_domains::MemoryDomain;
+
+#[test]
+fn test_peeking_registers_has_no_side_effects() {
+    let mut nes = nestest_menu();
+    while nes.peek_cpu(0x2002) & 0x80 == 0 {
+        nes.clock();
+    }
+    // still in vblank after peeking
+    assert_eq!(0x80, nes.peek_cpu(0x2002) & 0x80);
+    assert_eq!(0x80, nes.peek(MemoryDomain::CpuBus, 0x2002).unwrap() & 0x80);
+    // but not after reading
+    assert_eq!(0x80, nes.cpu.borrow_mut().read_bus_byte(0x2002) & 0x80);
+    assert_eq!(0, nes.peek_cpu(0x2002) & 0x80);
+}
+
+#[test]
+fn test_cpu_bus() {
+    let nes = nestest_menu();
+    nes.poke(MemoryDomain::WorkRam, 0x10, 0x42).unwrap();
+    assert_eq!(0x42, nes.peek_cpu(0x0810));
+
+    // 16K of PRG ROM is mapped twice
+    assert_eq!(0x4000, nes.memory_size(MemoryDomain::PrgRom));
+    assert_eq!(0x4C, nes.peek(MemoryDomain::PrgRom, 0).unwrap());
+    nes.poke_cpu(0xC000, 0xEA);
+    assert_eq!(0xEA, nes.peek(MemoryDomain::PrgRom, 0).unwrap());
+    assert_eq!(0xEA, nes.peek_cpu(0x8000));
+
+    nes.poke_cpu(0x6000, 0x12);
+    assert_eq!(0x12, nes.peek(MemoryDomain::PrgRam, 0).unwrap());
+}
+
+#[test]
+fn test_ppu_memory() {
+    let nes = nestest_menu();
+    assert_eq!(0x2000, nes.memory_size(MemoryDomain::Chr));
+    assert_eq!(
+        nes.peek(MemoryDomain::Chr, 0x1234).unwrap(),
+        nes.peek(MemoryDomain::PpuBus, 0x1234).unwrap()
+    );
+
+    // nestest's nametables are mirrored horizontally
+    nes.poke(MemoryDomain::PpuBus, 0x2005, 0x33).unwrap();
+    assert_eq!(0x33, nes.peek(MemoryDomain::Ciram, 0x05).unwrap());
+    assert_eq!(0x33, nes.peek(MemoryDomain::PpuBus, 0x2405).unwrap());
+
+    // 3F10 mirrors 3F00
+    nes.poke(MemoryDomain::PaletteRam, 0x10, 0x0F).unwrap();
+    assert_eq!(0x0F, nes.peek(MemoryDomain::PaletteRam, 0x00).unwrap());
+    assert_eq!(0x0F, nes.peek(MemoryDomain::PpuBus, 0x3F20).unwrap());
+
+    assert_eq!(0x100, nes.memory_size(MemoryDomain::Oam));
+    assert_eq!(0x80, nes.memory_size(MemoryDomain::SecondaryOam));
+    nes.poke(MemoryDomain::Oam, 0xFF, 0x77).unwrap();
+    assert_eq!(0x77, nes.peek(MemoryDomain::Oam, 0xFF).unwrap());
+}
+
+#[test]
+fn test_dump_and_import() {
+    let nes = nestest_menu();
+    for domain in MemoryDomain::ALL {
+        let dump = nes.dump(domain);
+        assert_eq!(nes.memory_size(domain), dump.len(), "{domain}");
+        assert_eq!(Some(domain), MemoryDomain::from_name(domain.name()));
+    }
+
+    let ram = (0..0x800).map(|i| i as u8).collect::<Vec<_>>();
+    nes.import(MemoryDomain::WorkRam, 0, &ram).unwrap();
+    assert_eq!(ram, nes.dump(MemoryDomain::WorkRam));
+    assert_eq!(0xFF, nes.peek_cpu(0x1FFF));
+
+    assert!(nes.import(MemoryDomain::WorkRam, 1, &ram).is_err());
+    assert!(nes.peek(MemoryDomain::Oam, 0x100).is_err());
+    assert!(nes.poke(MemoryDomain::PaletteRam, 0x20, 0).is_err());
+}
+
+#[test]
+fn test_assemble_into() {
+    let nes = nestest_menu();
+    let len = nes
+        .assemble_into(
+            MemoryDomain::PrgRom,
+            0x100,
+            0xC100,
+            "lda #$42\nloop: jmp loop",
+        )
+        .unwrap();
+
+    assert_eq!(5, len);
+    let bytes = (0xC100..0xC105).map(|addr| nes.peek_cpu(addr));
+    assert_eq!(
+        vec![0xA9, 0x42, 0x4C, 0x02, 0xC1],
+        bytes.collect::<Vec<_>>()
+    );
+    assert!(
+        nes.assemble_into(MemoryDomain::Oam, 0xFF, 0, "jmp $C000")
+            .is_err()
+    );
+}
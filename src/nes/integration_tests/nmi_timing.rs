@@ -0,0 +1,105 @@
+use super::fixtures::nes_with_program;
+use crate::nes::NES;
+use crate::nes::memory_domains::MemoryDomain;
+
+const NMI_HANDLER: u16 = 0xC003;
+const IDLE: u16 = 0xC004;
+const ENABLE_NMI: u16 = 0xC011;
+// vblank starts on dot 1 of scanline 241
+const VBLANK_SCANLINE: i16 = 241;
+const VBLANK_DOT: u16 = 1;
+const DOTS_PER_CYCLE: u16 = 3;
+// after the NMI is raised the CPU finishes the 3 cycle JMP it's polled in, then takes 7 cycles
+const MIN_NMI_CYCLES: u16 = 1 + 7;
+const MAX_NMI_CYCLES: u16 = 1 + 3 + 7;
+
+// waits out the PPU's warm up, then enables NMI and spins
+const PROGRAM: &str = "
+        jmp start
+    nmi:
+        rti
+    idle:
+        jmp idle
+    start:
+        bit $2002
+        bpl start
+    wait:
+        bit $2002
+        bpl wait
+    enable_nmi:
+        lda #$80
+        sta $2000
+    loop:
+        jmp loop
+";
+
+fn create_nes() -> NES {
+    let nes = nes_with_program(PROGRAM);
+    nes.assemble_into(
+        MemoryDomain::PrgRom,
+        0x3FFA,
+        0xFFFA,
+        &format!(".word ${NMI_HANDLER:04X}"),
+    )
+    .unwrap();
+    nes
+}
+
+// the PPU's scanline and dot when the CPU starts on the NMI handler
+fn next_nmi(nes: &mut NES, max_frames: usize) -> Option<(i16, u16)> {
+    let mut frames = 0;
+    while nes.cpu.borrow().pc == NMI_HANDLER {
+        nes.clock();
+    }
+    while nes.cpu.borrow().pc != NMI_HANDLER {
+        if nes.clock().0 {
+            frames += 1;
+            if frames == max_frames {
+                return None;
+            }
+        }
+    }
+    let ppu = nes.ppu.borrow();
+    Some((ppu.scan_line(), ppu.dot()))
+}
+
+fn run_until_scanline(nes: &mut NES, scan_line: i16) {
+    while nes.ppu.borrow().scan_line() != scan_line {
+        nes.clock();
+    }
+}
+
+#[test]
+fn test_nmi_at_start_of_vblank() {
+    let mut nes = create_nes();
+    let earliest = VBLANK_DOT + MIN_NMI_CYCLES * DOTS_PER_CYCLE;
+    // up to a cycle later, depending on how the CPU and PPU clocks line up
+    let latest = VBLANK_DOT + (MAX_NMI_CYCLES + 1) * DOTS_PER_CYCLE;
+
+    for _ in 0..6 {
+        let (scan_line, dot) = next_nmi(&mut nes, 3).unwrap();
+        assert_eq!(VBLANK_SCANLINE, scan_line);
+        assert!((earliest..=latest).contains(&dot), "NMI at dot {dot}");
+    }
+}
+
+#[test]
+fn test_no_nmi_when_disabled() {
+    let mut nes = create_nes();
+    nes.cpu.borrow_mut().reset_to(IDLE);
+
+    assert_eq!(None, next_nmi(&mut nes, 3));
+}
+
+#[test]
+fn test_nmi_enabled_during_vblank() {
+    let mut nes = create_nes();
+    nes.cpu.borrow_mut().reset_to(IDLE);
+    // past the PPU's warm up, and into vblank without reading the status
+    while !nes.clock().0 {}
+    run_until_scanline(&mut nes, 250);
+    nes.cpu.borrow_mut().reset_to(ENABLE_NMI);
+
+    // the vblank flag's still set, so enabling NMI raises it straight away
+    assert_eq!(250, next_nmi(&mut nes, 1).unwrap().0);
+}
@@ -0,0 +1,87 @@
+use super::fixtures::{SharedBuffer, nes_with_program};
+use crate::nes::NES;
+use crate::nes::ppu_events::{PpuEventKind, PpuEventLogger, overlay_image};
+
+const PROGRAM: &str = "
+    start:
+        lda #$1E
+        sta $2001
+        lda #0
+        sta $2005
+        sta $0300
+        sta $8000
+        sta $4014
+        jmp start
+";
+
+fn run_frames(nes: &mut NES, frames: usize) {
+    for _ in 0..frames {
+        while !nes.clock().0 {}
+    }
+}
+
+#[test]
+fn test_records_writes_by_frame() {
+    let mut nes = nes_with_program(PROGRAM);
+    let events = PpuEventLogger::attach(&mut nes, None);
+    run_frames(&mut nes, 3);
+
+    let events = events.borrow();
+    assert!(events.frame() >= 3);
+    let last_frame = events.last_frame();
+    assert!(!last_frame.is_empty());
+    // RAM writes aren't events
+    assert!(last_frame.iter().all(|e| e.addr != 0x0300));
+    let kinds = [
+        PpuEventKind::Mask,
+        PpuEventKind::Scroll,
+        PpuEventKind::Mapper,
+        PpuEventKind::OamDma,
+    ];
+    for (event, kind) in last_frame.iter().zip(kinds.iter().cycle()) {
+        assert_eq!(*kind, event.kind);
+    }
+    let mask = last_frame
+        .iter()
+        .find(|e| e.kind == PpuEventKind::Mask)
+        .unwrap();
+    assert_eq!((0xC002, 0x2001, 0x1E), (mask.pc, mask.addr, mask.data));
+
+    // the frame starts on the pre-render line and runs in order
+    assert!(last_frame.iter().all(|e| (-1..=260).contains(&e.scan_line)));
+    assert!(last_frame.is_sorted_by_key(|e| (e.scan_line, e.dot)));
+    // the loop takes about 5 scanlines, most of it OAM DMA
+    assert!(last_frame.first().unwrap().scan_line < 5);
+    assert!(last_frame.last().unwrap().scan_line > 255);
+}
+
+#[test]
+fn test_writes_events() {
+    let mut nes = nes_with_program(PROGRAM);
+    let buffer = SharedBuffer::default();
+    let events = PpuEventLogger::attach(&mut nes, Some(Box::new(buffer.clone())));
+    run_frames(&mut nes, 2);
+    events.borrow_mut().finish().unwrap();
+    PpuEventLogger::detach(&mut nes);
+
+    let text = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+    let mut lines = text.lines();
+    assert_eq!(Some("frame 1"), lines.next());
+    let first = lines.next().unwrap();
+    assert!(first.ends_with("$C002 $2001 = $1E PPUMASK"), "{first}");
+    assert!(text.contains("\nframe 2\n"));
+}
+
+#[test]
+fn test_overlay_image() {
+    let mut nes = nes_with_program(PROGRAM);
+    let events = PpuEventLogger::attach(&mut nes, None);
+    run_frames(&mut nes, 2);
+
+    let events = events.borrow();
+    let image = overlay_image(events.last_frame(), None);
+    assert_eq!((341, 262), (image.width, image.height));
+    let event = events.last_frame()[0];
+    let (x, y) = (event.dot as usize, (event.scan_line + 1) as usize);
+    assert_eq!(event.kind.color(), image.pixel(x, y));
+}
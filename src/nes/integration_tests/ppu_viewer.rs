@@ -0,0 +1,94 @@
+use super::fixtures::nestest_menu;
+use crate::nes::NES;
+use crate::nes::memory_domains::MemoryDomain;
+
+const BACKDROP: u32 = 0x050505;
+const RED: u32 = 0xFF2200;
+const WHITE: u32 = 0xFFFFFF;
+
+// a blank tile 0 and a tile 1 with a solid top row in color 1, and palettes
+// with a backdrop of $0F and color 1 of $16
+fn create_nes() -> NES {
+    let nes = nestest_menu();
+    nes.import(MemoryDomain::Chr, 0, &[0; 32]).unwrap();
+    nes.poke(MemoryDomain::Chr, 0x10, 0xFF).unwrap();
+    nes.poke(MemoryDomain::PaletteRam, 0x00, 0x0F).unwrap();
+    for palette in 0..8 {
+        nes.poke(MemoryDomain::PaletteRam, palette * 4 + 1, 0x16)
+            .unwrap();
+    }
+    nes
+}
+
+#[test]
+fn test_palettes() {
+    let nes = create_nes();
+    nes.poke(MemoryDomain::PaletteRam, 0x1F, 0x30).unwrap();
+
+    let image = nes.palette_image();
+    assert_eq!((256, 32), (image.width, image.height));
+    assert_eq!(BACKDROP, image.pixel(0, 0));
+    assert_eq!(RED, image.pixel(16, 15));
+    assert_eq!(WHITE, image.pixel(255, 31));
+}
+
+#[test]
+fn test_pattern_tables() {
+    let nes = create_nes();
+    let image = nes.pattern_table_image(0, 0);
+
+    assert_eq!((128, 128), (image.width, image.height));
+    assert_eq!(BACKDROP, image.pixel(0, 0));
+    assert_eq!(RED, image.pixel(8, 0));
+    assert_eq!(RED, image.pixel(15, 0));
+    assert_eq!(BACKDROP, image.pixel(8, 1));
+}
+
+#[test]
+fn test_nametables() {
+    let nes = create_nes();
+    for addr in 0x2000..0x3000 {
+        nes.poke(MemoryDomain::PpuBus, addr, 0).unwrap();
+    }
+    // tile 1 at the second row and column
+    nes.poke(MemoryDomain::PpuBus, 0x2021, 1).unwrap();
+
+    let image = nes.nametables_image();
+    assert_eq!((512, 480), (image.width, image.height));
+    assert_eq!(RED, image.pixel(8, 8));
+    assert_eq!(BACKDROP, image.pixel(8, 9));
+    // the scroll window is outlined at the top left
+    assert_eq!(WHITE, image.pixel(0, 100));
+    assert_eq!(WHITE, image.pixel(255, 239));
+    assert_eq!(BACKDROP, image.pixel(256, 100));
+}
+
+#[test]
+fn test_sprites() {
+    let nes = create_nes();
+    nes.import(MemoryDomain::Oam, 4, &[0x20, 0x01, 0b10100010, 0x30])
+        .unwrap();
+
+    let sprites = nes.sprites();
+    assert_eq!(64, sprites.len());
+    let sprite = sprites[1];
+    assert_eq!(
+        (0x30, 0x20, 0x01, 2),
+        (sprite.x, sprite.y, sprite.tile, sprite.palette)
+    );
+    assert!(sprite.behind_background && sprite.flip_vertical && !sprite.flip_horizontal);
+    assert_eq!(
+        "#01 x  48 y  32 tile $01 palette 2 behind flip-v",
+        sprite.to_string()
+    );
+
+    // flipped upside down, so the solid row is at the bottom
+    let image = nes.sprite_image(&sprite);
+    assert_eq!((8, 8), (image.width, image.height));
+    assert_eq!(BACKDROP, image.pixel(0, 0));
+    assert_eq!(RED, image.pixel(0, 7));
+
+    let image = nes.sprites_image();
+    assert_eq!((64, 128), (image.width, image.height));
+    assert_eq!(RED, image.pixel(8, 7));
+}
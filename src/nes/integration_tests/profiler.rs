@@ -0,0 +1,140 @@
+use super::fixtures::{automated_nestest, nestest_menu};
+use crate::nes::NES;
+use crate::nes::profiler::{Profile, Profiler, RoutineKind};
+use crate::nes::symbols::Labels;
+
+const FRAMES: usize = 10;
+
+// nestest's menu waits for input in a loop, with NMIs enabled
+fn profile_nestest_menu() -> Profile {
+    let mut nes = nestest_menu();
+    let profile = Profiler::attach(&mut nes);
+    let mut frames = 0;
+    while frames < FRAMES {
+        let (end_of_frame, _, _) = nes.clock();
+        if end_of_frame {
+            frames += 1;
+        }
+    }
+    Profiler::detach(&mut nes);
+    profile.borrow().clone()
+}
+
+#[test]
+fn test_cycles_add_up() {
+    let profile = profile_nestest_menu();
+    let total = profile.cycles();
+    // a frame is about 29780 cycles
+    assert!(total > 29000 * (FRAMES - 1));
+
+    let exclusive = profile
+        .routines()
+        .values()
+        .map(|r| r.exclusive)
+        .sum::<usize>();
+    assert_eq!(total, exclusive);
+    let instructions = profile
+        .instructions()
+        .values()
+        .map(|i| i.cycles)
+        .sum::<usize>();
+    assert_eq!(total, instructions);
+    assert_eq!(total, profile.stacks().values().sum::<usize>());
+    assert_eq!(
+        total,
+        profile.frames().iter().map(|f| f.cycles).sum::<usize>()
+    );
+
+    let (main, stats) = profile
+        .routines()
+        .iter()
+        .find(|(r, _)| r.kind == RoutineKind::Main)
+        .unwrap();
+    assert_eq!(0xC004, main.entry.addr);
+    assert_eq!(Some(0x0004), main.entry.prg_rom_offset);
+    assert_eq!(total, stats.inclusive);
+    for stats in profile.routines().values() {
+        assert!(stats.exclusive <= stats.inclusive);
+    }
+}
+
+#[test]
+fn test_nmi_handlers() {
+    let profile = profile_nestest_menu();
+    let (nmi, stats) = profile
+        .routines()
+        .iter()
+        .find(|(r, _)| r.kind == RoutineKind::NMI)
+        .unwrap();
+    assert_eq!(0xC5AF, nmi.entry.addr);
+    // one per frame once nestest has turned them on
+    assert!((FRAMES - 3..=FRAMES + 1).contains(&stats.calls));
+
+    let frames = profile.frames();
+    assert!((FRAMES..=FRAMES + 1).contains(&frames.len()));
+    assert_eq!(1, frames[0].frame);
+    let nmi_cycles = frames.iter().map(|f| f.nmi_cycles).sum::<usize>();
+    assert_eq!(stats.inclusive, nmi_cycles);
+    // every stack with the NMI handler in it starts at the bottom
+    for stack in profile.stacks().keys() {
+        assert_eq!(RoutineKind::Main, stack[0].kind);
+        assert!(stack[1..].iter().all(|r| r.kind != RoutineKind::Main));
+    }
+}
+
+#[test]
+fn test_subroutines_return() {
+    let mut nes = automated_nestest();
+    let profile = Profiler::attach(&mut nes);
+    while nes.cpu.borrow().cycles < 26560 {
+        nes.clock();
+    }
+    let profile = profile.borrow();
+
+    // nestest runs each test as a subroutine called from one place, which all return
+    let subroutines = profile
+        .routines()
+        .iter()
+        .filter(|(r, _)| r.kind == RoutineKind::Subroutine)
+        .collect::<Vec<_>>();
+    assert!(subroutines.len() > 10);
+    let deepest = profile.stacks().keys().map(|s| s.len()).max().unwrap();
+    assert!(deepest <= 4, "{deepest}");
+}
+
+#[test]
+fn test_folded_stacks() {
+    let profile = profile_nestest_menu();
+    let nes = NES::new();
+    let labels = Labels::new(&nes);
+    labels
+        .symbols()
+        .borrow_mut()
+        .parse_mlb("P:0004:reset\nP:05AF:nmi\n")
+        .unwrap();
+
+    let mut output = Vec::new();
+    profile.write_folded(&mut output, Some(&labels)).unwrap();
+    let text = String::from_utf8(output).unwrap();
+    let mut total = 0;
+    for line in text.lines() {
+        let (stack, cycles) = line.rsplit_once(' ').unwrap();
+        assert!(stack.starts_with("RESET:reset"));
+        assert!(!stack.contains(' '));
+        total += cycles.parse::<usize>().unwrap();
+    }
+    assert_eq!(profile.cycles(), total);
+    assert!(text.contains(";NMI:nmi "));
+
+    let mut report = Vec::new();
+    profile.write_report(&mut report, None, 20).unwrap();
+    let report = String::from_utf8(report).unwrap();
+    assert!(report.contains("NMI:$C5AF"));
+    let routines = profile.routines().len();
+    assert!(routines < 20);
+    // totals, routines, instructions and frames, each with a header
+    assert_eq!(
+        4 + routines + 2 + 20 + 2 + profile.frames().len(),
+        report.lines().count()
+    );
+}
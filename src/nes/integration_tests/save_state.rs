@@ -0,0 +1,66 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::fixtures::{load_nestest, nestest_menu};
+use crate::nes::NES;
+use crate::nes::controllers::{JoyPad, JoyPadButton};
+
+fn run_frames(nes: &mut NES, frames: usize) -> Vec<(u16, u16, u8, u8, u8)> {
+    let mut pixels = Vec::new();
+    let mut frame = 0;
+    while frame < frames {
+        let (end_of_frame, pixel_info, _) = nes.clock();
+        if let Some(p) = pixel_info {
+            pixels.push((p.x, p.y, p.r, p.g, p.b));
+        }
+        if end_of_frame {
+            frame += 1;
+        }
+    }
+    pixels
+}
+
+#[test]
+fn test_save_and_load_state_round_trip() {
+    let mut nes = load_nestest();
+    let joypad = Rc::new(RefCell::new(JoyPad::new()));
+    nes.plugin_controller1(joypad.clone());
+    nes.reset();
+
+    run_frames(&mut nes, 20);
+    // start the tests so that the machine isn't just sitting idle
+    joypad.borrow_mut().set_buttons(0 | JoyPadButton::Start);
+    run_frames(&mut nes, 2);
+    joypad.borrow_mut().set_buttons(0);
+    // land somewhere in the middle of a frame and an instruction
+    for _ in 0..12345 {
+        nes.clock();
+    }
+
+    let state = nes.save_state();
+    let expected_pixels = run_frames(&mut nes, 30);
+    let expected_state = nes.save_state();
+
+    nes.load_state(&state).unwrap();
+    let actual_pixels = run_frames(&mut nes, 30);
+    let actual_state = nes.save_state();
+
+    assert_eq!(expected_pixels, actual_pixels);
+    assert_eq!(expected_state, actual_state);
+}
+
+#[test]
+fn test_load_state_rejects_bad_data() {
+    let mut nes = nestest_menu();
+
+    assert!(nes.load_state(b"not a save state").is_err());
+
+    let state = nes.save_state();
+    assert!(nes.load_state(&state[0..state.len() / 2]).is_err());
+
+    let mut wrong_version = state.clone();
+    wrong_version[4] = wrong_version[4].wrapping_add(1);
+    assert!(nes.load_state(&wrong_version).is_err());
+
+    let mut other_nes = NES::new();
+    assert!(other_nes.load_state(&state).is_err());
+}
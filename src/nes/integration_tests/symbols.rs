@@ -0,0 +1,60 @@
+use super::fixtures::{SharedBuffer, automated_nestest};
+use crate::nes::NES;
+use crate::nes::debugger::{Debugger, RunMode, StopReason};
+use crate::nes::symbols::Labels;
+use crate::nes::trace_logger::{TraceLogger, TraceOptions};
+
+// nestest's PRG ROM is 16k, mirrored at 0x8000 and 0xC000
+const LABELS: &str = "P:0000:start\nP:05F5:main\nR:0010:temp\n";
+
+fn create_labelled_nes() -> (NES, Labels) {
+    let nes = automated_nestest();
+    let labels = Labels::new(&nes);
+    labels.symbols().borrow_mut().parse_mlb(LABELS).unwrap();
+    (nes, labels)
+}
+
+#[test]
+fn test_labels_follow_the_mapper() {
+    let (_nes, labels) = create_labelled_nes();
+    assert_eq!(Some("main".to_string()), labels.label(0xC5F5));
+    assert_eq!(Some("main".to_string()), labels.label(0x85F5));
+    assert_eq!(Some("main+2".to_string()), labels.label(0xC5F7));
+    assert_eq!(Some("temp".to_string()), labels.label(0x0010));
+    assert_eq!(None, labels.label(0x0011));
+    assert_eq!(Some(0xC5F5), labels.resolve("main"));
+    assert_eq!(Some(0x0010), labels.resolve("temp"));
+    assert_eq!(None, labels.resolve("nowhere"));
+}
+
+#[test]
+fn test_labelled_trace() {
+    let (mut nes, labels) = create_labelled_nes();
+    let output = SharedBuffer::default();
+    let logger =
+        TraceLogger::attach(&mut nes, TraceOptions::default(), Box::new(output.clone())).unwrap();
+    logger.borrow_mut().set_labels(labels);
+    while nes.cpu.borrow().cycles < 10 {
+        nes.clock();
+    }
+    logger.borrow_mut().finish().unwrap();
+
+    let text = String::from_utf8(output.0.take()).unwrap();
+    assert!(text.starts_with("C000  4C F5 C5  JMP main "));
+}
+
+#[test]
+fn test_debugger_labels() {
+    let (mut nes, labels) = create_labelled_nes();
+    let mut debugger = Debugger::attach(&mut nes);
+    debugger.set_labels(labels.clone());
+    let id = debugger.add_breakpoint(labels.resolve("main").unwrap() + 2, None);
+
+    assert_eq!(
+        StopReason::Breakpoint(id),
+        debugger.run(&mut nes, RunMode::Continue)
+    );
+    let pc = debugger.registers(&nes).pc;
+    assert_eq!(Some("main+2".to_string()), debugger.label(pc));
+    assert_eq!("jmp main", debugger.disassemble(&nes, 0xC000, 1)[0].text);
+}
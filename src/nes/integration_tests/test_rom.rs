@@ -0,0 +1,344 @@
+use std::path::Path;
+
+use anyhow::Result;
+use thiserror::Error;
+
+use crate::cpu::assembler::assemble;
+use crate::nes::NES;
+
+const STATUS_ADDR: u16 = 0x6000;
+const SIGNATURE_ADDR: u16 = 0x6001;
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const TEXT_ADDR: u16 = 0x6004;
+const MAX_TEXT_LEN: u16 = 0x1FFB;
+
+// older ROMs only report a result code here, and on screen
+const LEGACY_RESULT_ADDR: u16 = 0x00F8;
+const LEGACY_PASSED: u8 = 1;
+
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_NEEDS_RESET: u8 = 0x81;
+// the ROM asks for the reset to come at least 100ms later
+const RESET_DELAY_FRAMES: usize = 10;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct TestRomResult {
+    /** 0 means the test passed, anything else is the ROM's failure code */
+    pub status: u8,
+    pub text: String,
+}
+
+impl TestRomResult {
+    pub fn passed(&self) -> bool {
+        self.status == 0
+    }
+}
+
+/**
+ * Runs a test ROM that reports using blargg's protocol: once bytes
+ * 0x6001-0x6003 hold the signature, 0x6000 holds the status (0x80 while
+ * running, 0x81 when a reset is needed, otherwise the final result code)
+ * and 0x6004 onwards holds a zero terminated message.
+ */
+pub fn run_test_rom(rom: &Path, max_frames: usize) -> Result<TestRomResult> {
+    let mut nes = NES::new();
+    nes.load_cartridge(rom.to_string_lossy().to_string())?;
+    nes.reset();
+
+    let mut frames = 0;
+    let mut reset_countdown = None;
+    while frames < max_frames {
+        let (end_of_frame, _, _) = nes.clock();
+        if !end_of_frame {
+            continue;
+        }
+        frames += 1;
+
+        if let Some(countdown) = reset_countdown {
+            if countdown == 0 {
+                nes.reset();
+                reset_countdown = None;
+            } else {
+                reset_countdown = Some(countdown - 1);
+            }
+            continue;
+        }
+
+        if !has_signature(&nes) {
+            continue;
+        }
+        match nes.peek_cpu(STATUS_ADDR) {
+            STATUS_RUNNING => (),
+            STATUS_NEEDS_RESET => reset_countdown = Some(RESET_DELAY_FRAMES),
+            status => {
+                return Ok(TestRomResult {
+                    status,
+                    text: read_text(&nes),
+                });
+            }
+        }
+    }
+
+    let text = if has_signature(&nes) {
+        read_text(&nes)
+    } else {
+        String::new()
+    };
+    Err(TestRomError::TimedOut(max_frames, text))?
+}
+
+/**
+ * Runs a test ROM from before the 0x6000 protocol, which stores its result
+ * code at 0xF8 once it's done: 1 if it passed, otherwise the failure code.
+ */
+pub fn run_legacy_test_rom(rom: &Path, max_frames: usize) -> Result<TestRomResult> {
+    let mut nes = NES::new();
+    nes.load_cartridge(rom.to_string_lossy().to_string())?;
+    nes.reset();
+
+    let mut frames = 0;
+    while frames < max_frames {
+        let (end_of_frame, _, _) = nes.clock();
+        if !end_of_frame {
+            continue;
+        }
+        frames += 1;
+
+        match nes.peek_cpu(LEGACY_RESULT_ADDR) {
+            0 => (),
+            LEGACY_PASSED => {
+                return Ok(TestRomResult {
+                    status: 0,
+                    text: String::new(),
+                });
+            }
+            status => {
+                return Ok(TestRomResult {
+                    status,
+                    text: String::new(),
+                });
+            }
+        }
+    }
+    Err(TestRomError::TimedOut(max_frames, String::new()))?
+}
+
+fn has_signature(nes: &NES) -> bool {
+    SIGNATURE
+        .iter()
+        .enumerate()
+        .all(|(i, byte)| nes.peek_cpu(SIGNATURE_ADDR + i as u16) == *byte)
+}
+
+fn read_text(nes: &NES) -> String {
+    let bytes = (0..MAX_TEXT_LEN)
+        .map(|i| nes.peek_cpu(TEXT_ADDR + i))
+        .take_while(|b| *b != 0)
+        .collect::<Vec<_>>();
+    String::from_utf8_lossy(&bytes).trim().to_string()
+}
+
+#[derive(Error, Debug)]
+pub enum TestRomError {
+    #[error("The test ROM didn't finish within {0} frames. Output so far: '{1}'")]
+    TimedOut(usize, String),
+}
+
+/**
+ * Runs a ROM and fails with its own message unless it passes
+ */
+pub fn assert_test_rom_passes(rom: &str, max_frames: usize) {
+    let result = run_test_rom(Path::new(rom), max_frames).unwrap();
+    assert!(
+        result.passed(),
+        "{} failed with status {}:\n{}",
+        rom,
+        result.status,
+        result.text
+    );
+}
+
+/**
+ * Runs a legacy ROM and fails with its result code unless it passes
+ */
+pub fn assert_legacy_test_rom_passes(rom: &str, max_frames: usize) {
+    let result = run_legacy_test_rom(Path::new(rom), max_frames).unwrap();
+    assert!(
+        result.passed(),
+        "{} failed with result code {}",
+        rom,
+        result.status
+    );
+}
+
+/**
+ * Builds an NROM cartridge running the given program from 0xC001, with the
+ * text at 0xF000 and an RTI for interrupts at 0xC000
+ */
+fn nrom(program: &str, text: &str) -> Vec<u8> {
+    const PRG_SIZE: usize = 0x4000;
+    const CHR_SIZE: usize = 0x2000;
+    const TEXT_OFFSET: usize = 0x3000;
+    let code = assemble(
+        &format!(
+            "
+            text = $C000 + {TEXT_OFFSET}
+                rti
+            {program}
+            "
+        ),
+        0xC000,
+    )
+    .unwrap();
+
+    let mut prg = vec![0xEA; PRG_SIZE];
+    prg[..code.len()].copy_from_slice(&code);
+    prg[TEXT_OFFSET..TEXT_OFFSET + text.len()].copy_from_slice(text.as_bytes());
+    prg[TEXT_OFFSET + text.len()] = 0;
+    // NMI, reset and IRQ vectors
+    prg[PRG_SIZE - 6..].copy_from_slice(&[0x00, 0xC0, 0x01, 0xC0, 0x00, 0xC0]);
+
+    let mut rom = vec![b'N', b'E', b'S', 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    rom.extend(prg);
+    rom.extend(vec![0; CHR_SIZE]);
+    rom
+}
+
+/**
+ * A ROM that writes the text, then the signature, then the status that the
+ * given code leaves in A
+ */
+fn protocol_rom(load_status: &str, text: &str) -> Vec<u8> {
+    nrom(
+        &format!(
+            "
+                ldx #0
+            loop:
+                lda text,x
+                sta $6004,x
+                beq signature
+                inx
+                jmp loop
+            signature:
+                lda #$DE
+                sta $6001
+                lda #$B0
+                sta $6002
+                lda #$61
+                sta $6003
+            {load_status}
+                sta $6000
+            forever:
+                jmp forever
+            "
+        ),
+        text,
+    )
+}
+
+fn with_rom_file<T>(name: &str, rom: Vec<u8>, run: impl FnOnce(&Path) -> T) -> T {
+    let path = std::env::temp_dir().join(format!("nes-rs-{}-{}.nes", name, std::process::id()));
+    std::fs::write(&path, rom).unwrap();
+    let result = run(&path);
+    std::fs::remove_file(&path).unwrap();
+    result
+}
+
+fn run_protocol_rom(name: &str, status: u8, text: &str) -> TestRomResult {
+    let rom = protocol_rom(&format!("lda #{status}"), text);
+    with_rom_file(name, rom, |path| run_test_rom(path, 10)).unwrap()
+}
+
+#[test]
+fn test_passing_rom() {
+    let result = run_protocol_rom("pass", 0, "\nAll tests passed\n");
+
+    assert!(result.passed());
+    assert_eq!("All tests passed", result.text);
+}
+
+#[test]
+fn test_failing_rom() {
+    let result = run_protocol_rom("fail", 3, "Timing wrong\nFailed #3");
+
+    assert!(!result.passed());
+    assert_eq!(3, result.status);
+    assert_eq!("Timing wrong\nFailed #3", result.text);
+}
+
+#[test]
+fn test_rom_needing_reset() {
+    // asks for a reset the first time, using PRG RAM to remember it did
+    let rom = protocol_rom(
+        "
+            lda $6100
+            cmp #$42
+            beq after_reset
+            lda #$42
+            sta $6100
+            lda #$81
+            bne report
+        after_reset:
+            lda #0
+        report:
+        ",
+        "Passed after reset",
+    );
+    let result = with_rom_file("reset", rom, |path| run_test_rom(path, 30)).unwrap();
+
+    assert!(result.passed());
+    assert_eq!("Passed after reset", result.text);
+}
+
+#[test]
+fn test_rom_timing_out() {
+    let rom = protocol_rom("lda #$80", "Still running");
+    let error = with_rom_file("timeout", rom, |path| run_test_rom(path, 10)).unwrap_err();
+
+    assert_eq!(
+        "The test ROM didn't finish within 10 frames. Output so far: 'Still running'",
+        error.to_string()
+    );
+}
+
+fn legacy_rom(result: u8) -> Vec<u8> {
+    // reports after a few frames of waiting for vblank
+    nrom(
+        &format!(
+            "
+                ldy #3
+            wait:
+                bit $2002
+                bpl wait
+                dey
+                bne wait
+                lda #{result}
+                sta $F8
+            forever:
+                jmp forever
+            "
+        ),
+        "",
+    )
+}
+
+#[test]
+fn test_passing_legacy_rom() {
+    let result = with_rom_file("legacy-pass", legacy_rom(1), |path| {
+        run_legacy_test_rom(path, 10)
+    })
+    .unwrap();
+
+    assert!(result.passed());
+}
+
+#[test]
+fn test_failing_legacy_rom() {
+    let result = with_rom_file("legacy-fail", legacy_rom(4), |path| {
+        run_legacy_test_rom(path, 10)
+    })
+    .unwrap();
+
+    assert!(!result.passed());
+    assert_eq!(4, result.status);
+}
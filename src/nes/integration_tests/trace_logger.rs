@@ -0,0 +1,220 @@
+use std::fs::File;
+use std::io::{BufReader, Cursor};
+
+use super::fixtures::{SharedBuffer, automated_nestest, nestest_menu};
+use crate::nes::debugger::{Debugger, RunMode, StopReason};
+use crate::nes::trace_logger::{Comparison, TraceFormat, TraceLogger, TraceOptions};
+
+const NESTEST_CYCLES: usize = 26560;
+
+fn trace(options: TraceOptions, cycles: usize) -> Vec<String> {
+    let mut nes = automated_nestest();
+    let output = SharedBuffer::default();
+    let logger = TraceLogger::attach(&mut nes, options, Box::new(output.clone())).unwrap();
+    while nes.cpu.borrow().cycles < cycles {
+        nes.clock();
+    }
+    logger.borrow_mut().finish().unwrap();
+    let text = String::from_utf8(output.0.take()).unwrap();
+    text.lines().map(|l| l.to_string()).collect()
+}
+
+fn compare(reference: String, ignored: &[&str]) -> Comparison {
+    let mut nes = automated_nestest();
+    let logger =
+        TraceLogger::attach(&mut nes, TraceOptions::default(), Box::new(std::io::sink())).unwrap();
+    logger
+        .borrow_mut()
+        .compare_with(Box::new(Cursor::new(reference)), ignored);
+    while !logger.borrow().is_finished() && nes.cpu.borrow().cycles <= NESTEST_CYCLES {
+        nes.clock();
+    }
+    logger.borrow_mut().finish().unwrap().clone()
+}
+
+#[test]
+fn test_matches_nestest_log() {
+    // this PPU starts a scanline earlier than the one nestest.log came from
+    let reference = std::fs::read_to_string("resources/test/nestest.log").unwrap();
+    let lines = reference.lines().count();
+    assert_eq!(Comparison::Matched(lines), compare(reference, &["PPU"]));
+}
+
+#[test]
+fn test_compare_from_file() {
+    let mut nes = automated_nestest();
+    let logger =
+        TraceLogger::attach(&mut nes, TraceOptions::default(), Box::new(std::io::sink())).unwrap();
+    let file = File::open("resources/test/nestest.log").unwrap();
+    logger
+        .borrow_mut()
+        .compare_with(Box::new(BufReader::new(file)), &["PPU"]);
+    for _ in 0..3000 {
+        nes.clock();
+    }
+    assert_eq!(Comparison::Running, *logger.borrow().comparison());
+}
+
+#[test]
+fn test_stops_at_first_divergence() {
+    let reference = std::fs::read_to_string("resources/test/nestest.log").unwrap();
+    let mut lines = reference.lines().map(|l| l.to_string()).collect::<Vec<_>>();
+    lines[4] = lines[4].replace("A:00", "A:01");
+    lines[9] = lines[9].replace("A:00", "A:01");
+    let expected = lines[4].clone();
+
+    let Comparison::Diverged(divergence) = compare(lines.join("\n"), &["PPU"]) else {
+        panic!("The trace should diverge");
+    };
+    assert_eq!(5, divergence.line_number);
+    assert_eq!(expected, divergence.expected);
+    assert!(divergence.actual.starts_with("C5FB  86 11"));
+}
+
+#[test]
+fn test_short_run_diverges() {
+    let reference = "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7\nC5F5  A2 00     LDX #$00                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 30 CYC:10\n";
+    let mut nes = automated_nestest();
+    let logger =
+        TraceLogger::attach(&mut nes, TraceOptions::default(), Box::new(std::io::sink())).unwrap();
+    logger
+        .borrow_mut()
+        .compare_with(Box::new(Cursor::new(reference)), &["PPU"]);
+    // only the JMP runs
+    for _ in 0..3 * 3 {
+        nes.clock();
+    }
+    let mut logger = logger.borrow_mut();
+    let Comparison::Diverged(divergence) = logger.finish().unwrap() else {
+        panic!("The run stopped before the end of the reference");
+    };
+    assert_eq!(2, divergence.line_number);
+    assert_eq!("", divergence.actual);
+}
+
+#[test]
+fn test_formats() {
+    // reset_to runs the CPU on its own, so the PPU hasn't moved
+    let options = |format| TraceOptions {
+        format,
+        ..TraceOptions::default()
+    };
+    assert_eq!(
+        "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU: -1,  0 CYC:7",
+        trace(options(TraceFormat::Nestest), 10)[0]
+    );
+    assert_eq!(
+        "C000  JMP $C5F5                                A:00 X:00 Y:00 S:FD P:nvUbdIzc V:-1  H:0   Cycle:7",
+        trace(options(TraceFormat::Mesen), 10)[0]
+    );
+    assert_eq!(
+        "f1      c7         A:00 X:00 Y:00 S:FD P:nvUbdIzc  $C000:4C F5 C5  JMP $C5F5",
+        trace(options(TraceFormat::Fceux), 10)[0]
+    );
+
+    let lines = trace(
+        TraceOptions {
+            ppu_columns: false,
+            ..options(TraceFormat::Nestest)
+        },
+        20,
+    );
+    assert_eq!(
+        "C5F7  86 00     STX $00 = 00                    A:00 X:00 Y:00 P:26 SP:FD CYC:12",
+        lines[2]
+    );
+}
+
+#[test]
+fn test_annotations() {
+    let lines = trace(
+        TraceOptions {
+            format: TraceFormat::Fceux,
+            ppu_columns: false,
+            ..TraceOptions::default()
+        },
+        NESTEST_CYCLES,
+    );
+    assert!(lines.iter().any(|l| l.ends_with("STX $00 = #$00")));
+    assert!(
+        lines
+            .iter()
+            .any(|l| l.contains("LDA ($80,X) @ $80 = $0200 = #$5A"))
+    );
+}
+
+#[test]
+fn test_address_filter() {
+    let lines = trace(
+        TraceOptions {
+            addresses: Some((0xC72D, 0xC72F)),
+            ..TraceOptions::default()
+        },
+        200,
+    );
+    let pcs = lines.iter().map(|l| &l[..4]).collect::<Vec<_>>();
+    assert_eq!(vec!["C72D", "C72E", "C72F"], pcs);
+}
+
+#[test]
+fn test_frame_filter() {
+    let mut nes = nestest_menu();
+    let output = SharedBuffer::default();
+    let options = TraceOptions {
+        frames: Some((2, 2)),
+        ..TraceOptions::default()
+    };
+    TraceLogger::attach(&mut nes, options, Box::new(output.clone())).unwrap();
+
+    let mut frames = 0;
+    while frames < 3 {
+        let (end_of_frame, _, _) = nes.clock();
+        if end_of_frame {
+            frames += 1;
+        }
+    }
+
+    let text = String::from_utf8(output.0.take()).unwrap();
+    let scan_lines = text
+        .lines()
+        .map(|l| {
+            let ppu = l.split("PPU:").nth(1).unwrap();
+            ppu.split(',')
+                .next()
+                .unwrap()
+                .trim()
+                .parse::<i16>()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    assert!(!scan_lines.is_empty());
+    // from the pre-render line to the end of the frame
+    assert_eq!(-1, scan_lines[0]);
+    assert!(scan_lines.windows(2).all(|w| w[0] <= w[1]));
+}
+
+#[test]
+fn test_traces_alongside_debugger() {
+    let mut nes = automated_nestest();
+    let output = SharedBuffer::default();
+    let logger =
+        TraceLogger::attach(&mut nes, TraceOptions::default(), Box::new(output.clone())).unwrap();
+    let mut debugger = Debugger::attach(&mut nes);
+    let id = debugger.add_breakpoint(0xC5F7, None);
+
+    assert_eq!(
+        StopReason::Breakpoint(id),
+        debugger.run(&mut nes, RunMode::Continue)
+    );
+    debugger.detach(&mut nes);
+    while nes.cpu.borrow().cycles < 100 {
+        nes.clock();
+    }
+    logger.borrow_mut().finish().unwrap();
+
+    let text = String::from_utf8(output.0.take()).unwrap();
+    let lines = text.lines().collect::<Vec<_>>();
+    assert!(lines[0].starts_with("C000  4C F5 C5  JMP $C5F5"));
+    assert!(lines[2].starts_with("C5F7"));
+    assert!(lines.len() > 10);
+}
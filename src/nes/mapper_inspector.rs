@@ -0,0 +1,178 @@
+// What the cartridge's mapper is doing, for debuggers and logs: which banks are
+// where, how the nametables are mirrored, the IRQ counter and the mapper's own
+// registers. Describing the mapper doesn't change anything.
+
+use std::fmt::Display;
+
+use super::NES;
+
+pub use super::cartridge::MirrorType;
+
+/**
+ * A window of the address space and the bank showing through it
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BankMapping {
+    pub start: u16,
+    pub end: u16,
+    /**
+     * Counted in banks the size of the window
+     */
+    pub bank: usize,
+    /**
+     * Where the bank starts in its memory, e.g. in PRG ROM
+     */
+    pub offset: usize,
+    /**
+     * The bank is in memory the mapper swaps in instead, like TQROM's CHR RAM
+     */
+    pub alternate: bool,
+}
+
+impl Display for BankMapping {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "${:04X}-${:04X} bank {:3} (${:05X})",
+            self.start, self.end, self.bank, self.offset
+        )?;
+        if self.alternate {
+            write!(f, " alternate")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct IrqState {
+    pub counter: u32,
+    /**
+     * What the counter reloads from or is compared with, depending on the mapper
+     */
+    pub latch: u32,
+    pub enabled: bool,
+    /**
+     * The IRQ line is being held
+     */
+    pub pending: bool,
+}
+
+impl Display for IrqState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "counter {} latch {} {}",
+            self.counter,
+            self.latch,
+            if self.enabled { "enabled" } else { "disabled" }
+        )?;
+        if self.pending {
+            write!(f, " pending")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MapperRegister {
+    pub name: String,
+    pub value: u16,
+}
+
+impl MapperRegister {
+    pub fn new(name: impl Into<String>, value: impl Into<u16>) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct MapperDescription {
+    pub mapper_number: u16,
+    pub submapper: u8,
+    /**
+     * $8000-$FFFF
+     */
+    pub prg_rom: Vec<BankMapping>,
+    /**
+     * $6000-$7FFF
+     */
+    pub prg_ram: Vec<BankMapping>,
+    /**
+     * $0000-$1FFF on the PPU, ROM or RAM
+     */
+    pub chr: Vec<BankMapping>,
+    /**
+     * $2000-$2FFF on the PPU, in 1K banks of the console's and cartridge's nametable RAM
+     */
+    pub nametables: Vec<BankMapping>,
+    /**
+     * None when the nametables aren't arranged in one of the usual ways
+     */
+    pub mirroring: Option<MirrorType>,
+    pub irq: Option<IrqState>,
+    pub registers: Vec<MapperRegister>,
+}
+
+impl MapperDescription {
+    /**
+     * The PRG ROM bank the CPU sees at addr
+     */
+    pub fn prg_bank_at(&self, addr: u16) -> Option<&BankMapping> {
+        self.prg_rom
+            .iter()
+            .find(|b| (b.start..=b.end).contains(&addr))
+    }
+
+    pub fn chr_bank_at(&self, addr: u16) -> Option<&BankMapping> {
+        self.chr.iter().find(|b| (b.start..=b.end).contains(&addr))
+    }
+
+    pub fn register(&self, name: &str) -> Option<u16> {
+        self.registers
+            .iter()
+            .find(|r| r.name == name)
+            .map(|r| r.value)
+    }
+}
+
+impl Display for MapperDescription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "mapper {}.{}", self.mapper_number, self.submapper)?;
+        for (name, banks) in [
+            ("prg rom", &self.prg_rom),
+            ("prg ram", &self.prg_ram),
+            ("chr", &self.chr),
+            ("nametables", &self.nametables),
+        ] {
+            writeln!(f, "{name}:")?;
+            for bank in banks {
+                writeln!(f, "    {bank}")?;
+            }
+        }
+        match self.mirroring {
+            Some(mirroring) => write!(f, "mirroring {mirroring:?}")?,
+            None => write!(f, "mirroring mapper controlled")?,
+        }
+        if let Some(irq) = &self.irq {
+            write!(f, "\nirq {irq}")?;
+        }
+        if !self.registers.is_empty() {
+            let registers = self
+                .registers
+                .iter()
+                .map(|r| format!("{} ${:02X}", r.name, r.value))
+                .collect::<Vec<_>>();
+            write!(f, "\nregisters {}", registers.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl NES {
+    pub fn mapper_description(&self) -> MapperDescription {
+        self.cartridge_cpu_port.borrow().describe()
+    }
+}
@@ -0,0 +1,255 @@
+use anyhow::Result;
+use thiserror::Error;
+
+use crate::bus::BusDevice;
+use crate::cpu::assembler::assemble;
+
+use super::NES;
+
+/**
+ * A named block of the NES's memory that tools can read and write without
+ * side effects. Addresses within a domain start at 0.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum MemoryDomain {
+    /**
+     * The CPU's address space with the current banks, registers included
+     */
+    CpuBus,
+    /**
+     * The PPU's address space with the current banks
+     */
+    PpuBus,
+    /**
+     * The console's 2K of work RAM
+     */
+    WorkRam,
+    /**
+     * All of PRG ROM, by its offset in the ROM rather than where it's mapped
+     */
+    PrgRom,
+    /**
+     * The cartridge's work or battery backed RAM
+     */
+    PrgRam,
+    /**
+     * CHR ROM or CHR RAM
+     */
+    Chr,
+    /**
+     * The console's nametable RAM, along with any the cartridge adds for four screen mirroring
+     */
+    Ciram,
+    PaletteRam,
+    /**
+     * The 64 sprites
+     */
+    Oam,
+    /**
+     * The sprites found for the next scanline
+     */
+    SecondaryOam,
+}
+
+impl MemoryDomain {
+    pub const ALL: [MemoryDomain; 10] = [
+        MemoryDomain::CpuBus,
+        MemoryDomain::PpuBus,
+        MemoryDomain::WorkRam,
+        MemoryDomain::PrgRom,
+        MemoryDomain::PrgRam,
+        MemoryDomain::Chr,
+        MemoryDomain::Ciram,
+        MemoryDomain::PaletteRam,
+        MemoryDomain::Oam,
+        MemoryDomain::SecondaryOam,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MemoryDomain::CpuBus => "cpu",
+            MemoryDomain::PpuBus => "ppu",
+            MemoryDomain::WorkRam => "ram",
+            MemoryDomain::PrgRom => "prg-rom",
+            MemoryDomain::PrgRam => "prg-ram",
+            MemoryDomain::Chr => "chr",
+            MemoryDomain::Ciram => "ciram",
+            MemoryDomain::PaletteRam => "palette",
+            MemoryDomain::Oam => "oam",
+            MemoryDomain::SecondaryOam => "oam2",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|domain| domain.name().eq_ignore_ascii_case(name))
+    }
+}
+
+impl std::fmt::Display for MemoryDomain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+const CPU_BUS_SIZE: usize = 0x10000;
+const PPU_BUS_SIZE: usize = 0x4000;
+const PALETTE_RAM_START: u16 = 0x3F00;
+const PALETTE_RAM_SIZE: usize = 0x20;
+
+impl NES {
+    /**
+     * Reads the CPU's address space without side effects, e.g. peeking at
+     * 0x2002 doesn't clear vblank
+     */
+    pub fn peek_cpu(&self, addr: u16) -> u8 {
+        // the same devices as the CPU's bus, without needing to borrow the CPU
+        match addr {
+            0x0000..=0x1FFF => self.ram.borrow().peek(addr),
+            0x2000..=0x3FFF => self.ppu.borrow().peek(addr),
+            0x4000..=0x4017 => self.apu.borrow().peek(addr),
+            0x4018..=0x401F => self
+                .developer_cartridge
+                .as_ref()
+                .map_or(0, |c| c.borrow().peek(addr)),
+            _ => self.cartridge_cpu_port.borrow().peek(addr),
+        }
+    }
+
+    /**
+     * Writes RAM or patches ROM in the CPU's address space, without writing
+     * to any registers. Writes to registers are ignored.
+     */
+    pub fn poke_cpu(&self, addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram.borrow_mut().poke(addr, data),
+            0x2000..=0x3FFF => self.ppu.borrow_mut().poke(addr, data),
+            0x4000..=0x4017 => self.apu.borrow_mut().poke(addr, data),
+            0x4018..=0x401F => {}
+            _ => self.cartridge_cpu_port.borrow_mut().poke(addr, data),
+        }
+    }
+
+    /**
+     * How many bytes the domain has. 0 for memory the cartridge doesn't have.
+     */
+    pub fn memory_size(&self, domain: MemoryDomain) -> usize {
+        match domain {
+            MemoryDomain::CpuBus => CPU_BUS_SIZE,
+            MemoryDomain::PpuBus => PPU_BUS_SIZE,
+            MemoryDomain::WorkRam => self.ram.borrow().memory().len(),
+            MemoryDomain::PaletteRam => PALETTE_RAM_SIZE,
+            MemoryDomain::Oam => self.ppu.borrow().oam().len(),
+            MemoryDomain::SecondaryOam => self.ppu.borrow().secondary_oam().len(),
+            MemoryDomain::PrgRom
+            | MemoryDomain::PrgRam
+            | MemoryDomain::Chr
+            | MemoryDomain::Ciram => self
+                .cartridge_cpu_port
+                .borrow()
+                .memory(domain)
+                .map_or(0, |memory| memory.len()),
+        }
+    }
+
+    pub fn peek(&self, domain: MemoryDomain, addr: usize) -> Result<u8> {
+        self.check_range(domain, addr, 1)?;
+        let value = match domain {
+            MemoryDomain::CpuBus => self.peek_cpu(addr as u16),
+            MemoryDomain::PpuBus => self.ppu.borrow().peek_vram(addr as u16),
+            MemoryDomain::WorkRam => self.ram.borrow().memory()[addr],
+            MemoryDomain::PaletteRam => {
+                self.ppu.borrow().peek_vram(PALETTE_RAM_START + addr as u16)
+            }
+            MemoryDomain::Oam => self.ppu.borrow().oam()[addr],
+            MemoryDomain::SecondaryOam => self.ppu.borrow().secondary_oam()[addr],
+            MemoryDomain::PrgRom
+            | MemoryDomain::PrgRam
+            | MemoryDomain::Chr
+            | MemoryDomain::Ciram => {
+                let port = self.cartridge_cpu_port.borrow();
+                port.memory(domain).map_or(0, |memory| memory[addr])
+            }
+        };
+        Ok(value)
+    }
+
+    /**
+     * Writes the domain without side effects. ROM can be patched this way,
+     * but registers in the CPU's address space ignore it.
+     */
+    pub fn poke(&self, domain: MemoryDomain, addr: usize, data: u8) -> Result<()> {
+        self.check_range(domain, addr, 1)?;
+        match domain {
+            MemoryDomain::CpuBus => self.poke_cpu(addr as u16, data),
+            MemoryDomain::PpuBus => self.ppu.borrow_mut().poke_vram(addr as u16, data),
+            MemoryDomain::WorkRam => self.ram.borrow_mut().memory_mut()[addr] = data,
+            MemoryDomain::PaletteRam => self
+                .ppu
+                .borrow_mut()
+                .poke_vram(PALETTE_RAM_START + addr as u16, data),
+            MemoryDomain::Oam => self.ppu.borrow_mut().oam_mut()[addr] = data,
+            MemoryDomain::SecondaryOam => self.ppu.borrow_mut().secondary_oam_mut()[addr] = data,
+            MemoryDomain::PrgRom
+            | MemoryDomain::PrgRam
+            | MemoryDomain::Chr
+            | MemoryDomain::Ciram => {
+                if let Some(mut memory) = self.cartridge_cpu_port.borrow().memory_mut(domain) {
+                    memory[addr] = data;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * A copy of the whole domain
+     */
+    pub fn dump(&self, domain: MemoryDomain) -> Vec<u8> {
+        (0..self.memory_size(domain))
+            .map(|addr| self.peek(domain, addr).unwrap_or(0))
+            .collect()
+    }
+
+    /**
+     * Overwrites the domain from addr onwards with data, e.g. to restore a dump
+     */
+    pub fn import(&self, domain: MemoryDomain, addr: usize, data: &[u8]) -> Result<()> {
+        self.check_range(domain, addr, data.len())?;
+        for (i, byte) in data.iter().enumerate() {
+            self.poke(domain, addr + i, *byte)?;
+        }
+        Ok(())
+    }
+
+    /**
+     * Assembles source into the domain at addr for the CPU to run at origin, e.g. to
+     * patch PRG ROM, and returns how many bytes it took
+     */
+    pub fn assemble_into(
+        &self,
+        domain: MemoryDomain,
+        addr: usize,
+        origin: u16,
+        source: &str,
+    ) -> Result<usize> {
+        let bytes = assemble(source, origin)?;
+        self.import(domain, addr, &bytes)?;
+        Ok(bytes.len())
+    }
+
+    fn check_range(&self, domain: MemoryDomain, addr: usize, len: usize) -> Result<()> {
+        let size = self.memory_size(domain);
+        if addr.checked_add(len).is_none_or(|end| end > size) {
+            Err(MemoryDomainError::OutOfRange(domain, addr, size))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum MemoryDomainError {
+    #[error("{1:#x} is outside {0}, which has {2:#x} bytes")]
+    OutOfRange(MemoryDomain, usize, usize),
+}
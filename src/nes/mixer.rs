@@ -0,0 +1,58 @@
+#[cfg(test)]
+mod unit_tests;
+
+// the output of a single APU pulse channel at full volume
+const APU_PULSE_FULL_VOLUME: f32 = 95.88 / ((8128.0 / 15.0) + 100.0);
+
+/**
+ * Sound chips that can be found on cartridges. Each produces samples in
+ * its own native units which the mixer scales to sit at the right level
+ * alongside the APU.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExpansionAudioChip {
+    /** Already on the APU's scale since it uses the same DACs */
+    Mmc5,
+    /** Average of the enabled channels, -120..=105 */
+    Namco163,
+    /** Sum of the pulse and sawtooth levels, 0..=61 */
+    Vrc6,
+    /** Sum of the 3 channels' linear amplitudes, 0.0..=3.0 */
+    Sunsoft5B,
+}
+
+impl ExpansionAudioChip {
+    fn level(self) -> f32 {
+        match self {
+            ExpansionAudioChip::Mmc5 => 1.0,
+            // one channel at full volume is about as loud as an APU pulse
+            ExpansionAudioChip::Namco163 => APU_PULSE_FULL_VOLUME / 120.0,
+            // each step of a VRC6 pulse matches a step of an APU pulse
+            ExpansionAudioChip::Vrc6 => APU_PULSE_FULL_VOLUME / 15.0,
+            // one channel at full volume is about twice as loud as an APU pulse
+            ExpansionAudioChip::Sunsoft5B => APU_PULSE_FULL_VOLUME * 2.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ExpansionAudioSample {
+    pub chip: ExpansionAudioChip,
+    pub output: f32,
+}
+
+impl ExpansionAudioSample {
+    pub fn new(chip: ExpansionAudioChip, output: f32) -> Self {
+        Self { chip, output }
+    }
+}
+
+/**
+ * Combines the APU's output with whatever the cartridge contributes
+ */
+pub fn mix(apu_output: f32, expansion: Option<ExpansionAudioSample>) -> f32 {
+    match expansion {
+        Some(sample) => apu_output + sample.output * sample.chip.level(),
+        None => apu_output,
+    }
+}
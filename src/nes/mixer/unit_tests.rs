@@ -0,0 +1,30 @@
+use crate::nes::mixer::{APU_PULSE_FULL_VOLUME, ExpansionAudioChip, ExpansionAudioSample, mix};
+
+#[test]
+fn test_no_expansion_audio() {
+    assert_eq!(0.25, mix(0.25, None));
+}
+
+#[test]
+fn test_mmc5_is_on_apu_scale() {
+    let sample = ExpansionAudioSample::new(ExpansionAudioChip::Mmc5, 0.1);
+    assert!((mix(0.25, Some(sample)) - 0.35).abs() < 0.0001);
+}
+
+#[test]
+fn test_vrc6_pulse_matches_apu_pulse() {
+    let sample = ExpansionAudioSample::new(ExpansionAudioChip::Vrc6, 15.0);
+    assert!((mix(0.0, Some(sample)) - APU_PULSE_FULL_VOLUME).abs() < 0.0001);
+}
+
+#[test]
+fn test_silent_chip_adds_nothing() {
+    for chip in [
+        ExpansionAudioChip::Mmc5,
+        ExpansionAudioChip::Namco163,
+        ExpansionAudioChip::Vrc6,
+        ExpansionAudioChip::Sunsoft5B,
+    ] {
+        assert_eq!(0.5, mix(0.5, Some(ExpansionAudioSample::new(chip, 0.0))));
+    }
+}
@@ -8,12 +8,13 @@ mod unit_tests;
 
 use std::{cell::RefCell, rc::Rc};
 
+use anyhow::Result;
+
 use crate::bus::{Bus, BusDevice, InterruptFlags};
+use crate::save_state::{SaveState, SaveStateError, StateReader, StateWriter};
 
-use self::{
-    flags::{CtrlFlags, MaskFlags, StatusFlags},
-    rgb::translate_nes_to_rgb,
-};
+use self::flags::{CtrlFlags, MaskFlags, StatusFlags};
+pub(crate) use self::rgb::translate_nes_to_rgb;
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct PixelInfo {
@@ -24,6 +25,44 @@ pub struct PixelInfo {
     pub b: u8,
 }
 
+/**
+ * What the PPU is fetching when it reads its bus. Some mappers, e.g. MMC5,
+ * bank differently for sprites and background or substitute attributes.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PpuFetchKind {
+    Nametable,
+    Attribute,
+    BackgroundPattern,
+    SpritePattern,
+    /**
+     * Reads on behalf of the CPU through 0x2007 (and the dummy read after 0x2006)
+     */
+    Data,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PpuFetchContext {
+    pub kind: PpuFetchKind,
+    pub sprite_size_large: bool,
+    pub rendering_enabled: bool,
+    pub scan_line: i16,
+    pub dot: u16,
+}
+
+/**
+ * Told about each PPU bus read just before it happens
+ */
+pub trait PpuFetchObserver {
+    fn ppu_fetch(&mut self, addr: u16, context: PpuFetchContext);
+
+    /**
+     * Told about writes through 0x2007, including the ones to palette memory
+     */
+    #[allow(unused_variables)]
+    fn ppu_write(&mut self, addr: u16, data: u8) {}
+}
+
 const CPU_ADDR_START: u16 = 0x2000;
 const CPU_ADDR_END: u16 = 0x3FFF;
 const CPU_ADDR_MASK: u16 = 0x2007;
@@ -53,6 +92,7 @@ pub struct PPU {
     status_register: StatusFlags,
 
     bus: Bus,
+    fetch_observers: Vec<Rc<RefCell<dyn PpuFetchObserver>>>,
 
     primary_oam: OAMData<PRIMARY_OAM_SIZE>,
     secondary_oam: OAMData<SECONDARY_OAM_SIZE>,
@@ -86,6 +126,7 @@ impl PPU {
 
             resetting: true,
             bus: Bus::new(),
+            fetch_observers: Vec::new(),
             primary_oam: OAMData::new(),
             secondary_oam: OAMData::new(),
             sprite_row_data: SpriteRowSet::new(),
@@ -133,6 +174,129 @@ impl PPU {
         self.bus.add_device(device);
     }
 
+    pub fn add_fetch_observer(&mut self, observer: Rc<RefCell<dyn PpuFetchObserver>>) {
+        self.fetch_observers.push(observer);
+    }
+
+    pub fn remove_fetch_observer(&mut self, observer: &Rc<RefCell<dyn PpuFetchObserver>>) {
+        self.fetch_observers.retain(|o| !Rc::ptr_eq(o, observer));
+    }
+
+    pub fn scan_line(&self) -> i16 {
+        self.scan_line
+    }
+
+    pub fn dot(&self) -> u16 {
+        self.dot
+    }
+
+    /**
+     * Reads the PPU's address space without side effects. Palette entries
+     * are as stored, without greyscale applied.
+     */
+    pub fn peek_vram(&self, addr: u16) -> u8 {
+        let addr = addr & 0x3FFF;
+        if (PALETTE_START..=PALETTE_END).contains(&addr) {
+            self.palettes[palette_index(addr)]
+        } else {
+            self.bus.peek(addr)
+        }
+    }
+
+    pub fn poke_vram(&mut self, addr: u16, data: u8) {
+        let addr = addr & 0x3FFF;
+        if (PALETTE_START..=PALETTE_END).contains(&addr) {
+            self.write_palette(addr, data);
+        } else {
+            self.bus.poke(addr, data);
+        }
+    }
+
+    pub fn oam(&self) -> &[u8] {
+        &self.primary_oam.table
+    }
+
+    pub fn oam_mut(&mut self) -> &mut [u8] {
+        &mut self.primary_oam.table
+    }
+
+    /**
+     * The sprites found for the next scanline
+     */
+    pub fn secondary_oam(&self) -> &[u8] {
+        &self.secondary_oam.table
+    }
+
+    pub fn secondary_oam_mut(&mut self) -> &mut [u8] {
+        &mut self.secondary_oam.table
+    }
+
+    /**
+     * Where the top left of the screen is in the 512x480 space of the four
+     * nametables, going by the scroll registers
+     */
+    pub fn scroll(&self) -> (u16, u16) {
+        let t = &self.temporary_vram_address;
+        let x = t.get_x() as u16
+            + if t.get_horizontal_nametable_selected() {
+                256
+            } else {
+                0
+            };
+        let y = t.get_y() as u16
+            + if t.get_vertical_nametable_selected() {
+                240
+            } else {
+                0
+            };
+        (x, y)
+    }
+
+    pub fn background_pattern_table(&self) -> u16 {
+        if self.read_ctrl_flag(CtrlFlags::BackgroundPatternHigh) {
+            0x1000
+        } else {
+            0x0000
+        }
+    }
+
+    /**
+     * The pattern table 8x8 sprites use. 8x16 sprites choose with their tile number.
+     */
+    pub fn sprite_pattern_table(&self) -> u16 {
+        if self.read_ctrl_flag(CtrlFlags::SpriteTableHigh) {
+            0x1000
+        } else {
+            0x0000
+        }
+    }
+
+    pub fn large_sprites(&self) -> bool {
+        self.read_ctrl_flag(CtrlFlags::SpriteSizeLarge)
+    }
+
+    fn notify_write(&self, addr: u16, data: u8) {
+        for observer in &self.fetch_observers {
+            observer.borrow_mut().ppu_write(addr, data);
+        }
+    }
+
+    fn read_bus(&mut self, addr: u16, kind: PpuFetchKind) -> u8 {
+        if !self.fetch_observers.is_empty() {
+            let context = PpuFetchContext {
+                kind,
+                sprite_size_large: self.read_ctrl_flag(CtrlFlags::SpriteSizeLarge),
+                rendering_enabled: self.rendering_enabled(),
+                scan_line: self.scan_line,
+                dot: self.dot,
+            };
+            for observer in &self.fetch_observers {
+                observer.borrow_mut().ppu_fetch(addr, context);
+            }
+        }
+        self.bus.read(addr)
+    }
+
     pub fn reset(&mut self) {
         self.resetting = true;
         self.even_frame = true;
@@ -161,11 +325,12 @@ impl PPU {
 
     fn manage_bus_request(&mut self) {
         match self.bus_request {
-            BusRequest::Read(addr) => {
-                self.data_buffer = self.bus.read(addr);
+            BusRequest::Read(addr, kind) => {
+                self.data_buffer = self.read_bus(addr, kind);
                 self.bus_request = BusRequest::None;
             }
             BusRequest::Write(addr, data) => {
+                self.notify_write(addr, data);
                 self.bus.write(addr, data);
                 self.bus_request = BusRequest::None;
             }
@@ -364,6 +529,7 @@ impl PPU {
                 1 => {
                     self.start_bus_request(BusRequest::Read(
                         self.vram_address.get_nametable_address(),
+                        PpuFetchKind::Nametable,
                     ));
                     if self.dot >= 9 {
                         self.bg_shift_registers.latch();
@@ -373,14 +539,18 @@ impl PPU {
                     .bg_shift_registers
                     .load_name_table_data(self.data_buffer),
 
-                3 if self.dot != 339 => self
-                    .start_bus_request(BusRequest::Read(self.vram_address.get_attribute_address())),
+                3 if self.dot != 339 => self.start_bus_request(BusRequest::Read(
+                    self.vram_address.get_attribute_address(),
+                    PpuFetchKind::Attribute,
+                )),
                 4 if self.dot != 340 => self
                     .bg_shift_registers
                     .load_attribute_data(self.data_buffer, self.vram_address.get_attribute_shift()),
 
-                3 if self.dot == 339 => self
-                    .start_bus_request(BusRequest::Read(self.vram_address.get_nametable_address())),
+                3 if self.dot == 339 => self.start_bus_request(BusRequest::Read(
+                    self.vram_address.get_nametable_address(),
+                    PpuFetchKind::Nametable,
+                )),
                 4 if self.dot == 340 => self
                     .bg_shift_registers
                     .load_name_table_data(self.data_buffer),
@@ -389,14 +559,20 @@ impl PPU {
                     if 261 <= self.dot && self.dot <= 320 {
                         if -1 <= self.scan_line && self.scan_line <= 239 {
                             let addr = self.compute_base_sprite_pattern_address();
-                            self.start_bus_request(BusRequest::Read(addr));
+                            self.start_bus_request(BusRequest::Read(
+                                addr,
+                                PpuFetchKind::SpritePattern,
+                            ));
                         }
                     } else {
                         let address = self.bg_shift_registers.get_pattern_address(
                             self.read_ctrl_flag(CtrlFlags::BackgroundPatternHigh),
                             self.vram_address.get_fine_y(),
                         );
-                        self.start_bus_request(BusRequest::Read(address));
+                        self.start_bus_request(BusRequest::Read(
+                            address,
+                            PpuFetchKind::BackgroundPattern,
+                        ));
                     };
                 }
                 6 => {
@@ -415,14 +591,20 @@ impl PPU {
                     if 261 <= self.dot && self.dot <= 320 {
                         if -1 <= self.scan_line && self.scan_line <= 239 {
                             let addr = self.compute_base_sprite_pattern_address() | 0b00001000;
-                            self.start_bus_request(BusRequest::Read(addr));
+                            self.start_bus_request(BusRequest::Read(
+                                addr,
+                                PpuFetchKind::SpritePattern,
+                            ));
                         }
                     } else {
                         let address = self.bg_shift_registers.get_pattern_address(
                             self.read_ctrl_flag(CtrlFlags::BackgroundPatternHigh),
                             self.vram_address.get_fine_y(),
                         ) | 0b00001000;
-                        self.start_bus_request(BusRequest::Read(address));
+                        self.start_bus_request(BusRequest::Read(
+                            address,
+                            PpuFetchKind::BackgroundPattern,
+                        ));
                     };
                 }
                 0 => {
@@ -454,13 +636,11 @@ impl PPU {
     fn compute_base_sprite_pattern_address(&mut self) -> u16 {
         let sprite_large_mode = self.read_ctrl_flag(CtrlFlags::SpriteSizeLarge);
         let sprite_high_mode = self.read_ctrl_flag(CtrlFlags::SpriteTableHigh);
-        let result = self.sprite_row_data.current_sprite().get_pattern_address(
+        self.sprite_row_data.current_sprite().get_pattern_address(
             sprite_large_mode,
             sprite_high_mode,
             self.scan_line,
-        );
-
-        result
+        )
     }
 
     fn manage_render(&mut self) -> Option<PixelInfo> {
@@ -534,11 +714,11 @@ impl PPU {
             */
             let color = self.read_palette(palette_address);
 
-            let (r, g, b) = if SHOW_GRID && ((x % 32 == 0) || (y % 32 == 0)) {
+            let (r, g, b) = if SHOW_GRID && (x.is_multiple_of(32) || y.is_multiple_of(32)) {
                 (255, 0, 0)
-            } else if SHOW_GRID && ((x % 16 == 0) || (y % 16 == 0)) {
+            } else if SHOW_GRID && (x.is_multiple_of(16) || y.is_multiple_of(16)) {
                 (0, 255, 0)
-            } else if SHOW_GRID && ((x % 8 == 0) || (y % 8 == 0)) {
+            } else if SHOW_GRID && (x.is_multiple_of(8) || y.is_multiple_of(8)) {
                 (0, 0, 255)
             } else {
                 translate_nes_to_rgb(color)
@@ -570,16 +750,7 @@ impl PPU {
     }
 
     fn read_palette(&self, addr: u16) -> u8 {
-        let mirrored = addr & PALETTE_MASK;
-
-        // 10/14/18/1C are mapped to 00/04/08/0C
-        let physical = if mirrored & 0b11110011 == 0b00010000 {
-            mirrored & 0b00001100
-        } else {
-            mirrored
-        };
-
-        let data = self.palettes[physical as usize];
+        let data = self.palettes[palette_index(addr)];
         // greyscale mode asks off the low bits
         if self.read_mask_flag(MaskFlags::Greyscale) {
             data & 0b00110000
@@ -589,16 +760,9 @@ impl PPU {
     }
 
     fn write_palette(&mut self, addr: u16, data: u8) -> u8 {
-        let mirrored = addr & PALETTE_MASK;
-
-        // 10/14/18/1C are mapped to 00/04/08/0C
-        let physical = if mirrored & 0b00010011 == 0b00010000 {
-            mirrored & 0b00001100
-        } else {
-            mirrored
-        };
-        let old = self.palettes[physical as usize];
-        self.palettes[physical as usize] = data & 0b00111111;
+        let physical = palette_index(addr);
+        let old = self.palettes[physical];
+        self.palettes[physical] = data & 0b00111111;
         old
     }
 
@@ -685,7 +849,7 @@ impl BusDevice for PPU {
                         self.data_buffer
                     };
                     // vram is read even when in palette address range
-                    self.start_bus_request(BusRequest::Read(addr));
+                    self.start_bus_request(BusRequest::Read(addr, PpuFetchKind::Data));
 
                     self.inc_vram_addr();
                     result
@@ -749,7 +913,7 @@ impl BusDevice for PPU {
                             let result = self.temporary_vram_address.set_address_low(data);
                             self.vram_address.register = self.temporary_vram_address.register;
                             // do an extra read to signal for MMC3 style mappers
-                            self.bus.read(self.vram_address.register);
+                            self.read_bus(self.vram_address.register, PpuFetchKind::Data);
                             result
                         }
                     } else {
@@ -759,6 +923,7 @@ impl BusDevice for PPU {
                 0x2007 => {
                     let addr = self.vram_address.register & 0x3FFF;
                     let result = if (PALETTE_START..PALETTE_END).contains(&addr) {
+                        self.notify_write(addr, data);
                         self.write_palette(addr, data)
                     } else {
                         let old = self.data_buffer;
@@ -775,6 +940,25 @@ impl BusDevice for PPU {
         }
     }
 
+    fn peek(&self, addr: u16) -> u8 {
+        match addr & CPU_ADDR_MASK {
+            0x2002 => self.status_register.bits() | (self.data_buffer & 0x1F),
+            0x2004 => self.primary_oam.read_data(),
+            0x2007 => {
+                let addr = self.vram_address.register & 0x3FFF;
+                if (PALETTE_START..PALETTE_END).contains(&addr) {
+                    self.read_palette(addr)
+                } else {
+                    self.data_buffer
+                }
+            }
+            _ => self.data_buffer,
+        }
+    }
+
+    // every register write changes the PPU's state, so they can't be poked
+    fn poke(&mut self, _addr: u16, _data: u8) {}
+
     fn bus_clock(&mut self) -> InterruptFlags {
         // NMI is edge detected from on to off. Only
         // trigger the off condition when both
@@ -789,6 +973,17 @@ impl BusDevice for PPU {
     }
 }
 
+// 10/14/18/1C are mapped to 00/04/08/0C
+fn palette_index(addr: u16) -> usize {
+    let mirrored = addr & PALETTE_MASK;
+    let physical = if mirrored & 0b00010011 == 0b00010000 {
+        mirrored & 0b00001100
+    } else {
+        mirrored
+    };
+    physical as usize
+}
+
 #[cfg(test)]
 pub fn create_test_configuration() -> (PPU, Rc<RefCell<crate::ram::RAM>>) {
     use crate::ram::RAM;
@@ -1188,7 +1383,7 @@ impl BGShiftRegisterSet {
  */
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 enum BusRequest {
-    Read(u16),
+    Read(u16, PpuFetchKind),
     Write(u16, u8),
     None,
 }
@@ -1441,6 +1636,7 @@ impl SpriteRowSet {
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 enum SpriteEvalState {
     ReadY,
     WriteCompareY,
@@ -1451,3 +1647,188 @@ enum SpriteEvalState {
     ReadX,
     WriteX,
 }
+
+impl SaveState for PPU {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("PPU");
+        writer.write_usize(self.ppu_tick);
+        writer.write_usize(self.last_status_read_tick);
+        writer.write_u8(self.ctrl_high_register.bits());
+        writer.write_u8(self.mask_register.bits());
+        writer.write_u8(self.status_register.bits());
+        self.primary_oam.save_state(writer);
+        self.secondary_oam.save_state(writer);
+        self.sprite_row_data.save_state(writer);
+        writer.write_bytes(&self.palettes);
+        writer.write_i16(self.scan_line);
+        writer.write_u16(self.dot);
+        writer.write_bool(self.even_frame);
+        writer.write_bool(self.write_toggle);
+        self.vram_address.save_state(writer);
+        self.temporary_vram_address.save_state(writer);
+        self.bg_shift_registers.save_state(writer);
+        writer.write_u8(self.oam_buffer);
+        writer.write_u8(self.sprite_eval_state as u8);
+        match self.bus_request {
+            BusRequest::None => writer.write_u8(0),
+            BusRequest::Read(addr, kind) => {
+                writer.write_u8(1);
+                writer.write_u16(addr);
+                writer.write_u8(kind as u8);
+            }
+            BusRequest::Write(addr, data) => {
+                writer.write_u8(2);
+                writer.write_u16(addr);
+                writer.write_u8(data);
+            }
+        }
+        writer.write_u8(self.data_buffer);
+        writer.write_bool(self.resetting);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("PPU")?;
+        self.ppu_tick = reader.read_usize()?;
+        self.last_status_read_tick = reader.read_usize()?;
+        self.ctrl_high_register = CtrlFlags::from_bits_retain(reader.read_u8()?);
+        self.mask_register = MaskFlags::from_bits_retain(reader.read_u8()?);
+        self.status_register = StatusFlags::from_bits_retain(reader.read_u8()?);
+        self.primary_oam.load_state(reader)?;
+        self.secondary_oam.load_state(reader)?;
+        self.sprite_row_data.load_state(reader)?;
+        reader.read_bytes_into(&mut self.palettes)?;
+        self.scan_line = reader.read_i16()?;
+        self.dot = reader.read_u16()?;
+        self.even_frame = reader.read_bool()?;
+        self.write_toggle = reader.read_bool()?;
+        self.vram_address.load_state(reader)?;
+        self.temporary_vram_address.load_state(reader)?;
+        self.bg_shift_registers.load_state(reader)?;
+        self.oam_buffer = reader.read_u8()?;
+        self.sprite_eval_state = match reader.read_u8()? {
+            0 => SpriteEvalState::ReadY,
+            1 => SpriteEvalState::WriteCompareY,
+            2 => SpriteEvalState::ReadTileIndex,
+            3 => SpriteEvalState::WriteTileIndex,
+            4 => SpriteEvalState::ReadAttributes,
+            5 => SpriteEvalState::WriteTileAttributes,
+            6 => SpriteEvalState::ReadX,
+            7 => SpriteEvalState::WriteX,
+            n => Err(SaveStateError::InvalidValue("sprite eval state", n as u32))?,
+        };
+        self.bus_request = match reader.read_u8()? {
+            0 => BusRequest::None,
+            1 => BusRequest::Read(
+                reader.read_u16()?,
+                match reader.read_u8()? {
+                    0 => PpuFetchKind::Nametable,
+                    1 => PpuFetchKind::Attribute,
+                    2 => PpuFetchKind::BackgroundPattern,
+                    3 => PpuFetchKind::SpritePattern,
+                    4 => PpuFetchKind::Data,
+                    n => Err(SaveStateError::InvalidValue("ppu fetch kind", n as u32))?,
+                },
+            ),
+            2 => BusRequest::Write(reader.read_u16()?, reader.read_u8()?),
+            n => Err(SaveStateError::InvalidValue("ppu bus request", n as u32))?,
+        };
+        self.data_buffer = reader.read_u8()?;
+        self.resetting = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+impl SaveState for VramAddress {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.register);
+        writer.write_u8(self.fine_x);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.register = reader.read_u16()?;
+        self.fine_x = reader.read_u8()?;
+        Ok(())
+    }
+}
+
+impl SaveState for BGShiftRegisterPair {
+    fn save_state(&self, writer: &mut StateWriter) {
+        for register in [&self.high, &self.low] {
+            writer.write_u8(register.prefetch);
+            writer.write_u16(register.data);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        for register in [&mut self.high, &mut self.low] {
+            register.prefetch = reader.read_u8()?;
+            register.data = reader.read_u16()?;
+        }
+        Ok(())
+    }
+}
+
+impl SaveState for BGShiftRegisterSet {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.pattern_data.save_state(writer);
+        self.attribute_data.save_state(writer);
+        writer.write_u8(self.name_table_data);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.pattern_data.load_state(reader)?;
+        self.attribute_data.load_state(reader)?;
+        self.name_table_data = reader.read_u8()?;
+        Ok(())
+    }
+}
+
+impl<const SIZE: usize> SaveState for OAMData<SIZE> {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.addr);
+        writer.write_bytes(&self.table);
+        writer.write_bool(self.read_enabled);
+        writer.write_bool(self.write_enabled);
+        writer.write_bool(self.has_sprite0);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.addr = reader.read_u8()?;
+        reader.read_bytes_into(&mut self.table)?;
+        self.read_enabled = reader.read_bool()?;
+        self.write_enabled = reader.read_bool()?;
+        self.has_sprite0 = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+impl SaveState for SpriteRowSet {
+    fn save_state(&self, writer: &mut StateWriter) {
+        for sprite in &self.sprite_data {
+            writer.write_u8(sprite.y);
+            writer.write_u8(sprite.tile_id);
+            writer.write_u8(sprite.attributes);
+            writer.write_i16(sprite.x);
+            writer.write_bool(sprite.sprite0);
+            writer.write_u8(sprite.pattern_high);
+            writer.write_u8(sprite.pattern_low);
+        }
+        writer.write_usize(self.sprite_number);
+        writer.write_bool(self.write_enabled);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        for sprite in &mut self.sprite_data {
+            sprite.y = reader.read_u8()?;
+            sprite.tile_id = reader.read_u8()?;
+            sprite.attributes = reader.read_u8()?;
+            sprite.x = reader.read_i16()?;
+            sprite.sprite0 = reader.read_bool()?;
+            sprite.pattern_high = reader.read_u8()?;
+            sprite.pattern_low = reader.read_u8()?;
+        }
+        self.sprite_number = reader.read_usize()? & 0b00000111;
+        self.write_enabled = reader.read_bool()?;
+        Ok(())
+    }
+}
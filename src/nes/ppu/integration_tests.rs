@@ -4,7 +4,7 @@ use super::PPU;
 
 use crate::{
     bus::{BusDevice, InterruptFlags},
-    cpu::{CPU, CPUCycleType, CPUType},
+    cpu::{CPU, CPUType},
     nes::apu::{APU, APUCycleType},
     nes::cartridge::{Cartridge, CartridgeCPUPort},
     ram::RAM,
@@ -39,20 +39,25 @@ fn test_dma() {
         // make sure the scanline is in a range that allows oam writes
         ppu.borrow_mut().scan_line = 240;
 
-        let cycles = cpu.borrow_mut().cycles();
-
         ppu.borrow_mut().write(0x2003, 0x02);
         apu.borrow_mut().write(0x4014, 0x03);
-        let _ = apu.borrow_mut().clock(CPUCycleType::Write); // process the write
-        // make sure rdy didn't get cleared with that tick, because it was a write cycle
-        assert!(cpu.borrow().is_rdy());
-        if alignment == 1 {
-            apu.borrow_mut().cycle_type = APUCycleType::Put;
-            _ = apu.borrow_mut().clock(CPUCycleType::Read); // need extra cycle to align
-        }
 
-        for _ in 0..512 {
-            let _ = apu.borrow_mut().clock(CPUCycleType::Read);
+        // the get/put cycle the dma starts on decides if it needs an extra cycle to align
+        apu.borrow_mut().cycle_type = if alignment == 0 {
+            APUCycleType::Get
+        } else {
+            APUCycleType::Put
+        };
+
+        // the dma pulls RDY before the cpu's next cycle, and the cpu halts on its first read
+        let _ = apu.borrow_mut().clock();
+        assert!(!cpu.borrow().is_rdy());
+        cpu.borrow_mut().clock();
+        assert_eq!(Some(0xC000), cpu.borrow().halted_at());
+        let cycles = cpu.borrow_mut().cycles();
+
+        for _ in 0..512 + alignment {
+            let _ = apu.borrow_mut().clock();
             cpu.borrow_mut().clock();
             assert!(!(cpu.borrow().is_rdy()));
             assert_eq!(cycles, cpu.borrow_mut().cycles());
@@ -64,8 +69,12 @@ fn test_dma() {
             assert_eq!(InterruptFlags::NMI, ppu.borrow_mut().bus_clock());
         }
 
-        let _ = apu.borrow_mut().clock(CPUCycleType::Read);
+        // the cpu gets the bus back and repeats the read it was halted on
+        let _ = apu.borrow_mut().clock();
         assert!(cpu.borrow().is_rdy());
+        cpu.borrow_mut().clock();
+        assert_eq!(None, cpu.borrow().halted_at());
+        assert_eq!(cycles, cpu.borrow_mut().cycles());
 
         for address in 0..0x0100 {
             let data = (address as u8).wrapping_sub(0x02) as usize;
@@ -73,3 +82,40 @@ fn test_dma() {
         }
     }
 }
+
+#[test]
+fn test_dma_from_cpu_write() {
+    use crate::nes::NES;
+
+    // waits for the ppu to come out of reset, then copies page 3 to OAM
+    const PROGRAM: [u8; 18] = [
+        0x2C, 0x02, 0x20, // wait: bit $2002
+        0x10, 0xFB, //       bpl wait
+        0x2C, 0x02, 0x20, // wait2: bit $2002
+        0x10, 0xFB, //       bpl wait2
+        0xA9, 0x03, //       lda #3
+        0x8D, 0x14, 0x40, // sta $4014
+        0x4C, 0x0F, 0x04, // loop: jmp loop
+    ];
+
+    let mut nes = NES::new();
+    nes.load_cartridge("resources/test/nestest.nes".to_string())
+        .unwrap();
+    nes.reset();
+    {
+        let mut ram = nes.ram.borrow_mut();
+        for i in 0..0x0100 {
+            ram.raw()[i + 0x0300] = i as u8;
+        }
+        ram.raw()[0x0400..0x0400 + PROGRAM.len()].copy_from_slice(&PROGRAM);
+    }
+    nes.cpu.borrow_mut().reset_to(0x0400);
+
+    // the write halts the cpu that's making it
+    for _ in 0..3 {
+        while !nes.clock().0 {}
+    }
+    for address in 0..0x0100 {
+        assert_eq!(address as u8, nes.ppu.borrow().primary_oam.table[address]);
+    }
+}
@@ -0,0 +1,315 @@
+// Records the CPU's writes that change how the PPU draws: its registers, OAM DMA
+// and the mapper's registers, with where the PPU was at the time. Raster effects
+// like split scrolling, mid-frame CHR swaps and status bars show up as events
+// part way down the frame.
+
+use std::{any::Any, cell::RefCell, fmt::Display, io::Write, rc::Rc};
+
+use anyhow::Result;
+
+use crate::{
+    cpu::monitor::Monitor,
+    nes::{NES, ppu::PPU, ppu_viewer::Image},
+};
+
+const DOTS: usize = 341;
+const SCAN_LINES: usize = 262;
+const VISIBLE_DOTS: usize = 256;
+const VISIBLE_SCAN_LINES: usize = 240;
+const OFFSCREEN_COLOR: u32 = 0x202020;
+const VISIBLE_COLOR: u32 = 0x404040;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PpuEventKind {
+    Ctrl,
+    Mask,
+    Status,
+    OamAddr,
+    OamData,
+    Scroll,
+    Addr,
+    Data,
+    OamDma,
+    /**
+     * Any write to $4020-$5FFF or $8000-$FFFF, since which of those addresses a
+     * mapper decodes isn't known here. Writes to $4020-$5FFF the mapper ignores
+     * are recorded too. PRG RAM at $6000-$7FFF isn't counted, so the few mappers
+     * with registers there aren't seen, and neither are the developer
+     * cartridge's registers at $4018-$401F.
+     */
+    Mapper,
+}
+
+impl PpuEventKind {
+    pub fn from_addr(addr: u16) -> Option<Self> {
+        use PpuEventKind::*;
+
+        let kind = match addr {
+            0x2000..=0x3FFF => match addr & 0x0007 {
+                0 => Ctrl,
+                1 => Mask,
+                2 => Status,
+                3 => OamAddr,
+                4 => OamData,
+                5 => Scroll,
+                6 => Addr,
+                _ => Data,
+            },
+            0x4014 => OamDma,
+            0x4020..=0x5FFF | 0x8000..=0xFFFF => Mapper,
+            _ => return None,
+        };
+        Some(kind)
+    }
+
+    pub fn name(&self) -> &'static str {
+        use PpuEventKind::*;
+
+        match self {
+            Ctrl => "PPUCTRL",
+            Mask => "PPUMASK",
+            Status => "PPUSTATUS",
+            OamAddr => "OAMADDR",
+            OamData => "OAMDATA",
+            Scroll => "PPUSCROLL",
+            Addr => "PPUADDR",
+            Data => "PPUDATA",
+            OamDma => "OAMDMA",
+            Mapper => "mapper",
+        }
+    }
+
+    /**
+     * The color events are plotted in, as 0RGB
+     */
+    pub fn color(&self) -> u32 {
+        use PpuEventKind::*;
+
+        match self {
+            Ctrl => 0xFF4040,
+            Mask => 0x40FF40,
+            Status => 0x808080,
+            OamAddr | OamData => 0xFF9020,
+            Scroll => 0xFFFF40,
+            Addr => 0x40FFFF,
+            Data => 0x4080FF,
+            OamDma => 0xFF40FF,
+            Mapper => 0xFFFFFF,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PpuEvent {
+    pub scan_line: i16,
+    pub dot: u16,
+    /**
+     * The instruction that made the write
+     */
+    pub pc: u16,
+    pub addr: u16,
+    pub data: u8,
+    pub kind: PpuEventKind,
+}
+
+impl Display for PpuEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:3} {:3} ${:04X} ${:04X} = ${:02X} {}",
+            self.scan_line,
+            self.dot,
+            self.pc,
+            self.addr,
+            self.data,
+            self.kind.name()
+        )
+    }
+}
+
+/**
+ * The events of the frame being drawn and the one before it. With a writer, every
+ * frame's events are also written out as they finish.
+ */
+pub struct PpuEvents {
+    frame: u32,
+    current_frame: Vec<PpuEvent>,
+    last_frame: Vec<PpuEvent>,
+    writer: Option<Box<dyn Write>>,
+}
+
+impl PpuEvents {
+    /**
+     * The frame being recorded, the first being 1. Frames start on the pre-render line.
+     */
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn current_frame(&self) -> &[PpuEvent] {
+        &self.current_frame
+    }
+
+    pub fn last_frame(&self) -> &[PpuEvent] {
+        &self.last_frame
+    }
+
+    /**
+     * Writes out the frame in progress
+     */
+    pub fn finish(&mut self) -> Result<()> {
+        write_frame(&mut self.writer, self.frame, &self.current_frame)?;
+        if let Some(writer) = &mut self.writer {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn end_frame(&mut self) -> Result<()> {
+        self.last_frame = std::mem::take(&mut self.current_frame);
+        write_frame(&mut self.writer, self.frame, &self.last_frame)?;
+        self.frame += 1;
+        Ok(())
+    }
+}
+
+fn write_frame(writer: &mut Option<Box<dyn Write>>, frame: u32, events: &[PpuEvent]) -> Result<()> {
+    let Some(writer) = writer else {
+        return Ok(());
+    };
+    writeln!(writer, "frame {frame}")?;
+    for event in events {
+        writeln!(writer, "{event}")?;
+    }
+    Ok(())
+}
+
+/**
+ * Plots events on a grid of the frame's 341 dots by 262 scanlines, with the
+ * pre-render line at the top. The visible area shows screen, dimmed, when it's given.
+ */
+pub fn overlay_image(events: &[PpuEvent], screen: Option<&Image>) -> Image {
+    let mut image = Image::new(DOTS, SCAN_LINES);
+    for y in 0..SCAN_LINES {
+        for x in 0..DOTS {
+            let visible = (1..=VISIBLE_SCAN_LINES).contains(&y) && (1..=VISIBLE_DOTS).contains(&x);
+            let color = match screen {
+                Some(screen) if visible => (screen.pixel(x - 1, y - 1) >> 1) & 0x7F7F7F,
+                _ if visible => VISIBLE_COLOR,
+                _ => OFFSCREEN_COLOR,
+            };
+            image.set_pixel(x, y, color);
+        }
+    }
+
+    // a 3x3 mark for each, so single events stand out
+    for event in events {
+        let x = event.dot as usize;
+        let y = (event.scan_line + 1) as usize;
+        for mark_y in y.saturating_sub(1)..=(y + 1).min(SCAN_LINES - 1) {
+            for mark_x in x.saturating_sub(1)..=(x + 1).min(DOTS - 1) {
+                image.set_pixel(mark_x, mark_y, event.kind.color());
+            }
+        }
+    }
+    image
+}
+
+pub struct PpuEventLogger {}
+
+impl PpuEventLogger {
+    /**
+     * Records events from the next instruction, alongside any other monitors on the CPU
+     */
+    pub fn attach(nes: &mut NES, writer: Option<Box<dyn Write>>) -> Rc<RefCell<PpuEvents>> {
+        let events = Rc::new(RefCell::new(PpuEvents {
+            frame: 1,
+            current_frame: Vec::new(),
+            last_frame: Vec::new(),
+            writer,
+        }));
+        nes.cpu.borrow_mut().add_monitor(Box::new(PpuEventMonitor {
+            events: events.clone(),
+            ppu: nes.ppu.clone(),
+            last_scan_line: i16::MIN,
+            pc: 0,
+        }));
+        events
+    }
+
+    pub fn detach(nes: &mut NES) {
+        nes.cpu.borrow_mut().remove_monitor::<PpuEventMonitor>();
+    }
+}
+
+struct PpuEventMonitor {
+    events: Rc<RefCell<PpuEvents>>,
+    ppu: Rc<RefCell<PPU>>,
+    last_scan_line: i16,
+    pc: u16,
+}
+
+impl PpuEventMonitor {
+    // where the PPU is, starting a new frame when it's gone back to the top
+    fn position(&mut self) -> Result<(i16, u16)> {
+        let (scan_line, dot) = {
+            let ppu = self.ppu.borrow();
+            (ppu.scan_line(), ppu.dot())
+        };
+        if scan_line < self.last_scan_line {
+            self.events.borrow_mut().end_frame()?;
+        }
+        self.last_scan_line = scan_line;
+        Ok((scan_line, dot))
+    }
+}
+
+#[allow(unused_variables)]
+impl Monitor for PpuEventMonitor {
+    fn new_instruction(
+        &mut self,
+        cycle: usize,
+        pc: u16,
+        sp: u8,
+        a: u8,
+        x: u8,
+        y: u8,
+        status: u8,
+    ) -> Result<()> {
+        self.position()?;
+        self.pc = pc;
+        Ok(())
+    }
+
+    fn fetch_instruction_byte(&mut self, byte: u8) -> Result<()> {
+        Ok(())
+    }
+
+    fn read_data_byte(&mut self, addr: u16, data: u8) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_data_byte(&mut self, addr: u16, old: u8, data: u8) -> Result<()> {
+        let Some(kind) = PpuEventKind::from_addr(addr) else {
+            return Ok(());
+        };
+        let (scan_line, dot) = self.position()?;
+        self.events.borrow_mut().current_frame.push(PpuEvent {
+            scan_line,
+            dot,
+            pc: self.pc,
+            addr,
+            data,
+            kind,
+        });
+        Ok(())
+    }
+
+    fn end_instruction(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
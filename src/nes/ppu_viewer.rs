@@ -0,0 +1,290 @@
+// Renders what's in the PPU's memory for tools: the nametables, pattern tables,
+// sprites and palettes. Everything is read without side effects, through the
+// current banks.
+
+use std::fmt::Display;
+
+use super::{
+    NES,
+    ppu::{PPU, translate_nes_to_rgb},
+};
+
+const TILE_SIZE: usize = 8;
+const NAMETABLE_WIDTH: usize = 256;
+const NAMETABLE_HEIGHT: usize = 240;
+const NAMETABLE_TILES_ACROSS: usize = 32;
+const NAMETABLE_TILES_DOWN: usize = 30;
+const ATTRIBUTE_OFFSET: u16 = 0x03C0;
+const PATTERN_TABLE_TILES_ACROSS: usize = 16;
+const SPRITE_COUNT: usize = 64;
+const SPRITES_ACROSS: usize = 8;
+const PALETTE_SWATCH_SIZE: usize = 16;
+const PALETTE_ENTRIES: usize = 32;
+const PALETTE_ENTRIES_ACROSS: usize = 16;
+const SCROLL_COLOR: u32 = 0xFFFFFF;
+
+/**
+ * Pixels as 0RGB, the format minifb takes
+ */
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u32 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: u32) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    /**
+     * Copies image in with its top left at x, y
+     */
+    pub fn draw(&mut self, x: usize, y: usize, image: &Image) {
+        for row in 0..image.height {
+            let start = (y + row) * self.width + x;
+            self.pixels[start..start + image.width]
+                .copy_from_slice(&image.pixels[row * image.width..(row + 1) * image.width]);
+        }
+    }
+}
+
+/**
+ * An entry in OAM with its attributes decoded
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Sprite {
+    pub index: usize,
+    pub x: u8,
+    /**
+     * As stored, the sprite appears a line lower
+     */
+    pub y: u8,
+    pub tile: u8,
+    /**
+     * The sprite palette, 0-3
+     */
+    pub palette: u8,
+    pub behind_background: bool,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+}
+
+impl Sprite {
+    fn decode(index: usize, bytes: &[u8]) -> Self {
+        let attributes = bytes[2];
+        Self {
+            index,
+            x: bytes[3],
+            y: bytes[0],
+            tile: bytes[1],
+            palette: attributes & 0b11,
+            behind_background: attributes & 0b00100000 != 0,
+            flip_horizontal: attributes & 0b01000000 != 0,
+            flip_vertical: attributes & 0b10000000 != 0,
+        }
+    }
+}
+
+impl Display for Sprite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "#{:02} x {:3} y {:3} tile ${:02X} palette {}",
+            self.index, self.x, self.y, self.tile, self.palette
+        )?;
+        for (set, name) in [
+            (self.behind_background, "behind"),
+            (self.flip_horizontal, "flip-h"),
+            (self.flip_vertical, "flip-v"),
+        ] {
+            if set {
+                write!(f, " {name}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl NES {
+    /**
+     * All four nametables, 512x480, with the screen's scroll window outlined
+     */
+    pub fn nametables_image(&self) -> Image {
+        let ppu = self.ppu.borrow();
+        let pattern_table = ppu.background_pattern_table();
+        let mut image = Image::new(NAMETABLE_WIDTH * 2, NAMETABLE_HEIGHT * 2);
+
+        for nametable in 0..4 {
+            let base = 0x2000 + nametable as u16 * 0x0400;
+            let left = (nametable % 2) * NAMETABLE_WIDTH;
+            let top = (nametable / 2) * NAMETABLE_HEIGHT;
+            for row in 0..NAMETABLE_TILES_DOWN {
+                for column in 0..NAMETABLE_TILES_ACROSS {
+                    let tile = ppu.peek_vram(base + (row * NAMETABLE_TILES_ACROSS + column) as u16);
+                    // each attribute byte covers 4x4 tiles, two bits per 2x2
+                    let attribute_addr =
+                        base + ATTRIBUTE_OFFSET + (row / 4 * 8 + column / 4) as u16;
+                    let shift = (row % 4 / 2) * 4 + (column % 4 / 2) * 2;
+                    let palette = (ppu.peek_vram(attribute_addr) >> shift) & 0b11;
+                    let tile = tile_image(&ppu, pattern_table + tile as u16 * 16, palette, false);
+                    image.draw(left + column * TILE_SIZE, top + row * TILE_SIZE, &tile);
+                }
+            }
+        }
+
+        // the window wraps around the edges like the scrolling does
+        let (scroll_x, scroll_y) = ppu.scroll();
+        let (scroll_x, scroll_y) = (scroll_x as usize, scroll_y as usize);
+        for i in 0..NAMETABLE_WIDTH {
+            let x = (scroll_x + i) % image.width;
+            image.set_pixel(x, scroll_y % image.height, SCROLL_COLOR);
+            image.set_pixel(
+                x,
+                (scroll_y + NAMETABLE_HEIGHT - 1) % image.height,
+                SCROLL_COLOR,
+            );
+        }
+        for i in 0..NAMETABLE_HEIGHT {
+            let y = (scroll_y + i) % image.height;
+            image.set_pixel(scroll_x % image.width, y, SCROLL_COLOR);
+            image.set_pixel(
+                (scroll_x + NAMETABLE_WIDTH - 1) % image.width,
+                y,
+                SCROLL_COLOR,
+            );
+        }
+        image
+    }
+
+    /**
+     * Pattern table 0 or 1, 128x128, drawn with palette 0-7 where 4-7 are
+     * the sprite palettes
+     */
+    pub fn pattern_table_image(&self, table: usize, palette: u8) -> Image {
+        let ppu = self.ppu.borrow();
+        let size = PATTERN_TABLE_TILES_ACROSS * TILE_SIZE;
+        let mut image = Image::new(size, size);
+        for tile in 0..PATTERN_TABLE_TILES_ACROSS * PATTERN_TABLE_TILES_ACROSS {
+            let addr = (table as u16 & 1) * 0x1000 + tile as u16 * 16;
+            image.draw(
+                (tile % PATTERN_TABLE_TILES_ACROSS) * TILE_SIZE,
+                (tile / PATTERN_TABLE_TILES_ACROSS) * TILE_SIZE,
+                &tile_image(&ppu, addr, palette & 0b111, false),
+            );
+        }
+        image
+    }
+
+    /**
+     * The 64 sprites in OAM
+     */
+    pub fn sprites(&self) -> Vec<Sprite> {
+        let ppu = self.ppu.borrow();
+        ppu.oam()
+            .chunks(4)
+            .enumerate()
+            .map(|(index, bytes)| Sprite::decode(index, bytes))
+            .collect()
+    }
+
+    /**
+     * The sprite as it would be drawn, 8x8 or 8x16 depending on PPUCTRL.
+     * Transparent pixels are the backdrop color.
+     */
+    pub fn sprite_image(&self, sprite: &Sprite) -> Image {
+        let ppu = self.ppu.borrow();
+        let palette = sprite.palette + 4;
+        let (tiles, pattern_table, first_tile) = if ppu.large_sprites() {
+            (2, (sprite.tile as u16 & 1) * 0x1000, sprite.tile & 0xFE)
+        } else {
+            (1, ppu.sprite_pattern_table(), sprite.tile)
+        };
+
+        let mut image = Image::new(TILE_SIZE, TILE_SIZE * tiles);
+        for i in 0..tiles {
+            let addr = pattern_table + (first_tile + i as u8) as u16 * 16;
+            let tile = tile_image(&ppu, addr, palette, sprite.flip_horizontal);
+            image.draw(0, i * TILE_SIZE, &tile);
+        }
+        // flipping 8x16 sprites vertically swaps the tiles too
+        if sprite.flip_vertical {
+            let rows = image.pixels.chunks(image.width).rev().flatten();
+            image.pixels = rows.copied().collect();
+        }
+        image
+    }
+
+    /**
+     * Previews of all 64 sprites in OAM order, 8 to a row. Each gets an 8x16 cell.
+     */
+    pub fn sprites_image(&self) -> Image {
+        let rows = SPRITE_COUNT / SPRITES_ACROSS;
+        let mut image = Image::new(SPRITES_ACROSS * TILE_SIZE, rows * TILE_SIZE * 2);
+        for sprite in self.sprites() {
+            image.draw(
+                (sprite.index % SPRITES_ACROSS) * TILE_SIZE,
+                (sprite.index / SPRITES_ACROSS) * TILE_SIZE * 2,
+                &self.sprite_image(&sprite),
+            );
+        }
+        image
+    }
+
+    /**
+     * The 32 entries of palette RAM as swatches, background palettes on the top row
+     * and sprite palettes on the bottom
+     */
+    pub fn palette_image(&self) -> Image {
+        let ppu = self.ppu.borrow();
+        let mut image = Image::new(
+            PALETTE_ENTRIES_ACROSS * PALETTE_SWATCH_SIZE,
+            PALETTE_ENTRIES / PALETTE_ENTRIES_ACROSS * PALETTE_SWATCH_SIZE,
+        );
+        for entry in 0..PALETTE_ENTRIES {
+            let color = palette_color(&ppu, entry as u16);
+            let left = (entry % PALETTE_ENTRIES_ACROSS) * PALETTE_SWATCH_SIZE;
+            let top = (entry / PALETTE_ENTRIES_ACROSS) * PALETTE_SWATCH_SIZE;
+            for y in top..top + PALETTE_SWATCH_SIZE {
+                for x in left..left + PALETTE_SWATCH_SIZE {
+                    image.set_pixel(x, y, color);
+                }
+            }
+        }
+        image
+    }
+}
+
+// the 8x8 tile at addr in the PPU's address space, with color 0 as the backdrop
+fn tile_image(ppu: &PPU, addr: u16, palette: u8, flip_horizontal: bool) -> Image {
+    let mut image = Image::new(TILE_SIZE, TILE_SIZE);
+    for row in 0..TILE_SIZE {
+        let low = ppu.peek_vram(addr + row as u16);
+        let high = ppu.peek_vram(addr + row as u16 + 8);
+        for column in 0..TILE_SIZE {
+            let bit = if flip_horizontal { column } else { 7 - column };
+            let value = (((high >> bit) & 1) << 1) | ((low >> bit) & 1);
+            let entry = if value == 0 { 0 } else { palette * 4 + value };
+            image.set_pixel(column, row, palette_color(ppu, entry as u16));
+        }
+    }
+    image
+}
+
+fn palette_color(ppu: &PPU, entry: u16) -> u32 {
+    let (r, g, b) = translate_nes_to_rgb(ppu.peek_vram(0x3F00 + entry) & 0x3F);
+    ((r as u32) << 16) | ((g as u32) << 8) | (b as u32)
+}
@@ -0,0 +1,536 @@
+// Attributes the cpu's cycles to the instruction that ran them, to the subroutines
+// on the JSR call stack and to interrupt handlers, to find where frame time goes.
+// Calls and returns are followed by the stack pointer rather than by matching JSR
+// with RTS, so code that returns with PLA or jumps through RTS doesn't confuse it.
+
+use std::{any::Any, cell::RefCell, cmp::Reverse, collections::HashMap, io::Write, rc::Rc};
+
+use anyhow::Result;
+
+use crate::{
+    cpu::monitor::{InterruptKind, Monitor},
+    nes::{NES, cartridge::CartridgeCPUPort, symbols::Labels, trace_logger::PpuPosition},
+};
+
+const JSR_OPCODE: u8 = 0x20;
+const JSR_CYCLES: usize = 6;
+// the cycles from the interrupted instruction finishing to the handler's first instruction
+const INTERRUPT_CYCLES: usize = 7;
+
+/**
+ * An address in the cpu's memory and, when it's in PRG ROM, where that is in the
+ * ROM, so that the same address in different banks is told apart
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct CodeAddress {
+    pub addr: u16,
+    pub prg_rom_offset: Option<usize>,
+}
+
+impl CodeAddress {
+    /**
+     * The address's label, or the address in hex
+     */
+    pub fn name(&self, labels: Option<&Labels>) -> String {
+        labels
+            .and_then(|l| l.symbols().borrow().label(self.addr, self.prg_rom_offset))
+            .unwrap_or_else(|| format!("${:04X}", self.addr))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum RoutineKind {
+    /**
+     * The code running since reset, at the bottom of every stack
+     */
+    Main,
+    Subroutine,
+    NMI,
+    /**
+     * IRQ and BRK handlers
+     */
+    IRQ,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Routine {
+    pub kind: RoutineKind,
+    pub entry: CodeAddress,
+}
+
+impl Routine {
+    /**
+     * e.g. `player_update`, or `NMI:$C100` for a handler. There are no spaces
+     * or semicolons, as the folded stack format needs.
+     */
+    pub fn name(&self, labels: Option<&Labels>) -> String {
+        let name = self.entry.name(labels);
+        match self.kind {
+            RoutineKind::Main => format!("RESET:{name}"),
+            RoutineKind::Subroutine => name,
+            RoutineKind::NMI => format!("NMI:{name}"),
+            RoutineKind::IRQ => format!("IRQ:{name}"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct RoutineStats {
+    pub calls: usize,
+    /**
+     * Cycles in the routine and everything it called
+     */
+    pub inclusive: usize,
+    /**
+     * Cycles in the routine's own instructions
+     */
+    pub exclusive: usize,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct InstructionStats {
+    /**
+     * How many times the instruction ran
+     */
+    pub count: usize,
+    pub cycles: usize,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct FrameStats {
+    /**
+     * Counting from 1, the same as the trace logger
+     */
+    pub frame: u32,
+    pub cycles: usize,
+    /**
+     * Cycles inside NMI handlers, and inside IRQ handlers
+     */
+    pub nmi_cycles: usize,
+    pub irq_cycles: usize,
+    /**
+     * Cycles the cpu was halted for DMA, which are included in cycles
+     */
+    pub dma_cycles: usize,
+    pub routines: HashMap<Routine, RoutineStats>,
+}
+
+/**
+ * Everything the profiler has counted. Cycles the cpu spends halted by DMA count
+ * towards the instruction that was halted.
+ */
+#[derive(Clone, Default, Debug)]
+pub struct Profile {
+    cycles: usize,
+    dma_cycles: usize,
+    instructions: HashMap<CodeAddress, InstructionStats>,
+    routines: HashMap<Routine, RoutineStats>,
+    stacks: HashMap<Vec<Routine>, usize>,
+    frames: Vec<FrameStats>,
+}
+
+impl Profile {
+    pub fn cycles(&self) -> usize {
+        self.cycles
+    }
+
+    pub fn dma_cycles(&self) -> usize {
+        self.dma_cycles
+    }
+
+    pub fn instructions(&self) -> &HashMap<CodeAddress, InstructionStats> {
+        &self.instructions
+    }
+
+    pub fn routines(&self) -> &HashMap<Routine, RoutineStats> {
+        &self.routines
+    }
+
+    /**
+     * Cycles for each call stack, from the bottom of the stack up
+     */
+    pub fn stacks(&self) -> &HashMap<Vec<Routine>, usize> {
+        &self.stacks
+    }
+
+    pub fn frames(&self) -> &[FrameStats] {
+        &self.frames
+    }
+
+    /**
+     * Writes a line per call stack, e.g. `RESET:$C000;main_loop;player_update 1234`,
+     * which flamegraph.pl and inferno read
+     */
+    pub fn write_folded(&self, writer: &mut dyn Write, labels: Option<&Labels>) -> Result<()> {
+        let mut lines = self
+            .stacks
+            .iter()
+            .map(|(stack, cycles)| {
+                let names = stack
+                    .iter()
+                    .map(|r| r.name(labels))
+                    .collect::<Vec<_>>()
+                    .join(";");
+                (names, cycles)
+            })
+            .collect::<Vec<_>>();
+        lines.sort();
+        for (names, cycles) in lines {
+            writeln!(writer, "{names} {cycles}")?;
+        }
+        Ok(())
+    }
+
+    /**
+     * Writes the totals, the top routines and instructions, and a line per frame
+     */
+    pub fn write_report(
+        &self,
+        writer: &mut dyn Write,
+        labels: Option<&Labels>,
+        top: usize,
+    ) -> Result<()> {
+        let percent = |cycles: usize| 100.0 * cycles as f64 / self.cycles.max(1) as f64;
+        let frames = self.frames.len().max(1);
+        let nmi_cycles = self.frames.iter().map(|f| f.nmi_cycles).sum();
+        let irq_cycles = self.frames.iter().map(|f| f.irq_cycles).sum();
+        writeln!(
+            writer,
+            "{} cycles over {} frames, {} per frame",
+            self.cycles,
+            self.frames.len(),
+            self.cycles / frames
+        )?;
+        writeln!(
+            writer,
+            "NMI handlers {:.1}%, IRQ handlers {:.1}%, DMA {:.1}%",
+            percent(nmi_cycles),
+            percent(irq_cycles),
+            percent(self.dma_cycles)
+        )?;
+
+        writeln!(writer)?;
+        writeln!(
+            writer,
+            "{:>10} {:>6} {:>10} {:>6} {:>8}  routine",
+            "inclusive", "%", "exclusive", "%", "calls"
+        )?;
+        let mut routines = self.routines.iter().collect::<Vec<_>>();
+        routines.sort_by_key(|(routine, stats)| (Reverse(stats.inclusive), routine.entry.addr));
+        for (routine, stats) in routines.into_iter().take(top) {
+            writeln!(
+                writer,
+                "{:>10} {:>6.1} {:>10} {:>6.1} {:>8}  {}",
+                stats.inclusive,
+                percent(stats.inclusive),
+                stats.exclusive,
+                percent(stats.exclusive),
+                stats.calls,
+                routine.name(labels)
+            )?;
+        }
+
+        writeln!(writer)?;
+        writeln!(
+            writer,
+            "{:>10} {:>6} {:>10}  instruction",
+            "cycles", "%", "count"
+        )?;
+        let mut instructions = self.instructions.iter().collect::<Vec<_>>();
+        instructions.sort_by_key(|(address, stats)| (Reverse(stats.cycles), address.addr));
+        for (address, stats) in instructions.into_iter().take(top) {
+            writeln!(
+                writer,
+                "{:>10} {:>6.1} {:>10}  {}",
+                stats.cycles,
+                percent(stats.cycles),
+                stats.count,
+                address.name(labels)
+            )?;
+        }
+
+        writeln!(writer)?;
+        writeln!(
+            writer,
+            "{:>6} {:>8} {:>8} {:>8} {:>8}  busiest routine",
+            "frame", "cycles", "nmi", "irq", "dma"
+        )?;
+        for frame in &self.frames {
+            // the main routine includes everything, so the busiest is the one doing the most itself
+            let busiest = frame
+                .routines
+                .iter()
+                .max_by_key(|(_, stats)| stats.exclusive)
+                .map(|(routine, _)| routine.name(labels))
+                .unwrap_or_default();
+            writeln!(
+                writer,
+                "{:>6} {:>8} {:>8} {:>8} {:>8}  {}",
+                frame.frame,
+                frame.cycles,
+                frame.nmi_cycles,
+                frame.irq_cycles,
+                frame.dma_cycles,
+                busiest
+            )?;
+        }
+        Ok(())
+    }
+
+    fn frame_mut(&mut self, frame: u32) -> &mut FrameStats {
+        if self.frames.last().is_none_or(|f| f.frame != frame) {
+            self.frames.push(FrameStats {
+                frame,
+                ..FrameStats::default()
+            });
+        }
+        self.frames.last_mut().unwrap()
+    }
+}
+
+pub struct Profiler {}
+
+impl Profiler {
+    /**
+     * Profiles the NES's CPU alongside any other monitors on it, from the next instruction
+     */
+    pub fn attach(nes: &mut NES) -> Rc<RefCell<Profile>> {
+        let profile = Rc::new(RefCell::new(Profile::default()));
+        let ppu: Rc<RefCell<dyn PpuPosition>> = nes.ppu.clone();
+        nes.cpu.borrow_mut().add_monitor(Box::new(ProfileMonitor {
+            profile: profile.clone(),
+            cartridge: nes.cartridge_cpu_port.clone(),
+            ppu,
+            last_scan_line: i16::MIN,
+            frame: 1,
+            last_cycle: None,
+            pc: None,
+            sp: 0,
+            stack: Vec::new(),
+            call_stack: Vec::new(),
+            bytes: Vec::with_capacity(3),
+        }));
+        profile
+    }
+
+    pub fn detach(nes: &mut NES) {
+        nes.cpu.borrow_mut().remove_monitor::<ProfileMonitor>();
+    }
+}
+
+struct StackFrame {
+    routine: Routine,
+    // the stack pointer on entry, which for interrupt handlers isn't known until
+    // their first instruction
+    sp: Option<u8>,
+}
+
+struct ProfileMonitor {
+    profile: Rc<RefCell<Profile>>,
+    cartridge: Rc<RefCell<CartridgeCPUPort>>,
+    ppu: Rc<RefCell<dyn PpuPosition>>,
+    last_scan_line: i16,
+    frame: u32,
+    // when the cycles up to now were last counted
+    last_cycle: Option<usize>,
+    // where the cycles being counted go
+    pc: Option<CodeAddress>,
+    sp: u8,
+    stack: Vec<StackFrame>,
+    // the routines in stack, to count stacks by
+    call_stack: Vec<Routine>,
+    // the current instruction's
+    bytes: Vec<u8>,
+}
+
+impl ProfileMonitor {
+    fn code_address(&self, addr: u16) -> CodeAddress {
+        CodeAddress {
+            addr,
+            prg_rom_offset: self.cartridge.borrow().prg_rom_offset(addr),
+        }
+    }
+
+    // counts the cycles since the last count towards the current instruction
+    fn count_to(&mut self, cycle: usize) {
+        let cycles = cycle.saturating_sub(self.last_cycle.unwrap_or(cycle));
+        self.last_cycle = Some(cycle.max(self.last_cycle.unwrap_or(cycle)));
+        self.count(cycles, false);
+    }
+
+    fn count(&mut self, cycles: usize, dma: bool) {
+        let Some(pc) = self.pc else {
+            return;
+        };
+        if cycles == 0 || self.stack.is_empty() {
+            return;
+        }
+        let mut guard = self.profile.borrow_mut();
+        let profile = &mut *guard;
+        profile.cycles += cycles;
+        if dma {
+            profile.dma_cycles += cycles;
+        }
+        profile.instructions.entry(pc).or_default().cycles += cycles;
+
+        let frame = profile.frame_mut(self.frame);
+        frame.cycles += cycles;
+        if dma {
+            frame.dma_cycles += cycles;
+        }
+        let handler = self
+            .stack
+            .iter()
+            .rev()
+            .map(|f| f.routine.kind)
+            .find(|k| matches!(k, RoutineKind::NMI | RoutineKind::IRQ));
+        match handler {
+            Some(RoutineKind::NMI) => frame.nmi_cycles += cycles,
+            Some(RoutineKind::IRQ) => frame.irq_cycles += cycles,
+            _ => {}
+        }
+
+        add_cycles(&mut frame.routines, &self.call_stack, cycles);
+        add_cycles(&mut profile.routines, &self.call_stack, cycles);
+        match profile.stacks.get_mut(&self.call_stack[..]) {
+            Some(total) => *total += cycles,
+            None => {
+                profile.stacks.insert(self.call_stack.clone(), cycles);
+            }
+        }
+    }
+
+    fn enter(&mut self, kind: RoutineKind, entry: CodeAddress, sp: Option<u8>) {
+        let routine = Routine { kind, entry };
+        self.stack.push(StackFrame { routine, sp });
+        self.call_stack.push(routine);
+
+        let mut profile = self.profile.borrow_mut();
+        profile.routines.entry(routine).or_default().calls += 1;
+        let frame = profile.frame_mut(self.frame);
+        frame.routines.entry(routine).or_default().calls += 1;
+    }
+}
+
+#[allow(unused_variables)]
+impl Monitor for ProfileMonitor {
+    fn new_instruction(
+        &mut self,
+        cycle: usize,
+        pc: u16,
+        sp: u8,
+        a: u8,
+        x: u8,
+        y: u8,
+        status: u8,
+    ) -> Result<()> {
+        self.count_to(cycle);
+
+        // instructions are much shorter than a scanline, so the wrap is always seen
+        let (scan_line, _) = self.ppu.borrow().ppu_position();
+        if scan_line < self.last_scan_line {
+            self.frame += 1;
+        }
+        self.last_scan_line = scan_line;
+
+        // returned from, or unwound past, anything entered with a lower stack pointer
+        while self.stack.len() > 1
+            && self
+                .stack
+                .last()
+                .is_some_and(|f| f.sp.is_some_and(|entry_sp| sp > entry_sp))
+        {
+            self.stack.pop();
+            self.call_stack.pop();
+        }
+        if let Some(top) = self.stack.last_mut() {
+            top.sp.get_or_insert(sp);
+        }
+
+        let address = self.code_address(pc);
+        if self.stack.is_empty() {
+            self.enter(RoutineKind::Main, address, None);
+        }
+        self.pc = Some(address);
+        self.sp = sp;
+        self.bytes.clear();
+        self.profile
+            .borrow_mut()
+            .instructions
+            .entry(address)
+            .or_default()
+            .count += 1;
+        Ok(())
+    }
+
+    fn fetch_instruction_byte(&mut self, byte: u8) -> Result<()> {
+        self.bytes.push(byte);
+        Ok(())
+    }
+
+    fn read_data_byte(&mut self, addr: u16, data: u8) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_data_byte(&mut self, addr: u16, old: u8, data: u8) -> Result<()> {
+        Ok(())
+    }
+
+    fn end_instruction(&mut self) -> Result<()> {
+        // the subroutine is entered here rather than at its first instruction in
+        // case an interrupt comes first
+        if let [JSR_OPCODE, low, high] = self.bytes[..] {
+            if let Some(start) = self.last_cycle {
+                self.count_to(start + JSR_CYCLES);
+            }
+            let target = self.code_address(u16::from_le_bytes([low, high]));
+            self.enter(
+                RoutineKind::Subroutine,
+                target,
+                Some(self.sp.wrapping_sub(2)),
+            );
+            self.pc = Some(target);
+        }
+        self.bytes.clear();
+        Ok(())
+    }
+
+    fn interrupt(&mut self, cycle: usize, kind: InterruptKind, handler: u16) -> Result<()> {
+        // the whole interrupt sequence counts towards the handler, and the vector
+        // is read on its last cycle
+        self.count_to((cycle + 1).saturating_sub(INTERRUPT_CYCLES));
+        let address = self.code_address(handler);
+        self.pc = Some(address);
+        match kind {
+            InterruptKind::RST => {
+                self.stack.clear();
+                self.call_stack.clear();
+                self.enter(RoutineKind::Main, address, None);
+            }
+            InterruptKind::NMI => self.enter(RoutineKind::NMI, address, None),
+            InterruptKind::IRQ | InterruptKind::BRK => self.enter(RoutineKind::IRQ, address, None),
+        }
+        Ok(())
+    }
+
+    fn dma_cycle(&mut self, cycle: usize, halted_at: u16) -> Result<()> {
+        self.count(1, true);
+        Ok(())
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+fn add_cycles(routines: &mut HashMap<Routine, RoutineStats>, stack: &[Routine], cycles: usize) {
+    for (i, routine) in stack.iter().enumerate() {
+        // a recursive routine's cycles only count once
+        if !stack[..i].contains(routine) {
+            routines.entry(*routine).or_default().inclusive += cycles;
+        }
+    }
+    if let Some(top) = stack.last() {
+        routines.entry(*top).or_default().exclusive += cycles;
+    }
+}
@@ -0,0 +1,415 @@
+// Labels from assemblers and other emulators. Labels in PRG ROM are kept by their
+// offset in the ROM so that they follow the mapper's banks: the same address can
+// be a different label depending on which bank is mapped there.
+
+#[cfg(test)]
+mod unit_tests;
+
+use std::{cell::RefCell, collections::BTreeMap, collections::HashMap, fs, path::Path, rc::Rc};
+
+use anyhow::Result;
+use thiserror::Error;
+
+use crate::nes::{NES, cartridge::CartridgeCPUPort};
+
+const INES_HEADER_SIZE: usize = 16;
+// FCEUX's .nl files are per 16k bank
+const NL_BANK_SIZE: usize = 0x4000;
+const PRG_ROM_START: u16 = 0x8000;
+const PRG_ROM_WINDOW_SIZE: usize = 0x2000;
+const PRG_RAM_START: u16 = 0x6000;
+// how far past a label without a size an address can be and still be named after it
+const MAX_UNSIZED_SPAN: usize = 0x100;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Location {
+    /**
+     * RAM and registers, which are always at the same address
+     */
+    Cpu(u16),
+    /**
+     * An offset into PRG ROM
+     */
+    PrgRom(usize),
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub location: Location,
+    /**
+     * The address the symbol was declared at, when the file says
+     */
+    pub addr: Option<u16>,
+    pub size: Option<usize>,
+}
+
+#[derive(Default)]
+pub struct SymbolTable {
+    // every symbol at each location, the first one names it
+    cpu: BTreeMap<u16, Vec<Symbol>>,
+    prg_rom: BTreeMap<usize, Vec<Symbol>>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+     * Loads a ca65 .dbg, FCEUX .nl or Mesen .mlb file, going by its extension.
+     * FCEUX names its files game.nes.ram.nl for RAM and game.nes.N.nl for 16k bank N.
+     * Returns how many symbols were added.
+     */
+    pub fn load(&mut self, path: &Path) -> Result<usize> {
+        let text = fs::read_to_string(path)?;
+        let extension = |path: &Path| {
+            path.extension()
+                .and_then(|e| e.to_str())
+                .map(|e| e.to_ascii_lowercase())
+        };
+        match extension(path).as_deref() {
+            Some("dbg") => self.parse_dbg(&text),
+            Some("mlb") => self.parse_mlb(&text),
+            Some("nl") => {
+                let stem = Path::new(path.file_stem().unwrap_or_default());
+                let bank = match extension(stem).as_deref() {
+                    Some("ram") | None => None,
+                    Some(bank) => Some(
+                        usize::from_str_radix(bank, 16)
+                            .map_err(|_| SymbolError::UnknownFormat(path.display().to_string()))?,
+                    ),
+                };
+                self.parse_nl(&text, bank)
+            }
+            _ => Err(SymbolError::UnknownFormat(path.display().to_string()))?,
+        }
+    }
+
+    /**
+     * Reads the labels out of an ld65 --dbgfile. Labels in segments that were
+     * written to the ROM are placed by where they were written.
+     */
+    pub fn parse_dbg(&mut self, text: &str) -> Result<usize> {
+        // id -> (start, ROM offset)
+        let mut segments = HashMap::new();
+        // id -> (name, parent)
+        let mut scopes = HashMap::new();
+        let mut labels = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let Some((kind, fields)) = line.split_once(char::is_whitespace) else {
+                continue;
+            };
+            let fields = dbg_fields(fields);
+            let number = |name: &str| -> Result<usize> {
+                let value = fields
+                    .get(name)
+                    .ok_or_else(|| SymbolError::InvalidLine(i + 1, line.to_string()))?;
+                parse_dbg_number(value)
+                    .ok_or_else(|| SymbolError::InvalidLine(i + 1, line.to_string()).into())
+            };
+            match kind {
+                "seg" => {
+                    let rom_offset = match fields.get("ooffs") {
+                        Some(_) => number("ooffs")?.checked_sub(INES_HEADER_SIZE),
+                        None => None,
+                    };
+                    segments.insert(number("id")?, (number("start")?, rom_offset));
+                }
+                "scope" => {
+                    let parent = fields.get("parent").and_then(|p| parse_dbg_number(p));
+                    let name = fields.get("name").cloned().unwrap_or_default();
+                    scopes.insert(number("id")?, (name, parent));
+                }
+                // cheap local labels have a parent and would clash with each other
+                "sym"
+                    if fields.get("type").map(String::as_str) == Some("lab")
+                        && !fields.contains_key("parent") =>
+                {
+                    let size = fields.get("size").and_then(|s| parse_dbg_number(s));
+                    let segment = fields.get("seg").and_then(|s| parse_dbg_number(s));
+                    let scope = fields.get("scope").and_then(|s| parse_dbg_number(s));
+                    let name = fields.get("name").cloned().unwrap_or_default();
+                    labels.push((name, number("val")?, segment, scope, size));
+                }
+                _ => {}
+            }
+        }
+
+        let count = labels.len();
+        for (name, value, segment, scope, size) in labels {
+            let mut qualified = name;
+            let mut scope = scope;
+            while let Some((scope_name, parent)) = scope.and_then(|s| scopes.get(&s)) {
+                if !scope_name.is_empty() {
+                    qualified = format!("{scope_name}::{qualified}");
+                }
+                scope = *parent;
+            }
+            let addr = value as u16;
+            let location = match segment.and_then(|s| segments.get(&s)) {
+                Some((start, Some(rom_offset))) if value >= *start => {
+                    Location::PrgRom(rom_offset + value - start)
+                }
+                _ => Location::Cpu(addr),
+            };
+            self.add(Symbol {
+                name: qualified,
+                location,
+                addr: Some(addr),
+                size,
+            });
+        }
+        Ok(count)
+    }
+
+    /**
+     * Reads an FCEUX name list, e.g. `$C0F3#player_update#comment` or
+     * `$0300/10#enemies#` for 16 bytes. Addresses from 0x8000 are in the given
+     * 16k bank, anything else is RAM or a register.
+     */
+    pub fn parse_nl(&mut self, text: &str, bank: Option<usize>) -> Result<usize> {
+        let mut count = 0;
+        for (i, line) in text.lines().enumerate() {
+            let invalid = || SymbolError::InvalidLine(i + 1, line.to_string());
+            let Some(entry) = line.strip_prefix('$') else {
+                continue;
+            };
+            let mut parts = entry.splitn(3, '#');
+            let address = parts.next().unwrap_or_default();
+            let name = parts.next().ok_or_else(invalid)?.trim();
+            if name.is_empty() {
+                continue;
+            }
+            let (addr, size) = match address.split_once('/') {
+                Some((addr, size)) => (addr, Some(size)),
+                None => (address, None),
+            };
+            let addr = u16::from_str_radix(addr.trim(), 16).map_err(|_| invalid())?;
+            let size = size
+                .map(|s| usize::from_str_radix(s.trim(), 16).map_err(|_| invalid()))
+                .transpose()?;
+
+            let location = match bank {
+                Some(bank) if addr >= PRG_ROM_START => Location::PrgRom(
+                    bank * NL_BANK_SIZE + (addr - PRG_ROM_START) as usize % NL_BANK_SIZE,
+                ),
+                _ => Location::Cpu(addr),
+            };
+            self.add(Symbol {
+                name: name.to_string(),
+                location,
+                addr: Some(addr),
+                size,
+            });
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /**
+     * Reads a Mesen label file, e.g. `P:00F3:player_update:comment` or, from
+     * Mesen 2, `NesPrgRom:00F3:player_update`. A range like `R:0300-030F` sizes the label.
+     */
+    pub fn parse_mlb(&mut self, text: &str) -> Result<usize> {
+        let mut count = 0;
+        for (i, line) in text.lines().enumerate() {
+            let invalid = || SymbolError::InvalidLine(i + 1, line.to_string());
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut parts = line.splitn(4, ':');
+            let (Some(kind), Some(range), Some(name)) = (parts.next(), parts.next(), parts.next())
+            else {
+                Err(invalid())?
+            };
+            let name = name.trim();
+            // comments can be saved without a label
+            if name.is_empty() {
+                continue;
+            }
+            let (start, end) = match range.split_once('-') {
+                Some((start, end)) => (start, Some(end)),
+                None => (range, None),
+            };
+            let start = usize::from_str_radix(start, 16).map_err(|_| invalid())?;
+            let size = end
+                .map(|e| usize::from_str_radix(e, 16).map_err(|_| invalid()))
+                .transpose()?
+                .map(|end| end.saturating_sub(start) + 1);
+
+            let location = match kind {
+                "P" | "NesPrgRom" => Location::PrgRom(start),
+                "R" | "G" | "NesInternalRam" | "NesMemory" => Location::Cpu(start as u16),
+                "S" | "W" | "NesSaveRam" | "NesWorkRam" => {
+                    Location::Cpu(PRG_RAM_START.wrapping_add(start as u16))
+                }
+                // CHR, palette and the like aren't in the CPU's address space
+                _ => continue,
+            };
+            self.add(Symbol {
+                name: name.to_string(),
+                location,
+                addr: None,
+                size,
+            });
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /**
+     * Where several symbols are at the same location, the first one added names it
+     */
+    pub fn add(&mut self, symbol: Symbol) {
+        match symbol.location {
+            Location::Cpu(addr) => self.cpu.entry(addr).or_default().push(symbol),
+            Location::PrgRom(offset) => self.prg_rom.entry(offset).or_default().push(symbol),
+        };
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /**
+     * RAM and register symbols, then PRG ROM ones, each in order
+     */
+    pub fn symbols(&self) -> impl Iterator<Item = &Symbol> {
+        self.cpu.values().chain(self.prg_rom.values()).flatten()
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Symbol> {
+        self.symbols().find(|s| s.name == name)
+    }
+
+    /**
+     * Names addr, e.g. `player_update+3`. prg_rom_offset is where addr is in
+     * PRG ROM with the current banks, if it's in PRG ROM at all.
+     */
+    pub fn label(&self, addr: u16, prg_rom_offset: Option<usize>) -> Option<String> {
+        let (symbol, offset) = match prg_rom_offset {
+            Some(offset) => covering(&self.prg_rom, offset, MAX_UNSIZED_SPAN),
+            None => covering(&self.cpu, addr, 1),
+        }?;
+        if offset == 0 {
+            Some(symbol.name.clone())
+        } else {
+            Some(format!("{}+{offset}", symbol.name))
+        }
+    }
+}
+
+// the symbol at or before key that key falls within, and how far into it key is
+fn covering<K: Ord + Copy + Into<usize>>(
+    symbols: &BTreeMap<K, Vec<Symbol>>,
+    key: K,
+    unsized_span: usize,
+) -> Option<(&Symbol, usize)> {
+    let (start, symbols) = symbols.range(..=key).next_back()?;
+    let symbol = symbols.first()?;
+    let offset = key.into() - (*start).into();
+    (offset < symbol.size.unwrap_or(unsized_span)).then_some((symbol, offset))
+}
+
+// key=value pairs separated by commas, where strings are quoted
+fn dbg_fields(text: &str) -> HashMap<&str, String> {
+    let mut fields = HashMap::new();
+    let mut rest = text.trim();
+    while let Some((key, value)) = rest.split_once('=') {
+        let (value, remaining) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                let after = quoted[end..].trim_start_matches('"');
+                (quoted[..end].to_string(), after)
+            }
+            None => {
+                let end = value.find(',').unwrap_or(value.len());
+                (value[..end].to_string(), &value[end..])
+            }
+        };
+        fields.insert(key.trim(), value);
+        rest = remaining.trim_start_matches(',');
+    }
+    fields
+}
+
+fn parse_dbg_number(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/**
+ * A symbol table for the NES's loaded cartridge, which looks labels in PRG ROM
+ * up through the mapper's current banks
+ */
+#[derive(Clone)]
+pub struct Labels {
+    symbols: Rc<RefCell<SymbolTable>>,
+    cartridge: Rc<RefCell<CartridgeCPUPort>>,
+}
+
+impl Labels {
+    pub fn new(nes: &NES) -> Self {
+        Self {
+            symbols: Rc::new(RefCell::new(SymbolTable::new())),
+            cartridge: nes.cartridge_cpu_port.clone(),
+        }
+    }
+
+    /**
+     * Adds the symbols in a file, see SymbolTable::load
+     */
+    pub fn load(&self, path: &Path) -> Result<usize> {
+        self.symbols.borrow_mut().load(path)
+    }
+
+    pub fn symbols(&self) -> &Rc<RefCell<SymbolTable>> {
+        &self.symbols
+    }
+
+    pub fn label(&self, addr: u16) -> Option<String> {
+        let prg_rom_offset = self.cartridge.borrow().prg_rom_offset(addr);
+        self.symbols.borrow().label(addr, prg_rom_offset)
+    }
+
+    /**
+     * Where the CPU sees the symbol with the current banks. A PRG ROM symbol
+     * whose bank isn't mapped falls back to where it was declared, if known.
+     */
+    pub fn resolve(&self, name: &str) -> Option<u16> {
+        let symbols = self.symbols.borrow();
+        let symbol = symbols.find(name)?;
+        match symbol.location {
+            Location::Cpu(addr) => Some(addr),
+            Location::PrgRom(offset) => {
+                let cartridge = self.cartridge.borrow();
+                // from the top down, as the top bank is usually the fixed one
+                (0..4)
+                    .rev()
+                    .map(|window| {
+                        PRG_ROM_START
+                            + (window * PRG_ROM_WINDOW_SIZE + offset % PRG_ROM_WINDOW_SIZE) as u16
+                    })
+                    .find(|addr| cartridge.prg_rom_offset(*addr) == Some(offset))
+                    .or(symbol.addr)
+            }
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum SymbolError {
+    #[error("{0} isn't a .dbg, .nl or .mlb file")]
+    UnknownFormat(String),
+    #[error("Line {0} isn't understood: {1}")]
+    InvalidLine(usize, String),
+}
@@ -0,0 +1,122 @@
+use crate::nes::symbols::{Location, Symbol, SymbolTable};
+
+const DBG: &str = r#"version	major=2,minor=0
+info	csym=0,file=2,lib=0,line=40,mod=1,scope=3,seg=4,span=50,sym=6,type=4
+seg	id=0,name="ZEROPAGE",start=0x000000,size=0x0010,addrsize=zeropage,type=rw
+seg	id=1,name="HEADER",start=0x000000,size=0x0010,addrsize=absolute,type=ro,oname="game.nes",ooffs=0
+seg	id=2,name="CODE",start=0x00C000,size=0x0100,addrsize=absolute,type=ro,oname="game.nes",ooffs=16400
+scope	id=0,name="",mod=0,size=256
+scope	id=1,name="player",mod=0,type=scope,size=32,parent=0,sym=1
+sym	id=0,name="frame_count",addrsize=zeropage,scope=0,def=1,ref=5,val=0x02,seg=0,type=lab
+sym	id=1,name="player",addrsize=absolute,size=32,scope=0,def=2,val=0xC010,seg=2,type=lab
+sym	id=2,name="update",addrsize=absolute,scope=1,def=3,val=0xC010,seg=2,type=lab
+sym	id=3,name="@loop",addrsize=absolute,scope=1,def=4,val=0xC014,seg=2,type=lab,parent=2
+sym	id=4,name="SPEED",addrsize=zeropage,scope=0,def=6,val=0x3,type=equ
+sym	id=5,name="reset",addrsize=absolute,scope=0,def=7,val=0xC000,seg=2,type=lab
+"#;
+
+#[test]
+fn test_dbg() {
+    let mut symbols = SymbolTable::new();
+    assert_eq!(4, symbols.parse_dbg(DBG).unwrap());
+
+    let update = symbols.find("player::update").unwrap();
+    assert_eq!(Location::PrgRom(0x4010), update.location);
+    assert_eq!(Some(0xC010), update.addr);
+    assert_eq!(
+        Location::Cpu(0x02),
+        symbols.find("frame_count").unwrap().location
+    );
+    assert!(symbols.find("@loop").is_none());
+    assert!(symbols.find("SPEED").is_none());
+
+    // player and player::update are both at 0xC010, the first one added is used
+    assert_eq!(
+        Some("player".to_string()),
+        symbols.label(0xC010, Some(0x4010))
+    );
+    assert_eq!(
+        Some("player+31".to_string()),
+        symbols.label(0xC02F, Some(0x402F))
+    );
+    assert_eq!(
+        Some("reset+15".to_string()),
+        symbols.label(0xC00F, Some(0x400F))
+    );
+    assert_eq!(None, symbols.label(0xC030, Some(0x4030)));
+    assert_eq!(Some("frame_count".to_string()), symbols.label(0x02, None));
+    assert_eq!(None, symbols.label(0x03, None));
+}
+
+#[test]
+fn test_nl() {
+    let mut symbols = SymbolTable::new();
+    let text = "$C0F3#player_update#moves the player\n$8000#bank_start#\n";
+    assert_eq!(2, symbols.parse_nl(text, Some(3)).unwrap());
+    assert_eq!(
+        Location::PrgRom(3 * 0x4000 + 0xF3),
+        symbols.find("player_update").unwrap().location
+    );
+    assert_eq!(
+        Location::PrgRom(3 * 0x4000),
+        symbols.find("bank_start").unwrap().location
+    );
+
+    assert_eq!(1, symbols.parse_nl("$0300/10#enemies#\n", None).unwrap());
+    assert_eq!(Some("enemies+15".to_string()), symbols.label(0x030F, None));
+    assert_eq!(None, symbols.label(0x0310, None));
+
+    assert!(symbols.parse_nl("$XYZ#oops#\n", None).is_err());
+}
+
+#[test]
+fn test_mlb() {
+    let mut symbols = SymbolTable::new();
+    let text = "P:00F3:player_update:moves the player\n\
+                R:0300-030F:enemies\n\
+                G:2000:PPUCTRL\n\
+                S:0010:high_score\n\
+                P:0100::just a comment\n\
+                NesPrgRom:4000:bank_one\n";
+    assert_eq!(5, symbols.parse_mlb(text).unwrap());
+    assert_eq!(
+        Some(&Symbol {
+            name: "enemies".to_string(),
+            location: Location::Cpu(0x0300),
+            addr: None,
+            size: Some(16),
+        }),
+        symbols.find("enemies")
+    );
+    assert_eq!(
+        Location::Cpu(0x6010),
+        symbols.find("high_score").unwrap().location
+    );
+    assert_eq!(
+        Location::PrgRom(0x4000),
+        symbols.find("bank_one").unwrap().location
+    );
+    assert_eq!(Some("PPUCTRL".to_string()), symbols.label(0x2000, None));
+    assert_eq!(
+        Some("player_update+2".to_string()),
+        symbols.label(0x80F5, Some(0xF5))
+    );
+}
+
+#[test]
+fn test_banks_are_told_apart() {
+    let mut symbols = SymbolTable::new();
+    symbols
+        .parse_mlb("P:0000:bank0_start\nP:4000:bank1_start\n")
+        .unwrap();
+    assert_eq!(
+        Some("bank0_start".to_string()),
+        symbols.label(0x8000, Some(0))
+    );
+    assert_eq!(
+        Some("bank1_start".to_string()),
+        symbols.label(0x8000, Some(0x4000))
+    );
+    // unsized labels only reach so far
+    assert_eq!(None, symbols.label(0x9000, Some(0x1000)));
+}
@@ -0,0 +1,680 @@
+// Writes a line per instruction in the style of another emulator's trace log, so a
+// run can be compared against that emulator line by line. Everything in a line comes
+// from what the CPU reports to its monitor, memory is never read to build it.
+
+use std::{
+    any::Any,
+    cell::RefCell,
+    fmt::Display,
+    io::{BufRead, Write},
+    mem,
+    rc::Rc,
+};
+
+use anyhow::Result;
+use thiserror::Error;
+
+use crate::{
+    cpu::{
+        decode::decode,
+        disassembler::{branch_target, instruction_length, is_official},
+        flags::StatusFlags,
+        instructions::{Instruction, Mode},
+        monitor::Monitor,
+    },
+    nes::{NES, ppu::PPU, symbols::Labels},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TraceFormat {
+    /**
+     * Nintendulator's layout, used by nestest.log
+     */
+    Nestest,
+    Mesen,
+    Fceux,
+}
+
+impl TraceFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "nestest" => Some(TraceFormat::Nestest),
+            "mesen" => Some(TraceFormat::Mesen),
+            "fceux" => Some(TraceFormat::Fceux),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * Where the PPU was when an instruction started
+ */
+pub trait PpuPosition {
+    /**
+     * The scanline and dot for the instruction that's starting
+     */
+    fn ppu_position(&self) -> (i16, u16);
+}
+
+impl PpuPosition for PPU {
+    fn ppu_position(&self) -> (i16, u16) {
+        (self.scan_line(), self.dot())
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TraceOptions {
+    pub format: TraceFormat,
+    /**
+     * Adds the PPU's position, or the frame number for FCEUX which doesn't log it
+     */
+    pub ppu_columns: bool,
+    /**
+     * Only instructions starting in this range are logged
+     */
+    pub addresses: Option<(u16, u16)>,
+    /**
+     * Only instructions in these frames are logged. The first frame is 1, and a
+     * new frame starts on the pre-render scanline.
+     */
+    pub frames: Option<(u32, u32)>,
+}
+
+impl Default for TraceOptions {
+    fn default() -> Self {
+        Self {
+            format: TraceFormat::Nestest,
+            ppu_columns: true,
+            addresses: None,
+            frames: None,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Divergence {
+    /**
+     * Counting from 1, in the reference log
+     */
+    pub line_number: usize,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The trace diverged at line {}\nexpected: {}\n  actual: {}",
+            self.line_number, self.expected, self.actual
+        )
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Comparison {
+    /**
+     * No reference log, or the run hasn't got to the end of it
+     */
+    Running,
+    /**
+     * Every line of the reference log matched
+     */
+    Matched(usize),
+    Diverged(Divergence),
+}
+
+struct Reference {
+    lines: Box<dyn BufRead>,
+    ignored_columns: Vec<String>,
+    line_number: usize,
+}
+
+#[derive(Default)]
+struct InstructionRecord {
+    cycle: usize,
+    pc: u16,
+    sp: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    status: u8,
+    bytes: Vec<u8>,
+    // (address, value) of each data access, with the value before any write
+    data: Vec<(u16, u8)>,
+    scan_line: i16,
+    dot: u16,
+}
+
+pub struct TraceLogger {
+    options: TraceOptions,
+    writer: Box<dyn Write>,
+    ppu: Option<Rc<RefCell<dyn PpuPosition>>>,
+    reference: Option<Reference>,
+    comparison: Comparison,
+    frame: u32,
+    last_scan_line: i16,
+    started: bool,
+    record: InstructionRecord,
+    labels: Option<Labels>,
+}
+
+impl TraceLogger {
+    /**
+     * The PPU columns and frame filter need the PPU's position
+     */
+    pub fn new(
+        options: TraceOptions,
+        writer: Box<dyn Write>,
+        ppu: Option<Rc<RefCell<dyn PpuPosition>>>,
+    ) -> Result<Self> {
+        if ppu.is_none() && (options.ppu_columns || options.frames.is_some()) {
+            Err(TraceError::NoPpu)?;
+        }
+        Ok(Self {
+            options,
+            writer,
+            ppu,
+            reference: None,
+            comparison: Comparison::Running,
+            frame: 1,
+            last_scan_line: i16::MIN,
+            started: false,
+            record: InstructionRecord::default(),
+            labels: None,
+        })
+    }
+
+    /**
+     * Logs the NES's CPU alongside any other monitors on it
+     */
+    pub fn attach(
+        nes: &mut NES,
+        options: TraceOptions,
+        writer: Box<dyn Write>,
+    ) -> Result<Rc<RefCell<TraceLogger>>> {
+        let ppu: Rc<RefCell<dyn PpuPosition>> = nes.ppu.clone();
+        let logger = Rc::new(RefCell::new(TraceLogger::new(options, writer, Some(ppu))?));
+
+        nes.cpu.borrow_mut().add_monitor(Box::new(TraceMonitor {
+            logger: logger.clone(),
+        }));
+        Ok(logger)
+    }
+
+    /**
+     * Compares each line with the next one of a reference log instead of
+     * only writing it. Columns named in ignored_columns, e.g. "PPU" or "CYC",
+     * are left out of the comparison, for logs that were started at a different time.
+     */
+    pub fn compare_with(&mut self, reference: Box<dyn BufRead>, ignored_columns: &[&str]) {
+        self.reference = Some(Reference {
+            lines: reference,
+            ignored_columns: ignored_columns.iter().map(|c| c.to_string()).collect(),
+            line_number: 0,
+        });
+    }
+
+    /**
+     * Writes operand addresses as labels where there's a symbol for them
+     */
+    pub fn set_labels(&mut self, labels: Labels) {
+        self.labels = Some(labels);
+    }
+
+    pub fn comparison(&self) -> &Comparison {
+        &self.comparison
+    }
+
+    /**
+     * Whether there's nothing more to log because the comparison finished
+     */
+    pub fn is_finished(&self) -> bool {
+        self.comparison != Comparison::Running
+    }
+
+    /**
+     * Flushes the log and checks that the run didn't stop short of the end of the
+     * reference log
+     */
+    pub fn finish(&mut self) -> Result<&Comparison> {
+        self.writer.flush()?;
+        if let (Comparison::Running, Some(reference)) = (&self.comparison, &mut self.reference) {
+            let mut expected = String::new();
+            self.comparison = if reference.lines.read_line(&mut expected)? == 0 {
+                Comparison::Matched(reference.line_number)
+            } else {
+                Comparison::Diverged(Divergence {
+                    line_number: reference.line_number + 1,
+                    expected: expected.trim_end().to_string(),
+                    actual: String::new(),
+                })
+            };
+        }
+        Ok(&self.comparison)
+    }
+
+    fn is_logged(&self) -> bool {
+        let in_range = |range: Option<(u16, u16)>, value: u16| {
+            range.is_none_or(|(start, end)| start <= value && value <= end)
+        };
+        let in_frames = self
+            .options
+            .frames
+            .is_none_or(|(start, end)| start <= self.frame && self.frame <= end);
+        in_range(self.options.addresses, self.record.pc) && in_frames && !self.is_finished()
+    }
+
+    fn write_line(&mut self) -> Result<()> {
+        let label = self
+            .labels
+            .as_ref()
+            .zip(operand_target(&self.record))
+            .and_then(|(labels, target)| labels.label(target));
+        let line = format_line(
+            self.options.format,
+            self.options.ppu_columns,
+            self.frame,
+            &self.record,
+            label.as_deref(),
+        );
+        writeln!(self.writer, "{line}")?;
+
+        let Some(reference) = &mut self.reference else {
+            return Ok(());
+        };
+        let mut expected = String::new();
+        if reference.lines.read_line(&mut expected)? == 0 {
+            self.comparison = Comparison::Matched(reference.line_number);
+            return Ok(());
+        }
+        reference.line_number += 1;
+        let expected = expected.trim_end();
+        let ignored = &reference.ignored_columns;
+        if remove_columns(expected, ignored) != remove_columns(&line, ignored) {
+            self.comparison = Comparison::Diverged(Divergence {
+                line_number: reference.line_number,
+                expected: expected.to_string(),
+                actual: line,
+            });
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Takes "NAME:value" columns out of a line. A value runs up to the next column,
+ * so it can contain spaces like nestest's "PPU:  0, 21".
+ */
+fn remove_columns(line: &str, names: &[String]) -> String {
+    let mut line = line.to_string();
+    for name in names {
+        let label = format!("{name}:");
+        let Some(start) = line
+            .match_indices(&label)
+            .map(|(i, _)| i)
+            .find(|i| *i == 0 || line.as_bytes()[i - 1] == b' ')
+        else {
+            continue;
+        };
+        let value_start = start + label.len();
+        let end = next_column(&line[value_start..]).map_or(line.len(), |i| value_start + i);
+        line.replace_range(start..end, "");
+    }
+    line.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// where the next " NAME:" starts in text, skipping the space
+fn next_column(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    (0..bytes.len()).find_map(|i| {
+        if bytes[i] != b' ' {
+            return None;
+        }
+        let name_length = bytes[i + 1..]
+            .iter()
+            .take_while(|b| b.is_ascii_alphabetic())
+            .count();
+        (name_length > 0 && bytes.get(i + 1 + name_length) == Some(&b':')).then_some(i + 1)
+    })
+}
+
+fn format_line(
+    format: TraceFormat,
+    ppu_columns: bool,
+    frame: u32,
+    record: &InstructionRecord,
+    label: Option<&str>,
+) -> String {
+    let status = record.status & !StatusFlags::Break.bits();
+    let bytes = record
+        .bytes
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let disassembly = disassemble(format, record, label);
+
+    match format {
+        TraceFormat::Nestest => {
+            let ppu = if ppu_columns {
+                format!(" PPU:{:3},{:3}", record.scan_line, record.dot)
+            } else {
+                String::new()
+            };
+            format!(
+                "{:04X}  {:8} {:32} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}{} CYC:{}",
+                record.pc,
+                bytes,
+                disassembly,
+                record.a,
+                record.x,
+                record.y,
+                status,
+                record.sp,
+                ppu,
+                record.cycle
+            )
+        }
+        TraceFormat::Mesen => {
+            let ppu = if ppu_columns {
+                format!(" V:{:<3} H:{:<3}", record.scan_line, record.dot)
+            } else {
+                String::new()
+            };
+            format!(
+                "{:04X}  {:40} A:{:02X} X:{:02X} Y:{:02X} S:{:02X} P:{}{} Cycle:{}",
+                record.pc,
+                disassembly,
+                record.a,
+                record.x,
+                record.y,
+                record.sp,
+                flag_letters(status),
+                ppu,
+                record.cycle
+            )
+        }
+        TraceFormat::Fceux => {
+            let frame = if ppu_columns {
+                format!("f{frame:<7}")
+            } else {
+                String::new()
+            };
+            format!(
+                "{}c{:<10}A:{:02X} X:{:02X} Y:{:02X} S:{:02X} P:{}  ${:04X}:{:8}  {}",
+                frame,
+                record.cycle,
+                record.a,
+                record.x,
+                record.y,
+                record.sp,
+                flag_letters(status),
+                record.pc,
+                bytes,
+                disassembly
+            )
+        }
+    }
+}
+
+// e.g. nvUbdIzc, upper case when set
+fn flag_letters(status: u8) -> String {
+    "NVUBDIZC"
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            if status & (0x80 >> i) != 0 {
+                c
+            } else {
+                c.to_ascii_lowercase()
+            }
+        })
+        .collect()
+}
+
+// The address the instruction's operand refers to
+fn operand_target(record: &InstructionRecord) -> Option<u16> {
+    use Mode::*;
+
+    let (_, mode, _, _) = decode(record.bytes[0]);
+    match (mode, &record.bytes[..]) {
+        (Rel, [_, offset]) => Some(branch_target(record.pc, *offset)),
+        (Zp | Zpx | Zpy | IndX | IndY, [_, lo]) => Some(*lo as u16),
+        (Abs | AbsX | AbsY | AbsInd, [_, lo, hi]) => Some(u16::from_le_bytes([*lo, *hi])),
+        _ => None,
+    }
+}
+
+// label replaces the address the operand refers to
+fn disassemble(format: TraceFormat, record: &InstructionRecord, label: Option<&str>) -> String {
+    use Mode::*;
+
+    let op = record.bytes[0];
+    let (instruction, mode, _, _) = decode(op);
+    let operand = match record.bytes[..] {
+        [_, lo, hi] => u16::from_le_bytes([lo, hi]),
+        [_, lo] => lo as u16,
+        _ => 0,
+    };
+    let zp = label.map_or_else(|| format!("${operand:02X}"), |l| l.to_string());
+    let abs = label.map_or_else(|| format!("${operand:04X}"), |l| l.to_string());
+
+    let data = &record.data;
+    let style = Annotation { format };
+    let annotation = match mode {
+        Abs | Zp => data.first().map(|(_, value)| style.value(*value)),
+        AbsX | AbsY => data.first().map(|(addr, value)| style.at(*addr, 4, *value)),
+        Zpx | Zpy => data
+            .first()
+            .map(|(addr, value)| style.at(*addr & 0xFF, 2, *value)),
+        AbsInd => {
+            (data.len() >= 2).then(|| style.pointer(u16::from_le_bytes([data[0].1, data[1].1])))
+        }
+        IndX if data.len() >= 3 => Some(format!(
+            "{}{}{}",
+            style.address(data[0].0 & 0xFF, 2),
+            style.pointer(data[2].0),
+            style.value(data[2].1)
+        )),
+        IndY if data.len() >= 3 => Some(format!(
+            "{}{}",
+            style.pointer(u16::from_le_bytes([data[0].1, data[1].1])),
+            style.at(data[2].0, 4, data[2].1)
+        )),
+        _ => None,
+    };
+
+    let operand = match mode {
+        Imp | Status | SP | X | Y => String::new(),
+        A => " A".to_string(),
+        Imm => format!(" #${operand:02X}"),
+        Zp => format!(" {zp}"),
+        Zpx => format!(" {zp},X"),
+        Zpy => format!(" {zp},Y"),
+        Abs => format!(" {abs}"),
+        AbsX => format!(" {abs},X"),
+        AbsY => format!(" {abs},Y"),
+        AbsInd => format!(" ({abs})"),
+        IndX => format!(" ({zp},X)"),
+        IndY => format!(" ({zp}),Y"),
+        Rel => match label {
+            Some(label) => format!(" {label}"),
+            None => format!(" ${:04X}", branch_target(record.pc, operand as u8)),
+        },
+    };
+
+    let mnemonic = match (format, instruction) {
+        (TraceFormat::Nestest, Instruction::ISC) => "ISB".to_string(),
+        _ => instruction.to_string(),
+    };
+    let official = match format {
+        TraceFormat::Nestest if is_official(op) => " ",
+        TraceFormat::Nestest => "*",
+        _ => "",
+    };
+    format!(
+        "{official}{mnemonic}{operand}{}",
+        annotation.unwrap_or_default()
+    )
+}
+
+/**
+ * How each format writes effective addresses and the values there
+ */
+struct Annotation {
+    format: TraceFormat,
+}
+
+impl Annotation {
+    fn value(&self, value: u8) -> String {
+        match self.format {
+            TraceFormat::Nestest => format!(" = {value:02X}"),
+            TraceFormat::Mesen => format!(" = ${value:02X}"),
+            TraceFormat::Fceux => format!(" = #${value:02X}"),
+        }
+    }
+
+    fn address(&self, addr: u16, digits: usize) -> String {
+        match self.format {
+            TraceFormat::Nestest => format!(" @ {addr:0digits$X}"),
+            TraceFormat::Mesen => format!(" [${addr:0digits$X}]"),
+            TraceFormat::Fceux => format!(" @ ${addr:0digits$X}"),
+        }
+    }
+
+    fn at(&self, addr: u16, digits: usize, value: u8) -> String {
+        format!("{}{}", self.address(addr, digits), self.value(value))
+    }
+
+    fn pointer(&self, addr: u16) -> String {
+        match self.format {
+            TraceFormat::Nestest => format!(" = {addr:04X}"),
+            _ => format!(" = ${addr:04X}"),
+        }
+    }
+}
+
+impl Monitor for TraceLogger {
+    fn new_instruction(
+        &mut self,
+        cycle: usize,
+        pc: u16,
+        sp: u8,
+        a: u8,
+        x: u8,
+        y: u8,
+        status: u8,
+    ) -> Result<()> {
+        let (scan_line, dot) = match &self.ppu {
+            Some(ppu) => ppu.borrow().ppu_position(),
+            None => (0, 0),
+        };
+        // instructions are much shorter than a scanline, so the wrap is always seen
+        if scan_line < self.last_scan_line {
+            self.frame += 1;
+        }
+        self.last_scan_line = scan_line;
+
+        self.started = true;
+        self.record = InstructionRecord {
+            cycle,
+            pc,
+            sp,
+            a,
+            x,
+            y,
+            status,
+            bytes: Vec::with_capacity(3),
+            data: Vec::with_capacity(5),
+            scan_line,
+            dot,
+        };
+        Ok(())
+    }
+
+    fn fetch_instruction_byte(&mut self, byte: u8) -> Result<()> {
+        self.record.bytes.push(byte);
+        Ok(())
+    }
+
+    fn read_data_byte(&mut self, addr: u16, data: u8) -> Result<()> {
+        // read/modify/write instructions read from then write to the same
+        // location, which only needs one entry
+        if self.record.data.last().is_none_or(|(a, _)| *a != addr) {
+            self.record.data.push((addr, data));
+        }
+        Ok(())
+    }
+
+    fn write_data_byte(&mut self, addr: u16, old: u8, _data: u8) -> Result<()> {
+        // logs show what was there before the write
+        self.read_data_byte(addr, old)
+    }
+
+    fn end_instruction(&mut self) -> Result<()> {
+        // interrupts have no bytes and aren't logged
+        let complete =
+            self.record.bytes.first().is_some_and(|op| {
+                self.record.bytes.len() as u16 == instruction_length(decode(*op).1)
+            });
+        if mem::take(&mut self.started) && complete && self.is_logged() {
+            self.write_line()?;
+        }
+        Ok(())
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/**
+ * Attaches the shared logger to the CPU
+ */
+struct TraceMonitor {
+    logger: Rc<RefCell<TraceLogger>>,
+}
+
+impl Monitor for TraceMonitor {
+    fn new_instruction(
+        &mut self,
+        cycle: usize,
+        pc: u16,
+        sp: u8,
+        a: u8,
+        x: u8,
+        y: u8,
+        status: u8,
+    ) -> Result<()> {
+        self.logger
+            .borrow_mut()
+            .new_instruction(cycle, pc, sp, a, x, y, status)
+    }
+
+    fn fetch_instruction_byte(&mut self, byte: u8) -> Result<()> {
+        self.logger.borrow_mut().fetch_instruction_byte(byte)
+    }
+
+    fn read_data_byte(&mut self, addr: u16, data: u8) -> Result<()> {
+        self.logger.borrow_mut().read_data_byte(addr, data)
+    }
+
+    fn write_data_byte(&mut self, addr: u16, old: u8, data: u8) -> Result<()> {
+        self.logger.borrow_mut().write_data_byte(addr, old, data)
+    }
+
+    fn end_instruction(&mut self) -> Result<()> {
+        self.logger.borrow_mut().end_instruction()
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum TraceError {
+    #[error("PPU columns and frame filters need the PPU's position")]
+    NoPpu,
+}
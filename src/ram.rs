@@ -1,4 +1,9 @@
-use crate::bus::{BusDevice, InterruptFlags};
+use anyhow::Result;
+
+use crate::{
+    bus::{BusDevice, InterruptFlags},
+    save_state::{SaveState, StateReader, StateWriter},
+};
 
 pub struct RAM {
     start_addr: u16,
@@ -25,6 +30,17 @@ impl RAM {
         &mut self.memory
     }
 
+    /**
+     * The physical memory, without mirrors
+     */
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
     fn physical(&self, addr: u16) -> usize {
         ((addr & self.addr_mask) - (self.start_addr & self.addr_mask)) as usize
     }
@@ -57,4 +73,25 @@ impl BusDevice for RAM {
     fn bus_clock(&mut self) -> InterruptFlags {
         InterruptFlags::empty()
     }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.memory[self.physical(addr)]
+    }
+
+    fn poke(&mut self, addr: u16, data: u8) {
+        let physical = self.physical(addr);
+        self.memory[physical] = data;
+    }
+}
+
+impl SaveState for RAM {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.begin_section("RAM");
+        writer.write_bytes(&self.memory);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.expect_section("RAM")?;
+        reader.read_bytes_into(&mut self.memory)
+    }
 }
@@ -0,0 +1,206 @@
+use anyhow::Result;
+use thiserror::Error;
+
+const MAGIC: [u8; 4] = [b'N', b'R', b'S', b'S'];
+/**
+ * Bump whenever the layout written by any SaveState implementation changes.
+ * Older versions are rejected rather than guessed at.
+ */
+pub const SAVE_STATE_VERSION: u16 = 5;
+
+/**
+ * Something whose complete runtime state can be captured and later restored.
+ * Implementations must read back exactly what they wrote, in the same order.
+ */
+pub trait SaveState {
+    fn save_state(&self, writer: &mut StateWriter);
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()>;
+}
+
+/**
+ * Builds a little-endian binary save state. Every state starts with a
+ * magic tag and the format version, and is divided into named sections
+ * so that a mismatched layout is caught early instead of silently
+ * loading garbage.
+ */
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        let mut result = Self { data: Vec::new() };
+        result.data.extend_from_slice(&MAGIC);
+        result.write_u16(SAVE_STATE_VERSION);
+        result
+    }
+
+    pub fn begin_section(&mut self, name: &str) {
+        self.write_bytes(name.as_bytes());
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_i16(&mut self, value: i16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_usize(&mut self, value: usize) {
+        self.write_u64(value as u64);
+    }
+
+    /**
+     * Length prefixed block of bytes
+     */
+    pub fn write_bytes(&mut self, value: &[u8]) {
+        self.write_u32(value.len() as u32);
+        self.data.extend_from_slice(value);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    version: u16,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        if data.len() < MAGIC.len() || data[0..MAGIC.len()] != MAGIC {
+            Err(SaveStateError::UnrecognizedFormat)?;
+        }
+        let mut result = Self {
+            data,
+            position: MAGIC.len(),
+            version: 0,
+        };
+        result.version = result.read_u16()?;
+        if result.version != SAVE_STATE_VERSION {
+            Err(SaveStateError::UnsupportedVersion(result.version))?;
+        }
+        Ok(result)
+    }
+
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    pub fn expect_section(&mut self, name: &str) -> Result<()> {
+        let found = self.read_bytes()?;
+        if found != name.as_bytes() {
+            Err(SaveStateError::SectionMismatch(
+                name.to_string(),
+                String::from_utf8_lossy(&found).to_string(),
+            ))?;
+        }
+        Ok(())
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let end = self.position + N;
+        if end > self.data.len() {
+            Err(SaveStateError::UnexpectedEnd)?;
+        }
+        let mut result = [0; N];
+        result.copy_from_slice(&self.data[self.position..end]);
+        self.position = end;
+        Ok(result)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    pub fn read_i16(&mut self) -> Result<i16> {
+        Ok(i16::from_le_bytes(self.take()?))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    pub fn read_usize(&mut self) -> Result<usize> {
+        Ok(self.read_u64()? as usize)
+    }
+
+    pub fn read_bytes(&mut self) -> Result<Vec<u8>> {
+        let len = self.read_u32()? as usize;
+        let end = self.position + len;
+        if end > self.data.len() {
+            Err(SaveStateError::UnexpectedEnd)?;
+        }
+        let result = self.data[self.position..end].to_vec();
+        self.position = end;
+        Ok(result)
+    }
+
+    /**
+     * Reads a length prefixed block of bytes into memory that must already be the right size
+     */
+    pub fn read_bytes_into(&mut self, dest: &mut [u8]) -> Result<()> {
+        let bytes = self.read_bytes()?;
+        if bytes.len() != dest.len() {
+            Err(SaveStateError::SizeMismatch(dest.len(), bytes.len()))?;
+        }
+        dest.copy_from_slice(&bytes);
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum SaveStateError {
+    #[error("The data wasn't recognized as a save state")]
+    UnrecognizedFormat,
+    #[error("Save state version {0} isn't supported")]
+    UnsupportedVersion(u16),
+    #[error("The save state ended unexpectedly")]
+    UnexpectedEnd,
+    #[error("Expected save state section {0} but found {1}")]
+    SectionMismatch(String, String),
+    #[error("Expected {0} bytes of memory in the save state but found {1}")]
+    SizeMismatch(usize, usize),
+    #[error("Invalid value {1} for {0} in the save state")]
+    InvalidValue(&'static str, u32),
+    #[error("The save state was made with a different cartridge")]
+    CartridgeMismatch,
+}